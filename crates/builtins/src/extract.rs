@@ -0,0 +1,161 @@
+use std::{fs, io, path};
+
+#[derive(Debug, failure::Fail)]
+pub enum ExtractError {
+    #[fail(display = "Unrecognised archive format for {:?}.", _0)]
+    UnknownFormat(path::PathBuf),
+    #[fail(display = "I/O error extracting {:?}.", _0)]
+    Io(path::PathBuf, #[fail(cause)] io::Error),
+    #[fail(display = "Bad zip archive {:?}.", _0)]
+    Zip(path::PathBuf, #[fail(cause)] zip::result::ZipError),
+}
+
+enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    fn detect(archive: &path::Path) -> Option<Self> {
+        let name = archive.file_name()?.to_str()?;
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` names an archive `extract` can unpack.
+pub(crate) fn is_archive(path: &path::Path) -> bool {
+    Format::detect(path).is_some()
+}
+
+/// Unpacks `archive` into the directory `target`, replacing anything already
+/// there. Extracted files keep the modification times recorded in the
+/// archive so repeated extractions are indistinguishable from one another.
+pub fn extract(archive: &path::Path, target: &path::Path) -> Result<(), ExtractError> {
+    let format = Format::detect(archive)
+        .ok_or_else(|| ExtractError::UnknownFormat(archive.to_path_buf()))?;
+
+    let io_error = |path: &path::Path| {
+        let path = path.to_path_buf();
+        move |err| ExtractError::Io(path, err)
+    };
+
+    // Unpack alongside the target so that an interrupted extraction is never
+    // mistaken for a complete one.
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = path::PathBuf::from(partial);
+
+    remove_dir_if_exists(&partial).map_err(io_error(&partial))?;
+    fs::create_dir_all(&partial).map_err(io_error(&partial))?;
+
+    let file = io::BufReader::new(fs::File::open(archive).map_err(io_error(archive))?);
+    match format {
+        Format::Tar => tar::Archive::new(file)
+            .unpack(&partial)
+            .map_err(io_error(archive))?,
+        Format::TarGz => tar::Archive::new(flate2::read::GzDecoder::new(file))
+            .unpack(&partial)
+            .map_err(io_error(archive))?,
+        Format::Zip => unzip(archive, file, &partial)?,
+    }
+
+    remove_dir_if_exists(target).map_err(io_error(target))?;
+    fs::rename(&partial, target).map_err(io_error(target))
+}
+
+pub(crate) fn remove_dir_if_exists(dir: &path::Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+fn unzip<R>(archive: &path::Path, reader: R, dir: &path::Path) -> Result<(), ExtractError>
+where
+    R: io::Read + io::Seek,
+{
+    let zip_error = |err| ExtractError::Zip(archive.to_path_buf(), err);
+
+    let mut zip = zip::ZipArchive::new(reader).map_err(zip_error)?;
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(zip_error)?;
+
+        // Entries which would escape the target directory are skipped.
+        let path = match entry.enclosed_name() {
+            Some(name) => dir.join(name),
+            None => continue,
+        };
+        let io_error = |err| ExtractError::Io(path.clone(), err);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(io_error)?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut file = fs::File::create(&path).map_err(io_error)?;
+        io::copy(&mut entry, &mut file).map_err(io_error)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = entry.unix_mode() {
+                file.set_permissions(fs::Permissions::from_mode(mode))
+                    .map_err(io_error)?;
+            }
+        }
+
+        let mtime = filetime::FileTime::from_unix_time(unix_time(entry.last_modified()), 0);
+        filetime::set_file_handle_times(&file, None, Some(mtime)).map_err(io_error)?;
+    }
+
+    Ok(())
+}
+
+// Zip archives record local times without a zone; treat them as UTC.
+fn unix_time(time: zip::DateTime) -> i64 {
+    let (year, month, day) = (
+        i64::from(time.year()),
+        i64::from(time.month()),
+        i64::from(time.day()),
+    );
+
+    // Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    days * 86_400
+        + i64::from(time.hour()) * 3_600
+        + i64::from(time.minute()) * 60
+        + i64::from(time.second())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_convert_zip_time() {
+        let time = |y, m, d, h, min, s| {
+            unix_time(zip::DateTime::from_date_and_time(y, m, d, h, min, s).unwrap())
+        };
+        assert_eq!(time(1980, 1, 1, 0, 0, 0), 315_532_800);
+        assert_eq!(time(2000, 2, 29, 12, 30, 10), 951_827_410);
+        assert_eq!(time(2024, 12, 31, 23, 59, 58), 1_735_689_598);
+    }
+}
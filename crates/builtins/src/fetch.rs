@@ -0,0 +1,157 @@
+use std::{fs, io, path, thread, time};
+
+use sha2::Digest;
+
+const ATTEMPTS: u32 = 4;
+
+#[derive(Debug, failure::Fail)]
+pub enum FetchError {
+    #[fail(display = "Failed to download '{}'.", _0)]
+    Http(String, #[fail(cause)] Box<ureq::Error>),
+    #[fail(display = "I/O error writing {:?}.", _0)]
+    Io(path::PathBuf, #[fail(cause)] io::Error),
+    #[fail(
+        display = "Checksum mismatch for '{}': expected {}, got {}.",
+        url, expected, actual
+    )]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl FetchError {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Http(_, err) => match err.as_ref() {
+                ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+                ureq::Error::Transport(_) => true,
+            },
+            Self::Io(..) | Self::ChecksumMismatch { .. } => false,
+        }
+    }
+}
+
+/// Downloads `url` to `target`, retrying transient failures with
+/// exponential back-off. Proxies are taken from the usual environment
+/// variables.
+pub fn fetch(url: &str, sha256: &str, target: &path::Path) -> Result<(), FetchError> {
+    let agent = ureq::AgentBuilder::new().try_proxy_from_env(true).build();
+
+    let mut attempt = 1;
+    loop {
+        match download(&agent, url, sha256, target) {
+            Err(ref err) if err.is_transient() && attempt < ATTEMPTS => {
+                thread::sleep(time::Duration::from_secs(1 << attempt));
+                attempt += 1;
+            }
+            r => return r,
+        }
+    }
+}
+
+fn download(
+    agent: &ureq::Agent,
+    url: &str,
+    sha256: &str,
+    target: &path::Path,
+) -> Result<(), FetchError> {
+    let response = agent
+        .get(url)
+        .call()
+        .map_err(|err| FetchError::Http(url.to_string(), Box::new(err)))?;
+
+    // Download alongside the target so that a partial file is never
+    // mistaken for a complete one.
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = path::PathBuf::from(partial);
+
+    let io_error = |err| FetchError::Io(partial.clone(), err);
+
+    let mut hasher = sha2::Sha256::new();
+    {
+        let mut file = io::BufWriter::new(fs::File::create(&partial).map_err(io_error)?);
+        let mut reader = response.into_reader();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let count = match io::Read::read(&mut reader, &mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(io_error(err)),
+            };
+            hasher.update(&buffer[..count]);
+            io::Write::write_all(&mut file, &buffer[..count]).map_err(io_error)?;
+        }
+        io::Write::flush(&mut file).map_err(io_error)?;
+    }
+
+    let actual: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    if !actual.eq_ignore_ascii_case(sha256) {
+        let _ = fs::remove_file(&partial);
+        return Err(FetchError::ChecksumMismatch {
+            url: url.to_string(),
+            expected: sha256.to_string(),
+            actual,
+        });
+    }
+
+    fs::rename(&partial, target).map_err(|err| FetchError::Io(target.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve, sha256};
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn downloads_are_kept_only_once_they_check_out() {
+        let dir = TempDir::new("fetch");
+        let target = dir.join("hello.tar.gz");
+        let (url, server) = serve(vec![(200, b"hello"), (200, b"tampered")]);
+        // Digests may be given in either case.
+        fetch(&url, &sha256(b"hello").to_uppercase(), &target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"hello");
+
+        let target = dir.join("tampered.tar.gz");
+        match fetch(&url, &sha256(b"hello"), &target) {
+            Err(FetchError::ChecksumMismatch { actual, .. }) => {
+                assert_eq!(actual, sha256(b"tampered"))
+            }
+            fetched => panic!("{:?}", fetched),
+        }
+        server.join().unwrap();
+        let left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["hello.tar.gz"]);
+    }
+
+    #[test]
+    fn only_failures_which_may_pass_are_tried_again() {
+        let dir = TempDir::new("fetch-retry");
+        let target = dir.join("hello");
+        let (url, server) = serve(vec![(503, b""), (200, b"hello")]);
+        fetch(&url, &sha256(b"hello"), &target).unwrap();
+        server.join().unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"hello");
+
+        // The server answering only the once, trying again would fail
+        // differently.
+        let (url, server) = serve(vec![(404, b"")]);
+        match fetch(&url, &sha256(b"hello"), &dir.join("missing")) {
+            Err(FetchError::Http(_, err)) => assert!(matches!(*err, ureq::Error::Status(404, _))),
+            fetched => panic!("{:?}", fetched),
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::path;
+
+mod extract;
+mod fetch;
+mod probe;
+mod provision;
+#[cfg(test)]
+mod testing;
+
+pub use provision::{provision, ProvisionError};
+
+#[derive(Debug, failure::Fail)]
+pub enum BuiltinError {
+    #[fail(display = "No built-in recipe named '{}'.", _0)]
+    NoSuchBuiltin(String),
+    #[fail(display = "Usage: {}", _0)]
+    Usage(&'static str),
+    #[fail(display = "Fetch failed.")]
+    FetchError(#[fail(cause)] fetch::FetchError),
+    #[fail(display = "Extraction failed.")]
+    ExtractError(#[fail(cause)] extract::ExtractError),
+    #[fail(display = "Probe failed.")]
+    ProbeError(#[fail(cause)] probe::ProbeError),
+}
+
+impl From<fetch::FetchError> for BuiltinError {
+    fn from(err: fetch::FetchError) -> Self {
+        Self::FetchError(err)
+    }
+}
+
+impl From<extract::ExtractError> for BuiltinError {
+    fn from(err: extract::ExtractError) -> Self {
+        Self::ExtractError(err)
+    }
+}
+
+impl From<probe::ProbeError> for BuiltinError {
+    fn from(err: probe::ProbeError) -> Self {
+        Self::ProbeError(err)
+    }
+}
+
+/// Runs the built-in recipe named by the first argument.
+pub fn run(args: &[String]) -> Result<(), BuiltinError> {
+    let (name, args) = args
+        .split_first()
+        .ok_or(BuiltinError::Usage("builtin <name> [args...]"))?;
+
+    match (name.as_str(), args) {
+        ("fetch", [url, sha256, target]) => Ok(fetch::fetch(url, sha256, path::Path::new(target))?),
+        ("fetch", _) => Err(BuiltinError::Usage("fetch <url> <sha256> <target>")),
+        ("extract", [archive, target]) => Ok(extract::extract(
+            path::Path::new(archive),
+            path::Path::new(target),
+        )?),
+        ("extract", _) => Err(BuiltinError::Usage("extract <archive> <target>")),
+        ("probe", [target, tool, args @ ..]) => {
+            Ok(probe::probe(tool, args, path::Path::new(target))?)
+        }
+        ("probe", _) => Err(BuiltinError::Usage("probe <target> <tool> [args...]")),
+        _ => Err(BuiltinError::NoSuchBuiltin(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_are_told_how_they_were_misused() {
+        let run = |args: &[&str]| run(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+        assert!(matches!(run(&[]), Err(BuiltinError::Usage(_))));
+        assert!(matches!(
+            run(&["frobnicate"]),
+            Err(BuiltinError::NoSuchBuiltin(name)) if name == "frobnicate"
+        ));
+        for builtin in &["fetch", "extract", "probe"] {
+            match run(&[builtin]) {
+                Err(BuiltinError::Usage(usage)) => assert!(usage.starts_with(builtin)),
+                ran => panic!("{:?}", ran),
+            }
+        }
+    }
+}
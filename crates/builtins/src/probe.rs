@@ -0,0 +1,62 @@
+use std::{fs, io, path, process};
+
+#[derive(Debug, failure::Fail)]
+pub enum ProbeError {
+    #[fail(display = "Failed to run '{}'.", _0)]
+    Spawn(String, #[fail(cause)] io::Error),
+    #[fail(display = "'{}' failed ({}).", _0, _1)]
+    Failed(String, process::ExitStatus),
+    #[fail(display = "I/O error writing {:?}.", _0)]
+    Io(path::PathBuf, #[fail(cause)] io::Error),
+}
+
+// Line endings, trailing whitespace and blank lines vary with no more than
+// the terminal or platform the tool ran on.
+fn normalise(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let lines = output.lines().map(str::trim_end);
+    let lines: Vec<_> = lines.filter(|line| !line.is_empty()).collect();
+    lines.join("\n") + "\n"
+}
+
+/// Runs `tool` with `args`, e.g. `--version`, writing what it said to
+/// `target`, should it differ from what's there already. Targets are left
+/// alone otherwise, so that those depending upon them only run again once
+/// the tool changes, e.g. on a compiler upgrade behind a wrapper script.
+pub fn probe(tool: &str, args: &[String], target: &path::Path) -> Result<(), ProbeError> {
+    let command = std::iter::once(tool)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = process::Command::new(tool)
+        .args(args)
+        .stdin(process::Stdio::null())
+        .output()
+        .map_err(|err| ProbeError::Spawn(command.clone(), err))?;
+    if !output.status.success() {
+        return Err(ProbeError::Failed(command, output.status));
+    }
+
+    // Some tools, e.g. `gcc -v`, say who they are on stderr.
+    let mut said = output.stdout;
+    said.extend(output.stderr);
+    let fingerprint = normalise(&said);
+    if fs::read_to_string(target).ok().as_deref() == Some(fingerprint.as_str()) {
+        return Ok(());
+    }
+    fs::write(target, fingerprint).map_err(|err| ProbeError::Io(target.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_ignore_line_endings_and_blank_lines() {
+        assert_eq!(
+            normalise(b"cc (GCC) 13.2.0  \r\n\r\nCopyright (C)\r\n"),
+            "cc (GCC) 13.2.0\nCopyright (C)\n"
+        );
+        assert_eq!(normalise(b"cc 13.2.0"), normalise(b"cc 13.2.0\n\n"));
+    }
+}
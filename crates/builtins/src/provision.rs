@@ -0,0 +1,120 @@
+use std::{fs, io, path};
+
+use crate::{extract, fetch};
+
+#[derive(Debug, failure::Fail)]
+pub enum ProvisionError {
+    #[fail(display = "Failed to download tool '{}'.", _0)]
+    Fetch(String, #[fail(cause)] fetch::FetchError),
+    #[fail(display = "Failed to unpack tool '{}'.", _0)]
+    Extract(String, #[fail(cause)] extract::ExtractError),
+    #[fail(display = "I/O error provisioning {:?}.", _0)]
+    Io(path::PathBuf, #[fail(cause)] io::Error),
+}
+
+/// Downloads the tool `name` from `url` into the directory `dir`, unpacking
+/// it there if it's an archive and otherwise keeping it as an executable
+/// named after the tool. Nothing is done if `dir` already exists, which it
+/// only does once the tool is complete.
+pub fn provision(
+    name: &str,
+    url: &str,
+    sha256: &str,
+    dir: &path::Path,
+) -> Result<(), ProvisionError> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+
+    let io_error = |path: &path::Path| {
+        let path = path.to_path_buf();
+        move |err| ProvisionError::Io(path, err)
+    };
+    let sibling = |suffix: &str| {
+        let mut sibling = dir.as_os_str().to_owned();
+        sibling.push(suffix);
+        path::PathBuf::from(sibling)
+    };
+
+    // The download keeps the name the URL gives it, by which archives are
+    // recognised.
+    let downloads = sibling(".download");
+    fs::create_dir_all(&downloads).map_err(io_error(&downloads))?;
+    let file_name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|file_name| !file_name.is_empty())
+        .unwrap_or(name);
+    let download = downloads.join(file_name);
+    fetch::fetch(url, sha256, &download)
+        .map_err(|err| ProvisionError::Fetch(name.to_string(), err))?;
+
+    if extract::is_archive(&download) {
+        extract::extract(&download, dir)
+            .map_err(|err| ProvisionError::Extract(name.to_string(), err))?;
+    } else {
+        let partial = sibling(".part");
+        extract::remove_dir_if_exists(&partial).map_err(io_error(&partial))?;
+        fs::create_dir_all(&partial).map_err(io_error(&partial))?;
+        let exe = partial.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+        fs::rename(&download, &exe).map_err(io_error(&exe))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).map_err(io_error(&exe))?;
+        }
+        fs::rename(&partial, dir).map_err(io_error(dir))?;
+    }
+
+    fs::remove_dir_all(&downloads).map_err(io_error(&downloads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve, sha256};
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn executables_are_kept_named_after_their_tool() {
+        let store = TempDir::new("provision");
+        let body = b"#!/bin/sh\necho hello\n";
+        let (url, server) = serve(vec![(200, body)]);
+        let dir = store.join(sha256(body));
+        provision("hello", &url, &sha256(body), &dir).unwrap();
+        server.join().unwrap();
+
+        let exe = dir.join(format!("hello{}", std::env::consts::EXE_SUFFIX));
+        assert_eq!(fs::read(&exe).unwrap(), body);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(&exe).unwrap().permissions().mode() & 0o777,
+                0o755
+            );
+        }
+        let left: Vec<_> = fs::read_dir(&store).unwrap().collect();
+        assert_eq!(left.len(), 1);
+
+        // Tools already in the store aren't downloaded again, the server
+        // having answered its one request.
+        provision("hello", &url, &sha256(body), &dir).unwrap();
+    }
+
+    #[test]
+    fn tools_failing_their_checksum_are_left_out_of_the_store() {
+        let store = TempDir::new("provision-mismatch");
+        let (url, server) = serve(vec![(200, b"tampered")]);
+        let dir = store.join(sha256(b"expected"));
+        match provision("hello", &url, &sha256(b"expected"), &dir) {
+            Err(ProvisionError::Fetch(name, fetch::FetchError::ChecksumMismatch { .. })) => {
+                assert_eq!(name, "hello")
+            }
+            provisioned => panic!("{:?}", provisioned),
+        }
+        server.join().unwrap();
+        assert!(!dir.exists());
+    }
+}
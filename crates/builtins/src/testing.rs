@@ -0,0 +1,39 @@
+//! What the tests of one module and another have in common.
+
+use std::{io, net, thread};
+
+use sha2::Digest;
+
+/// Answers a request with each status and body in turn, at the URL
+/// returned.
+pub(crate) fn serve(responses: Vec<(u16, &'static [u8])>) -> (String, thread::JoinHandle<()>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "http://{}/releases/hello-1.0",
+        listener.local_addr().unwrap()
+    );
+    let server = thread::spawn(move || {
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut byte = [0u8];
+            while !request.ends_with(b"\r\n\r\n") {
+                io::Read::read_exact(&mut stream, &mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            let head = format!(
+                "HTTP/1.1 {} Whatever\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            io::Write::write_all(&mut stream, head.as_bytes()).unwrap();
+            io::Write::write_all(&mut stream, body).unwrap();
+        }
+    });
+    (url, server)
+}
+
+pub(crate) fn sha256(body: &[u8]) -> String {
+    let digest = sha2::Sha256::digest(body);
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
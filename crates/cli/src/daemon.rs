@@ -0,0 +1,697 @@
+//! `asmbl daemon` serves builds over a unix socket at `.asmbl/daemon.sock`
+//! beneath the target directory, for `asmbl --daemon` and editor plugins
+//! alike. Messages are JSON-RPC 2.0, one per line.
+//!
+//! - `build`, with optional `targets` (an array of strings), `keep_going`,
+//!   `dry_run`, `jobs` and `urgent`, runs a build, sending an `output`
+//!   notification (`id`, `stream` and `line`) for each line it prints. The
+//!   result holds its `exit_code` and, where the build got that far, its
+//!   `summary`. Builds are `urgent` when someone is waiting on them, as with
+//!   `asmbl --daemon`, rather than kept up to date in the background.
+//! - `cancel` stops whichever build is running, which then finishes as any
+//!   other would; the result says whether there was one. Builds still
+//!   waiting their turn are left to run.
+//! - `graph` results in the task graph, as `asmbl graph --format json`
+//!   would print it.
+//! - `diagnostics` results in `subscribed`, after which a `diagnostics`
+//!   notification holding the `problems` `asmbl check --json` would list is
+//!   sent whenever a unit or source file changes, as `asmbl serve-diagnostics`
+//!   prints them, until the connection is closed.
+//!
+//! Only one build runs at a time; those asked for meanwhile wait their turn,
+//! urgent builds first and otherwise in the order they were asked for. An
+//! urgent build asked for during a background one has it make way: the
+//! background build starts nothing more, and once its running tasks finish,
+//! sends a `preempted` notification (`id`) and waits its turn again. Each
+//! build is a fresh `asmbl`, so that units are always read as they are now.
+
+use std::{fs, io, path};
+
+#[derive(Debug, failure::Fail)]
+pub enum DaemonError {
+    #[fail(display = "A daemon is already listening on '{}'.", _0)]
+    AlreadyRunning(String),
+    #[fail(display = "No daemon is listening on '{}'.", _0)]
+    NotRunning(String, #[fail(cause)] io::Error),
+    #[fail(display = "The daemon couldn't build: {}", _0)]
+    Refused(String),
+    #[fail(display = "The daemon hung up part way through the build.")]
+    HungUp,
+    #[cfg(not(unix))]
+    #[fail(display = "The daemon isn't supported on this platform.")]
+    Unsupported,
+}
+
+/// What's asked of the daemon's build.
+pub struct BuildRequest<'a> {
+    pub targets: Vec<&'a str>,
+    pub keep_going: bool,
+    pub dry_run: bool,
+    pub jobs: Option<usize>,
+    pub urgent: bool,
+}
+
+fn socket(target_dir: &path::Path) -> path::PathBuf {
+    target_dir.join(".asmbl").join("daemon.sock")
+}
+
+fn summary_file(target_dir: &path::Path) -> path::PathBuf {
+    target_dir.join(".asmbl").join("summary")
+}
+
+// The summary, if the build got as far as writing one; any left by an earlier
+// build is removed before each starts.
+fn summary(target_dir: &path::Path) -> Option<serde_json::Value> {
+    serde_json::from_str(&fs::read_to_string(summary_file(target_dir)).ok()?).ok()
+}
+
+#[cfg(unix)]
+pub use self::unix::{build, serve};
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        io::{self, BufRead, Read, Write},
+        os::unix::{net, process::CommandExt},
+        path, process, sync, thread,
+    };
+
+    use failure::Error;
+    use serde_json::{json, Value};
+
+    use super::{socket, summary, summary_file, BuildRequest, DaemonError};
+
+    const PARSE_ERROR: i64 = -32700;
+    const INVALID_REQUEST: i64 = -32600;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    const INTERNAL_ERROR: i64 = -32603;
+
+    type Response = Result<Value, (i64, String)>;
+
+    // Messages are written whole, whichever thread they come from.
+    #[derive(Clone)]
+    struct Sender(sync::Arc<sync::Mutex<net::UnixStream>>);
+
+    impl Sender {
+        fn send(&self, message: Value) -> io::Result<()> {
+            let mut stream = self.0.lock().unwrap();
+            writeln!(stream, "{}", message)
+        }
+
+        fn respond(&self, id: Value, response: Response) -> io::Result<()> {
+            self.send(match response {
+                Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": code, "message": message},
+                }),
+            })
+        }
+    }
+
+    // Hands builds their turns, urgent builds first and otherwise in the
+    // order they ask for them.
+    #[derive(Default)]
+    struct Queue {
+        tickets: sync::Mutex<Tickets>,
+        turn: sync::Condvar,
+    }
+
+    #[derive(Default)]
+    struct Tickets {
+        next: u64,
+        // The tickets of the builds waiting, and whether each is urgent.
+        waiting: Vec<(u64, bool)>,
+        taken: bool,
+    }
+
+    // A build's turn, which passes to the next once dropped.
+    struct Turn<'a>(&'a Queue);
+
+    impl Queue {
+        // Has a build wait its turn, returning its ticket.
+        fn join(&self, urgent: bool) -> u64 {
+            let mut tickets = self.tickets.lock().unwrap();
+            tickets.next += 1;
+            let ticket = tickets.next;
+            tickets.waiting.push((ticket, urgent));
+            ticket
+        }
+
+        // A build made to wait its turn again keeps its place.
+        fn rejoin(&self, ticket: u64, urgent: bool) {
+            self.tickets.lock().unwrap().waiting.push((ticket, urgent));
+        }
+
+        fn urgent_waiting(&self) -> bool {
+            let tickets = self.tickets.lock().unwrap();
+            tickets.waiting.iter().any(|(_, urgent)| *urgent)
+        }
+
+        fn wait(&self, ticket: u64) -> Turn<'_> {
+            let mut tickets = self.tickets.lock().unwrap();
+            loop {
+                let next = tickets
+                    .waiting
+                    .iter()
+                    .min_by_key(|(ticket, urgent)| (!urgent, *ticket));
+                if !tickets.taken && next.map(|next| next.0) == Some(ticket) {
+                    break;
+                }
+                tickets = self.turn.wait(tickets).unwrap();
+            }
+            tickets.waiting.retain(|waiting| waiting.0 != ticket);
+            tickets.taken = true;
+            Turn(self)
+        }
+    }
+
+    impl Drop for Turn<'_> {
+        fn drop(&mut self) {
+            self.0.tickets.lock().unwrap().taken = false;
+            self.0.turn.notify_all();
+        }
+    }
+
+    // The build whose turn it is.
+    struct Running {
+        // The build's process, once started, which leads its own group.
+        pid: Option<u32>,
+        urgent: bool,
+        // Whether it's been asked to make way for an urgent build.
+        preempted: bool,
+        cancelled: bool,
+    }
+
+    struct Daemon {
+        exe: path::PathBuf,
+        context_dir: path::PathBuf,
+        target_dir: path::PathBuf,
+        queue: Queue,
+        running: sync::Mutex<Option<Running>>,
+    }
+
+    impl Daemon {
+        fn new(exe: path::PathBuf, context_dir: &path::Path, target_dir: &path::Path) -> Self {
+            Self {
+                exe,
+                context_dir: context_dir.to_path_buf(),
+                target_dir: target_dir.to_path_buf(),
+                queue: Queue::default(),
+                running: sync::Mutex::new(None),
+            }
+        }
+
+        fn listen(self: sync::Arc<Self>, listener: net::UnixListener) -> io::Result<()> {
+            for stream in listener.incoming() {
+                let daemon = self.clone();
+                let stream = stream?;
+                thread::spawn(move || daemon.connection(stream));
+            }
+            Ok(())
+        }
+
+        fn asmbl(&self) -> process::Command {
+            let mut cmd = process::Command::new(&self.exe);
+            cmd.arg("--context")
+                .arg(&self.context_dir)
+                .arg("--target")
+                .arg(&self.target_dir);
+            cmd
+        }
+
+        fn connection(self: sync::Arc<Self>, stream: net::UnixStream) -> io::Result<()> {
+            let sender = Sender(sync::Arc::new(sync::Mutex::new(stream.try_clone()?)));
+            for line in io::BufReader::new(stream).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let request: Value = match serde_json::from_str(&line) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        sender.respond(Value::Null, Err((PARSE_ERROR, err.to_string())))?;
+                        continue;
+                    }
+                };
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                let response = match request.get("method").and_then(Value::as_str) {
+                    // Builds carry on in the background, so that they can be
+                    // cancelled over the same connection.
+                    Some("build") => {
+                        let daemon = self.clone();
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            let response = daemon.build(&id, &params, &sender);
+                            let _ = sender.respond(id, response);
+                        });
+                        continue;
+                    }
+                    // The subscription is confirmed before any diagnostics.
+                    Some("diagnostics") => {
+                        sender.respond(id, Ok(json!({"subscribed": true})))?;
+                        let daemon = self.clone();
+                        let sender = sender.clone();
+                        thread::spawn(move || daemon.diagnostics(&sender));
+                        continue;
+                    }
+                    Some("cancel") => self.cancel(),
+                    Some("graph") => self.graph(),
+                    Some(method) => Err((METHOD_NOT_FOUND, format!("No method '{}'.", method))),
+                    None => Err((INVALID_REQUEST, String::from("No method given."))),
+                };
+                sender.respond(id, response)?;
+            }
+            Ok(())
+        }
+
+        fn build(&self, id: &Value, params: &Value, sender: &Sender) -> Response {
+            let invalid = |message: &str| Err((INVALID_PARAMS, message.to_string()));
+            let flag = |name| params.get(name).and_then(Value::as_bool).unwrap_or(false);
+
+            let mut cmd = self.asmbl();
+            if flag("keep_going") {
+                cmd.arg("--keep-going");
+            }
+            if flag("dry_run") {
+                cmd.arg("--dry-run");
+            }
+            match params.get("jobs") {
+                Some(Value::Number(jobs)) if jobs.is_u64() => {
+                    cmd.arg("--jobs").arg(jobs.to_string());
+                }
+                None | Some(Value::Null) => {}
+                Some(_) => return invalid("'jobs' must be a whole number."),
+            }
+            match params.get("targets") {
+                Some(Value::Array(targets)) => {
+                    for target in targets {
+                        match target.as_str() {
+                            Some(target) => cmd.arg(target),
+                            None => return invalid("'targets' must be strings."),
+                        };
+                    }
+                }
+                None | Some(Value::Null) => {}
+                Some(_) => return invalid("'targets' must be an array."),
+            }
+
+            let urgent = match params.get("urgent") {
+                Some(Value::Bool(urgent)) => *urgent,
+                None | Some(Value::Null) => false,
+                Some(_) => return invalid("'urgent' must be true or false."),
+            };
+
+            // The build gets a process group of its own, so that cancelling
+            // it reaches the tasks it's running too.
+            cmd.stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped())
+                .process_group(0);
+
+            let ticket = self.queue.join(urgent);
+            if urgent {
+                self.preempt();
+            }
+            loop {
+                let _turn = self.queue.wait(ticket);
+                *self.running.lock().unwrap() = Some(Running {
+                    pid: None,
+                    urgent,
+                    preempted: false,
+                    cancelled: false,
+                });
+                let status = self.run(&mut cmd, id, sender);
+                let running = self.running.lock().unwrap().take().unwrap();
+                let status = status?;
+                // Whatever the build didn't get to is left for its next
+                // turn; one which never started didn't get to anything.
+                let made_way = match status {
+                    Some(status) => {
+                        running.preempted
+                            && !running.cancelled
+                            && matches!(status.code(), None | Some(3))
+                    }
+                    None => true,
+                };
+                match status {
+                    Some(status) if !made_way => {
+                        return Ok(json!({
+                            "exit_code": status.code(),
+                            "summary": summary(&self.target_dir),
+                        }))
+                    }
+                    _ => {
+                        self.queue.rejoin(ticket, urgent);
+                        let params = json!({"id": id});
+                        sender
+                            .send(
+                                json!({"jsonrpc": "2.0", "method": "preempted", "params": params}),
+                            )
+                            .map_err(|err| (INTERNAL_ERROR, err.to_string()))?;
+                    }
+                }
+            }
+        }
+
+        // Runs the build whose turn it is, unless it'd only be asked to make
+        // way, returning how it exited.
+        fn run(
+            &self,
+            cmd: &mut process::Command,
+            id: &Value,
+            sender: &Sender,
+        ) -> Result<Option<process::ExitStatus>, (i64, String)> {
+            let _ = std::fs::remove_file(summary_file(&self.target_dir));
+            let mut child = {
+                let mut running = self.running.lock().unwrap();
+                let running = running.as_mut().unwrap();
+                if running.preempted || (!running.urgent && self.queue.urgent_waiting()) {
+                    return Ok(None);
+                }
+                let child = cmd
+                    .spawn()
+                    .map_err(|err| (INTERNAL_ERROR, err.to_string()))?;
+                running.pid = Some(child.id());
+                child
+            };
+
+            let forward = |name: &'static str, stream: Option<Box<dyn Read + Send>>| {
+                let id = id.clone();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    for line in io::BufReader::new(stream?).lines() {
+                        let params = json!({"id": id, "stream": name, "line": line.ok()?});
+                        sender
+                            .send(json!({"jsonrpc": "2.0", "method": "output", "params": params}))
+                            .ok()?;
+                    }
+                    Some(())
+                })
+            };
+            let stdout = child
+                .stdout
+                .take()
+                .map(|s| Box::new(s) as Box<dyn Read + Send>);
+            let stderr = child
+                .stderr
+                .take()
+                .map(|s| Box::new(s) as Box<dyn Read + Send>);
+            let forwarders = [forward("stdout", stdout), forward("stderr", stderr)];
+            for forwarder in forwarders {
+                let _ = forwarder.join();
+            }
+
+            let status = child
+                .wait()
+                .map_err(|err| (INTERNAL_ERROR, err.to_string()))?;
+            Ok(Some(status))
+        }
+
+        // Has a background build make way for an urgent one. Only asmbl
+        // itself is told, as it sees its running tasks through.
+        fn preempt(&self) {
+            let mut running = self.running.lock().unwrap();
+            if let Some(running) = running.as_mut().filter(|running| !running.urgent) {
+                running.preempted = true;
+                if let Some(pid) = running.pid {
+                    unsafe {
+                        libc::kill(pid as libc::pid_t, libc::SIGUSR1);
+                    }
+                }
+            }
+        }
+
+        fn cancel(&self) -> Response {
+            let mut running = self.running.lock().unwrap();
+            let group = running.as_mut().and_then(|running| {
+                running.cancelled = true;
+                running.pid
+            });
+            if let Some(group) = group {
+                unsafe {
+                    libc::kill(-(group as libc::pid_t), libc::SIGTERM);
+                }
+            }
+            Ok(json!({"cancelled": group.is_some()}))
+        }
+
+        // Passes on what `serve-diagnostics` reports until there's nobody
+        // left to hear it.
+        fn diagnostics(&self, sender: &Sender) -> io::Result<()> {
+            let mut child = self
+                .asmbl()
+                .arg("serve-diagnostics")
+                .stdout(process::Stdio::piped())
+                .spawn()?;
+            let stdout = child.stdout.take().unwrap();
+            let relayed = io::BufReader::new(stdout).lines().try_for_each(|line| {
+                let message = serde_json::from_str(&line?)?;
+                sender.send(message)
+            });
+            let _ = child.kill();
+            child.wait()?;
+            relayed
+        }
+
+        fn graph(&self) -> Response {
+            let output = self
+                .asmbl()
+                .args(["graph", "--format", "json"])
+                .output()
+                .map_err(|err| (INTERNAL_ERROR, err.to_string()))?;
+            if !output.status.success() {
+                let message = String::from_utf8_lossy(&output.stdout);
+                return Err((INTERNAL_ERROR, message.trim().to_string()));
+            }
+            serde_json::from_slice(&output.stdout).map_err(|err| (INTERNAL_ERROR, err.to_string()))
+        }
+    }
+
+    /// Serves builds of the project until killed.
+    pub fn serve(context_dir: &path::Path, target_dir: &path::Path) -> Result<(), Error> {
+        let socket = socket(target_dir);
+        if net::UnixStream::connect(&socket).is_ok() {
+            return Err(DaemonError::AlreadyRunning(socket.display().to_string()).into());
+        }
+        // Whatever's left is from a daemon which didn't get to tidy up.
+        let _ = std::fs::remove_file(&socket);
+        std::fs::create_dir_all(socket.parent().unwrap())?;
+        let listener = net::UnixListener::bind(&socket)?;
+        println!("Listening on {}", socket.display());
+
+        let daemon = Daemon::new(std::env::current_exe()?, context_dir, target_dir);
+        sync::Arc::new(daemon).listen(listener)?;
+        Ok(())
+    }
+
+    /// Has the daemon run a build, passing on what it prints, and returns
+    /// the exit code it finished with.
+    pub fn build(target_dir: &path::Path, request: &BuildRequest) -> Result<i32, Error> {
+        let socket = socket(target_dir);
+        let mut stream = net::UnixStream::connect(&socket)
+            .map_err(|err| DaemonError::NotRunning(socket.display().to_string(), err))?;
+        let params = json!({
+            "targets": request.targets,
+            "keep_going": request.keep_going,
+            "dry_run": request.dry_run,
+            "jobs": request.jobs,
+            "urgent": request.urgent,
+        });
+        let message = json!({"jsonrpc": "2.0", "id": 1, "method": "build", "params": params});
+        writeln!(stream, "{}", message)?;
+
+        for line in io::BufReader::new(stream).lines() {
+            let message: Value = serde_json::from_str(&line?)?;
+            if let Some(error) = message.get("error") {
+                let message = error.get("message").and_then(Value::as_str);
+                return Err(DaemonError::Refused(message.unwrap_or_default().to_string()).into());
+            }
+            if let Some(result) = message.get("result") {
+                // A build killed by a signal has no exit code of its own.
+                let code = result.get("exit_code").and_then(Value::as_i64);
+                return Ok(code.unwrap_or(crate::ExitCode::Interrupted as i64) as i32);
+            }
+            let params = &message["params"];
+            if let Some(line) = params["line"].as_str() {
+                match params["stream"].as_str() {
+                    Some("stderr") => eprintln!("{}", line),
+                    _ => println!("{}", line),
+                }
+            }
+        }
+        Err(DaemonError::HungUp.into())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use asmbl_utils::testing::TempDir;
+        use std::{fs, os::unix::fs::PermissionsExt, time};
+
+        // A daemon listening in `dir`, whose builds are run by `script` in
+        // place of asmbl, with the target directory as its fourth argument.
+        fn daemon(dir: &path::Path, script: &str) -> path::PathBuf {
+            let exe = dir.join("asmbl");
+            fs::write(&exe, format!("#!/bin/sh\n{}", script)).unwrap();
+            fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+            let socket = socket(dir);
+            fs::create_dir_all(socket.parent().unwrap()).unwrap();
+            let listener = net::UnixListener::bind(&socket).unwrap();
+            let daemon = sync::Arc::new(Daemon::new(exe, dir, dir));
+            thread::spawn(move || daemon.listen(listener));
+            socket
+        }
+
+        // Asks for a build, returning every message sent back for it.
+        fn request(socket: &path::Path, params: Value) -> Vec<Value> {
+            let mut stream = net::UnixStream::connect(socket).unwrap();
+            stream
+                .set_read_timeout(Some(time::Duration::from_secs(10)))
+                .unwrap();
+            let message = json!({"jsonrpc": "2.0", "id": 7, "method": "build", "params": params});
+            writeln!(stream, "{}", message).unwrap();
+            let mut messages = vec![];
+            for line in io::BufReader::new(stream).lines() {
+                let message: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let done = message.get("id").is_some();
+                messages.push(message);
+                if done {
+                    break;
+                }
+            }
+            messages
+        }
+
+        #[test]
+        fn builds_are_served_over_the_socket() {
+            let dir = TempDir::new("daemon");
+            let socket = daemon(
+                &dir,
+                "echo \"$@\"\n\
+                 echo oops >&2\n\
+                 echo '{\"success\": false}' > \"$4/.asmbl/summary\"\n\
+                 exit 3\n",
+            );
+
+            let mut messages = request(&socket, json!({"targets": ["a"], "keep_going": true}));
+            let result = messages.pop().unwrap();
+            assert_eq!(
+                result,
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 7,
+                    "result": {"exit_code": 3, "summary": {"success": false}},
+                })
+            );
+            let mut lines: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    assert_eq!(message["method"], "output");
+                    assert_eq!(message["params"]["id"], 7);
+                    let params = &message["params"];
+                    (params["stream"].clone(), params["line"].clone())
+                })
+                .collect();
+            lines.sort_by_key(|line| line.0.to_string());
+            let args = format!("--context {0} --target {0} --keep-going a", dir.display());
+            assert_eq!(
+                lines,
+                vec![
+                    (json!("stderr"), json!("oops")),
+                    (json!("stdout"), json!(args)),
+                ]
+            );
+
+            let mut messages = request(&socket, json!({"jobs": "all"}));
+            assert_eq!(
+                messages.pop().unwrap()["error"]["code"],
+                json!(INVALID_PARAMS)
+            );
+        }
+
+        #[test]
+        fn builds_asked_for_at_once_wait_their_turn() {
+            let dir = TempDir::new("daemon-queue");
+            let log = dir.join("log");
+            let socket = daemon(
+                &dir,
+                &format!(
+                    "echo began >> {0}\nsleep 0.2\necho ended >> {0}\n",
+                    log.display()
+                ),
+            );
+
+            let requests: Vec<_> = (0..2)
+                .map(|_| {
+                    let socket = socket.clone();
+                    thread::spawn(move || request(&socket, json!({})))
+                })
+                .collect();
+            for request in requests {
+                let result = request.join().unwrap().pop().unwrap();
+                assert_eq!(result["result"]["exit_code"], 0);
+            }
+            assert_eq!(
+                fs::read_to_string(&log).unwrap(),
+                "began\nended\nbegan\nended\n"
+            );
+        }
+
+        #[test]
+        fn urgent_builds_go_ahead_of_background_ones() {
+            let dir = TempDir::new("daemon-urgent");
+            let log = dir.join("log");
+            // Background builds take a while, unless asked to make way.
+            let socket = daemon(
+                &dir,
+                &format!(
+                    "echo began $5 >> {0}\n\
+                     trap 'echo made way >> {0}; exit 3' USR1\n\
+                     if [ $5 = background ]; then\n\
+                     for i in 1 2 3 4 5 6 7 8 9 10; do sleep 0.1; done\n\
+                     fi\n\
+                     echo ended $5 >> {0}\n",
+                    log.display()
+                ),
+            );
+
+            let background = {
+                let socket = socket.clone();
+                thread::spawn(move || request(&socket, json!({"targets": ["background"]})))
+            };
+            while !fs::read_to_string(&log)
+                .unwrap_or_default()
+                .contains("began")
+            {
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            let urgent = request(&socket, json!({"targets": ["urgent"], "urgent": true}));
+            assert_eq!(urgent.last().unwrap()["result"]["exit_code"], 0);
+
+            let background = background.join().unwrap();
+            assert_eq!(background.last().unwrap()["result"]["exit_code"], 0);
+            assert!(background
+                .iter()
+                .any(|message| message["method"] == "preempted" && message["params"]["id"] == 7));
+            assert_eq!(
+                fs::read_to_string(&log).unwrap(),
+                "began background\nmade way\n\
+                 began urgent\nended urgent\n\
+                 began background\nended background\n"
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn serve(_: &path::Path, _: &path::Path) -> Result<(), failure::Error> {
+    Err(DaemonError::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+pub fn build(_: &path::Path, _: &BuildRequest) -> Result<i32, failure::Error> {
+    Err(DaemonError::Unsupported.into())
+}
@@ -0,0 +1,751 @@
+//! `asmbl import-make` turns the database GNU make prints when run with `-p`
+//! into a unit, as a start on moving a project over from make.
+//!
+//! Recipes are expanded as far as make's own variables and the makefile's
+//! go, each running through `sh -c` much as make would run it. Variables from
+//! the environment are left for the shell, and inherited by the task. Make's
+//! functions are left as they are, and pointed out, for whoever finishes the
+//! job.
+
+use std::{collections, fmt::Write, process};
+
+#[derive(Debug, failure::Fail)]
+pub enum ImportError {
+    #[fail(display = "'{}' failed ({}).", _0, _1)]
+    CommandFailed(String, process::ExitStatus),
+    #[fail(display = "No make database found; was make run with -p?")]
+    NoDatabase,
+}
+
+// Targets which mean something to make rather than naming files.
+const SPECIAL_TARGETS: [&str; 17] = [
+    ".PHONY",
+    ".SUFFIXES",
+    ".DEFAULT",
+    ".PRECIOUS",
+    ".INTERMEDIATE",
+    ".NOTINTERMEDIATE",
+    ".SECONDARY",
+    ".SECONDEXPANSION",
+    ".DELETE_ON_ERROR",
+    ".IGNORE",
+    ".LOW_RESOLUTION_TIME",
+    ".SILENT",
+    ".EXPORT_ALL_VARIABLES",
+    ".NOTPARALLEL",
+    ".ONESHELL",
+    ".POSIX",
+    ".EXTRA_PREREQS",
+];
+
+// Variables nested any deeper than this are taken to refer to themselves.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Default)]
+struct Rule {
+    target: String,
+    prerequisites: Vec<String>,
+    order_only: Vec<String>,
+    recipe: Vec<String>,
+    phony: bool,
+    stem: String,
+}
+
+/// The rules and variables of a make database.
+pub struct Database {
+    variables: collections::HashMap<String, String>,
+    rules: Vec<Rule>,
+}
+
+// Origins of the variables which are expanded; the rest belong to the shell.
+fn expandable(origin: &str) -> bool {
+    [
+        "default",
+        "makefile",
+        "command line",
+        "override",
+        "'override'",
+    ]
+    .iter()
+    .any(|o| origin.starts_with(o))
+}
+
+// Where make prints the start of a new part of its database.
+fn section(line: &str) -> Option<&str> {
+    [
+        "Variables",
+        "Pattern-specific Variable Values",
+        "Directories",
+        "Implicit Rules",
+        "Files",
+        "files hash-table stats:",
+        "VPATH Search Paths",
+        "Finished Make data base",
+    ]
+    .iter()
+    .find(|name| line.strip_prefix("# ") == Some(**name))
+    .copied()
+}
+
+/// Reads the database make printed.
+pub fn read(dump: &str) -> Result<Database, ImportError> {
+    let mut variables = collections::HashMap::new();
+    let mut rules = vec![];
+
+    let mut current = None;
+    let mut origin = "";
+    let mut in_define = false;
+    let mut not_a_target = false;
+    let mut rule: Option<Rule> = None;
+    let mut in_recipe = false;
+    let mut seen_files = false;
+
+    for line in dump.lines() {
+        if let Some(name) = section(line) {
+            current = Some(name);
+            seen_files |= name == "Files";
+            continue;
+        }
+        match current {
+            Some("Variables") => {
+                if in_define {
+                    in_define = line != "endef";
+                } else if line.starts_with("define ") {
+                    in_define = true;
+                } else if let Some(o) = line.strip_prefix("# ") {
+                    origin = o;
+                } else if expandable(origin) {
+                    let definition = [" := ", " ::= ", " = "]
+                        .iter()
+                        .filter_map(|op| line.split_once(op))
+                        .min_by_key(|(name, _)| name.len());
+                    if let Some((name, value)) = definition {
+                        variables.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+            Some("Files") => {
+                if line.is_empty() {
+                    rules.extend(rule.take());
+                    not_a_target = false;
+                    in_recipe = false;
+                } else if line == "# Not a target:" {
+                    not_a_target = true;
+                } else if let Some(recipe) = line.strip_prefix('\t') {
+                    if in_recipe {
+                        if let Some(rule) = rule.as_mut() {
+                            rule.recipe.push(recipe.trim_start().to_string());
+                        }
+                    }
+                } else if let Some(note) = line.strip_prefix("#  ") {
+                    if let Some(rule) = rule.as_mut() {
+                        if note.starts_with("Phony target") {
+                            rule.phony = true;
+                        } else if let Some(stem) =
+                            note.strip_prefix("Implicit/static pattern stem: ")
+                        {
+                            rule.stem = stem.trim_matches('\'').to_string();
+                        } else if note.starts_with("recipe to execute") {
+                            in_recipe = true;
+                        }
+                    }
+                } else if !line.starts_with('#') && !not_a_target {
+                    rule = header(line);
+                }
+            }
+            _ => {}
+        }
+    }
+    rules.extend(rule);
+
+    if !seen_files {
+        return Err(ImportError::NoDatabase);
+    }
+
+    rules.retain(|rule| !SPECIAL_TARGETS.contains(&rule.target.as_str()));
+    Ok(Database { variables, rules })
+}
+
+// A rule's first line: its target and what it depends upon.
+fn header(line: &str) -> Option<Rule> {
+    let (target, rest) = line.split_once(':')?;
+    // Double-colon rules are taken like any other.
+    let rest = rest.strip_prefix(':').unwrap_or(rest);
+    let (prerequisites, order_only) = rest.split_once('|').unwrap_or((rest, ""));
+    let words = |s: &str| s.split_whitespace().map(String::from).collect();
+    Some(Rule {
+        target: target.trim().to_string(),
+        prerequisites: words(prerequisites),
+        order_only: words(order_only),
+        ..Rule::default()
+    })
+}
+
+// What's left unexpanded in a recipe.
+#[derive(Default)]
+struct Leftovers {
+    env: Vec<String>,
+    functions: Vec<String>,
+}
+
+impl Database {
+    // Spells out a recipe line in asmbl's terms.
+    fn expand(&self, text: &str, rule: &Rule, depth: usize, leftovers: &mut Leftovers) -> String {
+        let mut expanded = String::new();
+        let mut chars = text.char_indices();
+        while let Some((_, c)) = chars.next() {
+            if c != '$' {
+                expanded.push(c);
+                continue;
+            }
+            let reference = match chars.next() {
+                Some((start, open @ '(')) | Some((start, open @ '{')) => {
+                    let close = if open == '(' { ')' } else { '}' };
+                    let mut nesting = 0;
+                    let mut end = text.len();
+                    for (i, c) in chars.by_ref() {
+                        if c == open {
+                            nesting += 1;
+                        } else if c == close && nesting == 0 {
+                            end = i;
+                            break;
+                        } else if c == close {
+                            nesting -= 1;
+                        }
+                    }
+                    &text[start + 1..end]
+                }
+                Some((start, c)) => &text[start..start + c.len_utf8()],
+                None => "",
+            };
+            expanded.push_str(&self.reference(reference, rule, depth, leftovers));
+        }
+        expanded
+    }
+
+    fn reference(
+        &self,
+        reference: &str,
+        rule: &Rule,
+        depth: usize,
+        leftovers: &mut Leftovers,
+    ) -> String {
+        match reference {
+            "" => String::from("$$"),
+            "$" => String::from("$$"),
+            "@" => String::from("$@"),
+            "<" => self.first_prerequisite(rule, "$<[0]"),
+            "^" | "+" | "?" => String::from("$<"),
+            "*" => rule.stem.clone(),
+            "|" => rule.order_only.join(" "),
+            "@D" => String::from("$$(dirname $@)"),
+            "@F" => String::from("$$(basename $@)"),
+            "<D" => self.first_prerequisite(rule, "$$(dirname $<[0])"),
+            "<F" => self.first_prerequisite(rule, "$$(basename $<[0])"),
+            _ if reference.contains(|c: char| c.is_whitespace() || c == ',') => {
+                leftovers.functions.push(format!("$({})", reference));
+                format!("$({})", reference)
+            }
+            _ => match self.variables.get(reference) {
+                Some(value) if depth < MAX_DEPTH => self.expand(value, rule, depth + 1, leftovers),
+                _ => {
+                    if !leftovers.env.iter().any(|name| name == reference) {
+                        leftovers.env.push(reference.to_string());
+                    }
+                    format!("$${{{}}}", reference)
+                }
+            },
+        }
+    }
+
+    // What `$<` comes to in the rule's recipe, for which `first` stands in
+    // should the first prerequisite be a file. Make leaves `$<` empty for
+    // rules without any, and phony prerequisites are no input of the task's.
+    fn first_prerequisite(&self, rule: &Rule, first: &str) -> String {
+        match rule.prerequisites.first() {
+            None => String::new(),
+            Some(prerequisite) if self.phony(prerequisite) => String::new(),
+            Some(_) => first.to_string(),
+        }
+    }
+
+    // The rule's recipe as a single shell command, stopping at the first
+    // line to fail unless make was told to carry on past it.
+    fn command(&self, rule: &Rule, leftovers: &mut Leftovers) -> Option<String> {
+        let lines: Vec<_> = rule
+            .recipe
+            .iter()
+            .map(|line| {
+                let command = line.trim_start_matches(['@', '-', '+']);
+                let prefix = &line[..line.len() - command.len()];
+                let command = self.expand(command.trim(), rule, 0, leftovers);
+                match prefix.contains('-') {
+                    true => format!("({}) || true", command),
+                    false => command,
+                }
+            })
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+        // Make's recipes see the whole environment, not least the PATH.
+        if !leftovers.env.iter().any(|name| name == "PATH") {
+            leftovers.env.insert(0, String::from("PATH"));
+        }
+        Some(lines.join(" && "))
+    }
+
+    // The rules, each after whichever others produce what it depends upon.
+    fn ordered(&self) -> Vec<&Rule> {
+        let index: collections::HashMap<_, _> = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| (rule.target.as_str(), i))
+            .collect();
+
+        let mut ordered = vec![];
+        let mut visited = vec![false; self.rules.len()];
+        for root in 0..self.rules.len() {
+            let mut stack = vec![(root, false)];
+            while let Some((i, expanded)) = stack.pop() {
+                if expanded {
+                    ordered.push(&self.rules[i]);
+                    continue;
+                }
+                if visited[i] {
+                    continue;
+                }
+                visited[i] = true;
+                stack.push((i, true));
+                let rule = &self.rules[i];
+                for prerequisite in rule.prerequisites.iter().chain(&rule.order_only).rev() {
+                    match index.get(prerequisite.as_str()) {
+                        Some(&upstream) if !visited[upstream] => stack.push((upstream, false)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        ordered
+    }
+
+    fn phony(&self, file: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.phony && rule.target == file)
+    }
+
+    /// The rules as a Lua unit.
+    pub fn lua(&self) -> String {
+        let mut names = collections::HashMap::new();
+        let mut unit = String::from("-- Imported from make's database by `asmbl import-make`.\n");
+        for rule in self.ordered() {
+            let mut leftovers = Leftovers::default();
+            let command = self.command(rule, &mut leftovers);
+            let list = |files: &[String]| {
+                let files: Vec<_> = files
+                    .iter()
+                    .map(|file| match names.get(file) {
+                        Some(name) => String::clone(name),
+                        None => lua_string(file),
+                    })
+                    .collect();
+                format!("{{{}}}", files.join(", "))
+            };
+
+            unit.push('\n');
+            for function in leftovers.functions.iter() {
+                unit.push_str(&format!(
+                    "-- TODO: make function left unexpanded: {}\n",
+                    function
+                ));
+            }
+            if !rule.phony && command.is_none() {
+                unit.push_str("-- TODO: make had no recipe for this target.\n");
+            }
+            let name = lua_name(&rule.target, names.len());
+            unit.push_str(&format!("local {} = task {{\n", name));
+            match rule.phony {
+                true => unit.push_str(&format!("    name = {},\n", lua_string(&rule.target))),
+                false => unit.push_str(&format!("    target = {},\n", lua_string(&rule.target))),
+            }
+            if !rule.prerequisites.is_empty() {
+                unit.push_str(&format!("    consumes = {},\n", list(&rule.prerequisites)));
+            }
+            if !rule.order_only.is_empty() {
+                unit.push_str(&format!("    not_before = {},\n", list(&rule.order_only)));
+            }
+            if !leftovers.env.is_empty() {
+                let env: Vec<_> = leftovers.env.iter().map(|name| lua_string(name)).collect();
+                unit.push_str(&format!("    env = {{{}}},\n", env.join(", ")));
+            }
+            if let Some(command) = command {
+                let command = lua_string(&command);
+                unit.push_str(&format!("    run = {{\"sh\", \"-c\", {}}},\n", command));
+            }
+            unit.push_str("}\n");
+            names.insert(rule.target.clone(), name);
+        }
+        unit
+    }
+
+    /// The rules as a TOML unit, which has no way to declare phony tasks.
+    pub fn toml(&self) -> String {
+        let mut unit = String::from("# Imported from make's database by `asmbl import-make`.\n");
+        for rule in self.ordered() {
+            unit.push('\n');
+            if rule.phony {
+                unit.push_str(&format!(
+                    "# TODO: make's phony target '{}' can only be declared in Lua.\n",
+                    rule.target
+                ));
+                continue;
+            }
+
+            let mut leftovers = Leftovers::default();
+            let command = self.command(rule, &mut leftovers);
+            // Phony targets have no task to consume.
+            let list = |files: &[String]| -> Vec<_> {
+                let files = files.iter().filter(|file| !self.phony(file));
+                files.cloned().collect()
+            };
+
+            for function in leftovers.functions.iter() {
+                unit.push_str(&format!(
+                    "# TODO: make function left unexpanded: {}\n",
+                    function
+                ));
+            }
+            if command.is_none() {
+                unit.push_str("# TODO: make had no recipe for this target.\n");
+            }
+            let task = TomlTask {
+                targets: &rule.target,
+                consumes: list(&rule.prerequisites),
+                not_before: list(&rule.order_only),
+                run: command.as_ref().map(|command| ["sh", "-c", command]),
+                env: leftovers
+                    .env
+                    .iter()
+                    .map(|name| (name.as_str(), true))
+                    .collect(),
+            };
+            // Serialising each task alone leaves room for the comments above.
+            let task = toml::to_string(&TomlUnit { task: [task] });
+            unit.push_str(&task.expect("tasks are always representable in TOML"));
+        }
+        unit
+    }
+}
+
+// A task as the TOML front-end reads it.
+#[derive(serde::Serialize)]
+struct TomlTask<'a> {
+    targets: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    consumes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    not_before: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run: Option<[&'a str; 3]>,
+    #[serde(skip_serializing_if = "collections::BTreeMap::is_empty")]
+    env: collections::BTreeMap<&'a str, bool>,
+}
+
+#[derive(serde::Serialize)]
+struct TomlUnit<'a> {
+    task: [TomlTask<'a>; 1],
+}
+
+// A Lua identifier for the task producing `target`, unique by way of `n`.
+fn lua_name(target: &str, n: usize) -> String {
+    let name: String = target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.trim_matches('_') {
+        name if name.starts_with(|c: char| c.is_ascii_alphabetic()) => format!("{}_{}", name, n),
+        name => format!("t_{}_{}", name, n),
+    }
+}
+
+fn lua_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\{:03}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pared down from what make 4.3 prints for a small makefile.
+    const DUMP: &str = "\
+# GNU Make 4.3
+
+# Variables
+
+# default
+MAKE_COMMAND := make
+# makefile (from 'Makefile', line 1)
+CC := cc
+# makefile (from 'Makefile', line 2)
+CFLAGS = -O2 $(WARN)
+# makefile (from 'Makefile', line 3)
+WARN = -Wall
+# makefile (from 'Makefile', line 4)
+LOOP = $(LOOP)
+# environment
+HOME = /root
+# makefile (from 'Makefile', line 5)
+define RECIPE
+CC = gcc
+endef
+
+# Files
+
+# Not a target:
+.c.o:
+#  Builtin rule
+#  recipe to execute (built-in):
+\t$(COMPILE.c) $(OUTPUT_OPTION) $<
+
+app: a.o | out
+#  Implicit/static pattern stem: ''
+#  recipe to execute (from 'Makefile', line 10):
+\t$(CC) -o $@ $^
+
+clean:
+#  Phony target (prerequisite of .PHONY).
+#  recipe to execute (from 'Makefile', line 22):
+\trm -f app
+
+out:
+#  recipe to execute (from 'Makefile', line 16):
+\t-mkdir -p $@
+
+a.o: a.c
+#  Implicit/static pattern stem: 'a'
+#  recipe to execute (from 'Makefile', line 13):
+\t@$(CC) $(CFLAGS) -c $< -o $@ $(HOME)
+
+all: clean app
+#  Phony target (prerequisite of .PHONY).
+
+.PHONY: all clean
+
+# files hash-table stats:
+# Finished Make data base on Thu Jan  1 00:00:00 1970
+";
+
+    fn rule(database: &Database, target: &str) -> Rule {
+        let rule = database.rules.iter().find(|rule| rule.target == target);
+        let rule = rule.unwrap();
+        Rule {
+            target: rule.target.clone(),
+            prerequisites: rule.prerequisites.clone(),
+            order_only: rule.order_only.clone(),
+            recipe: rule.recipe.clone(),
+            phony: rule.phony,
+            stem: rule.stem.clone(),
+        }
+    }
+
+    #[test]
+    fn databases_are_read_for_their_variables_and_targets() {
+        let database = read(DUMP).unwrap();
+        let targets: Vec<_> = database.rules.iter().map(|rule| &rule.target).collect();
+        assert_eq!(targets, vec!["app", "clean", "out", "a.o", "all"]);
+
+        let variable = |name: &str| database.variables.get(name).map(String::as_str);
+        assert_eq!(variable("CC"), Some("cc"));
+        assert_eq!(variable("CFLAGS"), Some("-O2 $(WARN)"));
+        assert_eq!(variable("MAKE_COMMAND"), Some("make"));
+        // Environment variables are the shell's, and the definitions within
+        // `define` nothing of make's own.
+        assert_eq!(variable("HOME"), None);
+        assert_eq!(variable("RECIPE"), None);
+
+        let app = rule(&database, "app");
+        assert_eq!(app.prerequisites, vec!["a.o"]);
+        assert_eq!(app.order_only, vec!["out"]);
+        assert_eq!(app.recipe, vec!["$(CC) -o $@ $^"]);
+        assert!(!app.phony);
+        let object = rule(&database, "a.o");
+        assert_eq!(object.stem, "a");
+        assert!(rule(&database, "clean").phony);
+        assert!(rule(&database, "all").recipe.is_empty());
+
+        assert!(matches!(
+            read("make: *** No targets.  Stop.\n"),
+            Err(ImportError::NoDatabase)
+        ));
+    }
+
+    #[test]
+    fn recipes_are_expanded_in_asmbl_terms() {
+        let database = read(DUMP).unwrap();
+        let object = rule(&database, "a.o");
+        let mut leftovers = Leftovers::default();
+        let mut expand = |text| database.expand(text, &object, 0, &mut leftovers);
+
+        assert_eq!(
+            expand("$(CC) $(CFLAGS) -c $< -o $@"),
+            "cc -O2 -Wall -c $<[0] -o $@"
+        );
+        assert_eq!(expand("${CC} $$HOME $"), "cc $$HOME $$");
+        assert_eq!(expand("$(HOME) $(HOME)"), "$${HOME} $${HOME}");
+        assert_eq!(expand("$(LOOP)"), "$${LOOP}");
+        assert_eq!(
+            expand("$(patsubst %.c,%.o,$(wildcard *.c))"),
+            "$(patsubst %.c,%.o,$(wildcard *.c))"
+        );
+        assert_eq!(leftovers.env, vec!["HOME", "LOOP"]);
+        assert_eq!(
+            leftovers.functions,
+            vec!["$(patsubst %.c,%.o,$(wildcard *.c))"]
+        );
+    }
+
+    #[test]
+    fn automatic_variables_stand_for_targets_and_inputs() {
+        let database = read(DUMP).unwrap();
+        let expand = |target, text| {
+            let rule = rule(&database, target);
+            database.expand(text, &rule, 0, &mut Leftovers::default())
+        };
+
+        assert_eq!(expand("app", "$@ $< $^ $+ $? $|"), "$@ $<[0] $< $< $< out");
+        assert_eq!(
+            expand("a.o", "$* $(@D) $(@F)"),
+            "a $$(dirname $@) $$(basename $@)"
+        );
+        assert_eq!(
+            expand("a.o", "$(<D) $(<F)"),
+            "$$(dirname $<[0]) $$(basename $<[0])"
+        );
+        // Rules without inputs of their own leave `$<` empty, as make does.
+        assert_eq!(expand("out", "[$<] [$(<D)]"), "[] []");
+        assert_eq!(expand("all", "[$<]"), "[]");
+    }
+
+    #[test]
+    fn rules_come_after_whatever_they_depend_upon() {
+        let database = read(DUMP).unwrap();
+        let ordered: Vec<_> = database
+            .ordered()
+            .iter()
+            .map(|rule| rule.target.as_str())
+            .collect();
+        assert_eq!(ordered, vec!["a.o", "out", "app", "clean", "all"]);
+    }
+
+    #[test]
+    fn lua_units_declare_a_task_per_rule() {
+        let unit = read(DUMP).unwrap().lua();
+        assert_eq!(
+            unit,
+            r#"-- Imported from make's database by `asmbl import-make`.
+
+local a_o_0 = task {
+    target = "a.o",
+    consumes = {"a.c"},
+    env = {"PATH", "HOME"},
+    run = {"sh", "-c", "cc -O2 -Wall -c $<[0] -o $@ $${HOME}"},
+}
+
+local out_1 = task {
+    target = "out",
+    env = {"PATH"},
+    run = {"sh", "-c", "(mkdir -p $@) || true"},
+}
+
+local app_2 = task {
+    target = "app",
+    consumes = {a_o_0},
+    not_before = {out_1},
+    env = {"PATH"},
+    run = {"sh", "-c", "cc -o $@ $<"},
+}
+
+local clean_3 = task {
+    name = "clean",
+    env = {"PATH"},
+    run = {"sh", "-c", "rm -f app"},
+}
+
+local all_4 = task {
+    name = "all",
+    consumes = {clean_3, app_2},
+}
+"#
+        );
+    }
+
+    #[test]
+    fn toml_units_declare_a_task_per_file() {
+        let mut database = read(DUMP).unwrap();
+        database.rules.push(Rule {
+            target: String::from("a \"quoted\" name"),
+            recipe: vec![String::from("echo $(cfg.dir)")],
+            ..Rule::default()
+        });
+        let unit = database.toml();
+        assert_eq!(
+            unit,
+            r#"# Imported from make's database by `asmbl import-make`.
+
+[[task]]
+targets = "a.o"
+consumes = ["a.c"]
+run = ["sh", "-c", "cc -O2 -Wall -c $<[0] -o $@ $${HOME}"]
+
+[task.env]
+HOME = true
+PATH = true
+
+[[task]]
+targets = "out"
+run = ["sh", "-c", "(mkdir -p $@) || true"]
+
+[task.env]
+PATH = true
+
+[[task]]
+targets = "app"
+consumes = ["a.o"]
+not_before = ["out"]
+run = ["sh", "-c", "cc -o $@ $<"]
+
+[task.env]
+PATH = true
+
+# TODO: make's phony target 'clean' can only be declared in Lua.
+
+# TODO: make's phony target 'all' can only be declared in Lua.
+
+[[task]]
+targets = "a \"quoted\" name"
+run = ["sh", "-c", "echo $${cfg.dir}"]
+
+[task.env]
+PATH = true
+"cfg.dir" = true
+"#
+        );
+        assert!(unit.parse::<toml::Value>().is_ok());
+    }
+}
@@ -1,17 +1,136 @@
-use std::path;
+use std::{
+    cell, collections, fs,
+    io::{self, IsTerminal, Write},
+    path, process, time,
+};
 
 use failure::Error;
+use serde_json::json;
 
 use asmbl_core as core;
+use asmbl_exec as exec;
+
+mod daemon;
+#[cfg(feature = "make-frontend")]
+mod import_make;
 
 #[derive(Debug, failure::Fail)]
 enum RunError {
     #[fail(display = "No route from context to target.")]
     NoRouteFromContextToTarget,
+    #[fail(display = "Task producing '{}' failed ({}).", _0, _1)]
+    TaskFailed(String, process::ExitStatus),
+    #[fail(display = "{} tasks failed.", _0)]
+    TasksFailed(usize),
+    #[fail(display = "Bad definition '{}'; expected NAME=VALUE.", _0)]
+    BadDefine(String),
+    #[fail(display = "Profile '{}' must be usable as a directory name.", _0)]
+    BadProfile(String),
+    #[fail(display = "No task produces '{}'.", _0)]
+    NoSuchTarget(String),
+    #[fail(display = "Can't use '{}' as a directory.", _0)]
+    BadDirectory(String, #[cause] io::Error),
+    #[fail(display = "Warnings are denied ({} found).", _0)]
+    WarningsDenied(usize),
+    #[fail(display = "{} tasks are out of date.", _0)]
+    OutOfDate(usize),
+    #[fail(display = "Interrupted; the tasks running were stopped.")]
+    Interrupted,
+    #[fail(display = "Made way for another build; nothing more was started.")]
+    MadeWay,
+    #[fail(
+        display = "Can't keep state in '{}'; is the target directory writable?",
+        _0
+    )]
+    UnwritableTarget(String, #[cause] io::Error),
+}
+
+// What went wrong, for the benefit of scripts driving asmbl.
+#[derive(Clone, Copy)]
+enum ExitCode {
+    TaskFailed = 1,
+    Configuration = 2,
+    Interrupted = 3,
+    Internal = 4,
+}
+
+const EXIT_CODES: [(ExitCode, &str); 4] = [
+    (
+        ExitCode::TaskFailed,
+        "A task failed or, when asked with --question, some task is out of date.",
+    ),
+    (
+        ExitCode::Configuration,
+        "The arguments or the project's description were at fault, or check found problems.",
+    ),
+    (
+        ExitCode::Interrupted,
+        "A task was interrupted or terminated, or the build made way for another.",
+    ),
+    (
+        ExitCode::Internal,
+        "Anything else, such as failing to read or write a file.",
+    ),
+];
+
+impl ExitCode {
+    fn of(err: &Error) -> Self {
+        if let Some(err) = err.downcast_ref::<RunError>() {
+            return match err {
+                RunError::TaskFailed(_, status) if interrupted(*status) => Self::Interrupted,
+                RunError::Interrupted | RunError::MadeWay => Self::Interrupted,
+                RunError::TaskFailed(..) | RunError::TasksFailed(_) | RunError::OutOfDate(_) => {
+                    Self::TaskFailed
+                }
+                RunError::NoRouteFromContextToTarget
+                | RunError::NoSuchTarget(_)
+                | RunError::BadDirectory(..)
+                | RunError::WarningsDenied(_)
+                | RunError::BadDefine(_)
+                | RunError::BadProfile(_)
+                | RunError::UnwritableTarget(..) => Self::Configuration,
+            };
+        }
+
+        let configuration = err.downcast_ref::<clap::Error>().is_some()
+            || err.downcast_ref::<core::GatherUnitsError>().is_some()
+            || err.downcast_ref::<core::ParseUnitError>().is_some()
+            || err.downcast_ref::<core::NewTaskListError>().is_some()
+            || err.downcast_ref::<core::RecipeParseError>().is_some()
+            || err.downcast_ref::<core::BadShard>().is_some()
+            || err.downcast_ref::<core::UnknownSchedule>().is_some()
+            || err.downcast_ref::<std::num::ParseIntError>().is_some();
+        if configuration {
+            Self::Configuration
+        } else {
+            Self::Internal
+        }
+    }
+}
+
+// Whether a task was stopped by someone wanting the build over, rather
+// than falling over of its own accord.
+#[cfg(unix)]
+fn interrupted(status: process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    // SIGHUP, SIGINT and SIGTERM.
+    matches!(status.signal(), Some(1) | Some(2) | Some(15))
+}
+
+#[cfg(not(unix))]
+fn interrupted(_: process::ExitStatus) -> bool {
+    false
+}
+
+fn print_exit_codes() {
+    println!("0\tThe build succeeded.");
+    for (code, meaning) in EXIT_CODES.iter() {
+        println!("{}\t{}", *code as i32, meaning);
+    }
 }
 
 fn run() -> Result<(), Error> {
-    let args = clap::App::new("asmbl")
+    let app = clap::App::new("asmbl")
         .version("0.1.0")
         .about("Does great things")
         .author("G. Rushton")
@@ -37,46 +156,1896 @@ fn run() -> Result<(), Error> {
                 )
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            clap::Arg::with_name("no-env-clear")
+                .long("no-env-clear")
+                .help(
+                    "Passes the full environment through to every task rather \
+                     than only the variables each task declares.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("strict-path")
+                .long("strict-path")
+                .help(
+                    "Refuses to look recipe commands up on the PATH unless the \
+                     task inherits PATH.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("sandbox")
+                .long("sandbox")
+                .help("Runs each task apart from any inputs it doesn't declare."),
+        )
+        .arg(
+            clap::Arg::with_name("cache")
+                .long("cache")
+                .help("Keeps targets, restoring them rather than rerunning tasks on old inputs."),
+        )
+        .arg(
+            clap::Arg::with_name("sandbox-allow")
+                .long("sandbox-allow")
+                .value_name("DIR")
+                .help(
+                    "Lets sandboxed tasks read anything within DIR (relative to \
+                     the context, unless absolute), e.g. an SDK.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("env")
+                .long("env")
+                .value_name("NAME[=VALUE]")
+                .help(
+                    "Defines (or, without a value, inherits) an environment \
+                     variable for every task; units and tasks may override it.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("define")
+                .short("D")
+                .long("define")
+                .value_name("NAME=VALUE")
+                .help(
+                    "Defines an option, which units may read and recipes may \
+                     refer to as $NAME.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("max-unit-depth")
+                .long("max-unit-depth")
+                .value_name("DEPTH")
+                .help("Refuses sub-units nested more than DEPTH deep.")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help(
+                    "Names the kind of build, e.g. 'release', which recipes may \
+                     refer to as $profile. Targets go into a directory of that \
+                     name below the target directory, so that builds of each \
+                     kind sit side by side.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("wrapper")
+                .long("wrapper")
+                .value_name("CMD")
+                .help(
+                    "Specifies a command (e.g. \"ccache\") through which every \
+                     task should be run.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("container-runtime")
+                .long("container-runtime")
+                .value_name("CMD")
+                .help(
+                    "Specifies the program (e.g. docker or podman) used to run \
+                     tasks which declare a container image.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("git-bash")
+                .long("git-bash")
+                .value_name("FILE")
+                .help("Specifies the bash executable used by the git-bash launcher.")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("tool-store")
+                .long("tool-store")
+                .value_name("DIR")
+                .help("Specifies where the tools units pin are downloaded to.")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("separator")
+                .long("separator")
+                .value_name("SEPARATOR")
+                .possible_values(&["native", "forward"])
+                .default_value("native")
+                .help("Separates the paths in recipes as the platform does, or with '/'."),
+        )
+        .arg(
+            clap::Arg::with_name("shell-builtin")
+                .long("shell-builtin")
+                .value_name("NAME")
+                .help(
+                    "Runs recipes whose command is NAME through cmd /C, as NAME is \
+                     built into it rather than a program on PATH. Windows' usual \
+                     built-ins, e.g. copy, del and mkdir, are included by default.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("freshness")
+                .long("freshness")
+                .value_name("MODE")
+                .possible_values(&["stat", "git", "content"])
+                .default_value("stat")
+                .help(
+                    "Specifies how asmbl finds out when files last changed; \
+                     'git' reads the git index for tracked files and \
+                     'content' only counts changes to what files hold.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("hash-threshold")
+                .long("hash-threshold")
+                .value_name("BYTES")
+                .default_value("1048576")
+                .help(
+                    "Hashes files larger than BYTES up front, several at once, \
+                     when --freshness is 'content'.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("assume-new")
+                .short("W")
+                .long("assume-new")
+                .value_name("FILE")
+                .help(
+                    "Treats FILE (relative to the context) as though it had just \
+                     changed, rebuilding whatever depends upon it without \
+                     touching it.",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("shard")
+                .long("shard")
+                .value_name("K/N")
+                .help(
+                    "Builds only the K-th of N slices of the build, balanced \
+                     using how long tasks took previously.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("schedule")
+                .long("schedule")
+                .value_name("POLICY")
+                .possible_values(&["breadth", "depth", "critical-path"])
+                .default_value("breadth")
+                .help(
+                    "Specifies whether to interleave work on the final targets \
+                     (breadth), finish each in turn (depth) or favour the \
+                     longest chains of work (critical-path).",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .default_value("1")
+                .help("Runs up to N independent tasks at once."),
+        )
+        .arg(
+            clap::Arg::with_name("first")
+                .long("first")
+                .value_name("TARGET")
+                .help(
+                    "Builds the given target (relative to the target directory) \
+                     before anything it doesn't need.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("targets")
+                .value_name("TARGET")
+                .help(
+                    "Builds only the given targets (relative to the target \
+                     directory) and whatever they depend upon.",
+                )
+                .multiple(true),
+        )
+        .arg(
+            clap::Arg::with_name("keep-going")
+                .short("k")
+                .long("keep-going")
+                .help(
+                    "Carries on with whatever doesn't depend upon a failed task, \
+                     rather than stopping at the first failure.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help(
+                    "Passes on what tasks write as they write it, rather than \
+                     all at once as each finishes.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("trace")
+                .long("trace")
+                .value_name("FILE")
+                .help(
+                    "Records when each task ran, and in which job slot, to FILE in \
+                     Chrome's trace event format.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .short("n")
+                .long("dry-run")
+                .help("Lists the commands which would be run without running them."),
+        )
+        .arg(
+            clap::Arg::with_name("question")
+                .short("q")
+                .long("question")
+                .help("Runs nothing, failing if any task is out of date."),
+        )
+        .arg(
+            clap::Arg::with_name("explain")
+                .long("explain")
+                .help("Says why each task is to run, and how long it should all take."),
+        )
+        .arg(
+            clap::Arg::with_name("retry-failed")
+                .long("retry-failed")
+                .help(
+                    "Re-runs the tasks which failed last time, and whatever depends \
+                     upon them, along with whatever else is out of date.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("on-success")
+                .long("on-success")
+                .value_name("CMD")
+                .help("Specifies a command to run once the build has succeeded.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("on-failure")
+                .long("on-failure")
+                .value_name("CMD")
+                .help("Specifies a command to run once the build has failed.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("print-exit-codes")
+                .long("print-exit-codes")
+                .help("Lists the exit codes asmbl may finish with, and what each means."),
+        )
+        .arg(
+            clap::Arg::with_name("allow")
+                .long("allow")
+                .value_name("KIND")
+                .possible_values(core::Finding::KINDS)
+                .help("Ignores warnings of the given kind.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("deny")
+                .long("deny")
+                .value_name("warnings")
+                .possible_values(&["warnings"])
+                .help("Refuses to build whilst there are any warnings.")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("Builds again whenever a source file or unit changes."),
+        )
+        .arg(
+            clap::Arg::with_name("watch-backend")
+                .long("watch-backend")
+                .value_name("BACKEND")
+                .possible_values(&["native", "poll"])
+                .default_value(exec::WatchBackend::default().name())
+                .help(
+                    "Specifies how changes are noticed; 'poll' suits network \
+                     file-systems and containers, where the operating system \
+                     may not hear of them.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("poll-interval")
+                .long("poll-interval")
+                .value_name("MS")
+                .default_value("500")
+                .help("Specifies how often the 'poll' watch backend looks for changes."),
+        )
+        .arg(
+            clap::Arg::with_name("daemon")
+                .long("daemon")
+                .help("Has the daemon for the target directory run the build."),
+        )
+        .arg(clap::Arg::with_name("redact-env").long("redact-env").help(
+            "Hides the values of secret-looking variables when showing \
+                     the environment of a failed task, or of every task in \
+                     'graph --format json'.",
+        ))
+        .subcommand(
+            clap::SubCommand::with_name("stats")
+                .about("Summarises what's been learnt about the build from previous runs"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("log")
+                .about("Shows the commands run so far, most recent first, and how they went")
+                .arg(
+                    clap::Arg::with_name("target")
+                        .long("target")
+                        .value_name("TARGET")
+                        .help(
+                            "Shows only the commands run to produce the given target \
+                             (relative to the target directory).",
+                        ),
+                )
+                .arg(
+                    clap::Arg::with_name("last")
+                        .long("last")
+                        .value_name("COUNT")
+                        .help("Shows only the given number of commands."),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("status")
+                .about("Says how much is out of date and how the last build went, quickly")
+                .arg(
+                    clap::Arg::with_name("porcelain")
+                        .long("porcelain")
+                        .help("Says so on one line of NAME=VALUE pairs, e.g. for a prompt."),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("daemon")
+                .about("Serves builds to clients such as editors over a unix socket"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("check")
+                .about("Looks the build over for problems without running anything")
+                .arg(
+                    clap::Arg::with_name("json")
+                        .long("json")
+                        .help("Lists the problems found as JSON."),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("serve-diagnostics")
+                .about("Reports the problems check would find whenever they might have changed")
+                .after_help(
+                    "Each report is a JSON-RPC 'diagnostics' notification written to \
+                     stdout on a line of its own, as the daemon would send it.",
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clean")
+                .about("Removes whatever tasks have produced, leaving only their sources"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("graph")
+                .about("Prints the task graph, for Graphviz or Mermaid to draw or tools to read")
+                .arg(
+                    clap::Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["dot", "mermaid", "json"])
+                        .default_value("dot")
+                        .help("The language to describe the graph in."),
+                ),
+        );
+    // Only offered where the importer was built in.
+    #[cfg(feature = "make-frontend")]
+    let app = app.subcommand(
+        clap::SubCommand::with_name("import-make")
+            .about("Writes a unit from the rules GNU make knows of, to start a migration from")
+            .arg(
+                clap::Arg::with_name("dump")
+                    .value_name("FILE")
+                    .help("Reads a database saved from make -p, or '-' for stdin."),
+            )
+            .arg(
+                clap::Arg::with_name("from")
+                    .long("from")
+                    .value_name("COMMAND")
+                    .default_value("make -pn")
+                    .help("The command printing make's database, run in the context."),
+            )
+            .arg(
+                clap::Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .possible_values(&["lua", "toml"])
+                    .default_value("lua")
+                    .help("The language to write the unit in."),
+            ),
+    );
+    let args = app.get_matches_safe().or_else(|err| match err.kind {
+        clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => err.exit(),
+        _ => Err(err),
+    })?;
+
+    if args.is_present("print-exit-codes") {
+        print_exit_codes();
+        return Ok(());
+    }
 
-    let target_dir = match args.value_of("target") {
-        Some(s) => path::Path::new(s).canonicalize()?,
+    let mut target_dir = match args.value_of("target") {
+        Some(s) => path::Path::new(s)
+            .canonicalize()
+            .map_err(|err| RunError::BadDirectory(s.to_string(), err))?,
         None => std::env::current_dir()?,
     };
+    if let Some(profile) = args.value_of("profile") {
+        let mut components = path::Path::new(profile).components();
+        match (components.next(), components.next()) {
+            (Some(path::Component::Normal(_)), None) => {}
+            _ => return Err(RunError::BadProfile(profile.to_string()).into()),
+        }
+        target_dir.push(profile);
+        fs::create_dir_all(&target_dir)?;
+    }
 
     let context_dir = match args.value_of("context") {
         Some(s) => {
-            let context_dir = path::Path::new(s).canonicalize()?;
+            let context_dir = path::Path::new(s)
+                .canonicalize()
+                .map_err(|err| RunError::BadDirectory(s.to_string(), err))?;
             std::env::set_current_dir(&context_dir)?;
             context_dir
         }
         None => std::env::current_dir()?,
     };
 
-    let target_prefix = pathdiff::diff_paths(&target_dir, &context_dir)
-        .ok_or_else(|| RunError::NoRouteFromContextToTarget)?;
+    if args.subcommand_matches("stats").is_some() {
+        return stats(&target_dir);
+    }
+
+    if let Some(log_args) = args.subcommand_matches("log") {
+        return log(log_args, &context_dir, &target_dir);
+    }
+
+    if let Some(status_args) = args.subcommand_matches("status") {
+        return status(status_args, &target_dir);
+    }
+
+    #[cfg(feature = "make-frontend")]
+    if let Some(import_args) = args.subcommand_matches("import-make") {
+        return import(import_args);
+    }
+
+    if args.subcommand_matches("daemon").is_some() {
+        return daemon::serve(&context_dir, &target_dir);
+    }
+
+    if args.is_present("daemon") {
+        let request = daemon::BuildRequest {
+            targets: args.values_of("targets").into_iter().flatten().collect(),
+            keep_going: args.is_present("keep-going"),
+            dry_run: args.is_present("dry-run"),
+            jobs: match args.occurrences_of("jobs") {
+                0 => None,
+                _ => Some(args.value_of("jobs").unwrap().parse()?),
+            },
+            // Someone's waiting on it.
+            urgent: true,
+        };
+        process::exit(daemon::build(&target_dir, &request)?);
+    }
+
+    // The daemon has background builds make way for those someone's
+    // waiting on.
+    exec::yield_on_signal();
+
+    let serving = args.subcommand_matches("serve-diagnostics").is_some();
+    if !args.is_present("watch") && !serving {
+        return execute(&args, &context_dir, &target_dir, &mut vec![]);
+    }
+
+    let backend: exec::WatchBackend = args.value_of("watch-backend").unwrap().parse()?;
+    let interval = time::Duration::from_millis(args.value_of("poll-interval").unwrap().parse()?);
+    let mut watched = vec![];
+    loop {
+        // A failed build is reported and then waited out like any other.
+        // Diagnostics have stdout to themselves.
+        let baseline = exec::Baseline::take(&watched);
+        if let Err(err) = execute(&args, &context_dir, &target_dir, &mut watched) {
+            if exec::interrupted().is_some() {
+                return Err(err);
+            }
+            for cause in err.iter_chain() {
+                match serving {
+                    true => eprintln!("{}", cause),
+                    false => println!("{}", cause),
+                }
+            }
+        }
+        if !serving {
+            println!("Watching {} files for changes...", watched.len());
+        }
+        backend.wait(&watched, &baseline, interval)?;
+    }
+}
+
+// Does whatever was asked once, noting the files which, were they to change,
+// would call for it to be done again.
+fn execute(
+    args: &clap::ArgMatches,
+    context_dir: &path::Path,
+    target_dir: &path::Path,
+    watched: &mut Vec<path::PathBuf>,
+) -> Result<(), Error> {
+    let target_prefix = pathdiff::diff_paths(target_dir, context_dir)
+        .ok_or(RunError::NoRouteFromContextToTarget)?;
+
+    // Definitions are both options units may read and variables recipes
+    // may refer to.
+    let mut defines = collections::BTreeMap::new();
+    for define in args.values_of("define").into_iter().flatten() {
+        let (name, value) = define
+            .split_once('=')
+            .ok_or_else(|| RunError::BadDefine(define.to_string()))?;
+        defines.insert(name.to_string(), value.to_string());
+    }
 
     let mut engine = core::Engine::new();
+    if let Some(profile) = args.value_of("profile") {
+        engine.set_profile(profile);
+    }
+    engine.set_options(defines.clone());
+    if let Some(depth) = args.value_of("max-unit-depth") {
+        engine.set_max_depth(depth.parse()?);
+    }
+    #[cfg(feature = "lua-frontend")]
     engine.register_frontend("lua", asmbl_lua_frontend::FrontEnd::new());
+    #[cfg(feature = "toml-frontend")]
+    engine.register_frontend("toml", asmbl_toml_frontend::FrontEnd::new());
+
+    // Should the units fail to load, whatever was watched before still is.
+    if let Some(root_unit) = engine.root_unit(context_dir) {
+        if !watched.contains(&root_unit) {
+            watched.push(root_unit);
+        }
+    }
+
+    let units = engine.gather_units(context_dir);
+
+    let mut unit_files: Vec<_> = engine.root_unit(context_dir).into_iter().collect();
+    for (_, unit) in units.iter().flatten() {
+        unit_files.extend(unit.sub_units.iter().cloned());
+        // Units are read again should what they probed for come or go.
+        unit_files.extend(unit.probed.iter().cloned());
+    }
+
+    let tasks = units.map_err(Error::from).and_then(|units| {
+        core::TaskList::new(context_dir, &target_prefix, units).map_err(Error::from)
+    });
+
+    let mut config = core::Config::new(context_dir.to_path_buf(), target_dir.to_path_buf());
+    config.clear_env = !args.is_present("no-env-clear");
+    config.strict_path = args.is_present("strict-path");
+    for dir in args.values_of_os("sandbox-allow").into_iter().flatten() {
+        config.sandbox_allow.push(path::PathBuf::from(dir));
+    }
+    #[cfg(feature = "sandbox")]
+    {
+        config.deny_network = exec::deny_network;
+    }
+    // What tasks write to stderr is passed on to asmbl's own.
+    config.terminal = io::stderr().is_terminal();
+    if let Some(env) = args.values_of("env") {
+        config.env = env.map(core::EnvSpec::parse).collect();
+    }
+    // Definitions can't stand in for what the build was actually asked for.
+    config.variables.extend(defines);
+    // Without a profile $profile is left unbound, so that recipes relying on
+    // one say so rather than building into the wrong place.
+    for name in &["profile", "jobs"] {
+        if let Some(value) = args.value_of(name) {
+            config.variables.insert(name.to_string(), value.to_string());
+        }
+    }
+    if let Some(wrapper) = args.value_of("wrapper") {
+        config.wrapper = wrapper.split_whitespace().map(String::from).collect();
+    }
+    if let Some(runtime) = args.value_of("container-runtime") {
+        config.container_runtime = runtime.to_string();
+    }
+    if let Some(git_bash) = args.value_of("git-bash") {
+        config.git_bash = path::PathBuf::from(git_bash);
+    }
+    if let Some(tool_store) = args.value_of_os("tool-store") {
+        config.tool_store = path::PathBuf::from(tool_store);
+    }
+    config.separator = args.value_of("separator").unwrap().parse()?;
+    for builtin in args.values_of("shell-builtin").into_iter().flatten() {
+        config.shell_builtins.push(builtin.to_string());
+    }
+    for (arg, trigger) in [
+        ("on-success", core::HookTrigger::Success),
+        ("on-failure", core::HookTrigger::Failure),
+    ] {
+        for cmd in args.values_of(arg).into_iter().flatten() {
+            let recipe = core::Recipe::parse(cmd)?;
+            config.hooks.push(core::Hook::new(trigger, recipe, vec![]));
+        }
+    }
+
+    let content = match args.value_of("freshness") {
+        Some("content") => Some(exec::ContentHashes::load(&config.target_dir)?),
+        _ => None,
+    };
+
+    let git_index;
+    let stat: &dyn core::Stat = match (args.value_of("freshness"), &content) {
+        (_, Some(content)) => content,
+        (Some("git"), _) => {
+            git_index = core::GitIndex::load(&config.context_dir)?;
+            &git_index
+        }
+        _ => &core::FileSystem,
+    };
+
+    let assumed;
+    let stat: &dyn core::Stat = match args.values_of_os("assume-new") {
+        Some(paths) => {
+            assumed = core::AssumeNew::new(stat, paths.map(path::PathBuf::from));
+            &assumed
+        }
+        None => stat,
+    };
+
+    let allowed: Vec<_> = args.values_of("allow").into_iter().flatten().collect();
+
+    // Files the context's .asmblignore files pass over, e.g. editors' swap
+    // files, never trigger a rebuild.
+    let ignore = core::Ignore::new(context_dir.to_path_buf());
+    if let Ok(tasks) = &tasks {
+        *watched = unit_files;
+        for handle in tasks.handles() {
+            for (upstream, _) in tasks.upstream(handle) {
+                if let core::Upstream::File(file) = upstream {
+                    if !ignore.is_ignored(file) {
+                        watched.push(file.to_path_buf());
+                    }
+                }
+            }
+        }
+        watched.sort();
+        watched.dedup();
+    }
+
+    let report = match args.subcommand() {
+        ("check", Some(check_args)) if check_args.is_present("json") => Some(Report::Json),
+        ("check", _) => Some(Report::Text),
+        ("serve-diagnostics", _) => Some(Report::Notification),
+        _ => None,
+    };
+    if let Some(report) = report {
+        return check(tasks, &target_prefix, stat, &allowed, report);
+    }
+
+    let tasks = tasks?;
+
+    if let Some(graph_args) = args.subcommand_matches("graph") {
+        match graph_args.value_of("format") {
+            Some("json") => graph_json(&tasks, &config, args.is_present("redact-env")),
+            format => graph(&tasks, format == Some("mermaid")),
+        }
+        return Ok(());
+    }
 
-    let units = engine.gather_units(&context_dir)?;
+    // Everything asmbl keeps between runs lives under the target directory,
+    // leaving the context alone, which may well be read-only.
+    let state_dir = config.target_dir.join(".asmbl");
+    fs::create_dir_all(&state_dir)
+        .map_err(|err| RunError::UnwritableTarget(state_dir.display().to_string(), err))?;
 
-    let tasks = core::TaskList::new(&context_dir, &target_prefix, units)?;
+    let mut produced = exec::Produced::load(&config.target_dir)?;
 
-    for (_handle, task) in tasks.retain_out_of_date()? {
-        let mut cmd = task.prepare()?;
+    if args.subcommand_matches("clean").is_some() {
+        clean(&tasks, &mut produced)?;
+        return Ok(produced.save()?);
+    }
+
+    // Large files would otherwise be hashed one at a time as the build is
+    // planned.
+    if let Some(content) = &content {
+        let threshold = args.value_of("hash-threshold").unwrap().parse()?;
+        let mut files: Vec<_> = tasks.targets().map(|(target, _)| target).collect();
+        for handle in tasks.handles() {
+            for (upstream, _) in tasks.upstream(handle) {
+                if let core::Upstream::File(file) = upstream {
+                    files.push(file);
+                }
+            }
+        }
+        content.hash_ahead(files, threshold);
+    }
+
+    let warnings: Vec<_> = tasks
+        .validate(&target_prefix, stat)
+        .into_iter()
+        .filter(|finding| !allowed.contains(&finding.kind()))
+        .collect();
+    for warning in warnings.iter() {
+        println!("Warning: {} [{}]", warning, warning.kind());
+    }
+    if args.is_present("deny") && !warnings.is_empty() {
+        return Err(RunError::WarningsDenied(warnings.len()).into());
+    }
+
+    let mut durations = core::Durations::load(&config.target_dir)?;
+
+    let mut quarantine = exec::Quarantine::load(&config.target_dir)?;
+
+    let mut state = core::State::load(&config.target_dir)?;
+
+    let mut flaky = exec::Flaky::load(&config.target_dir)?;
+
+    let mut restat = exec::Restat::load(&config.target_dir)?;
+
+    let weight = |task: &core::Task| {
+        durations
+            .estimate(task)
+            .map_or(1, |duration| duration.as_millis() as u64)
+    };
+
+    let mut selected = match args.value_of("shard") {
+        Some(shard) => Some(tasks.shard(shard.parse()?, weight)),
+        None => None,
+    };
+
+    // Failed tasks are re-run even if they happened to leave their targets
+    // looking up to date, as are tasks whose recipe, environment or inputs
+    // have since changed, or which have lost anything they produced.
+    let failed = quarantine.handles(&tasks);
+    let mut forced = state.stale(&tasks, &config);
+    forced.extend(failed.iter().cloned());
+    forced.extend(produced.missing(&tasks, stat));
+
+    // Restat tasks which left their targets older than their inputs needn't
+    // run again until those inputs change.
+    let settled = restat.settled(&tasks, stat);
+
+    if let Some(targets) = args.values_of("targets") {
+        let mut handles = targets
+            .map(|target| {
+                tasks
+                    .find(&target_prefix.join(target))
+                    .ok_or_else(|| RunError::NoSuchTarget(target.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // Failed tasks are forced anyway, so needn't be asked for when every
+        // task is.
+        if args.is_present("retry-failed") {
+            handles.extend(tasks.downstream_closure(failed.iter().cloned()));
+        }
+        let needed = tasks.upstream_closure(handles);
+        selected = Some(match selected {
+            Some(selected) => needed.intersection(&selected).cloned().collect(),
+            None => needed,
+        });
+    }
+
+    let first = match args.value_of("first") {
+        Some(first) => Some(
+            tasks
+                .find(&target_prefix.join(first))
+                .ok_or_else(|| RunError::NoSuchTarget(first.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let plan = tasks.plan(&core::Request {
+        stat,
+        forced: &forced,
+        settled: &settled,
+        selected: selected.as_ref(),
+        schedule: args.value_of("schedule").unwrap().parse()?,
+        first,
+        durations: &durations,
+    })?;
+    let order = plan.order();
+
+    if args.is_present("explain") {
+        for handle in order {
+            let task = tasks.get(*handle);
+            let target = task.targets().next().unwrap();
+            println!("{}: {}", target.display(), plan.reason(*handle).unwrap());
+            // Flags passed through the environment are hunted down one by one.
+            for change in state.env_changes(task, &config) {
+                println!("    {}", change);
+            }
+        }
+        println!(
+            "{} tasks to run, taking about {:.1}s one after another.",
+            order.len(),
+            plan.estimate().as_secs_f64()
+        );
+    }
+
+    if args.is_present("question") {
+        return match plan.is_empty() {
+            true => Ok(()),
+            false => Err(RunError::OutOfDate(order.len()).into()),
+        };
+    }
+
+    if args.is_present("dry-run") {
+        for handle in order {
+            let task = tasks.get(*handle);
+            if task.has_recipe() {
+                println!("{}", task.render(&config)?);
+            }
+        }
+        return Ok(());
+    }
+
+    // The status subcommand goes by the graph as it was last built.
+    let mut snapshot = exec::Snapshot::load(&config.target_dir)?;
+    snapshot.record(&tasks);
+    snapshot.save()?;
+
+    // Tools are fetched up front, and only for the tasks about to use them.
+    for tool in tasks.tools(order) {
+        let dir = tool.dir(&config.tool_store);
+        if !dir.is_dir() {
+            println!("Provisioning {} from {}", tool.name(), tool.url());
+            asmbl_builtins::provision(tool.name(), tool.url(), tool.sha256(), &dir)?;
+        }
+    }
+
+    let mut records = Records {
+        durations: &mut durations,
+        quarantine: &mut quarantine,
+        state: &mut state,
+        flaky: &mut flaky,
+        produced: &mut produced,
+        restat: &mut restat,
+        content: content.as_ref(),
+        history: &exec::History::open(&config.target_dir),
+        saved: time::Instant::now(),
+    };
+
+    let options = BuildOptions {
+        jobs: args.value_of("jobs").unwrap().parse()?,
+        keep_going: args.is_present("keep-going"),
+        redact_env: args.is_present("redact-env"),
+        verbose: args.is_present("verbose"),
+        trace: args.value_of_os("trace").map(path::PathBuf::from),
+        sandbox: args.is_present("sandbox"),
+        cache: args.is_present("cache"),
+    };
+
+    let freshness = Freshness {
+        stat,
+        forced: &forced,
+        settled: &settled,
+    };
+
+    let start = time::Instant::now();
+    let mut failures = vec![];
+    let result = build(
+        &tasks,
+        order,
+        &config,
+        &freshness,
+        &options,
+        &mut records,
+        &mut failures,
+    )
+    .and_then(|()| report_failures(&failures));
+
+    records.save()?;
+    let outcome = exec::Outcome {
+        finished: time::SystemTime::now(),
+        took: start.elapsed(),
+        succeeded: result.is_ok(),
+    };
+    outcome.save(&config.target_dir)?;
+
+    // Tasks which have just run may have found more to depend upon.
+    for handle in tasks.handles() {
+        let discovered = tasks.get(handle).discovered_dependencies(context_dir);
+        let discovered = discovered.unwrap_or_default().into_iter();
+        // Targets among them are written by the build itself.
+        watched.extend(
+            discovered.filter(|file| !ignore.is_ignored(file) && tasks.find(file).is_none()),
+        );
+    }
+    watched.sort();
+    watched.dedup();
+
+    // Should the build itself have gone wrong, that's what's reported,
+    // whatever becomes of the hooks.
+    let hooks = run_hooks(
+        &tasks,
+        &config,
+        &result,
+        &failures,
+        order.len(),
+        start.elapsed(),
+    );
+    result.and(hooks)
+}
+
+// How the build goes about running tasks.
+struct BuildOptions {
+    jobs: usize,
+    keep_going: bool,
+    redact_env: bool,
+    // Whether tasks' output is passed on as it comes, rather than all at
+    // once as each finishes.
+    verbose: bool,
+    // Where to record when each task ran, if anywhere.
+    trace: Option<path::PathBuf>,
+    // Whether tasks are run with only their declared inputs to hand.
+    sandbox: bool,
+    // Whether tasks' targets are kept, and restored rather than run again.
+    cache: bool,
+}
+
+// A task which failed, as reported once the build is over.
+struct Failure {
+    target: String,
+    // Where the task was declared, so it can be found and fixed.
+    origin: Option<core::Origin>,
+    status: process::ExitStatus,
+    stderr: String,
+}
+
+fn report_failures(failures: &[Failure]) -> Result<(), Error> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    println!("Failed tasks:");
+    for failure in failures {
+        match &failure.origin {
+            Some(origin) => println!(
+                "  {} ({}), declared at {}",
+                failure.target, failure.status, origin
+            ),
+            None => println!("  {} ({})", failure.target, failure.status),
+        }
+        for line in failure.stderr.lines() {
+            println!("    {}", line);
+        }
+    }
+
+    match failures {
+        [failure] => Err(RunError::TaskFailed(failure.target.clone(), failure.status).into()),
+        _ => Err(RunError::TasksFailed(failures.len()).into()),
+    }
+}
+
+// Announces each task as it starts, numbered against all those the build
+// may yet run; the command itself is shown only if verbose.
+struct Progress<'a> {
+    config: &'a core::Config,
+    verbose: bool,
+    started: usize,
+    planned: usize,
+}
+
+impl exec::BuildObserver for Progress<'_> {
+    fn planned(&mut self, count: usize) {
+        self.planned = count;
+    }
+
+    fn started(&mut self, task: &core::Task, _slot: usize) {
+        self.started += 1;
+        if !task.is_echoed() {
+            return;
+        }
+        let announcement = match (task.description(), self.verbose) {
+            (None, true) => task.render(self.config).ok(),
+            _ => None,
+        };
+        let announcement = announcement.unwrap_or_else(|| task.summary());
+        println!("[{}/{}] {}", self.started, self.planned, announcement);
+    }
+
+    fn restored(&mut self, task: &core::Task) {
+        self.started += 1;
+        if task.is_echoed() {
+            let (started, planned) = (self.started, self.planned);
+            println!("[{}/{}] {} (cached)", started, planned, task.summary());
+        }
+    }
+
+    fn skipped(&mut self, _task: &core::Task) {
+        self.planned -= 1;
+    }
+}
+
+// Each task that ran as a complete event in Chrome's trace event format,
+// with the job slot it ran in as its thread.
+struct Trace {
+    began: time::Instant,
+    events: Vec<serde_json::Value>,
+}
+
+impl Trace {
+    fn save(&self, file: &path::Path) -> io::Result<()> {
+        fs::write(file, format!("{}\n", json!({ "traceEvents": self.events })))
+    }
+}
+
+impl exec::BuildObserver for Trace {
+    fn finished(&mut self, task: &core::Task, slot: usize, succeeded: bool, took: time::Duration) {
+        let began = self.began.elapsed().saturating_sub(took);
+        let target = task.targets().next().unwrap().display().to_string();
+        self.events.push(json!({
+            "name": task.summary(),
+            "cat": "task",
+            "ph": "X",
+            "ts": began.as_micros() as u64,
+            "dur": took.as_micros() as u64,
+            "pid": 1,
+            "tid": slot,
+            "args": { "target": target, "succeeded": succeeded },
+        }));
+    }
+}
+
+// Everything watching the build, each told of all that happens.
+struct Observers<'a> {
+    progress: Progress<'a>,
+    trace: Option<Trace>,
+}
+
+impl Observers<'_> {
+    fn each(&mut self) -> impl Iterator<Item = &mut dyn exec::BuildObserver> {
+        let progress: &mut dyn exec::BuildObserver = &mut self.progress;
+        let trace = self
+            .trace
+            .as_mut()
+            .map(|trace| trace as &mut dyn exec::BuildObserver);
+        std::iter::once(progress).chain(trace)
+    }
+}
+
+impl exec::BuildObserver for Observers<'_> {
+    fn planned(&mut self, count: usize) {
+        self.each().for_each(|observer| observer.planned(count));
+    }
+
+    fn started(&mut self, task: &core::Task, slot: usize) {
+        self.each()
+            .for_each(|observer| observer.started(task, slot));
+    }
+
+    fn finished(&mut self, task: &core::Task, slot: usize, succeeded: bool, took: time::Duration) {
+        self.each()
+            .for_each(|observer| observer.finished(task, slot, succeeded, took));
+    }
+
+    fn restored(&mut self, task: &core::Task) {
+        self.each().for_each(|observer| observer.restored(task));
+    }
+
+    fn skipped(&mut self, task: &core::Task) {
+        self.each().for_each(|observer| observer.skipped(task));
+    }
+}
+
+// What decides whether tasks are out of date, both before the build and as
+// restat tasks turn out to have left their targets as they were.
+struct Freshness<'a> {
+    stat: &'a dyn core::Stat,
+    forced: &'a collections::HashSet<core::TaskHandle>,
+    settled: &'a collections::HashSet<core::TaskHandle>,
+}
+
+// What's kept about each task between runs.
+struct Records<'a> {
+    durations: &'a mut core::Durations,
+    quarantine: &'a mut exec::Quarantine,
+    state: &'a mut core::State,
+    flaky: &'a mut exec::Flaky,
+    produced: &'a mut exec::Produced,
+    restat: &'a mut exec::Restat,
+    content: Option<&'a exec::ContentHashes>,
+    history: &'a exec::History,
+    saved: time::Instant,
+}
+
+impl Records<'_> {
+    // Every file is replaced whole, so none is ever left half written.
+    fn save(&mut self) -> io::Result<()> {
+        self.durations.save()?;
+        self.quarantine.save()?;
+        self.state.save()?;
+        self.flaky.save()?;
+        self.produced.save()?;
+        self.restat.save()?;
+        if let Some(content) = self.content {
+            content.save()?;
+        }
+        self.saved = time::Instant::now();
+        Ok(())
+    }
+
+    // Notes that the task's targets are as its inputs make them, whether it
+    // ran or they were restored from the cache.
+    fn succeeded(
+        &mut self,
+        task: &core::Task,
+        input_state: String,
+        config: &core::Config,
+    ) -> Result<(), Error> {
+        self.restat.record(task, input_state);
+        self.state.record(task, config);
+        self.produced.record(task);
+        if let (Some(content), false) = (self.content, task.is_phony()) {
+            let produced = match task.is_restat() {
+                true => exec::ContentHashes::restat,
+                false => exec::ContentHashes::produced,
+            };
+            for target in task.targets() {
+                produced(content, target)?;
+            }
+            for product in self.produced.get(task) {
+                produced(content, product)?;
+            }
+        }
+        self.checkpoint()?;
+        Ok(())
+    }
+
+    // Saves what's been recorded so far, so that a build which is killed
+    // part way through still remembers the work it did. Saving after every
+    // one of a rush of quick tasks would cost more than the tasks, so saves
+    // are at least a second apart.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        if self.saved.elapsed() >= time::Duration::from_secs(1) {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+// The files kept in the cache for a task: its targets, and its depfile,
+// without which what it was found to depend upon would be lost.
+fn cached_files(task: &core::Task, config: &core::Config) -> Vec<path::PathBuf> {
+    let mut files: Vec<_> = task
+        .targets()
+        .map(|target| config.context_dir.join(target))
+        .collect();
+    if let Some(depfile) = task.depfile() {
+        let depfile = config.context_dir.join(depfile);
+        if !files.contains(&depfile) {
+            files.push(depfile);
+        }
+    }
+    files
+}
+
+fn build(
+    tasks: &core::TaskList,
+    order: &[core::TaskHandle],
+    config: &core::Config,
+    freshness: &Freshness,
+    options: &BuildOptions,
+    records: &mut Records,
+    failures: &mut Vec<Failure>,
+) -> Result<(), Error> {
+    let stat = freshness.stat;
+    let records = cell::RefCell::new(records);
+    let cache = exec::Cache::open(&config.target_dir);
+    let mut observers = Observers {
+        progress: Progress {
+            config,
+            verbose: options.verbose,
+            started: 0,
+            planned: 0,
+        },
+        trace: options.trace.as_ref().map(|_| Trace {
+            began: time::Instant::now(),
+            events: vec![],
+        }),
+    };
+    let result = exec::run(
+        tasks,
+        order,
+        options.jobs,
+        options.keep_going,
+        &mut observers,
+        |handle| -> Result<_, Error> {
+            Ok(tasks.is_out_of_date(handle, stat, freshness.forced, freshness.settled)?)
+        },
+        |task| -> Result<_, Error> {
+            let input_state = task.input_state(stat);
+            let action = match options.cache {
+                true => task.action(tasks, config)?,
+                false => None,
+            };
+            let files = cached_files(task, config);
+            if let Some(action) = &action {
+                if cache.restore(action, &files)? {
+                    records.borrow_mut().succeeded(task, input_state, config)?;
+                    return Ok(None);
+                }
+            }
+            // The task mustn't write through links to what's kept.
+            cache.detach(&files)?;
+            let sandbox = match options.sandbox {
+                true => core::Sandbox::new(tasks, task, config)?,
+                false => None,
+            };
+            let mut child = task.execute(config, |cmd| {
+                // Tasks have process groups of their own, out of the
+                // terminal's foreground, so can't read from it.
+                exec::own_group(cmd);
+                cmd.stdin(process::Stdio::null());
+                cmd.stdout(process::Stdio::piped());
+                cmd.stderr(process::Stdio::piped());
+                if let Some(sandbox) = &sandbox {
+                    sandbox.enter(task, config, cmd);
+                }
+            })?;
+            let output = exec::Capture::new(&mut child, options.verbose);
+            Ok(Some((child, (input_state, output, sandbox, action))))
+        },
+        |task, (input_state, output, sandbox, action), status, duration| {
+            let mut records = records.borrow_mut();
+            let stderr = output.finish();
+            if let Some(sandbox) = sandbox {
+                sandbox.finish(status.success())?;
+            }
+            task.finish(config, status.success())?;
+            records.durations.record(task, duration);
+            // Rendered commands leave out the environment, which may hold
+            // secrets, so are fit to be kept on disk.
+            let command = task.render(config)?;
+            records.history.record(task, &command, status, duration)?;
+            // A task stopped part way through may have left its targets
+            // half written.
+            if !status.success() && exec::interrupted().is_some() {
+                task.discard(config)?;
+                return Ok(false);
+            }
+
+            // A task whose result is captured hasn't failed as far as the
+            // build is concerned; whatever consumes the result decides what
+            // to do.
+            if let Some(result) = task.result() {
+                write_result(result, status, duration)?;
+            } else if !status.success() {
+                records.quarantine.insert(task, input_state);
+                // Failures are often down to the environment, so show
+                // exactly what the task was given.
+                println!("Environment:");
+                for (name, value) in environment(task, config, options.redact_env) {
+                    println!("  {}={}", name, value);
+                }
+                failures.push(Failure {
+                    target: task.targets().next().unwrap().display().to_string(),
+                    origin: task.origin().cloned(),
+                    status,
+                    stderr: String::from_utf8_lossy(&stderr).into_owned(),
+                });
+                return Ok(false);
+            }
+            // Succeeding against the very inputs it failed on marks it as
+            // flaky.
+            if records.quarantine.remove(task).as_ref() == Some(&input_state) {
+                records.flaky.record(task);
+            }
+            records.succeeded(task, input_state, config)?;
+            if let Some(action) = action {
+                cache.store(&action, &cached_files(task, config))?;
+            }
+            Ok(true)
+        },
+    );
+
+    // The trace is most wanted when the build went wrong, so it's written
+    // whatever the outcome.
+    if let (Some(trace), Some(file)) = (&observers.trace, &options.trace) {
+        trace.save(file)?;
+    }
+    result?;
+    match exec::interrupted() {
+        Some(_) => Err(RunError::Interrupted.into()),
+        None if exec::yielded() => Err(RunError::MadeWay.into()),
+        None => Ok(()),
+    }
+}
+
+// Hooks learn how the build went from the summary file named by
+// ASMBL_BUILD_SUMMARY. A failing hook is reported but leaves the outcome of
+// the build alone.
+fn run_hooks(
+    tasks: &core::TaskList,
+    config: &core::Config,
+    result: &Result<(), Error>,
+    failures: &[Failure],
+    planned: usize,
+    duration: time::Duration,
+) -> Result<(), Error> {
+    let success = result.is_ok();
+    let hooks: Vec<_> = config
+        .hooks
+        .iter()
+        .chain(tasks.hooks())
+        .filter(|hook| hook.applies(success))
+        .collect();
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let summary = json!({
+        "success": success,
+        "tasks": planned,
+        "failed": failures.first().map(|failure| &failure.target),
+        "failures": failures
+            .iter()
+            .map(|failure| {
+                json!({
+                    "target": failure.target,
+                    "exit_code": failure.status.code(),
+                    "stderr": failure.stderr,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "duration_ms": duration.as_millis() as u64,
+    });
+    let summary_file = config.target_dir.join(".asmbl").join("summary");
+    fs::create_dir_all(summary_file.parent().unwrap())?;
+    fs::write(&summary_file, format!("{}\n", summary))?;
+
+    let summary = [
+        (
+            "ASMBL_BUILD_STATUS",
+            if success { "success" } else { "failure" }.into(),
+        ),
+        ("ASMBL_BUILD_SUMMARY", summary_file.into_os_string()),
+    ];
+    for hook in hooks {
+        let mut cmd = hook.prepare(config, &summary)?;
         println!("{:?}", cmd);
-        cmd.spawn()?.wait()?;
+        let status = cmd.spawn()?.wait()?;
+        if !status.success() {
+            println!("Hook failed ({}).", status);
+        }
     }
+    Ok(())
+}
 
+#[cfg(feature = "make-frontend")]
+fn import(args: &clap::ArgMatches) -> Result<(), Error> {
+    let dump = match args.value_of("dump") {
+        Some("-") => io::read_to_string(io::stdin())?,
+        Some(file) => fs::read_to_string(file)?,
+        None => {
+            let cmd = args.value_of("from").unwrap();
+            let output = process::Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .stderr(process::Stdio::inherit())
+                .output()?;
+            if !output.status.success() {
+                let err = import_make::ImportError::CommandFailed(cmd.to_string(), output.status);
+                return Err(err.into());
+            }
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+    };
+
+    let database = import_make::read(&dump)?;
+    match args.value_of("format") {
+        Some("toml") => print!("{}", database.toml()),
+        _ => print!("{}", database.lua()),
+    }
+    Ok(())
+}
+
+// Days since 1970-01-01 as a (year, month, day) date.
+fn civil(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn log(
+    args: &clap::ArgMatches,
+    context_dir: &path::Path,
+    target_dir: &path::Path,
+) -> Result<(), Error> {
+    let target_prefix = pathdiff::diff_paths(target_dir, context_dir)
+        .ok_or(RunError::NoRouteFromContextToTarget)?;
+    let target = args
+        .value_of("target")
+        .map(|target| target_prefix.join(target));
+    let last = match args.value_of("last") {
+        Some(last) => last.parse()?,
+        None => usize::MAX,
+    };
+
+    let runs = exec::History::open(target_dir).runs()?;
+    let runs = runs.iter().rev().filter(|run| match &target {
+        Some(target) => run.targets.contains(target),
+        None => true,
+    });
+    for run in runs.take(last) {
+        let since = run
+            .started
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let (year, month, day) = civil(since.div_euclid(86_400));
+        let time = since.rem_euclid(86_400);
+        println!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02} UTC  {}  {} in {:.2}s",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60,
+            run.targets[0].display(),
+            run.status,
+            run.took.as_secs_f64()
+        );
+        println!("  {}", run.command);
+    }
+    Ok(())
+}
+
+// Status is asked for often, e.g. by shell prompts, so goes by what the last
+// build left behind rather than reading the units.
+fn status(args: &clap::ArgMatches, target_dir: &path::Path) -> Result<(), Error> {
+    let snapshot = exec::Snapshot::load(target_dir)?;
+    let out_of_date = snapshot.out_of_date(&core::FileSystem);
+    let outcome = exec::Outcome::load(target_dir)?;
+
+    if args.is_present("porcelain") {
+        let mut line = format!("dirty={} total={}", out_of_date, snapshot.len());
+        match &outcome {
+            Some(outcome) => line.push_str(&format!(
+                " last={} finished={} took={:.2}",
+                match outcome.succeeded {
+                    true => "ok",
+                    false => "failed",
+                },
+                outcome
+                    .finished
+                    .duration_since(time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                outcome.took.as_secs_f64()
+            )),
+            None => line.push_str(" last=none"),
+        }
+        println!("{}", line);
+        return Ok(());
+    }
+
+    let outcome = match outcome {
+        Some(outcome) => outcome,
+        None => {
+            println!("Nothing built yet.");
+            return Ok(());
+        }
+    };
+    println!("{} of {} tasks out of date.", out_of_date, snapshot.len());
+    let ago = outcome.finished.elapsed().unwrap_or_default().as_secs();
+    let ago = match ago {
+        0..=59 => format!("{}s", ago),
+        60..=3599 => format!("{}m", ago / 60),
+        3600..=86_399 => format!("{}h", ago / 3600),
+        _ => format!("{}d", ago / 86_400),
+    };
+    let result = match outcome.succeeded {
+        true => "succeeded",
+        false => "failed",
+    };
+    println!(
+        "Last build {} {} ago, taking {:.2}s.",
+        result,
+        ago,
+        outcome.took.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn stats(target_dir: &path::Path) -> Result<(), Error> {
+    let flaky = exec::Flaky::load(target_dir)?;
+    let flaky = flaky.worst();
+    if flaky.is_empty() {
+        println!("No flaky tasks.");
+    } else {
+        println!("Flaky tasks (failed then passed with unchanged inputs):");
+        for (target, count) in flaky {
+            println!("  {:>4}  {}", count, target);
+        }
+    }
     Ok(())
 }
 
+// How check reports the problems it finds.
+#[derive(Clone, Copy, PartialEq)]
+enum Report {
+    Text,
+    Json,
+    // A JSON-RPC notification, for editors to underline the problems.
+    Notification,
+}
+
+struct Problem {
+    kind: &'static str,
+    message: String,
+    // The unit at fault, and where in it, if known.
+    file: Option<String>,
+    line: Option<usize>,
+    paths: Vec<String>,
+}
+
+impl Problem {
+    fn json(&self) -> serde_json::Value {
+        json!({
+            "kind": self.kind,
+            "message": self.message,
+            "file": self.file,
+            "line": self.line,
+            "paths": self.paths,
+        })
+    }
+}
+
+// Finds the line of `file` a front-end's error points at, whether Lua's
+// `[string "<file>"]:<line>:` or a plain `line <line>`.
+fn line_of(file: &str, message: &str) -> Option<usize> {
+    let digits = |s: &str| -> Option<usize> {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        s[..end].parse().ok()
+    };
+    let lua = format!("{}\"]:", file);
+    match message.find(&lua) {
+        Some(at) => digits(&message[at + lua.len()..]),
+        None => digits(&message[message.find("line ")? + "line ".len()..]),
+    }
+}
+
+fn check(
+    tasks: Result<core::TaskList, Error>,
+    target_prefix: &path::Path,
+    stat: &dyn core::Stat,
+    allowed: &[&str],
+    report: Report,
+) -> Result<(), Error> {
+    // A graph which can't be built at all is just another problem to report.
+    let problems: Vec<Problem> = match &tasks {
+        Ok(tasks) => tasks
+            .validate(target_prefix, stat)
+            .iter()
+            .filter(|finding| !allowed.contains(&finding.kind()))
+            .map(|finding| {
+                // Findings are blamed on the unit declaring the task at fault,
+                // whose target comes last.
+                let paths = finding.paths();
+                let origin = paths
+                    .last()
+                    .and_then(|target| tasks.find(target))
+                    .and_then(|handle| tasks.get(handle).origin());
+                Problem {
+                    kind: finding.kind(),
+                    message: finding.to_string(),
+                    file: origin.map(|origin| origin.unit.display().to_string()),
+                    line: origin.and_then(|origin| origin.line),
+                    paths: paths.into_iter().map(|p| p.display().to_string()).collect(),
+                }
+            })
+            .collect(),
+        Err(err) => {
+            let causes: Vec<_> = err.iter_chain().map(|cause| cause.to_string()).collect();
+            let message = causes.join(": ");
+            let unit = match err.downcast_ref::<core::GatherUnitsError>() {
+                Some(core::GatherUnitsError::ParseError { file, .. })
+                | Some(core::GatherUnitsError::BadSubUnit { file })
+                | Some(core::GatherUnitsError::NoFrontEnd { file, .. })
+                | Some(core::GatherUnitsError::UnitNotInContext { file }) => Some(file.clone()),
+                _ => None,
+            };
+            match err.downcast_ref::<core::NewTaskListError>() {
+                Some(core::NewTaskListError::Cycle(cycle)) => vec![Problem {
+                    kind: "cycle",
+                    message: err.to_string(),
+                    file: None,
+                    line: None,
+                    paths: cycle
+                        .targets()
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect(),
+                }],
+                _ => vec![Problem {
+                    kind: "error",
+                    line: unit.as_ref().and_then(|file| line_of(file, &message)),
+                    file: unit,
+                    message,
+                    paths: vec![],
+                }],
+            }
+        }
+    };
+
+    match report {
+        Report::Text => {
+            for problem in problems.iter() {
+                println!("{}", problem.message);
+            }
+        }
+        Report::Json => {
+            let problems: Vec<_> = problems.iter().map(Problem::json).collect();
+            println!("{}", serde_json::Value::from(problems));
+        }
+        Report::Notification => {
+            let problems: Vec<_> = problems.iter().map(Problem::json).collect();
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "diagnostics",
+                "params": { "problems": problems },
+            });
+            println!("{}", notification);
+            io::stdout().flush()?;
+            return Ok(());
+        }
+    }
+
+    if !problems.is_empty() {
+        process::exit(ExitCode::Configuration as i32);
+    }
+
+    Ok(())
+}
+
+// Removes the targets of every task which produces files, along with
+// whatever else they were last found to have produced.
+fn clean(tasks: &core::TaskList, produced: &mut exec::Produced) -> io::Result<()> {
+    for handle in tasks.handles() {
+        let task = tasks.get(handle);
+        if task.is_phony() {
+            continue;
+        }
+        let products = produced.get(task).to_vec();
+        let products = products.iter().map(path::PathBuf::as_path);
+        for path in task.targets().chain(products) {
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            match removed {
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+        }
+        produced.forget(task);
+    }
+    Ok(())
+}
+
+fn graph(tasks: &core::TaskList, mermaid: bool) {
+    // Files no task produces are drawn once, however many tasks use them.
+    let mut files = collections::HashMap::new();
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    let indices: collections::HashMap<_, _> = tasks.handles().zip(0..).collect();
+    let task_node = |handle| format!("t{}", indices[&handle]);
+    for handle in tasks.handles() {
+        let task = tasks.get(handle);
+        let targets: Vec<_> = task.targets().map(|t| t.display().to_string()).collect();
+        nodes.push((task_node(handle), targets, task.is_phony()));
+        for (upstream, edge) in tasks.upstream(handle) {
+            let from = match upstream {
+                core::Upstream::Task(upstream) => task_node(upstream),
+                core::Upstream::File(file) => {
+                    let count = files.len();
+                    files
+                        .entry(file)
+                        .or_insert_with(|| format!("f{}", count))
+                        .clone()
+                }
+            };
+            edges.push((from, task_node(handle), edge));
+        }
+    }
+    let mut files: Vec<_> = files.into_iter().collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+
+    if mermaid {
+        let quote = |s: &str| s.replace('"', "#quot;").replace('\n', "<br>");
+        println!("flowchart LR");
+        for (node, targets, phony) in nodes.iter() {
+            let label = quote(&targets.join("\n"));
+            match phony {
+                true => println!("    {}([\"{}\"])", node, label),
+                false => println!("    {}[\"{}\"]", node, label),
+            }
+        }
+        for (file, node) in files.iter() {
+            println!("    {}[/\"{}\"/]", node, quote(&file.display().to_string()));
+        }
+        for (from, to, edge) in edges.iter() {
+            match edge {
+                core::Edge::Consumes => println!("    {} --> {}", from, to),
+                core::Edge::DependsOn => println!("    {} -- depends_on --> {}", from, to),
+                core::Edge::NotBefore => println!("    {} -. not_before .-> {}", from, to),
+                core::Edge::Discovered => println!("    {} -. discovered .-> {}", from, to),
+            }
+        }
+    } else {
+        // Each target has a line of the label to itself.
+        let quote = |s: &str| format!("\"{}\"", dot_escape(s));
+        println!("digraph asmbl {{");
+        println!("    rankdir=LR;");
+        for (node, targets, phony) in nodes.iter() {
+            let shape = if *phony { "ellipse" } else { "box" };
+            let lines: Vec<_> = targets.iter().map(|target| dot_escape(target)).collect();
+            let label = format!("\"{}\"", lines.join("\\n"));
+            println!("    {} [label={}, shape={}];", node, label, shape);
+        }
+        for (file, node) in files.iter() {
+            let label = quote(&file.display().to_string());
+            println!("    {} [label={}, shape=note];", node, label);
+        }
+        for (from, to, edge) in edges.iter() {
+            let style = match edge {
+                core::Edge::Consumes => "solid",
+                core::Edge::DependsOn => "dashed",
+                core::Edge::NotBefore => "dotted",
+                core::Edge::Discovered => "dotted",
+            };
+            println!(
+                "    {} -> {} [label={}, style={}];",
+                from,
+                to,
+                quote(edge.kind()),
+                style
+            );
+        }
+        println!("}}");
+    }
+}
+
+// Graphviz's quoted strings escape only quotes, and the backslashes which
+// would otherwise start escapes of their own.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// The environment a task is run with, as it's shown to people, with the values
+// of secret-looking variables hidden if asked.
+fn environment(task: &core::Task, config: &core::Config, redact: bool) -> Vec<(String, String)> {
+    task.environment(config)
+        .into_iter()
+        .map(|(name, value)| {
+            let value = if redact && core::looks_secret(&name) {
+                "<redacted>".to_string()
+            } else {
+                value.to_string_lossy().into_owned()
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+// Lists every task, in the order they'd run, along with what it depends upon
+// and the environment it'd be given; tasks refer to one another by their
+// positions in the list.
+fn graph_json(tasks: &core::TaskList, config: &core::Config, redact: bool) {
+    let indices: collections::HashMap<_, _> = tasks.handles().zip(0..).collect();
+    let entries: Vec<_> = tasks
+        .handles()
+        .map(|handle| {
+            let task = tasks.get(handle);
+            let targets: Vec<_> = task
+                .targets()
+                .map(|target| target.display().to_string())
+                .collect();
+            let upstream: Vec<_> = tasks
+                .upstream(handle)
+                .map(|(upstream, edge)| match upstream {
+                    core::Upstream::Task(upstream) => {
+                        json!({ "task": indices[&upstream], "edge": edge.kind() })
+                    }
+                    core::Upstream::File(file) => {
+                        json!({ "file": file.display().to_string(), "edge": edge.kind() })
+                    }
+                })
+                .collect();
+            let env: serde_json::Map<_, _> = environment(task, config, redact)
+                .into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect();
+            json!({
+                "targets": targets,
+                "phony": task.is_phony(),
+                "upstream": upstream,
+                "env": env,
+            })
+        })
+        .collect();
+    println!("{}", json!({ "tasks": entries }));
+}
+
+fn write_result(
+    file: &path::Path,
+    status: process::ExitStatus,
+    duration: time::Duration,
+) -> io::Result<()> {
+    let result = json!({
+        "success": status.success(),
+        "exit_code": status.code(),
+        "duration_ms": duration.as_millis() as u64,
+    });
+    fs::write(file, format!("{}\n", result))
+}
+
 fn main() {
-    if let Err(err) = run() {
+    // Built-in recipes are run by re-invoking asmbl itself.
+    let args: Vec<_> = std::env::args().collect();
+    let result = match args.get(1).map(|arg| arg.as_str()) {
+        Some("builtin") => asmbl_builtins::run(&args[2..]).map_err(Error::from),
+        _ => run(),
+    };
+
+    if let Err(err) = result {
         for cause in err.iter_chain() {
             println!("{}", cause);
         }
-        std::process::exit(1)
+        std::process::exit(ExitCode::of(&err) as i32)
     }
 }
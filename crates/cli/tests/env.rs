@@ -0,0 +1,35 @@
+//! The variables every task is given to find its way around.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn tasks_are_told_where_the_build_is() {
+    let dir = TempDir::new("env");
+    let (context, target) = (dir.join("src"), dir.join("out"));
+    fs::create_dir_all(&context).unwrap();
+    fs::create_dir_all(&target).unwrap();
+    fs::write(
+        context.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"dirs\"\n\
+         run = [\"sh\", \"-c\", \"printf '%s\\\\n' \\\"$$ASMBL_CONTEXT_DIR\\\" \\\"$$ASMBL_TARGET_DIR\\\" \\\"$$ASMBL_TASK_TARGET0\\\" > \\\"$$0\\\"\", \"$@\"]\n",
+    )
+    .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(["--context", "src", "--target", "out"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let dirs = fs::read_to_string(target.join("dirs")).unwrap();
+    let dirs: Vec<_> = dirs.lines().collect();
+    let absolute = |dir: &std::path::Path| dir.canonicalize().unwrap().display().to_string();
+    assert_eq!(dirs[0], absolute(&context));
+    assert_eq!(dirs[1], absolute(&target));
+    // The first target, as the recipe itself names it.
+    assert_eq!(dirs[2], "../out/dirs");
+}
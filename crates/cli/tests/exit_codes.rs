@@ -0,0 +1,51 @@
+//! What asmbl's exit status says went wrong, for scripts driving it.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+// The status asmbl exits with having read `unit` as its asmbl.toml.
+fn exit_code(name: &str, unit: &str) -> Option<i32> {
+    let dir = TempDir::new(name);
+    fs::write(dir.join("asmbl.toml"), unit).unwrap();
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    output.status.code()
+}
+
+#[test]
+fn failing_tasks_exit_1() {
+    let unit = "[[task]]\n\
+                targets = \"out\"\n\
+                run = [\"false\"]\n";
+    assert_eq!(exit_code("exit-task", unit), Some(1));
+}
+
+#[test]
+fn bad_units_exit_2() {
+    // A task without targets.
+    let unit = "[[task]]\n\
+                run = \"true\"\n";
+    assert_eq!(exit_code("exit-unit", unit), Some(2));
+    assert_eq!(exit_code("exit-syntax", "[[task\n"), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted_tasks_exit_3() {
+    let unit = "[[task]]\n\
+                targets = \"out\"\n\
+                run = [\"sh\", \"-c\", \"kill -TERM $$$$\"]\n";
+    assert_eq!(exit_code("exit-interrupted", unit), Some(3));
+}
+
+#[test]
+fn anything_else_exits_4() {
+    // The target's directory can't be made, the unit being in the way.
+    let unit = "[[task]]\n\
+                targets = \"asmbl.toml/out\"\n\
+                run = [\"touch\", \"$@\"]\n";
+    assert_eq!(exit_code("exit-internal", unit), Some(4));
+}
@@ -0,0 +1,37 @@
+//! The task graph as `graph --format json` describes it.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+fn graph(dir: &TempDir, args: &[&str]) -> serde_json::Value {
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(args)
+        .args(["graph", "--format", "json"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn tasks_come_with_their_environment() {
+    let dir = TempDir::new("graph-env");
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"out\"\n\
+         run = [\"touch\", \"$@\"]\n\
+         env = { API_TOKEN = \"hunter2\", GREETING = \"hello\" }\n",
+    )
+    .unwrap();
+
+    let env = &graph(&dir, &[])["tasks"][0]["env"];
+    assert_eq!(env["API_TOKEN"], "hunter2");
+    assert_eq!(env["GREETING"], "hello");
+
+    let env = &graph(&dir, &["--redact-env"])["tasks"][0]["env"];
+    assert_eq!(env["API_TOKEN"], "<redacted>");
+    assert_eq!(env["GREETING"], "hello");
+}
@@ -0,0 +1,37 @@
+//! The commands run so far, as kept in `.asmbl/history` and shown by `log`.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn secrets_given_to_tasks_arent_kept() {
+    let dir = TempDir::new("history");
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"out\"\n\
+         run = [\"sh\", \"-c\", \"test -n \\\"$$API_TOKEN\\\" && touch \\\"$$0\\\"\", \"$@\"]\n\
+         env = { API_TOKEN = \"hunter2\" }\n",
+    )
+    .unwrap();
+
+    let asmbl = |args: &[&str]| {
+        process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+    };
+    let output = asmbl(&[]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("out").exists());
+
+    let history = fs::read_to_string(dir.join(".asmbl/history")).unwrap();
+    assert!(history.contains("API_TOKEN"), "{}", history);
+    assert!(!history.contains("hunter2"), "{}", history);
+    let output = asmbl(&["log"]);
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success() && log.contains("touch"), "{}", log);
+    assert!(!log.contains("hunter2"), "{}", log);
+}
@@ -0,0 +1,45 @@
+//! Commands run once a build is over, e.g. to report how it went.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn hooks_are_told_how_the_build_went_without_hiding_it() {
+    let dir = TempDir::new("hooks");
+    fs::write(
+        dir.join("hook.sh"),
+        "cp \"$ASMBL_BUILD_SUMMARY\" summary.json\n\
+         echo \"$ASMBL_BUILD_STATUS\" > status\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"out\"\n\
+         run = [\"sh\", \"-c\", \"exit 1\"]\n",
+    )
+    .unwrap();
+
+    // A hook which can't even be started is no reason to forget why the
+    // build failed.
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(["--on-success", "sh hook.sh"])
+        .args(["--on-failure", "sh hook.sh"])
+        .args(["--on-failure", "no-such-hook"])
+        .arg("out")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // As ExitCode::TaskFailed.
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("Task producing 'out' failed"), "{}", stdout);
+
+    assert_eq!(fs::read_to_string(dir.join("status")).unwrap(), "failure\n");
+    let summary = fs::read_to_string(dir.join("summary.json")).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+    assert_eq!(summary["success"], false);
+    assert_eq!(summary["failed"], "out");
+    assert_eq!(summary["failures"][0]["exit_code"], 1);
+}
@@ -0,0 +1,127 @@
+//! Asking asmbl to stop part way through a build, as Ctrl-C would.
+
+#![cfg(unix)]
+
+use std::{fs, path, process, thread, time};
+
+use asmbl_utils::testing::TempDir;
+
+// Whether the process is gone, or as good as, having exited without
+// anything left to wait on it.
+fn gone(pid: &str) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+        Ok(stat) => stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .trim_start()
+            .starts_with('Z'),
+        Err(_) => true,
+    }
+}
+
+fn wait_for(file: &path::Path) {
+    let deadline = time::Instant::now() + time::Duration::from_secs(10);
+    while !file.exists() {
+        assert!(
+            time::Instant::now() < deadline,
+            "{} never appeared",
+            file.display()
+        );
+        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn interrupted_builds_stop_their_tasks_and_discard_what_they_left() {
+    let dir = TempDir::new("interrupt");
+    // The task writes half its target and then waits on a process of its
+    // own, which should be stopped along with it.
+    fs::write(
+        dir.join("task.sh"),
+        "echo partial > \"$1\"\n\
+         sleep 30 &\n\
+         echo $! > sleeper.part\n\
+         mv sleeper.part sleeper\n\
+         wait\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"out\"\n\
+         consumes = \"task.sh\"\n\
+         run = [\"sh\", \"$<\", \"$@\"]\n",
+    )
+    .unwrap();
+
+    let mut asmbl = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .arg("out")
+        .current_dir(&dir)
+        .stdout(process::Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for(&dir.join("sleeper"));
+    unsafe { libc::kill(asmbl.id() as libc::pid_t, libc::SIGINT) };
+    let status = asmbl.wait().unwrap();
+
+    // As ExitCode::Interrupted.
+    assert_eq!(status.code(), Some(3));
+    assert!(!dir.join("out").exists());
+    let sleeper = fs::read_to_string(dir.join("sleeper")).unwrap();
+    let deadline = time::Instant::now() + time::Duration::from_secs(10);
+    while !gone(&sleeper) {
+        assert!(
+            time::Instant::now() < deadline,
+            "task's process left running"
+        );
+        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn builds_making_way_see_their_running_tasks_through() {
+    let dir = TempDir::new("make-way");
+    // The first task waits to be let go once the build has been asked to
+    // make way.
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"first\"\n\
+         run = [\"sh\", \"-c\", \"touch started; while [ ! -e go ]; do sleep 0.01; done; touch \\\"$$0\\\"\", \"$@\"]\n\
+         \n\
+         [[task]]\n\
+         targets = \"second\"\n\
+         consumes = \"first\"\n\
+         run = [\"touch\", \"$@\"]\n",
+    )
+    .unwrap();
+
+    let mut asmbl = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .arg("second")
+        .current_dir(&dir)
+        .stdout(process::Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for(&dir.join("started"));
+    unsafe { libc::kill(asmbl.id() as libc::pid_t, libc::SIGUSR1) };
+    // Signals arrive in their own time, so it's given a moment.
+    thread::sleep(time::Duration::from_millis(200));
+    fs::write(dir.join("go"), "").unwrap();
+    let status = asmbl.wait().unwrap();
+
+    // As ExitCode::Interrupted.
+    assert_eq!(status.code(), Some(3));
+    assert!(dir.join("first").exists());
+    assert!(!dir.join("second").exists());
+
+    // Nothing's lost by it.
+    let status = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .arg("second")
+        .current_dir(&dir)
+        .stdout(process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(dir.join("second").exists());
+}
@@ -0,0 +1,37 @@
+//! Tasks kept off the network, as `network = false` asks.
+#![cfg(all(target_os = "linux", feature = "sandbox"))]
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+// The network interfaces the process writing `file` could see.
+fn interfaces(file: &std::path::Path) -> Vec<String> {
+    fs::read_to_string(file)
+        .unwrap()
+        .lines()
+        .filter_map(|line| Some(line.split(':').next()?.trim().to_string()))
+        .filter(|name| !name.contains('|'))
+        .collect()
+}
+
+#[test]
+fn tasks_denied_the_network_only_see_loopback() {
+    let dir = TempDir::new("network");
+    fs::write(
+        dir.join("asmbl.lua"),
+        "task{target = 'online', run = {'cp', '/proc/self/net/dev', '$@'}}\n\
+         task{target = 'offline', run = {'cp', '/proc/self/net/dev', '$@'}, network = false}\n",
+    )
+    .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_eq!(interfaces(&dir.join("offline")), ["lo"]);
+    // Whatever else the host has, it has loopback too.
+    assert!(interfaces(&dir.join("online")).contains(&String::from("lo")));
+}
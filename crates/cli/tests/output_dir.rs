@@ -0,0 +1,29 @@
+//! Units choosing where their targets go, with `set_output_dir`.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn units_can_put_their_targets_elsewhere() {
+    let dir = TempDir::new("output-dir");
+    let lib = dir.join("lib");
+    fs::create_dir_all(&lib).unwrap();
+    fs::create_dir_all(dir.join("out")).unwrap();
+    fs::write(dir.join("asmbl.lua"), "sub_unit('lib')\n").unwrap();
+    fs::write(
+        lib.join("asmbl.lua"),
+        "set_output_dir('gen/lib')\n\
+         task{target = 'a', run = {'touch', '$@'}}\n",
+    )
+    .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(["--target", "out"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("out/gen/lib/a").exists());
+    assert!(!dir.join("out/lib/a").exists());
+}
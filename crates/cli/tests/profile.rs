@@ -0,0 +1,33 @@
+//! Builds of different kinds, as named by `--profile`.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn only_named_profiles_have_their_own_directory() {
+    let dir = TempDir::new("profile");
+    fs::write(
+        dir.join("asmbl.toml"),
+        "[[task]]\n\
+         targets = \"out\"\n\
+         run = [\"touch\", \"$@\"]\n",
+    )
+    .unwrap();
+
+    let asmbl = |args: &[&str]| {
+        let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+    };
+
+    asmbl(&[]);
+    assert!(dir.join("out").exists());
+    assert!(!dir.join("debug").exists());
+
+    asmbl(&["--profile", "release"]);
+    assert!(dir.join("release").join("out").exists());
+}
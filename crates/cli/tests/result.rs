@@ -0,0 +1,33 @@
+//! Tasks whose exit status is captured as a result, rather than failing the
+//! build.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn failing_tasks_can_have_their_result_consumed() {
+    let dir = TempDir::new("result");
+    fs::write(
+        dir.join("asmbl.lua"),
+        "local log, result = task{\n\
+             target = 'test.log', result = 'test.json',\n\
+             run = {'sh', '-c', 'echo ran > \"$$0\"; exit 3', '$@[0]'},\n\
+         }\n\
+         task{target = 'report', consumes = result, run = {'cp', '$<', '$@'}}\n",
+    )
+    .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(fs::read_to_string(dir.join("test.log")).unwrap(), "ran\n");
+
+    let report = fs::read_to_string(dir.join("report")).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+    assert_eq!(report["success"], false);
+    assert_eq!(report["exit_code"], 3);
+    assert!(report["duration_ms"].is_u64());
+}
@@ -0,0 +1,58 @@
+//! Running again the tasks which failed last time.
+
+use std::{fs, path, process};
+
+use asmbl_utils::testing::TempDir;
+
+fn asmbl(dir: &path::Path, args: &[&str]) -> process::Output {
+    process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn failed_tasks_are_retried_along_with_whatever_else_is_out_of_date() {
+    let dir = TempDir::new("retry");
+    fs::write(dir.join("in"), "in").unwrap();
+    // 'flaky' fails until there's a file saying it may pass.
+    fs::write(
+        dir.join("asmbl.toml"),
+        format!(
+            "[[task]]\n\
+             targets = \"flaky\"\n\
+             run = [\"sh\", \"-c\", \"test -e '{}' && touch \\\"$$0\\\"\", \"$@\"]\n\
+             \n\
+             [[task]]\n\
+             targets = \"copy\"\n\
+             consumes = \"in\"\n\
+             run = [\"cp\", \"$<\", \"$@\"]\n\
+             \n\
+             [[task]]\n\
+             targets = \"other\"\n\
+             run = [\"touch\", \"$@\"]\n",
+            dir.join("passes").display()
+        ),
+    )
+    .unwrap();
+
+    let output = asmbl(&dir, &["--keep-going"]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::write(dir.join("passes"), "").unwrap();
+    let (flaky, copy, other) = (dir.join("flaky"), dir.join("copy"), dir.join("other"));
+    assert!(!flaky.exists() && copy.exists());
+
+    // Retrying doesn't leave out what's since gone out of date.
+    fs::remove_file(&copy).unwrap();
+    fs::remove_file(&other).unwrap();
+    let output = asmbl(&dir, &["--retry-failed"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(flaky.exists() && copy.exists() && other.exists());
+
+    // Nothing failing is no reason to build nothing.
+    fs::remove_file(&other).unwrap();
+    let output = asmbl(&dir, &["--retry-failed", "other"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(other.exists());
+}
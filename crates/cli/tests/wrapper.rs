@@ -0,0 +1,44 @@
+//! Commands through which tasks are run, e.g. `ccache`.
+
+use std::{fs, process};
+
+use asmbl_utils::testing::TempDir;
+
+#[test]
+fn tasks_run_through_every_wrapper_given() {
+    let dir = TempDir::new("wrapper");
+    for name in &["outer", "inner"] {
+        fs::write(
+            dir.join(format!("{}.sh", name)),
+            format!("echo \"{} $*\" >> wrapped\nexec \"$@\"\n", name),
+        )
+        .unwrap();
+    }
+    fs::write(
+        dir.join("asmbl.lua"),
+        "task{target = 'plain', run = {'touch', '$@'}}\n\
+         task{target = 'special', run = {'touch', '$@'}, wrapper = {'sh', 'inner.sh'}}\n",
+    )
+    .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_asmbl-cli"))
+        .args(["--wrapper", "sh outer.sh"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("plain").exists() && dir.join("special").exists());
+
+    // The invocation's wrapper runs the task's own.
+    let wrapped = fs::read_to_string(dir.join("wrapped")).unwrap();
+    let mut wrapped: Vec<_> = wrapped.lines().collect();
+    wrapped.sort_unstable();
+    assert_eq!(
+        wrapped,
+        [
+            "inner touch special",
+            "outer sh inner.sh touch special",
+            "outer touch plain",
+        ]
+    );
+}
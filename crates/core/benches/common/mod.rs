@@ -0,0 +1,31 @@
+use std::path;
+
+use asmbl_core::{PrerequisiteSpec, Recipe, TaskOptions, TaskSpec, Unit, UnitBuilder};
+
+// A monorepo-ish graph: a layer of sources followed by tasks which each
+// consume a few of those which came before them.
+pub fn units(context: &path::Path, count: usize) -> Vec<(path::PathBuf, Unit)> {
+    let components: Vec<_> = context.components().collect();
+    let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+    let mut handles = vec![];
+    for index in 0..count {
+        let consumes = if index < 1000 {
+            vec![]
+        } else {
+            (1..4)
+                .map(|step| PrerequisiteSpec::Handle(handles[(index * 7919 * step) % index]))
+                .collect()
+        };
+        let spec = TaskSpec {
+            consumes,
+            depends_on: vec![],
+            not_before: vec![],
+            env: vec![],
+            recipe: Some(Recipe::parse("true").unwrap()),
+            options: TaskOptions::default(),
+            origin: None,
+        };
+        handles.extend(builder.add_task(vec![format!("t{}", index)], spec).unwrap());
+    }
+    vec![(context.to_path_buf(), builder.unit())]
+}
@@ -0,0 +1,39 @@
+use std::path;
+
+use asmbl_core::Relativiser;
+
+// Roughly what a depfile for a single translation unit looks like: a
+// handful of local headers and a long tail of system ones, most of which
+// every other depfile names too.
+fn depfile_paths() -> Vec<path::PathBuf> {
+    let mut paths = vec![];
+    for round in 0..20 {
+        for header in 0..50 {
+            paths.push(path::PathBuf::from(format!(
+                "/usr/include/c++/9/bits/header{}.h",
+                header
+            )));
+        }
+        paths.push(path::PathBuf::from(format!("../include/local{}.h", round)));
+        paths.push(path::PathBuf::from(format!("src{}.cpp", round)));
+    }
+    paths
+}
+
+fn relativise_depfile(c: &mut criterion::Criterion) {
+    let paths = depfile_paths();
+    c.bench_function("relativise depfile", |b| {
+        b.iter(|| {
+            let relativiser = Relativiser::new(
+                path::Path::new("/home/user/project"),
+                path::PathBuf::from("/home/user/project/lib/component"),
+            );
+            for path in paths.iter() {
+                criterion::black_box(relativiser.relativise(path).unwrap());
+            }
+        })
+    });
+}
+
+criterion::criterion_group!(benches, relativise_depfile);
+criterion::criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! How long building the graph of a 20,000 task unit takes.
+//!
+//! Keeping downstream edges in one arena rather than a `Vec` per task
+//! bought a little time when it went in, taking the median on the same
+//! machine, run twice either side of the change:
+//!
+//!   before the arena: 24.8 ms, 22.4 ms
+//!   after the arena:  23.0 ms, 21.1 ms
+
+use std::path;
+
+use asmbl_core::TaskList;
+
+mod common;
+
+fn new_task_list(c: &mut criterion::Criterion) {
+    let context = path::Path::new("/project");
+    c.bench_function("new task list", |b| {
+        b.iter_batched(
+            || common::units(context, 20_000),
+            |units| TaskList::new(context, path::Path::new("../out"), units).unwrap(),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion::criterion_group!(benches, new_task_list);
+criterion::criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! How much memory building the graph of `task_list` takes at its peak,
+//! over and above the units it's built from, which criterion can't say.
+//!
+//! The downstream edge arena saved none when it went in; tasks, their
+//! targets and their upstream prerequisites are still allocated one by one:
+//!
+//!   before the arena: 12928 KiB
+//!   after the arena:  12928 KiB
+
+use std::{
+    alloc, path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use asmbl_core::TaskList;
+
+mod common;
+
+// Keeps count of the bytes allocated, and the most there have been at once.
+struct Counting;
+
+static NOW: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl alloc::GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+        let now = NOW.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(now, Ordering::SeqCst);
+        alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+        NOW.fetch_sub(layout.size(), Ordering::SeqCst);
+        alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let context = path::Path::new("/project");
+    let units = common::units(context, 20_000);
+    let before = NOW.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+
+    let tasks = TaskList::new(context, path::Path::new("../out"), units).unwrap();
+    let peak = PEAK.load(Ordering::SeqCst) - before;
+    println!("new task list: peak {} KiB", peak / 1024);
+    drop(tasks);
+}
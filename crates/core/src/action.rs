@@ -0,0 +1,69 @@
+use std::{fs, io, path};
+
+use sha2::Digest;
+
+use crate::config::Config;
+use crate::state;
+use crate::{Edge, Prerequisite, Task, TaskList};
+
+impl Task {
+    /// Digests everything bearing upon what the task produces: its command
+    /// and environment, and the content of whatever it depends upon, so
+    /// that its targets may be reused whenever it's run alike. Tasks whose
+    /// targets aren't down to that alone, e.g. those which always run or
+    /// produce files they don't name, have none.
+    pub fn action(&self, tasks: &TaskList, config: &Config) -> io::Result<Option<String>> {
+        let options = &self.options;
+        let opaque = options.always || !options.produces.is_empty() || options.result.is_some();
+        if !self.has_recipe() || options.phony || opaque {
+            return Ok(None);
+        }
+
+        let (command, env) = state::identity(self, config);
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(command.as_bytes());
+        hasher.update(env.as_bytes());
+
+        let mut upstream = vec![];
+        for (prerequisite, edge) in self.upstream.iter().zip(self.edges.iter()) {
+            match (prerequisite, edge) {
+                (_, Edge::NotBefore) => {}
+                (Prerequisite::Named(path, _), _) => upstream.push(path.clone()),
+                (Prerequisite::Handle(handle), _) => {
+                    upstream.extend(tasks.get(*handle).targets.iter().cloned())
+                }
+            }
+        }
+        for path in self.inputs().iter().chain(upstream.iter()) {
+            hasher.update([0]);
+            hasher.update(path.to_string_lossy().as_bytes());
+            match content(&config.context_dir.join(path))? {
+                Some(digest) => hasher.update(digest),
+                // There's no telling what a directory holds.
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        ))
+    }
+}
+
+// The digest of a file's content, with missing files, e.g. optional inputs,
+// standing apart from every other; directories have none.
+fn content(path: &path::Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => return Ok(None),
+        Ok(_) => {}
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some(vec![])),
+        Err(err) => return Err(err),
+    }
+    let mut hasher = sha2::Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(Some(hasher.finalize().to_vec()))
+}
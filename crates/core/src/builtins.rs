@@ -0,0 +1,100 @@
+use std::path;
+
+use crate::env::EnvSpec;
+use crate::recipe::{Recipe, RecipeParseError};
+use crate::unit::{PrerequisiteSpec, TaskOptions, TaskSpec};
+
+// Honoured by the fetch built-in, so passed through despite env clearing.
+const PROXY_ENV: &[&str] = &[
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+    "NO_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "all_proxy",
+    "no_proxy",
+];
+
+fn escape(arg: &str) -> String {
+    arg.replace('$', "$$")
+}
+
+/// A task downloading `url` to its single target, failing unless the
+/// downloaded content has the given SHA-256 digest.
+pub fn fetch(url: &str, sha256: &str) -> Result<TaskSpec<path::PathBuf>, RecipeParseError> {
+    Ok(TaskSpec {
+        consumes: vec![],
+        depends_on: vec![],
+        not_before: vec![],
+        env: PROXY_ENV
+            .iter()
+            .map(|name| EnvSpec::inherit(name.to_string()))
+            .collect(),
+        recipe: Some(Recipe::builtin(
+            "fetch",
+            vec![escape(url), escape(sha256), String::from("$@[0]")],
+        )?),
+        options: TaskOptions::default(),
+        origin: None,
+    })
+}
+
+/// A task unpacking a tar, gzipped tar or zip archive into the directory
+/// named by its single target.
+pub fn extract(
+    archive: PrerequisiteSpec<path::PathBuf>,
+) -> Result<TaskSpec<path::PathBuf>, RecipeParseError> {
+    Ok(TaskSpec {
+        consumes: vec![archive],
+        depends_on: vec![],
+        not_before: vec![],
+        env: vec![],
+        recipe: Some(Recipe::builtin(
+            "extract",
+            vec![String::from("$<[0]"), String::from("$@[0]")],
+        )?),
+        options: TaskOptions::default(),
+        origin: None,
+    })
+}
+
+/// A task running `tool` with `args`, e.g. `--version`, every build, writing
+/// what it said to its single target only should that have changed, so that
+/// whatever consumes the target is rebuilt when the tool is, even though its
+/// path and modification time look the same.
+pub fn probe(tool: &str, args: &[String]) -> Result<TaskSpec<path::PathBuf>, RecipeParseError> {
+    let mut recipe = vec![String::from("$@[0]"), escape(tool)];
+    recipe.extend(args.iter().map(|arg| escape(arg)));
+    Ok(TaskSpec {
+        consumes: vec![],
+        depends_on: vec![],
+        not_before: vec![],
+        env: vec![],
+        recipe: Some(Recipe::builtin("probe", recipe)?),
+        options: TaskOptions {
+            always: true,
+            restat: true,
+            ..TaskOptions::default()
+        },
+        origin: None,
+    })
+}
+
+/// A task grouping `files`, e.g. the headers of a header-only library, which
+/// is named by its targets and never runs.
+pub fn group(files: Vec<PrerequisiteSpec<path::PathBuf>>) -> TaskSpec<path::PathBuf> {
+    TaskSpec {
+        consumes: vec![],
+        depends_on: files,
+        not_before: vec![],
+        env: vec![],
+        recipe: None,
+        options: TaskOptions {
+            phony: true,
+            group: true,
+            ..TaskOptions::default()
+        },
+        origin: None,
+    }
+}
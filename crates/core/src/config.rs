@@ -0,0 +1,105 @@
+use std::{collections, path, process};
+
+use crate::env::EnvSpec;
+use crate::hooks::Hook;
+use crate::separator::Separator;
+
+// Commands cmd carries out itself, having no program of their own.
+const CMD_BUILTINS: &[&str] = &[
+    "copy", "del", "dir", "echo", "erase", "md", "mkdir", "mklink", "move", "rd", "ren", "rename",
+    "rmdir", "type",
+];
+
+#[derive(Debug, failure::Fail)]
+pub enum IsolationError {
+    #[fail(display = "Network access can't be denied to tasks on this platform.")]
+    NetworkUnsupported,
+}
+
+/// Invocation-wide settings applied when preparing tasks.
+#[derive(Debug)]
+pub struct Config {
+    /// Absolute path of the directory containing the root unit.
+    pub context_dir: path::PathBuf,
+    /// Absolute path of the directory below which targets are generated.
+    pub target_dir: path::PathBuf,
+    /// Start every task from an empty environment, unless the task opts out.
+    pub clear_env: bool,
+    /// Refuse to look commands up on the PATH of tasks which don't inherit
+    /// it, so that units say where their tools come from.
+    pub strict_path: bool,
+    /// Whether what tasks write ends up on a terminal, in which case they're
+    /// told so unless they opt out.
+    pub terminal: bool,
+    /// Environment given to every task, beneath that of its unit and its own.
+    pub env: Vec<EnvSpec>,
+    /// Command prefixed to every task, outside of any per-task wrapper.
+    pub wrapper: Vec<String>,
+    /// Program used to run tasks which declare a container image.
+    pub container_runtime: String,
+    /// Location of the bash executable used by the Git-Bash launcher.
+    pub git_bash: path::PathBuf,
+    /// Commands built into `shell` rather than found on PATH, which are run
+    /// through it with `/C`. Only Windows has any by default.
+    pub shell_builtins: Vec<String>,
+    /// The shell which carries out `shell_builtins`.
+    pub shell: path::PathBuf,
+    /// Executable implementing the built-in recipes, usually asmbl itself.
+    pub builtin_exe: path::PathBuf,
+    /// Directory shared by every build on the machine into which the tools
+    /// units pin are downloaded.
+    pub tool_store: path::PathBuf,
+    /// How the paths in expanded recipes are separated.
+    pub separator: Separator,
+    /// Values recipes may refer to by name, e.g. `$jobs`.
+    pub variables: collections::BTreeMap<String, String>,
+    /// Hooks run after the build, before those declared by units.
+    pub hooks: Vec<Hook>,
+    /// Directories every sandboxed task may read anything within, besides
+    /// those its unit allows.
+    pub sandbox_allow: Vec<path::PathBuf>,
+    /// Denies network access to a task's command, for those which don't
+    /// ask for it; left to whatever runs the tasks to provide.
+    pub deny_network: fn(&mut process::Command) -> Result<(), IsolationError>,
+}
+
+// The per-user cache directory the platform conventionally offers, falling
+// back on the temporary directory.
+fn tool_store() -> path::PathBuf {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(path::PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(path::PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| path::Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    cache.join("asmbl").join("tools")
+}
+
+impl Config {
+    pub fn new(context_dir: path::PathBuf, target_dir: path::PathBuf) -> Self {
+        Self {
+            context_dir,
+            target_dir,
+            clear_env: true,
+            strict_path: false,
+            terminal: false,
+            env: vec![],
+            wrapper: vec![],
+            container_runtime: String::from("docker"),
+            git_bash: path::PathBuf::from(r"C:\Program Files\Git\bin\bash.exe"),
+            shell_builtins: match cfg!(windows) {
+                true => CMD_BUILTINS.iter().map(|cmd| cmd.to_string()).collect(),
+                false => vec![],
+            },
+            shell: std::env::var_os("COMSPEC")
+                .map(path::PathBuf::from)
+                .unwrap_or_else(|| path::PathBuf::from(r"C:\Windows\System32\cmd.exe")),
+            builtin_exe: std::env::current_exe().unwrap_or_else(|_| path::PathBuf::from("asmbl")),
+            tool_store: tool_store(),
+            separator: Separator::Native,
+            variables: collections::BTreeMap::new(),
+            hooks: vec![],
+            sandbox_allow: vec![],
+            deny_network: |_| Err(IsolationError::NetworkUnsupported),
+        }
+    }
+}
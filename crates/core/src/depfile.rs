@@ -0,0 +1,135 @@
+use std::{fs, io, path, sync};
+
+use crate::make;
+use crate::recipe::{self, ArgElement, Variable};
+use crate::relativiser;
+use crate::targets::Targets;
+
+#[derive(Debug, failure::Fail)]
+pub enum DepfileError {
+    #[fail(display = "Invalid depfile '{}'.", _0)]
+    BadSpec(String, #[fail(cause)] recipe::RecipeParseError),
+    #[fail(
+        display = "Depfile '{}' may only name the task's targets, as $@ or $@[n].",
+        _0
+    )]
+    BadVariable(String),
+    #[fail(
+        display = "Depfile '{}' names target {}, which the task doesn't have.",
+        _0, _1
+    )]
+    NoSuchTarget(String, usize),
+    #[fail(display = "Failed to read depfile '{}'.", _0)]
+    Io(String, #[fail(cause)] io::Error),
+    #[fail(display = "Failed to parse depfile '{}'.", _0)]
+    Parse(String, #[fail(cause)] make::StreamError),
+    #[fail(display = "Depfile '{}' lists a file above the root directory.", _0)]
+    Relativise(String, #[fail(cause)] relativiser::Error),
+}
+
+/// Spells out where a task writes its depfile, given its targets.
+pub fn resolve(spec: &str, targets: &Targets) -> Result<sync::Arc<path::Path>, DepfileError> {
+    let elements =
+        recipe::parse_elements(spec).map_err(|err| DepfileError::BadSpec(spec.to_string(), err))?;
+
+    let mut depfile = String::new();
+    for element in elements {
+        let target = match element {
+            ArgElement::Str(s) => {
+                depfile.push_str(&s);
+                continue;
+            }
+            ArgElement::Var(Variable::Target(index)) => targets
+                .iter()
+                .nth(index)
+                .ok_or_else(|| DepfileError::NoSuchTarget(spec.to_string(), index))?,
+            // Several targets would make for several depfiles.
+            ArgElement::Var(Variable::Targets) if targets.iter().count() == 1 => &targets[0],
+            _ => return Err(DepfileError::BadVariable(spec.to_string())),
+        };
+        depfile.push_str(&target.to_string_lossy());
+    }
+    Ok(sync::Arc::from(path::Path::new(&depfile)))
+}
+
+/// The files a depfile lists its targets as depending upon, relative to the
+/// context, which is where tasks run. A missing depfile, as before its task
+/// has first run, lists nothing.
+pub fn read(
+    depfile: &path::Path,
+    context_dir: &path::Path,
+) -> Result<Vec<path::PathBuf>, DepfileError> {
+    let name = || depfile.to_string_lossy().into_owned();
+    let file = match fs::File::open(depfile) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(DepfileError::Io(name(), err)),
+    };
+
+    let relativiser = relativiser::Relativiser::new(context_dir, context_dir.to_path_buf());
+    let mut prerequisites = vec![];
+    for pair in make::stream(io::BufReader::new(file)) {
+        let (_, prerequisite) = pair.map_err(|err| DepfileError::Parse(name(), err))?;
+        let prerequisite = relativiser
+            .relativise(path::Path::new(&prerequisite))
+            .map_err(|err| DepfileError::Relativise(name(), err))?;
+        if !prerequisites.contains(&prerequisite) {
+            prerequisites.push(prerequisite);
+        }
+    }
+    Ok(prerequisites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn can_name_depfiles_after_targets() {
+        let targets = Targets::Multi(vec![
+            sync::Arc::from(path::Path::new("../out/a.o")),
+            sync::Arc::from(path::Path::new("../out/a.lst")),
+        ]);
+        assert_eq!(
+            resolve("$@[0].d", &targets).unwrap().as_ref(),
+            path::Path::new("../out/a.o.d")
+        );
+        assert!(resolve("$@.d", &targets).is_err());
+        assert!(resolve("$<.d", &targets).is_err());
+    }
+
+    #[test]
+    fn files_listed_are_relative_to_the_context() {
+        let context = TempDir::new("depfile-read");
+        let depfile = context.join("a.o.d");
+        assert_eq!(
+            read(&depfile, &context).unwrap(),
+            Vec::<path::PathBuf>::new()
+        );
+
+        let header = context.join("include/a.h");
+        // Each file is listed once, however often the depfile names it.
+        fs::write(&depfile, format!("a.o: a.c {} a.c\n", header.display())).unwrap();
+        assert_eq!(
+            read(&depfile, &context).unwrap(),
+            vec![
+                path::PathBuf::from("a.c"),
+                path::PathBuf::from("include/a.h")
+            ]
+        );
+
+        let root = "../".repeat(context.components().count());
+        fs::write(&depfile, format!("a.o: {}../a.h\n", root)).unwrap();
+        match read(&depfile, &context) {
+            Err(err @ DepfileError::Relativise(..)) => assert_eq!(
+                err.to_string(),
+                format!(
+                    "Depfile '{}' lists a file above the root directory.",
+                    depfile.display()
+                )
+            ),
+            read => panic!("{:?}", read),
+        }
+    }
+}
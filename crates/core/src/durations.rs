@@ -0,0 +1,60 @@
+use std::{collections, io, path, time};
+
+use asmbl_utils::io::{records, StateFile};
+
+use crate::Task;
+
+/// How long the task producing each target took when it last ran, kept in
+/// `.asmbl/durations` under the target directory.
+pub struct Durations {
+    file: StateFile,
+    durations: collections::HashMap<String, time::Duration>,
+}
+
+impl Durations {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "durations");
+
+        // Each record holds a duration in milliseconds and a target path.
+        let durations = records(&file.read()?, 2)
+            .filter_map(|record| {
+                let millis = record[0].parse().ok()?;
+                Some((record[1].to_string(), time::Duration::from_millis(millis)))
+            })
+            .collect();
+
+        Ok(Self { file, durations })
+    }
+
+    pub fn get(&self, task: &Task) -> Option<time::Duration> {
+        self.durations.get(&task.key()).cloned()
+    }
+
+    pub fn record(&mut self, task: &Task, duration: time::Duration) {
+        self.durations.insert(task.key(), duration);
+    }
+
+    /// The mean of all recorded durations, if there are any.
+    pub fn mean(&self) -> Option<time::Duration> {
+        if self.durations.is_empty() {
+            None
+        } else {
+            Some(self.durations.values().sum::<time::Duration>() / self.durations.len() as u32)
+        }
+    }
+
+    /// How long the task is expected to take: as long as it did last time,
+    /// or, having never run, as long as tasks typically do.
+    pub fn estimate(&self, task: &Task) -> Option<time::Duration> {
+        self.get(task).or_else(|| self.mean())
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, duration) in self.durations.iter() {
+            content.push_str(&format!("{}\t{}\n", duration.as_millis(), target));
+        }
+
+        self.file.write(&content)
+    }
+}
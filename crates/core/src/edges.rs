@@ -0,0 +1,53 @@
+use crate::TaskHandle;
+
+/// The tasks downstream of every task, held end to end in a single
+/// allocation rather than a list apiece.
+#[derive(Debug, Default)]
+pub(crate) struct Edges {
+    // Where each task's downstream tasks start, with a final entry marking
+    // the end of the last task's.
+    offsets: Vec<usize>,
+    handles: Vec<TaskHandle>,
+}
+
+impl Edges {
+    /// Gathers `(upstream, downstream)` pairs of indices amongst `count`
+    /// tasks, keeping their order for each upstream task.
+    pub(crate) fn new(count: usize, pairs: &[(usize, usize)]) -> Self {
+        let mut offsets = vec![0; count + 1];
+        for (upstream, _) in pairs.iter() {
+            offsets[upstream + 1] += 1;
+        }
+        for index in 0..count {
+            offsets[index + 1] += offsets[index];
+        }
+
+        let mut next = offsets.clone();
+        let mut handles = vec![TaskHandle::new(0); pairs.len()];
+        for (upstream, downstream) in pairs.iter() {
+            handles[next[*upstream]] = TaskHandle::new(*downstream);
+            next[*upstream] += 1;
+        }
+
+        Self { offsets, handles }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &[TaskHandle] {
+        &self.handles[self.offsets[index]..self.offsets[index + 1]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_gather_edges() {
+        let edges = Edges::new(4, &[(2, 3), (0, 1), (0, 2), (2, 1)]);
+        let get = |index| -> Vec<_> { edges.get(index).iter().map(|h| h.index).collect() };
+        assert_eq!(get(0), vec![1, 2]);
+        assert_eq!(get(1), vec![]);
+        assert_eq!(get(2), vec![3, 1]);
+        assert_eq!(get(3), vec![]);
+    }
+}
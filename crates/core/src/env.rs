@@ -1,10 +1,14 @@
-#[derive(Debug)]
+use std::{ffi, path};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EnvSpecValue {
     INHERIT,
     DEFINE(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EnvSpec {
     name: String,
     value: EnvSpecValue,
@@ -25,6 +29,14 @@ impl EnvSpec {
         }
     }
 
+    /// Parses `NAME=VALUE` as a definition and a lone `NAME` as inherited.
+    pub fn parse(s: &str) -> Self {
+        match s.split_once('=') {
+            Some((name, value)) => Self::define(name.to_string(), value.to_string()),
+            None => Self::inherit(s.to_string()),
+        }
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -33,3 +45,143 @@ impl EnvSpec {
         &self.value
     }
 }
+
+/// Resolves each spec to its value, dropping inherited variables which
+/// aren't set.
+pub fn resolve<'a, I>(env: I) -> Vec<(&'a str, ffi::OsString)>
+where
+    I: IntoIterator<Item = &'a EnvSpec>,
+{
+    env.into_iter()
+        .filter_map(|env| {
+            let value = match env.value() {
+                EnvSpecValue::INHERIT => std::env::var_os(env.name()),
+                EnvSpecValue::DEFINE(value) => Some(ffi::OsString::from(value)),
+            };
+            value.map(|v| (env.name(), v))
+        })
+        .collect()
+}
+
+/// The variables given to every task so that helper scripts can find their
+/// way around: `ASMBL_CONTEXT_DIR` and `ASMBL_TARGET_DIR` hold the absolute
+/// roots, and `ASMBL_TASK_TARGET0` the task's first target (if it has any)
+/// as the recipe sees it.
+pub fn derived(config: &Config, target: Option<&path::Path>) -> Vec<(&'static str, ffi::OsString)> {
+    let mut derived = vec![
+        ("ASMBL_CONTEXT_DIR", config.context_dir.as_os_str().into()),
+        ("ASMBL_TARGET_DIR", config.target_dir.as_os_str().into()),
+    ];
+    if let Some(target) = target {
+        derived.push(("ASMBL_TASK_TARGET0", target.as_os_str().into()));
+    }
+    derived
+}
+
+/// What tasks are told of the terminal their output ends up on, so that
+/// tools piped through asmbl keep colouring their diagnostics; nothing
+/// unless `config.terminal`. Colour is only forced where `NO_COLOR` isn't
+/// set.
+pub fn terminal(config: &Config) -> Vec<(&'static str, ffi::OsString)> {
+    if !config.terminal {
+        return vec![];
+    }
+    let mut terminal: Vec<_> = ["TERM", "COLORTERM", "NO_COLOR"]
+        .iter()
+        .filter_map(|name| std::env::var_os(name).map(|value| (*name, value)))
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    if terminal.iter().all(|(name, _)| *name != "NO_COLOR") {
+        terminal.push(("CLICOLOR_FORCE", ffi::OsString::from("1")));
+    }
+    terminal
+}
+
+/// Layers `env` over `defaults`, each spec replacing any default of the
+/// same name.
+pub fn merge<'a>(defaults: &'a [EnvSpec], env: &'a [EnvSpec]) -> Vec<&'a EnvSpec> {
+    defaults
+        .iter()
+        .filter(|default| env.iter().all(|spec| spec.name() != default.name()))
+        .chain(env.iter())
+        .collect()
+}
+
+/// Whether the named variable probably holds a credential, going by the
+/// `_`-separated words making up its name, so that e.g. `AUTHOR` doesn't.
+pub fn looks_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    let words: Vec<_> = name.split('_').collect();
+    // A word may be plural, as in `GOOGLE_APPLICATION_CREDENTIALS`.
+    let same = |word: &str, secret: &str| word == secret || word.strip_suffix('S') == Some(secret);
+    [
+        &["SECRET"][..],
+        &["TOKEN"],
+        &["PASSWORD"],
+        &["PASSWD"],
+        &["CREDENTIAL"],
+        &["PRIVATE"],
+        &["API", "KEY"],
+        &["APIKEY"],
+        &["AUTH"],
+    ]
+    .iter()
+    .any(|secret| {
+        words.windows(secret.len()).any(|words| {
+            words
+                .iter()
+                .zip(secret.iter())
+                .all(|(word, secret)| same(word, secret))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_spec() {
+        assert_eq!(EnvSpec::parse("PATH"), EnvSpec::inherit("PATH".to_string()));
+        assert_eq!(
+            EnvSpec::parse("CC=gcc -m32"),
+            EnvSpec::define("CC".to_string(), "gcc -m32".to_string())
+        );
+    }
+
+    #[test]
+    fn can_merge_specs() {
+        let defaults = vec![EnvSpec::parse("PATH"), EnvSpec::parse("CC=gcc")];
+        let env = vec![EnvSpec::parse("CC=clang"), EnvSpec::parse("HOME")];
+        assert_eq!(merge(&defaults, &env), vec![&defaults[0], &env[0], &env[1]]);
+    }
+
+    #[test]
+    fn only_tasks_writing_to_a_terminal_are_told_of_it() {
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        assert!(terminal(&config).is_empty());
+
+        config.terminal = true;
+        let forced = terminal(&config)
+            .iter()
+            .any(|(name, _)| *name == "CLICOLOR_FORCE");
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        assert_eq!(forced, !no_color);
+    }
+
+    #[test]
+    fn can_spot_secrets() {
+        assert!(looks_secret("GITHUB_TOKEN"));
+        assert!(looks_secret("aws_secret_access_key"));
+        assert!(looks_secret("NPM_API_KEY"));
+        assert!(looks_secret("GOOGLE_APPLICATION_CREDENTIALS"));
+        assert!(looks_secret("AUTH"));
+        assert!(!looks_secret("PATH"));
+        assert!(!looks_secret("CC"));
+        // Only whole words count.
+        assert!(!looks_secret("AUTHOR"));
+        assert!(!looks_secret("GIT_AUTHOR_NAME"));
+        assert!(!looks_secret("TOKENIZER_THREADS"));
+        assert!(!looks_secret("KEYBOARD_API"));
+    }
+}
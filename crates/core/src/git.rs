@@ -0,0 +1,269 @@
+use std::{collections, ffi, fs, io, path, process, time};
+
+use crate::stat::{FileSystem, Stat};
+
+#[derive(Debug, failure::Fail)]
+pub enum GitError {
+    #[fail(display = "Failed to run git.")]
+    Spawn(#[fail(cause)] io::Error),
+    #[fail(display = "'git {}' failed: {}", _0, _1)]
+    Command(String, String),
+    #[fail(display = "Unable to read the git index {:?}.", _0)]
+    Io(path::PathBuf, #[fail(cause)] io::Error),
+    #[fail(display = "Unsupported or corrupt git index {:?}.", _0)]
+    BadIndex(path::PathBuf),
+}
+
+/// Modification times taken from the git index rather than the file-system.
+///
+/// The index already records stat information for every tracked file, so
+/// only files git reports as modified, or which it doesn't track at all,
+/// need to be stat'd individually. The index is read as git left it; it is
+/// never refreshed, nor written to.
+pub struct GitIndex {
+    context_dir: path::PathBuf,
+    // Location of the context directory relative to the work-tree root.
+    prefix: path::PathBuf,
+    times: collections::HashMap<Vec<u8>, time::SystemTime>,
+}
+
+fn git(dir: &path::Path, args: &[&str]) -> Result<Vec<u8>, GitError> {
+    let output = process::Command::new("git")
+        .current_dir(dir)
+        .arg("--no-optional-locks")
+        .args(args)
+        .stderr(process::Stdio::piped())
+        .output()
+        .map_err(GitError::Spawn)?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(GitError::Command(
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+impl GitIndex {
+    pub fn load(context_dir: &path::Path) -> Result<Self, GitError> {
+        let locations = git(
+            context_dir,
+            &["rev-parse", "--git-path", "index", "--show-prefix"],
+        )?;
+        let locations = String::from_utf8_lossy(&locations);
+        let mut lines = locations.lines();
+        let index = context_dir.join(lines.next().unwrap_or_default());
+        let prefix = path::PathBuf::from(lines.next().unwrap_or_default());
+
+        let content = fs::read(&index).map_err(|err| GitError::Io(index.clone(), err))?;
+        let mut times = parse_index(&content).ok_or_else(|| GitError::BadIndex(index.clone()))?;
+
+        // Without a refresh, entries which were merely touched are reported
+        // along with those whose content differs; either way they're stat'd.
+        // Only the context's own files are asked about, and where git has a
+        // file-system monitor it needn't look at those it knows are clean.
+        let modified = git(context_dir, &["diff-files", "--name-only", "-z", "--", "."])?;
+        for name in modified.split(|b| *b == 0) {
+            times.remove(name);
+        }
+
+        Ok(Self {
+            context_dir: context_dir.to_path_buf(),
+            prefix,
+            times,
+        })
+    }
+
+    // Converts a path relative to the context into one relative to the
+    // work-tree root, in the form git uses.
+    fn key(&self, path: &path::Path) -> Option<Vec<u8>> {
+        let mut components: Vec<&ffi::OsStr> = vec![];
+        for component in self.prefix.components().chain(path.components()) {
+            match component {
+                path::Component::Normal(c) => components.push(c),
+                path::Component::ParentDir => {
+                    components.pop()?;
+                }
+                path::Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        let components = components
+            .into_iter()
+            .map(|c| c.to_str())
+            .collect::<Option<Vec<_>>>()?;
+        Some(components.join("/").into_bytes())
+    }
+}
+
+impl Stat for GitIndex {
+    fn modified(&self, path: &path::Path) -> io::Result<time::SystemTime> {
+        match self.key(path).and_then(|key| self.times.get(&key)) {
+            Some(time) => Ok(*time),
+            None => FileSystem.modified(&self.context_dir.join(path)),
+        }
+    }
+}
+
+fn read_u32(content: &[u8], offset: usize) -> Option<u32> {
+    let bytes = content.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u16(content: &[u8], offset: usize) -> Option<u16> {
+    let bytes = content.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Git's variable-length offset encoding, as used by index version 4.
+fn read_varint(content: &[u8], offset: &mut usize) -> Option<usize> {
+    let mut byte = *content.get(*offset)?;
+    *offset += 1;
+    let mut value = usize::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = *content.get(*offset)?;
+        *offset += 1;
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+    }
+    Some(value)
+}
+
+// Extracts the recorded mtime of every entry in an index file; see
+// Documentation/gitformat-index.txt in the git sources.
+fn parse_index(content: &[u8]) -> Option<collections::HashMap<Vec<u8>, time::SystemTime>> {
+    const ENTRY_HEADER: usize = 62;
+    const EXTENDED: u16 = 0x4000;
+
+    if content.get(0..4)? != b"DIRC" {
+        return None;
+    }
+    let version = read_u32(content, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = read_u32(content, 8)?;
+
+    let mut times = collections::HashMap::with_capacity(count as usize);
+    let mut offset = 12;
+    let mut previous: Vec<u8> = vec![];
+    for _ in 0..count {
+        let start = offset;
+        let seconds = read_u32(content, offset + 8)?;
+        let nanoseconds = read_u32(content, offset + 12)?;
+        let flags = read_u16(content, offset + 60)?;
+        offset += ENTRY_HEADER;
+        if version >= 3 && flags & EXTENDED != 0 {
+            offset += 2;
+        }
+
+        let name = if version == 4 {
+            let strip = read_varint(content, &mut offset)?;
+            let length = content.get(offset..)?.iter().position(|b| *b == 0)?;
+            let mut name = previous.get(..previous.len().checked_sub(strip)?)?.to_vec();
+            name.extend_from_slice(&content[offset..offset + length]);
+            offset += length + 1;
+            name
+        } else {
+            let length = content.get(offset..)?.iter().position(|b| *b == 0)?;
+            let name = content[offset..offset + length].to_vec();
+            // Entries are NUL padded to a multiple of eight bytes.
+            offset = start + ((offset - start + length + 8) & !7);
+            name
+        };
+
+        let time = time::UNIX_EPOCH
+            + time::Duration::new(u64::from(seconds), nanoseconds.min(999_999_999));
+        times.insert(name.clone(), time);
+        previous = name;
+    }
+
+    Some(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use asmbl_utils::testing::TempDir;
+
+    fn run(dir: &path::Path, args: &[&str]) {
+        let status = process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=asmbl", "-c", "user.email=asmbl@localhost"])
+            .args(args)
+            .stdout(process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    fn write(file: &path::Path, content: &str, seconds: u64) -> time::SystemTime {
+        let time = time::UNIX_EPOCH + time::Duration::from_secs(seconds);
+        fs::write(file, content).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(file)
+            .and_then(|file| file.set_modified(time))
+            .unwrap();
+        time
+    }
+
+    #[test]
+    fn can_read_varint() {
+        let mut offset = 0;
+        assert_eq!(read_varint(&[0x05], &mut offset), Some(5));
+        assert_eq!(offset, 1);
+
+        let mut offset = 0;
+        assert_eq!(read_varint(&[0x80, 0x00], &mut offset), Some(128));
+        assert_eq!(offset, 2);
+    }
+
+    #[test]
+    fn can_make_key() {
+        let index = GitIndex {
+            context_dir: path::PathBuf::from("/repo/project"),
+            prefix: path::PathBuf::from("project/"),
+            times: collections::HashMap::new(),
+        };
+        assert_eq!(
+            index.key(path::Path::new("src/a.c")),
+            Some(b"project/src/a.c".to_vec())
+        );
+        assert_eq!(index.key(path::Path::new("../b.c")), Some(b"b.c".to_vec()));
+        assert_eq!(index.key(path::Path::new("../../c.c")), None);
+    }
+
+    #[test]
+    fn only_modified_and_untracked_files_are_stated() {
+        let repo = TempDir::new("git-index");
+        let context = repo.join("project");
+        fs::create_dir(&context).unwrap();
+        run(&repo, &["init", "-q"]);
+
+        let clean = write(&context.join("a.c"), "a", 1_000_000);
+        write(&context.join("b.c"), "b", 1_000_000);
+        run(&repo, &["add", "."]);
+        run(&repo, &["commit", "-q", "-m", "Initial"]);
+
+        let modified = write(&context.join("b.c"), "bb", 2_000_000);
+        let untracked = write(&context.join("c.c"), "c", 3_000_000);
+
+        let index_file = repo.join(".git").join("index");
+        let before = fs::read(&index_file).unwrap();
+        let index = GitIndex::load(&context).unwrap();
+        assert_eq!(fs::read(&index_file).unwrap(), before);
+
+        assert!(index.times.contains_key(&b"project/a.c"[..]));
+        assert!(!index.times.contains_key(&b"project/b.c"[..]));
+
+        // Clean files' times come from the index alone.
+        write(&context.join("a.c"), "a", 4_000_000);
+        let stat = |name| index.modified(path::Path::new(name));
+        assert_eq!(stat("a.c").unwrap(), clean);
+        assert_eq!(stat("b.c").unwrap(), modified);
+        assert_eq!(stat("c.c").unwrap(), untracked);
+        assert_eq!(stat("d.c").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,61 @@
+use std::path;
+
+use crate::{Prerequisite, TaskHandle, TaskList};
+
+/// How a task came to depend upon something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Consumes,
+    DependsOn,
+    NotBefore,
+    /// Found in the task's depfile when it last ran.
+    Discovered,
+}
+
+impl Edge {
+    /// A stable name for the kind of edge, as it's spelt in units.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Consumes => "consumes",
+            Self::DependsOn => "depends_on",
+            Self::NotBefore => "not_before",
+            Self::Discovered => "discovered",
+        }
+    }
+}
+
+/// Something a task depends upon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upstream<'a> {
+    /// Another task.
+    Task(TaskHandle),
+    /// A file no task produces.
+    File(&'a path::Path),
+}
+
+impl TaskList {
+    /// Every task, upstream tasks first.
+    pub fn handles(&self) -> impl Iterator<Item = TaskHandle> {
+        (0..self.tasks.len()).map(TaskHandle::new)
+    }
+
+    /// The tasks depending directly upon the given task.
+    pub fn downstream(&self, handle: TaskHandle) -> &[TaskHandle] {
+        self.downstream.get(handle.index)
+    }
+
+    /// What the given task depends upon, and how.
+    pub fn upstream(&self, handle: TaskHandle) -> impl Iterator<Item = (Upstream<'_>, Edge)> {
+        let task = &self.tasks[handle.index];
+        task.upstream
+            .iter()
+            .zip(task.edges.iter())
+            .map(|(upstream, edge)| {
+                let upstream = match upstream {
+                    Prerequisite::Named(file, _) => Upstream::File(file),
+                    Prerequisite::Handle(handle) => Upstream::Task(*handle),
+                };
+                (upstream, *edge)
+            })
+    }
+}
@@ -0,0 +1,133 @@
+use std::{ffi, process};
+
+use crate::config::Config;
+use crate::env::{self, EnvSpec};
+use crate::recipe::{Recipe, RecipePrepareError};
+use crate::targets::Targets;
+use crate::unit::TaskOptions;
+
+/// When a hook runs, relative to the outcome of the build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookTrigger {
+    Success,
+    Failure,
+    Always,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Unknown hook trigger '{}'.", _0)]
+pub struct UnknownHookTrigger(String);
+
+impl std::str::FromStr for HookTrigger {
+    type Err = UnknownHookTrigger;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            "always" => Ok(Self::Always),
+            _ => Err(UnknownHookTrigger(s.to_string())),
+        }
+    }
+}
+
+/// A command run once the build is over, outside of the task graph.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    trigger: HookTrigger,
+    recipe: Recipe,
+    env: Vec<EnvSpec>,
+}
+
+impl Hook {
+    pub fn new(trigger: HookTrigger, recipe: Recipe, env: Vec<EnvSpec>) -> Self {
+        Self {
+            trigger,
+            recipe,
+            env,
+        }
+    }
+
+    /// Whether the hook should run after a build which did or didn't succeed.
+    pub fn applies(&self, success: bool) -> bool {
+        match self.trigger {
+            HookTrigger::Success => success,
+            HookTrigger::Failure => !success,
+            HookTrigger::Always => true,
+        }
+    }
+
+    /// Prepares the hook much as a task without targets or inputs, adding
+    /// `summary` to its environment.
+    pub fn prepare(
+        &self,
+        config: &Config,
+        summary: &[(&str, ffi::OsString)],
+    ) -> Result<process::Command, RecipePrepareError> {
+        let mut cmd = self.recipe.prepare(
+            &Targets::Multi(vec![]),
+            &[],
+            &env::merge(&config.env, &self.env),
+            &TaskOptions::default(),
+            config,
+        )?;
+        cmd.envs(summary.iter().map(|(name, value)| (name, value)));
+        Ok(cmd)
+    }
+
+    pub(crate) fn add_default_env(&mut self, defaults: &[EnvSpec]) {
+        self.env = env::merge(defaults, &self.env)
+            .into_iter()
+            .cloned()
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path;
+
+    #[test]
+    fn hooks_run_after_the_builds_they_ask_for() {
+        let hook = |trigger: &str| {
+            let trigger = trigger.parse().unwrap();
+            Hook::new(trigger, Recipe::parse("true").unwrap(), vec![])
+        };
+        assert!(hook("success").applies(true));
+        assert!(!hook("success").applies(false));
+        assert!(!hook("failure").applies(true));
+        assert!(hook("failure").applies(false));
+        assert!(hook("always").applies(true));
+        assert!(hook("always").applies(false));
+        assert!("sometimes".parse::<HookTrigger>().is_err());
+    }
+
+    #[test]
+    fn hooks_are_told_how_the_build_went() {
+        let config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        let recipe = Recipe::parse("true").unwrap();
+        let env = vec![EnvSpec::define(
+            String::from("CHANNEL"),
+            String::from("builds"),
+        )];
+        let mut hook = Hook::new(HookTrigger::Always, recipe, env);
+        // The hook's own variables win out over the unit's.
+        hook.add_default_env(&[
+            EnvSpec::define(String::from("CHANNEL"), String::from("general")),
+            EnvSpec::define(String::from("URL"), String::from("https://example.com")),
+        ]);
+
+        let summary = [("ASMBL_BUILD_STATUS", ffi::OsString::from("failure"))];
+        let cmd = hook.prepare(&config, &summary).unwrap();
+        let env: Vec<_> = cmd.get_envs().collect();
+        for (name, value) in &[
+            ("ASMBL_BUILD_STATUS", "failure"),
+            ("CHANNEL", "builds"),
+            ("URL", "https://example.com"),
+        ] {
+            let value = Some(ffi::OsStr::new(value));
+            assert!(env.contains(&(ffi::OsStr::new(name), value)), "{}", name);
+        }
+    }
+}
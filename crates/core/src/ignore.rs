@@ -0,0 +1,163 @@
+use std::{cell, collections, ffi, fs, path, rc};
+
+/// Which files the `.asmblignore` files of a context tell globs and watching
+/// to pass over. Each holds a glob pattern per line, relative to its own
+/// directory: a pattern without a `/`, e.g. `*.swp`, matches any file or
+/// directory of that name beneath it, and one ending in `/`, e.g. `vendor/`,
+/// only directories. Blank lines and those starting with `#` are skipped.
+pub struct Ignore {
+    root: path::PathBuf,
+    rules: cell::RefCell<collections::HashMap<path::PathBuf, rc::Rc<[Rule]>>>,
+}
+
+struct Rule {
+    pattern: glob::Pattern,
+    // Whether the pattern names a path from the ignore file's directory,
+    // rather than a name found anywhere beneath it.
+    anchored: bool,
+    dir_only: bool,
+}
+
+const FILE_NAME: &str = ".asmblignore";
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let anchored = line.contains('/');
+        let pattern = glob::Pattern::new(line.trim_start_matches('/')).ok()?;
+        Some(Self {
+            pattern,
+            anchored,
+            dir_only,
+        })
+    }
+
+    // Whether the rule matches the path made of `names`, relative to the
+    // ignore file, or any directory on the way to it.
+    fn matches(&self, names: &[&ffi::OsStr]) -> bool {
+        // Directories are those with something beneath them.
+        let last = if self.dir_only {
+            names.len() - 1
+        } else {
+            names.len()
+        };
+        if self.anchored {
+            let mut path = path::PathBuf::new();
+            names[..last].iter().any(|name| {
+                path.push(name);
+                self.pattern.matches_path_with(&path, crate::GLOB_OPTIONS)
+            })
+        } else {
+            names[..last].iter().any(|name| {
+                self.pattern
+                    .matches_path_with(path::Path::new(name), crate::GLOB_OPTIONS)
+            })
+        }
+    }
+}
+
+impl Ignore {
+    pub fn new(root: path::PathBuf) -> Self {
+        Self {
+            root,
+            rules: cell::RefCell::new(collections::HashMap::new()),
+        }
+    }
+
+    /// Whether `path`, absolute or relative to the root, lies beneath the
+    /// root and is ignored by an `.asmblignore` file on the way to it.
+    pub fn is_ignored(&self, path: &path::Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) if path.is_relative() => path,
+            Err(_) => return false,
+        };
+        let mut names = vec![];
+        for component in relative.components() {
+            match component {
+                path::Component::Normal(name) => names.push(name),
+                path::Component::CurDir => {}
+                _ => return false,
+            }
+        }
+
+        let mut dir = self.root.clone();
+        for start in 0..names.len() {
+            if self
+                .rules(&dir)
+                .iter()
+                .any(|rule| rule.matches(&names[start..]))
+            {
+                return true;
+            }
+            dir.push(names[start]);
+        }
+        false
+    }
+
+    /// The ignore files consulted so far, whether or not they exist, upon
+    /// which what's ignored depends.
+    pub fn files(&self) -> Vec<path::PathBuf> {
+        let mut files: Vec<_> = self
+            .rules
+            .borrow()
+            .keys()
+            .map(|dir| dir.join(FILE_NAME))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn rules(&self, dir: &path::Path) -> rc::Rc<[Rule]> {
+        self.rules
+            .borrow_mut()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                // A missing or unreadable file ignores nothing.
+                let content = fs::read_to_string(dir.join(FILE_NAME)).unwrap_or_default();
+                content.lines().filter_map(Rule::parse).collect()
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn ignore_files_apply_beneath_their_directory() {
+        let root = TempDir::new("ignore");
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(
+            root.join(FILE_NAME),
+            "# Editors\n*.swp\n\nvendor/\n/build\n",
+        )
+        .unwrap();
+        fs::write(root.join("src").join(FILE_NAME), "gen/*.c\n").unwrap();
+
+        let ignore = Ignore::new(root.to_path_buf());
+        let ignored = |path: &str| ignore.is_ignored(path::Path::new(path));
+
+        assert!(ignored("main.c.swp"));
+        assert!(ignored("src/main.c.swp"));
+        assert!(ignored("vendor/zlib/inflate.c"));
+        assert!(ignored("src/vendor/inflate.c"));
+        assert!(!ignored("vendor"));
+        assert!(ignored("build/out.o"));
+        assert!(!ignored("src/build/out.o"));
+        assert!(ignored("src/gen/parser.c"));
+        assert!(!ignored("gen/parser.c"));
+        assert!(!ignored("src/main.c"));
+        assert!(ignore.is_ignored(&root.join("src/gen/parser.c")));
+        assert!(!ignore.is_ignored(path::Path::new("/elsewhere/main.c.swp")));
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections, path, sync};
+
+/// A path interned by a `PathInterner`, standing in for it wherever paths
+/// would otherwise be hashed and compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct PathId(u32);
+
+/// Every distinct path met with, held once in a single arena, so that the
+/// many tasks naming, e.g., the same header share it rather than each
+/// holding a copy of their own.
+#[derive(Debug, Default)]
+pub(crate) struct PathInterner {
+    paths: Vec<sync::Arc<path::Path>>,
+    ids: collections::HashMap<sync::Arc<path::Path>, PathId>,
+}
+
+impl PathInterner {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The id of `path`, copying it into the arena should it be new.
+    pub(crate) fn intern(&mut self, path: &path::Path) -> PathId {
+        match self.ids.get(path) {
+            Some(id) => *id,
+            None => self.insert(sync::Arc::from(path)),
+        }
+    }
+
+    /// As `intern`, but keeping `path` itself should it be new.
+    pub(crate) fn intern_shared(&mut self, path: &sync::Arc<path::Path>) -> PathId {
+        match self.ids.get(&**path) {
+            Some(id) => *id,
+            None => self.insert(path.clone()),
+        }
+    }
+
+    fn insert(&mut self, path: sync::Arc<path::Path>) -> PathId {
+        let id = PathId(self.paths.len() as u32);
+        self.paths.push(path.clone());
+        self.ids.insert(path, id);
+        id
+    }
+
+    /// The copy of `path` held in the arena, for whatever names it to share.
+    pub(crate) fn share(&mut self, path: &path::Path) -> sync::Arc<path::Path> {
+        let id = self.intern(path);
+        self.path(id).clone()
+    }
+
+    /// The id of `path`, should it have been interned.
+    pub(crate) fn get(&self, path: &path::Path) -> Option<PathId> {
+        self.ids.get(path).copied()
+    }
+
+    /// The path `id` stands for, shared with everything else naming it.
+    pub(crate) fn path(&self, id: PathId) -> &sync::Arc<path::Path> {
+        &self.paths[id.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_held_once() {
+        let mut interner = PathInterner::new();
+        let target: sync::Arc<path::Path> = sync::Arc::from(path::Path::new("../out/a.o"));
+        let a = interner.intern_shared(&target);
+        let b = interner.intern(path::Path::new("a.h"));
+        assert_ne!(a, b);
+        assert_eq!(interner.intern(path::Path::new("../out/a.o")), a);
+        assert_eq!(interner.intern(path::Path::new("a.h")), b);
+        assert!(sync::Arc::ptr_eq(interner.path(a), &target));
+        assert_eq!(interner.get(path::Path::new("a.h")), Some(b));
+        let shared = interner.share(path::Path::new("a.h"));
+        assert!(sync::Arc::ptr_eq(&shared, interner.path(b)));
+        assert_eq!(interner.get(path::Path::new("b.h")), None);
+    }
+}
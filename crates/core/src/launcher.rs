@@ -0,0 +1,117 @@
+use std::{ffi, path};
+
+/// How a task's command line reaches the program it names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Launcher {
+    /// Spawn the program directly on the host.
+    Native,
+    /// Run the program inside the default WSL distribution via `wsl.exe`.
+    Wsl,
+    /// Run the program through Git for Windows' bash.
+    GitBash,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Unknown launcher '{}'.", _0)]
+pub struct UnknownLauncher(String);
+
+impl std::str::FromStr for Launcher {
+    type Err = UnknownLauncher;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Self::Native),
+            "wsl" => Ok(Self::Wsl),
+            "git-bash" => Ok(Self::GitBash),
+            _ => Err(UnknownLauncher(s.to_string())),
+        }
+    }
+}
+
+impl Launcher {
+    /// Rewrites a host path into the form understood by the launched shell,
+    /// e.g. `C:\src\main.c` becomes `/mnt/c/src/main.c` under WSL.
+    pub fn translate(self, path: &str) -> String {
+        let drive_root = match self {
+            Self::Native => return path.to_string(),
+            Self::Wsl => "/mnt/",
+            Self::GitBash => "/",
+        };
+
+        let path = path.replace('\\', "/");
+        let mut chars = path.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(drive), Some(':'), Some('/')) if drive.is_ascii_alphabetic() => {
+                format!("{}{}{}", drive_root, drive.to_ascii_lowercase(), &path[2..])
+            }
+            _ => path,
+        }
+    }
+
+    pub fn command(
+        self,
+        git_bash: &path::Path,
+        cmd: &ffi::OsStr,
+        args: &[String],
+    ) -> std::process::Command {
+        match self {
+            Self::Native => {
+                let mut command = std::process::Command::new(cmd);
+                command.args(args);
+                command
+            }
+            Self::Wsl => {
+                let mut command = std::process::Command::new("wsl.exe");
+                command.arg("--exec").arg(cmd).args(args);
+                command
+            }
+            Self::GitBash => {
+                let cmd = cmd.to_string_lossy();
+                let script = std::iter::once(cmd.as_ref())
+                    .chain(args.iter().map(|arg| arg.as_str()))
+                    .map(quote)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let mut command = std::process::Command::new(git_bash);
+                command.arg("-c").arg(script);
+                command
+            }
+        }
+    }
+
+    /// WSL only forwards the Windows environment variables listed in `WSLENV`.
+    pub fn forward_env<'a, I>(self, command: &mut std::process::Command, names: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        if self == Self::Wsl {
+            let names: Vec<_> = names.into_iter().collect();
+            command.env("WSLENV", ffi::OsString::from(names.join(":")));
+        }
+    }
+}
+
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_translate_paths() {
+        assert_eq!(Launcher::Native.translate(r"C:\src\a.c"), r"C:\src\a.c");
+        assert_eq!(Launcher::Wsl.translate(r"C:\src\a.c"), "/mnt/c/src/a.c");
+        assert_eq!(Launcher::Wsl.translate("D:/out/a.o"), "/mnt/d/out/a.o");
+        assert_eq!(Launcher::Wsl.translate(r"..\out\a.o"), "../out/a.o");
+        assert_eq!(Launcher::GitBash.translate(r"C:\src\a.c"), "/c/src/a.c");
+        assert_eq!(Launcher::GitBash.translate("src/a.c"), "src/a.c");
+    }
+
+    #[test]
+    fn can_quote_args() {
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
@@ -1,26 +1,75 @@
-use std::{collections, ffi, fs, path, rc, time::SystemTime};
-
+use std::{collections, ffi, fmt, fs, path, sync, time::SystemTime};
+
+mod action;
+pub mod builtins;
+mod config;
+mod depfile;
+mod durations;
+mod edges;
 mod env;
+mod git;
+mod graph;
+mod hooks;
+mod ignore;
+mod intern;
+mod launcher;
 mod make;
+mod plan;
+mod probe;
 mod recipe;
 mod relativiser;
+mod requirements;
+mod sandbox;
+mod schedule;
+mod separator;
+mod shard;
+mod stat;
+mod state;
 mod targets;
 mod targets_spec;
+#[cfg(test)]
+mod testing;
+mod tools;
 mod unit;
+mod validate;
 
+use edges::Edges;
+use intern::{PathId, PathInterner};
 use targets::Targets;
 
-pub use env::EnvSpec;
-pub use recipe::Recipe;
-pub use relativiser::Error;
+pub use config::{Config, IsolationError};
+pub use depfile::DepfileError;
+pub use durations::Durations;
+pub use env::{looks_secret, EnvSpec};
+pub use git::{GitError, GitIndex};
+pub use graph::{Edge, Upstream};
+pub use hooks::{Hook, HookTrigger, UnknownHookTrigger};
+pub use ignore::Ignore;
+pub use launcher::{Launcher, UnknownLauncher};
+pub use plan::{Plan, Reason, Request};
+pub use probe::Probe;
+pub use recipe::{input_index, target_index, Recipe, RecipeParseError};
+pub use relativiser::{Error, Relativiser};
+pub use requirements::{
+    require_capability, require_version, RequirementError, CAPABILITIES, VERSION,
+};
+pub use sandbox::Sandbox;
+pub use schedule::{Schedule, UnknownSchedule};
+pub use separator::{Separator, UnknownSeparator};
+pub use shard::{BadShard, Shard};
+pub use stat::{AssumeNew, FileSystem, Stat};
+pub use state::{EnvChange, State};
 pub use targets_spec::{TargetSpec, TargetsSpec};
+pub use tools::{BadTool, Tool};
 pub use unit::{
-    PrerequisiteSpec, TargetSpecHandle, TargetSpecHandleIterator, TaskSpec, Unit, UnitBuilder,
+    BadVarName, EmptyPool, Origin, PrerequisiteSpec, SetOutputDirError, TargetSpecHandle,
+    TargetSpecHandleIterator, TaskOptions, TaskSpec, Unit, UnitBuilder,
 };
+pub use validate::{Cycle, Finding};
 
 #[derive(Debug)]
 enum Prerequisite {
-    Named(rc::Rc<path::Path>, bool),
+    Named(sync::Arc<path::Path>, bool),
     Handle(TaskHandle),
 }
 
@@ -53,23 +102,467 @@ pub enum CakeError {
 #[derive(Debug)]
 pub struct Task {
     targets: Targets,
-    inputs: Vec<rc::Rc<path::Path>>,
+    inputs: Vec<sync::Arc<path::Path>>,
     upstream: Vec<Prerequisite>,
-    downstream: Vec<TaskHandle>,
+    // How each of `upstream` came to be depended upon.
+    edges: Vec<Edge>,
     env: Vec<EnvSpec>,
-    recipe: Recipe,
+    recipe: Option<Recipe>,
+    options: TaskOptions,
+    // Patterns for inputs which can't be known until the tasks producing
+    // them have run.
+    late_inputs: Vec<String>,
+    depfile: Option<sync::Arc<path::Path>>,
+    // Patterns for the files the task produces besides its targets.
+    produces: Vec<glob::Pattern>,
+    origin: Option<Origin>,
 }
 
+// Files generators leave behind, and those units glob for, are found with the
+// same rules as globs over targets.
+pub(crate) const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
 impl Task {
+    pub fn targets(&self) -> impl Iterator<Item = &path::Path> {
+        self.targets.iter().map(|target| target.as_ref())
+    }
+
+    /// The environment the task runs with, sorted by name.
+    pub fn environment(&self, config: &Config) -> Vec<(String, ffi::OsString)> {
+        let mut environment = collections::BTreeMap::new();
+        if !config.clear_env || self.options.inherit_all_env {
+            environment.extend(
+                std::env::vars_os()
+                    .map(|(name, value)| (name.to_string_lossy().into_owned(), value)),
+            );
+        }
+        for (name, value) in env::derived(config, Some(&self.targets[0])) {
+            environment.insert(name.to_string(), value);
+        }
+        if self.options.color {
+            for (name, value) in env::terminal(config) {
+                environment.insert(name.to_string(), value);
+            }
+        }
+        for (name, value) in env::resolve(env::merge(&config.env, &self.env)) {
+            environment.insert(name.to_string(), value);
+        }
+        let path = environment.get("PATH").cloned();
+        environment.extend(tools::env(
+            &self.options.tools,
+            &config.tool_store,
+            path.as_deref(),
+        ));
+        environment.into_iter().collect()
+    }
+
+    /// Whether the task's targets are names rather than files.
+    pub fn is_phony(&self) -> bool {
+        self.options.phony
+    }
+
+    /// Whether the task only gathers others together, producing nothing.
+    pub fn is_group(&self) -> bool {
+        self.options.group
+    }
+
+    /// What to announce when the task runs in place of its command, if
+    /// anything.
+    pub fn description(&self) -> Option<&str> {
+        self.options.description.as_deref()
+    }
+
+    /// A short account of what the task does, for reporting progress: its
+    /// description, or failing that the program it runs and what on.
+    pub fn summary(&self) -> String {
+        if let Some(description) = self.description() {
+            return description.to_string();
+        }
+        let program = self.recipe.as_ref().and_then(Recipe::program);
+        let subject = self.inputs.first().unwrap_or(&self.targets[0]);
+        format!(
+            "{} {}",
+            program.unwrap_or("run").to_uppercase(),
+            subject.display()
+        )
+    }
+
+    /// Whether the task is to be announced when it runs.
+    pub fn is_echoed(&self) -> bool {
+        self.options.echo
+    }
+
+    /// Whether the task may leave its targets as they were when it runs.
+    pub fn is_restat(&self) -> bool {
+        self.options.restat
+    }
+
+    /// The pool limiting how many tasks like it run at once, if any.
+    pub fn pool(&self) -> Option<&str> {
+        self.options.pool.as_deref()
+    }
+
+    /// Where the task was declared, if its front-end said.
+    pub fn origin(&self) -> Option<&Origin> {
+        self.origin.as_ref()
+    }
+
+    /// Whether there's anything to run for the task; a phony task may do no
+    /// more than gather others together.
+    pub fn has_recipe(&self) -> bool {
+        self.recipe.is_some()
+    }
+
+    /// The tools the task runs with, which must be in the tool store first.
+    pub fn tools(&self) -> &[Tool] {
+        &self.options.tools
+    }
+
+    /// The target to which the task's exit status should be written, if any.
+    pub fn result(&self) -> Option<&path::Path> {
+        self.options
+            .result
+            .and_then(|index| self.targets.iter().nth(index))
+            .map(|target| target.as_ref())
+    }
+
+    /// The file in which the task lists whatever else it depends upon, if any.
+    pub fn depfile(&self) -> Option<&path::Path> {
+        self.depfile.as_deref()
+    }
+
+    /// The files the task's depfile currently lists, such as the headers a
+    /// compiler read the last time the task ran.
+    pub fn discovered_dependencies(
+        &self,
+        context_dir: &path::Path,
+    ) -> Result<Vec<path::PathBuf>, DepfileError> {
+        match &self.depfile {
+            Some(depfile) => depfile::read(depfile, context_dir),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The task's inputs, including whichever files currently match its late
+    /// inputs; once everything the task depends upon has run, these are the
+    /// inputs it runs with.
+    pub fn inputs(&self) -> Vec<sync::Arc<path::Path>> {
+        let mut inputs = self.inputs.clone();
+        inputs.extend(self.found_late_inputs());
+        inputs
+    }
+
+    // The files currently matching the task's late inputs.
+    fn found_late_inputs(&self) -> Vec<sync::Arc<path::Path>> {
+        let mut found = vec![];
+        for pattern in self.late_inputs.iter() {
+            // Patterns were checked as the task list was built.
+            found.extend(
+                glob::glob_with(pattern, GLOB_OPTIONS)
+                    .unwrap()
+                    .filter_map(Result::ok)
+                    .filter(|path| path.is_file())
+                    .map(sync::Arc::from),
+            );
+        }
+        found
+    }
+
+    /// The files currently matching what the task produces besides its
+    /// targets.
+    pub fn products(&self) -> Vec<path::PathBuf> {
+        let mut found = vec![];
+        for pattern in self.produces.iter() {
+            found.extend(
+                glob::glob_with(pattern.as_str(), GLOB_OPTIONS)
+                    .unwrap()
+                    .filter_map(Result::ok)
+                    .filter(|path| path.is_file()),
+            );
+        }
+        found
+    }
+
+    /// Summarises the paths and modification times of the task's inputs, so
+    /// that runs against identical inputs can be recognised.
+    pub fn input_state(&self, stat: &dyn Stat) -> String {
+        use sha2::Digest;
+
+        let named: Vec<_> = self
+            .upstream
+            .iter()
+            .filter_map(|upstream| match upstream {
+                Prerequisite::Named(path, _) => Some(path.clone()),
+                Prerequisite::Handle(_) => None,
+            })
+            .collect();
+
+        let mut hasher = sha2::Sha256::new();
+        for input in self.inputs().iter().chain(named.iter()) {
+            hasher.update(input.to_string_lossy().as_bytes());
+            match stat
+                .modified(input)
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            {
+                Some(since) => hasher.update(since.as_nanos().to_le_bytes()),
+                None => hasher.update(b"?"),
+            }
+        }
+        hasher.finalize()[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Identifies the task across runs by its first target, e.g. in the
+    /// records kept of how it last ran.
+    pub fn key(&self) -> String {
+        self.targets[0].to_string_lossy().into_owned()
+    }
+
+    // When the task's targets last changed, or nothing should any be
+    // missing.
+    fn modified(&self, stat: &dyn Stat) -> Result<Option<SystemTime>, CakeError> {
+        let target_mod_time = self
+            .targets
+            .iter()
+            .map(|target| match stat.modified(target) {
+                Ok(time) => Ok(Some(time)),
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(CakeError::IoError(target.to_path_buf(), err)),
+            })
+            .try_fold(
+                None,
+                |r, t| -> Result<Option<Option<SystemTime>>, CakeError> {
+                    let t = t?;
+                    Ok(Some(if let Some(r) = r {
+                        match (r, t) {
+                            (None, _) | (_, None) => None,
+                            (Some(r), Some(t)) => Some(std::cmp::max(t, r)),
+                        }
+                    } else {
+                        t
+                    }))
+                },
+            )?
+            .unwrap_or(None);
+
+        // Downstream tasks see the task as having changed when whatever else
+        // it produced did.
+        Ok(target_mod_time.map(|time| {
+            self.products()
+                .iter()
+                .filter_map(|product| stat.modified(product).ok())
+                .fold(time, std::cmp::max)
+        }))
+    }
+
     // TODO wouldn't it be nice if the was self
-    pub fn prepare(&self) -> Result<std::process::Command, recipe::RecipePrepareError> {
-        self.recipe.prepare(&self.targets, &self.inputs, &self.env)
+    pub fn prepare(
+        &self,
+        config: &Config,
+    ) -> Result<std::process::Command, recipe::RecipePrepareError> {
+        match &self.recipe {
+            Some(recipe) => recipe.prepare(
+                &self.recipe_targets(),
+                &self.inputs(),
+                &env::merge(&config.env, &self.env),
+                &self.options,
+                config,
+            ),
+            None => Err(recipe::RecipePrepareError::NoRecipe),
+        }
+    }
+
+    // Atomic tasks write their targets somewhere else to begin with.
+    pub(crate) fn recipe_targets(&self) -> std::borrow::Cow<'_, Targets> {
+        if self.is_atomic() {
+            std::borrow::Cow::Owned(self.targets.staged())
+        } else {
+            std::borrow::Cow::Borrowed(&self.targets)
+        }
+    }
+
+    fn is_atomic(&self) -> bool {
+        self.options.atomic && !self.options.phony
+    }
+
+    /// The command the task would run, spelt out without running it.
+    pub fn render(&self, config: &Config) -> Result<String, recipe::RecipePrepareError> {
+        let rendered = match &self.recipe {
+            Some(recipe) => recipe.render(
+                &self.recipe_targets(),
+                &self.inputs(),
+                &env::merge(&config.env, &self.env),
+                &self.options,
+                config,
+            )?,
+            None => return Err(recipe::RecipePrepareError::NoRecipe),
+        };
+        let rendered = match self.options.stdin {
+            Some(index) => match self.inputs().get(index) {
+                Some(stdin) => format!("{} < {}", rendered, stdin.display()),
+                None => return Err(recipe::RecipePrepareError::InputIndexOutOfRange(index)),
+            },
+            None => rendered,
+        };
+        Ok(match self.stdout() {
+            Some(stdout) => format!("{} > {}", rendered, stdout.display()),
+            None => rendered,
+        })
+    }
+
+    // The file the recipe's stdout is written to, if any.
+    fn stdout(&self) -> Option<path::PathBuf> {
+        let index = self.options.stdout?;
+        self.recipe_targets()
+            .iter()
+            .nth(index)
+            .map(|target| target.to_path_buf())
+    }
+
+    /// Runs the task, having first made the directories its targets are to
+    /// be written to. `adjust` is given the command before it's spawned,
+    /// e.g. to announce it or capture its output.
+    pub fn execute<F>(
+        &self,
+        config: &Config,
+        adjust: F,
+    ) -> Result<std::process::Child, ExecuteError>
+    where
+        F: FnOnce(&mut std::process::Command),
+    {
+        let mut cmd = self.prepare(config).map_err(ExecuteError::Prepare)?;
+        if !self.options.phony {
+            for target in self.targets() {
+                let dir = match config.context_dir.join(target).parent() {
+                    Some(dir) => dir.to_path_buf(),
+                    None => continue,
+                };
+                fs::create_dir_all(&dir).map_err(|err| ExecuteError::CreateDir(dir, err))?;
+            }
+        }
+        // Anything left over from an earlier run which never finished.
+        if self.is_atomic() {
+            self.discard_staged(config)
+                .map_err(ExecuteError::ClearStaged)?;
+        }
+        adjust(&mut cmd);
+        if let Some(index) = self.options.stdin {
+            let stdin = match self.inputs().get(index) {
+                Some(stdin) => config.context_dir.join(stdin),
+                None => {
+                    let err = recipe::RecipePrepareError::InputIndexOutOfRange(index);
+                    return Err(ExecuteError::Prepare(err));
+                }
+            };
+            let file = fs::File::open(&stdin).map_err(|err| ExecuteError::OpenStdin(stdin, err))?;
+            cmd.stdin(file);
+        }
+        if let Some(stdout) = self.stdout() {
+            let stdout = config.context_dir.join(stdout);
+            let file =
+                fs::File::create(&stdout).map_err(|err| ExecuteError::CreateStdout(stdout, err))?;
+            cmd.stdout(file);
+        }
+        cmd.spawn().map_err(ExecuteError::Spawn)
+    }
+
+    /// Moves the targets an atomic task wrote into place, should it have
+    /// succeeded, or otherwise clears them away so that nothing half
+    /// written is left looking up to date.
+    pub fn finish(&self, config: &Config, succeeded: bool) -> std::io::Result<()> {
+        if !self.is_atomic() {
+            return Ok(());
+        }
+        if !succeeded {
+            return self.discard_staged(config);
+        }
+        for target in self.targets() {
+            let target = config.context_dir.join(target);
+            let staged = targets::staging(&target);
+            if !staged.exists() {
+                continue;
+            }
+            // Directories can't be renamed over unless they're empty.
+            if target.is_dir() {
+                fs::remove_dir_all(&target)?;
+            }
+            fs::rename(&staged, &target)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the task's targets, e.g. having stopped it part way through,
+    /// so that nothing half written is left looking up to date.
+    pub fn discard(&self, config: &Config) -> std::io::Result<()> {
+        if self.options.phony {
+            return Ok(());
+        }
+        for target in self.targets() {
+            let target = config.context_dir.join(target);
+            let removed = if target.is_dir() {
+                fs::remove_dir_all(&target)
+            } else {
+                fs::remove_file(&target)
+            };
+            match removed {
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+        }
+        self.discard_staged(config)
+    }
+
+    fn discard_staged(&self, config: &Config) -> std::io::Result<()> {
+        for target in self.targets() {
+            let staged = targets::staging(&config.context_dir.join(target));
+            let removed = if staged.is_dir() {
+                fs::remove_dir_all(&staged)
+            } else {
+                fs::remove_file(&staged)
+            };
+            match removed {
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+        }
+        Ok(())
     }
 }
 
+#[derive(Debug, failure::Fail)]
+pub enum ExecuteError {
+    #[fail(display = "Unable to prepare the task's command.")]
+    Prepare(#[fail(cause)] recipe::RecipePrepareError),
+    #[fail(display = "Unable to create the directory {:?}.", _0)]
+    CreateDir(path::PathBuf, #[fail(cause)] std::io::Error),
+    #[fail(display = "Unable to clear away targets left half written.")]
+    ClearStaged(#[fail(cause)] std::io::Error),
+    #[fail(display = "Unable to create {:?} for the task's output.", _0)]
+    CreateStdout(path::PathBuf, #[fail(cause)] std::io::Error),
+    #[fail(display = "Unable to open {:?} for the task's input.", _0)]
+    OpenStdin(path::PathBuf, #[fail(cause)] std::io::Error),
+    #[fail(display = "Unable to start the task's command.")]
+    Spawn(#[fail(cause)] std::io::Error),
+}
+
 #[derive(Debug)]
 pub struct TaskList {
     tasks: Vec<Task>,
+    downstream: Edges,
+    hooks: Vec<Hook>,
+    // How many tasks may run at once in each pool.
+    pools: collections::BTreeMap<String, usize>,
+    // Every path the tasks name, each held once.
+    paths: PathInterner,
+    // The task producing each target.
+    producers: collections::HashMap<PathId, TaskHandle>,
 }
 
 #[derive(Debug, failure::Fail)]
@@ -79,9 +572,57 @@ pub enum NewTaskListError {
     #[fail(display = "Failed to relativise a path")]
     RelativiseError(#[fail(cause)] relativiser::Error),
     #[fail(display = "Failed to parse make file")]
-    MakeParseError(#[fail(cause)] make::ParserError),
+    MakeParseError(#[fail(cause)] make::StreamError),
     #[fail(display = "IO Error")]
-    IOError(#[fail(cause)] std::io::Error)
+    IOError(#[fail(cause)] std::io::Error),
+    #[fail(display = "Target '{}' is produced by more than one task{}.", _0, _1)]
+    DuplicateTarget(String, Declarations),
+    #[fail(
+        display = "Units in '{}' and '{}' have overlapping output directories.",
+        _0, _1
+    )]
+    OutputDirCollision(String, String),
+    #[fail(
+        display = "Units in '{}' and '{}' both produce '{}', from '{}' and '{}' respectively.",
+        unit, other_unit, target, spec, other_spec
+    )]
+    UnitTargetCollision {
+        target: String,
+        unit: String,
+        spec: String,
+        other_unit: String,
+        other_spec: String,
+    },
+    #[fail(display = "Tasks' targets are named after inputs which depend upon them.")]
+    TargetCycle,
+    #[fail(display = "Tasks producing {} depend upon each other.", _0)]
+    Cycle(validate::Cycle),
+    #[fail(display = "Invalid glob '{}'.", _0)]
+    BadGlob(String, #[fail(cause)] glob::PatternError),
+    #[fail(display = "Glob '{}' matches none of the targets of other tasks.", _0)]
+    UnmatchedGlob(String),
+    #[fail(display = "Bad depfile")]
+    Depfile(#[fail(cause)] DepfileError),
+    #[fail(display = "Pool '{}' is declared with differing depths.", _0)]
+    PoolConflict(String),
+    #[fail(display = "Tasks use pool '{}', which no unit declares{}.", _0, _1)]
+    UnknownPool(String, Declarations),
+}
+
+/// Where each of some tasks at odds with one another was declared, so far
+/// as is known.
+#[derive(Debug)]
+pub struct Declarations(pub Vec<Origin>);
+
+impl fmt::Display for Declarations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let origins: Vec<_> = self.0.iter().map(Origin::to_string).collect();
+        match origins.as_slice() {
+            [] => Ok(()),
+            [origin] => write!(f, " (one declared at {})", origin),
+            origins => write!(f, " (declared at {})", origins.join(" and ")),
+        }
+    }
 }
 
 impl From<targets_spec::ResolveError> for NewTaskListError {
@@ -96,12 +637,18 @@ impl From<relativiser::Error> for NewTaskListError {
     }
 }
 
-impl From<make::ParserError> for NewTaskListError {
-    fn from(err: make::ParserError) -> Self {
+impl From<make::StreamError> for NewTaskListError {
+    fn from(err: make::StreamError) -> Self {
         Self::MakeParseError(err)
     }
 }
 
+impl From<DepfileError> for NewTaskListError {
+    fn from(err: DepfileError) -> Self {
+        Self::Depfile(err)
+    }
+}
+
 impl From<std::io::Error> for NewTaskListError {
     fn from(err: std::io::Error) -> Self {
         Self::IOError(err)
@@ -117,21 +664,55 @@ impl TaskList {
     where
         I: IntoIterator<Item = (path::PathBuf, Unit)>,
     {
-        let context: Vec<_> = context_dir.components().collect();
+        let units: Vec<_> = units.into_iter().collect();
+
+        // Units may only share an output directory if it's their own.
+        let output_dirs: Vec<_> = units
+            .iter()
+            .filter_map(|(dir, unit)| unit.output_dir.as_ref().map(|output| (dir, output)))
+            .collect();
+        for (i, (dir, output)) in output_dirs.iter().enumerate() {
+            for (other_dir, other_output) in output_dirs[i + 1..].iter() {
+                if output.starts_with(other_output) || other_output.starts_with(output) {
+                    return Err(NewTaskListError::OutputDirCollision(
+                        dir.to_string_lossy().into_owned(),
+                        other_dir.to_string_lossy().into_owned(),
+                    ));
+                }
+            }
+        }
 
         // Extract the list of tasks from each unit,
         // flattening them into one big list.
 
+        let mut hooks = vec![];
+        let mut pools = collections::BTreeMap::new();
+        for (_, unit) in units.iter() {
+            for (name, depth) in unit.pools.iter() {
+                match pools.insert(name.clone(), *depth) {
+                    Some(other) if other != *depth => {
+                        return Err(NewTaskListError::PoolConflict(name.clone()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // The directory of the unit declaring each task.
+        let mut unit_dirs = vec![];
         let (cakes, includes): (Vec<_>, Vec<_>) = units
             .into_iter()
-            .map(|(dir, unit)| (dir, unit.decompose()))
+            .map(|(dir, mut unit)| {
+                hooks.append(&mut unit.hooks);
+                (dir, unit.decompose())
+            })
             .scan(0, |count, (dir, (task_specs, includes))| {
                 let offset = *count;
                 *count += task_specs.len();
+                unit_dirs.resize(*count, dir.clone());
                 let task_specs = task_specs
                     .into_iter()
                     .map(move |(targets_spec, task_spec)| {
-                        (Some(targets_spec), task_spec.resolve(offset))
+                        (targets_spec, task_spec.resolve(offset))
                     });
                 let includes = includes
                     .into_iter()
@@ -141,72 +722,90 @@ impl TaskList {
             })
             .unzip();
 
-        let (mut targets_specs, mut task_specs): (Vec<_>, Vec<_>) = cakes.into_iter().flatten().unzip();
+        let (targets_specs, mut task_specs): (Vec<_>, Vec<_>) = cakes.into_iter().flatten().unzip();
 
         let mut targets: Vec<Option<Targets>> = vec![None; targets_specs.len()];
 
-        fn something<'a>(
-            task_index: usize,
-            target_prefix: &path::Path,
-            targets: &mut Vec<Option<Targets>>,
-            targets_specs: &mut Vec<Option<TargetsSpec>>,
-            task_specs: &'a Vec<TaskSpec<rc::Rc<path::Path>>>,
-        ) -> Result<Option<rc::Rc<path::Path>>, crate::targets_spec::ResolveError> {
-            if let Some(targets_spec) = targets_specs[task_index].take() {
-                let input = task_specs[task_index]
-                    .consumes
-                    .first()
-                    .map(|p| match p {
-                        PrerequisiteSpec::Handle(handle) => something(
-                            handle.task_index,
-                            target_prefix,
-                            targets,
-                            targets_specs,
-                            task_specs,
-                        ),
-                        PrerequisiteSpec::Named(path, _) => Ok(Some(path.clone())),
-                    })
-                    .map_or(Ok(None), |r| r)?;
+        // A task's targets may be named after its first input, so tasks
+        // consuming another task's target are resolved after that task. Work
+        // through them with an explicit stack, deep pipelines being common.
+        let first_upstream = |task_index: usize| match task_specs[task_index].consumes.first() {
+            Some(PrerequisiteSpec::Handle(handle)) => Some(*handle),
+            _ => None,
+        };
+        let mut in_progress = vec![false; targets_specs.len()];
+        for task_index in 0..targets_specs.len() {
+            let mut stack = vec![task_index];
+            while let Some(&task_index) = stack.last() {
+                if targets[task_index].is_some() {
+                    stack.pop();
+                    continue;
+                }
+
+                let input = match first_upstream(task_index) {
+                    Some(handle) => match &targets[handle.task_index] {
+                        Some(upstream) => Some(upstream[handle.target_index].clone()),
+                        None if in_progress[handle.task_index] => {
+                            return Err(NewTaskListError::TargetCycle);
+                        }
+                        None => {
+                            in_progress[task_index] = true;
+                            stack.push(handle.task_index);
+                            continue;
+                        }
+                    },
+                    None => match task_specs[task_index].consumes.first() {
+                        Some(PrerequisiteSpec::Named(path, _)) => Some(path.clone()),
+                        _ => None,
+                    },
+                };
 
                 targets[task_index] = Some(Targets::try_from((
                     target_prefix.to_path_buf(),
                     &input,
-                    targets_spec,
+                    &targets_specs[task_index],
                 ))?);
-
-                Ok(input)
-            } else {
-                Ok(Some(targets[task_index].as_ref().unwrap()[0].clone()))
+                in_progress[task_index] = false;
+                stack.pop();
             }
-        };
+        }
 
-        for task_index in 0..targets_specs.len() {
-            something(
-                task_index,
-                target_prefix,
-                &mut targets,
-                &mut targets_specs,
-                &task_specs,
-            )?;
+        // Build a map from each target's path to the task producing it, and
+        // where among its targets it lies. Paths are interned from here on,
+        // so that those named again and again are held once.
+        let mut paths = PathInterner::new();
+        let mut target_lut = collections::HashMap::new();
+        for (task_index, target) in targets.iter().enumerate() {
+            for (target_index, path) in target.as_ref().unwrap().iter().enumerate() {
+                let id = paths.intern_shared(path);
+                // Tasks sharing a target would silently overwrite each other.
+                if let Some((other, other_target_index)) =
+                    target_lut.insert(id, (task_index, target_index))
+                {
+                    // Units elsewhere in the context are unlikely to mean
+                    // to collide, so say how each came by the target.
+                    if unit_dirs[other] != unit_dirs[task_index] {
+                        return Err(NewTaskListError::UnitTargetCollision {
+                            target: path.to_string_lossy().into_owned(),
+                            unit: unit_dirs[other].to_string_lossy().into_owned(),
+                            spec: targets_specs[other][other_target_index].to_string(),
+                            other_unit: unit_dirs[task_index].to_string_lossy().into_owned(),
+                            other_spec: targets_specs[task_index][target_index].to_string(),
+                        });
+                    }
+                    let origins = vec![other, task_index]
+                        .into_iter()
+                        .filter_map(|index| task_specs[index].origin.clone())
+                        .collect();
+                    return Err(NewTaskListError::DuplicateTarget(
+                        path.to_string_lossy().into_owned(),
+                        Declarations(origins),
+                    ));
+                }
+            }
         }
         drop(targets_specs);
 
-        // Build a flat list of files and a map from
-        // file-path to index within that list.
-        let target_lut: collections::HashMap<_, _> = targets
-            .iter()
-            .enumerate()
-            .map(|(task_index, target)| {
-                target
-                    .as_ref()
-                    .unwrap()
-                    .iter()
-                    .enumerate()
-                    .map(move |(target_index, path)| (path.clone(), (task_index, target_index)))
-            })
-            .flatten()
-            .collect();
-
         // Account for any extra prerequisites.
         let get_target = |handle: TargetSpecHandle| {
             let target = targets[handle.task_index].as_ref().unwrap();
@@ -214,241 +813,611 @@ impl TaskList {
         };
 
         for (dir, includes) in includes.into_iter() {
-            let relativiser = relativiser::Relativiser::new(dir);
+            let relativiser = relativiser::Relativiser::new(context_dir, dir);
             for include in includes {
-                let content = asmbl_utils::io::read_file(fs::File::open(get_target(include))?)?;
+                // An include yet to be produced, as it won't have been in a
+                // fresh target directory, has nothing to add until it is.
+                let file = match fs::File::open(get_target(include)) {
+                    Ok(file) => std::io::BufReader::new(file),
+                    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+
+                for pair in make::stream(file) {
+                    let (target, prerequisite) = pair?;
+
+                    let target = relativiser.relativise(path::Path::new(&target))?;
+                    let prerequisite = relativiser.relativise(path::Path::new(&prerequisite))?;
+
+                    let producer = paths.get(&target).and_then(|id| target_lut.get(&id));
+                    if let Some((task_index, _)) = producer {
+                        let prerequisite = paths.share(&prerequisite);
+                        task_specs[*task_index]
+                            .depends_on
+                            .push(PrerequisiteSpec::Named(prerequisite, true));
+                    }
+                }
+            }
+        }
 
-                for (target, prerequisite) in make::cake(&content)? {
+        // Whatever tasks found they depended upon when they last ran, much as
+        // includes would have it.
+        let mut depfiles = Vec::with_capacity(task_specs.len());
+        let mut discovered = Vec::with_capacity(task_specs.len());
+        for (task_index, task_spec) in task_specs.iter().enumerate() {
+            let depfile = match &task_spec.options.depfile {
+                Some(spec) => depfile::resolve(spec, targets[task_index].as_ref().unwrap())?,
+                None => {
+                    depfiles.push(None);
+                    discovered.push(vec![]);
+                    continue;
+                }
+            };
+            discovered.push(
+                depfile::read(&depfile, context_dir)?
+                    .into_iter()
+                    .map(|prerequisite| PrerequisiteSpec::Named(paths.share(&prerequisite), true))
+                    .collect(),
+            );
+            depfiles.push(Some(depfile));
+        }
 
-                    let target = relativiser.relativise(&context, path::Path::new(target))?;
-                    let prerequisite = relativiser.relativise(&context, path::Path::new(prerequisite))?;
+        // What tasks produce besides their targets lies under the target
+        // directory, just as their targets do.
+        let mut producers = Vec::with_capacity(task_specs.len());
+        for task_spec in task_specs.iter() {
+            let mut patterns = Vec::with_capacity(task_spec.options.produces.len());
+            for pattern in task_spec.options.produces.iter() {
+                let pattern = format!(
+                    "{}/{}",
+                    glob::Pattern::escape(&target_prefix.to_string_lossy()),
+                    pattern
+                );
+                match glob::Pattern::new(&pattern) {
+                    Ok(pattern) => patterns.push(pattern),
+                    Err(err) => return Err(NewTaskListError::BadGlob(pattern, err)),
+                }
+            }
+            producers.push(patterns);
+        }
 
-                    match target_lut.get(&rc::Rc::from(target)) {
-                        Some((task_index, _)) => {
-                            task_specs[*task_index]
-                                .depends_on
-                                .push(PrerequisiteSpec::Named(rc::Rc::from(prerequisite), true));
-                        }
-                        _ => {}
+        // Globs stand for whichever targets of other tasks match them, now
+        // that every target is known.
+        let expand = |s: usize, prerequisites: Vec<PrerequisiteSpec<sync::Arc<path::Path>>>| {
+            let mut expanded = Vec::with_capacity(prerequisites.len());
+            for prerequisite in prerequisites {
+                let pattern = match prerequisite {
+                    PrerequisiteSpec::Glob(pattern) => pattern,
+                    prerequisite => {
+                        expanded.push(prerequisite);
+                        continue;
+                    }
+                };
+                let matcher = glob::Pattern::new(&format!(
+                    "{}/{}",
+                    glob::Pattern::escape(&target_prefix.to_string_lossy()),
+                    pattern.to_string_lossy()
+                ))
+                .map_err(|err| {
+                    NewTaskListError::BadGlob(pattern.to_string_lossy().into_owned(), err)
+                })?;
+                let before = expanded.len();
+                for (task_index, target) in targets.iter().enumerate() {
+                    if task_index == s {
+                        continue;
                     }
+                    expanded.extend(
+                        target
+                            .as_ref()
+                            .unwrap()
+                            .iter()
+                            .filter(|path| matcher.matches_path_with(path, GLOB_OPTIONS))
+                            .map(|path| PrerequisiteSpec::Named(path.clone(), false)),
+                    );
+                }
+                if expanded.len() == before {
+                    return Err(NewTaskListError::UnmatchedGlob(
+                        pattern.to_string_lossy().into_owned(),
+                    ));
                 }
             }
+            Ok::<_, NewTaskListError>(expanded)
+        };
+        for (s, task_spec) in task_specs.iter_mut().enumerate() {
+            task_spec.consumes = expand(s, std::mem::take(&mut task_spec.consumes))?;
+            task_spec.depends_on = expand(s, std::mem::take(&mut task_spec.depends_on))?;
+            task_spec.not_before = expand(s, std::mem::take(&mut task_spec.not_before))?;
         }
 
-        let mut downstreams = vec![Vec::<TaskHandle>::new(); task_specs.len()];
+        // The task producing the given file besides its targets, if any.
+        let producer = |name: &path::Path| {
+            producers.iter().position(|patterns| {
+                patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(name, GLOB_OPTIONS))
+            })
+        };
+
+        // Pairs of upstream and downstream task indices.
+        let mut edges = vec![];
 
         let task_specs: Vec<_> = task_specs
             .into_iter()
+            .zip(discovered)
             .enumerate()
-            .map(|(s, task_spec)| {
+            .map(|(s, (task_spec, discovered))| {
+                // Each prerequisite comes with the id of the path it names,
+                // by which those named more than once are told apart.
                 let mut resolve_prequisite =
-                    |prerequisite: PrerequisiteSpec<rc::Rc<path::Path>>| {
-                        let (prerequisite, path) = match prerequisite {
+                    |prerequisite: PrerequisiteSpec<sync::Arc<path::Path>>| {
+                        let (prerequisite, id) = match prerequisite {
                             PrerequisiteSpec::Handle(handle) => (
                                 Prerequisite::Handle(TaskHandle::new(handle.task_index)),
-                                get_target(handle).clone(),
+                                paths.intern_shared(get_target(handle)),
+                            ),
+                            PrerequisiteSpec::Named(name, optional) => {
+                                let id = paths.intern_shared(&name);
+                                let name = paths.path(id).clone();
+                                // Targets were interned first, so a target
+                                // named is the very path its task holds.
+                                match target_lut.get(&id) {
+                                    Some((task_index, _)) => {
+                                        (Prerequisite::Handle(TaskHandle::new(*task_index)), id)
+                                    }
+                                    // Files another task produces besides its
+                                    // targets are depended upon through it.
+                                    None => match producer(&name) {
+                                        Some(task_index) if task_index != s => {
+                                            (Prerequisite::Handle(TaskHandle::new(task_index)), id)
+                                        }
+                                        _ => (Prerequisite::Named(name, optional), id),
+                                    },
+                                }
+                            }
+                            // Ordered after the task producing the files, which
+                            // are only found once it's run.
+                            PrerequisiteSpec::Late(handle, _) => (
+                                Prerequisite::Handle(TaskHandle::new(handle.task_index)),
+                                paths.intern_shared(get_target(handle)),
                             ),
-                            PrerequisiteSpec::Named(name, optional) => match target_lut.get(&name) {
-                                Some((task_index, target_index)) => (
-                                    Prerequisite::Handle(TaskHandle::new(*task_index)),
-                                    targets[*task_index].as_ref().unwrap()[*target_index].clone(),
-                                ),
-                                None => (Prerequisite::Named(name.clone(), optional), name),
-                            },
+                            // Globs are expanded into the targets they match
+                            // up front, so any left over matched none.
+                            PrerequisiteSpec::Glob(pattern) => {
+                                return Err(NewTaskListError::UnmatchedGlob(
+                                    pattern.to_string_lossy().into_owned(),
+                                ))
+                            }
                         };
                         if let Prerequisite::Handle(handle) = prerequisite {
-                            downstreams[handle.index].push(TaskHandle::new(s));
+                            edges.push((handle.index, s));
                         };
-                        (prerequisite, path)
+                        Ok((prerequisite, id))
                     };
 
-                let (mut upstream, inputs): (Vec<_>, Vec<_>) = task_spec
+                let mut late = vec![];
+                let consumes: Vec<_> = task_spec
                     .consumes
+                    .into_iter()
+                    .filter_map(|prerequisite| match prerequisite {
+                        PrerequisiteSpec::Late(handle, pattern) => {
+                            late.push((handle, pattern));
+                            None
+                        }
+                        prerequisite => Some(prerequisite),
+                    })
+                    .collect();
+
+                let (mut upstream, inputs): (Vec<_>, Vec<_>) = consumes
                     .into_iter()
                     .map(|prerequisite| resolve_prequisite(prerequisite))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
                     .unzip();
+                let mut declared: collections::HashSet<_> = inputs.iter().copied().collect();
+
+                let mut late_inputs = Vec::with_capacity(late.len());
+                for (handle, pattern) in late {
+                    let dir = get_target(handle);
+                    let pattern = format!(
+                        "{}/{}",
+                        glob::Pattern::escape(&dir.to_string_lossy()),
+                        pattern
+                    );
+                    if let Err(err) = glob::Pattern::new(&pattern) {
+                        return Err(NewTaskListError::BadGlob(pattern, err));
+                    }
+                    let (prerequisite, id) = resolve_prequisite(PrerequisiteSpec::Handle(handle))?;
+                    upstream.push(prerequisite);
+                    declared.insert(id);
+                    late_inputs.push(pattern);
+                }
 
-                upstream.extend(
-                    task_spec
-                        .depends_on
-                        .into_iter()
-                        .map(|prerequisite| resolve_prequisite(prerequisite).0),
-                );
-                upstream.extend(
-                    task_spec
-                        .not_before
-                        .into_iter()
-                        .map(|prerequisite| resolve_prequisite(prerequisite).0),
-                );
+                let mut edges = vec![Edge::Consumes; upstream.len()];
+                for (prerequisites, edge) in [
+                    (task_spec.depends_on, Edge::DependsOn),
+                    (task_spec.not_before, Edge::NotBefore),
+                ] {
+                    for prerequisite in prerequisites {
+                        let (prerequisite, id) = resolve_prequisite(prerequisite)?;
+                        upstream.push(prerequisite);
+                        declared.insert(id);
+                    }
+                    edges.resize(upstream.len(), edge);
+                }
+                // Depfiles often list the very sources a task was declared to
+                // consume, which needn't be depended upon twice.
+                for prerequisite in discovered {
+                    let (prerequisite, id) = resolve_prequisite(prerequisite)?;
+                    if declared.insert(id) {
+                        upstream.push(prerequisite);
+                    }
+                }
+                edges.resize(upstream.len(), Edge::Discovered);
 
-                (inputs, upstream, task_spec.env, task_spec.recipe)
+                let inputs = inputs
+                    .into_iter()
+                    .map(|id| paths.path(id).clone())
+                    .collect();
+                Ok((
+                    inputs,
+                    (upstream, edges),
+                    task_spec.env,
+                    task_spec.recipe,
+                    (task_spec.options, task_spec.origin),
+                    late_inputs,
+                ))
             })
-            .collect();
+            .collect::<Result<_, NewTaskListError>>()?;
 
         drop(target_lut);
 
-        // Combine each task spec with it's corresponding list of downstreams.
+        // Combine each task spec with its targets.
         let mut unordered_tasks: Vec<_> = targets
             .into_iter()
-            .zip(task_specs)
-            .zip(downstreams)
+            .zip(task_specs.into_iter().zip(depfiles).zip(producers))
             .map(
-                |((mut targets, (inputs, upstream, env, recipe)), downstream)| {
+                |(
+                    mut targets,
+                    (
+                        (
+                            (
+                                inputs,
+                                (upstream, edges),
+                                env,
+                                recipe,
+                                (options, origin),
+                                late_inputs,
+                            ),
+                            depfile,
+                        ),
+                        produces,
+                    ),
+                )| {
                     Some(Task {
                         targets: targets.take().unwrap(),
                         inputs,
                         upstream,
-                        downstream,
+                        edges,
                         env,
                         recipe,
+                        options,
+                        late_inputs,
+                        depfile,
+                        produces,
+                        origin,
                     })
                 },
             )
             .collect();
+        let downstream = Edges::new(unordered_tasks.len(), &edges);
 
-        // Extract any leaf tasks that have no upstream tasks.
-        let mut tasks: Vec<_> = unordered_tasks
-            .iter_mut()
-            .filter_map(|task| {
-                if task
-                    .as_ref()
+        // Order the tasks such that all downstream tasks appear after
+        // their upstream counterparts, starting from the leaf tasks that
+        // have no upstream tasks at all.
+        let mut pending: Vec<_> = unordered_tasks
+            .iter()
+            .map(|task| {
+                task.as_ref()
                     .unwrap()
                     .upstream
                     .iter()
-                    .any(|upstream| match upstream {
-                        Prerequisite::Handle(_) => true,
-                        _ => false,
-                    })
-                {
-                    None
-                } else {
-                    Some(task.take().unwrap())
-                }
+                    .filter(|upstream| matches!(upstream, Prerequisite::Handle(_)))
+                    .count()
             })
             .collect();
 
-        // Walk down from the leaf tasks to generate a list where all
-        // downstream tasks appear after their upstream counterparts.
-        let (mut s, mut e) = (0, tasks.len());
-        while s < tasks.len() {
-            for i in s..e {
-                let downstream_indices: Vec<_> = tasks[i]
-                    .downstream
-                    .iter()
-                    .map(|downstream| downstream.index)
-                    .collect();
-                for downstream_index in downstream_indices {
-                    if let Some(downstream_task) = unordered_tasks[downstream_index].take() {
-                        tasks.push(downstream_task);
-                    }
+        let mut order: collections::VecDeque<_> = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count == 0)
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut new_indices = vec![None; unordered_tasks.len()];
+        let mut tasks = Vec::with_capacity(unordered_tasks.len());
+        while let Some(index) = order.pop_front() {
+            let task = unordered_tasks[index].take().unwrap();
+            for downstream in downstream.get(index).iter() {
+                pending[downstream.index] -= 1;
+                if pending[downstream.index] == 0 {
+                    order.push_back(downstream.index);
                 }
             }
-            s = e + 1;
-            e = tasks.len();
+            new_indices[index] = Some(tasks.len());
+            tasks.push(task);
+        }
+        if let Some(cycle) = validate::cycle(&unordered_tasks) {
+            return Err(NewTaskListError::Cycle(cycle));
         }
         drop(unordered_tasks);
 
-        Ok(Self { tasks })
+        // Handles refer to positions in the ordered list from here on.
+        edges.clear();
+        for (index, task) in tasks.iter_mut().enumerate() {
+            for upstream in task.upstream.iter_mut() {
+                if let Prerequisite::Handle(handle) = upstream {
+                    handle.index = new_indices[handle.index].unwrap();
+                    edges.push((handle.index, index));
+                }
+            }
+        }
+        let downstream = Edges::new(tasks.len(), &edges);
+
+        let mut producers = collections::HashMap::new();
+        for (index, task) in tasks.iter().enumerate() {
+            for target in task.targets.iter() {
+                producers.insert(paths.intern_shared(target), TaskHandle::new(index));
+            }
+        }
+
+        for task in tasks.iter() {
+            match task.pool() {
+                Some(pool) if !pools.contains_key(pool) => {
+                    return Err(NewTaskListError::UnknownPool(
+                        pool.to_string(),
+                        Declarations(task.origin.iter().cloned().collect()),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            tasks,
+            downstream,
+            hooks,
+            pools,
+            paths,
+            producers,
+        })
+    }
+
+    /// How many tasks may run at once in each pool declared.
+    pub fn pools(&self) -> &collections::BTreeMap<String, usize> {
+        &self.pools
+    }
+
+    /// The hooks declared by every unit, to be run once the build is over.
+    pub fn hooks(&self) -> &[Hook] {
+        &self.hooks
+    }
+
+    pub fn get(&self, handle: TaskHandle) -> &Task {
+        &self.tasks[handle.index]
     }
 
-    pub fn retain_out_of_date(&self) -> Result<Vec<(TaskHandle, &Task)>, CakeError> {
+    /// Finds the task producing the given target.
+    pub fn find(&self, target: &path::Path) -> Option<TaskHandle> {
+        let id = self.paths.get(target)?;
+        self.producers.get(&id).copied()
+    }
+
+    /// Every target declared, along with the task producing it, in the
+    /// order the tasks would run.
+    pub fn targets(&self) -> impl Iterator<Item = (&path::Path, TaskHandle)> {
+        self.tasks.iter().enumerate().flat_map(|(index, task)| {
+            task.targets()
+                .map(move |target| (target, TaskHandle::new(index)))
+        })
+    }
+
+    /// The given tasks along with every task they transitively depend upon.
+    pub fn upstream_closure<I>(&self, handles: I) -> collections::HashSet<TaskHandle>
+    where
+        I: IntoIterator<Item = TaskHandle>,
+    {
+        self.closure(handles, |handle, stack| {
+            stack.extend(
+                self.tasks[handle.index]
+                    .upstream
+                    .iter()
+                    .filter_map(|upstream| match upstream {
+                        Prerequisite::Handle(handle) => Some(*handle),
+                        Prerequisite::Named(..) => None,
+                    }),
+            )
+        })
+    }
+
+    /// The given tasks along with every task transitively depending upon them.
+    pub fn downstream_closure<I>(&self, handles: I) -> collections::HashSet<TaskHandle>
+    where
+        I: IntoIterator<Item = TaskHandle>,
+    {
+        self.closure(handles, |handle, stack| {
+            stack.extend_from_slice(self.downstream.get(handle.index))
+        })
+    }
+
+    // Walks the graph from `handles`, with `next` pushing the neighbours of
+    // each task it's given onto the stack.
+    fn closure<I, F>(&self, handles: I, next: F) -> collections::HashSet<TaskHandle>
+    where
+        I: IntoIterator<Item = TaskHandle>,
+        F: Fn(TaskHandle, &mut Vec<TaskHandle>),
+    {
+        let mut closure = collections::HashSet::new();
+        let mut stack: Vec<_> = handles.into_iter().collect();
+        while let Some(handle) = stack.pop() {
+            if closure.insert(handle) {
+                next(handle, &mut stack);
+            }
+        }
+        closure
+    }
+
+    pub fn retain_out_of_date(
+        &self,
+        stat: &dyn Stat,
+        forced: &collections::HashSet<TaskHandle>,
+        settled: &collections::HashSet<TaskHandle>,
+    ) -> Result<Vec<(TaskHandle, &Task)>, CakeError> {
+        Ok(self
+            .dirty(stat, forced, settled)?
+            .into_iter()
+            .map(|(handle, _)| (handle, &self.tasks[handle.index]))
+            .collect())
+    }
+
+    // The tasks which are out of date, and why.
+    fn dirty(
+        &self,
+        stat: &dyn Stat,
+        forced: &collections::HashSet<TaskHandle>,
+        settled: &collections::HashSet<TaskHandle>,
+    ) -> Result<Vec<(TaskHandle, Reason)>, CakeError> {
         let now = SystemTime::now();
 
         let mut modification_times: Vec<Option<SystemTime>> = Vec::with_capacity(self.tasks.len());
 
-        self.tasks
+        (0..self.tasks.len())
+            .filter_map(|index| -> Option<Result<(TaskHandle, Reason), CakeError>> {
+                let handle = TaskHandle::new(index);
+                let upstream_time = |upstream: TaskHandle| modification_times[upstream.index];
+                match self.out_of_date(handle, stat, forced, settled, upstream_time, now) {
+                    Ok((reason, mod_time)) => {
+                        modification_times.push(mod_time);
+                        reason.map(|reason| Ok((handle, reason)))
+                    }
+                    Err(err) => Some(Err(err)),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the task is out of date as things now stand, rather than as
+    /// they were expected to once everything upstream of it had run.
+    pub fn is_out_of_date(
+        &self,
+        handle: TaskHandle,
+        stat: &dyn Stat,
+        forced: &collections::HashSet<TaskHandle>,
+        settled: &collections::HashSet<TaskHandle>,
+    ) -> Result<bool, CakeError> {
+        let now = SystemTime::now();
+        let upstream_time = |upstream: TaskHandle| {
+            let task = &self.tasks[upstream.index];
+            if task.options.group {
+                return self.group_modified(task, stat);
+            }
+            match task.modified(stat) {
+                Ok(Some(time)) if !task.options.phony => Some(time),
+                _ => Some(now),
+            }
+        };
+        Ok(self
+            .out_of_date(handle, stat, forced, settled, upstream_time, now)?
+            .0
+            .is_some())
+    }
+
+    // When whatever a group gathers together last changed, groups within it
+    // included.
+    fn group_modified(&self, group: &Task, stat: &dyn Stat) -> Option<SystemTime> {
+        group
+            .upstream
             .iter()
-            .enumerate()
-            .filter_map(
-                |(index, task)| -> Option<Result<(TaskHandle, &Task), CakeError>> {
-                    let upstream_mod_time = task
-                        .upstream
-                        .iter()
-                        .filter_map(|prerequisite| {
-                            match prerequisite {
-                                Prerequisite::Named(file, optional) => match fs::metadata(&file) {
-                                    Ok(metadata) => {
-                                        Some(metadata.modified()
-                                        .map_err(|err| {
-                                            CakeError::NoLastModifiedTime(file.to_path_buf(), err)
-                                        }))
-                                    },
-                                    Err(_) if *optional => None,
-                                    Err(err) => Some(Err(CakeError::PrerequisiteMissing(file.to_path_buf(), err)))
-                                },
-                                Prerequisite::Handle(handle) => {
-                                    modification_times[handle.index].map(|time| Ok(time))
-                                }
-                            }
-                        })
-                        .try_fold(None, |r, t| -> Result<Option<SystemTime>, CakeError> {
-                            let t = t?;
-                            Ok(Some(if let Some(r) = r {
-                                std::cmp::max(t, r)
-                            } else {
-                                t
-                            }))
-                        });
+            .filter_map(|prerequisite| match prerequisite {
+                Prerequisite::Named(file, _) => stat.modified(file).ok(),
+                Prerequisite::Handle(handle) => {
+                    let task = &self.tasks[handle.index];
+                    match task.options.group {
+                        true => self.group_modified(task, stat),
+                        false => task.modified(stat).ok().flatten(),
+                    }
+                }
+            })
+            .max()
+    }
 
-                    let upstream_mod_time = match upstream_mod_time {
-                        Ok(time) => time,
-                        Err(err) => return Some(Err(err)),
-                    };
+    // Why the task is out of date, if it is, given when each task upstream
+    // of it last changed, along with when the task itself will have last
+    // changed.
+    fn out_of_date<F>(
+        &self,
+        handle: TaskHandle,
+        stat: &dyn Stat,
+        forced: &collections::HashSet<TaskHandle>,
+        settled: &collections::HashSet<TaskHandle>,
+        upstream_time: F,
+        now: SystemTime,
+    ) -> Result<(Option<Reason>, Option<SystemTime>), CakeError>
+    where
+        F: Fn(TaskHandle) -> Option<SystemTime>,
+    {
+        let task = &self.tasks[handle.index];
 
-                    let target_mod_time = task
-                        .targets
-                        .iter()
-                        .map(|target| match fs::metadata(&target) {
-                            Ok(md) => match md.modified() {
-                                Ok(time) => Ok(Some(time)),
-                                Err(err) => {
-                                    return Err(CakeError::NoLastModifiedTime(
-                                        target.to_path_buf(),
-                                        err,
-                                    ))
-                                }
-                            },
-                            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
-                            Err(err) => return Err(CakeError::IoError(target.to_path_buf(), err)),
-                        })
-                        .try_fold(
-                            None,
-                            |r, t| -> Result<Option<Option<SystemTime>>, CakeError> {
-                                let t = t?;
-                                Ok(Some(if let Some(r) = r {
-                                    match (r, t) {
-                                        (None, _) | (_, None) => None,
-                                        (Some(r), Some(t)) => Some(std::cmp::max(t, r)),
-                                    }
-                                } else {
-                                    t
-                                }))
-                            },
-                        );
-
-                    let target_mod_time = match target_mod_time {
-                        Ok(time) => time.unwrap_or(None),
-                        Err(err) => return Some(Err(err)),
-                    };
+        let upstream_mod_time = task
+            .upstream
+            .iter()
+            .filter_map(|prerequisite| match prerequisite {
+                Prerequisite::Named(file, optional) => match stat.modified(file) {
+                    Ok(time) => Some(Ok(time)),
+                    Err(_) if *optional => None,
+                    Err(err) => Some(Err(CakeError::PrerequisiteMissing(file.to_path_buf(), err))),
+                },
+                Prerequisite::Handle(handle) => upstream_time(*handle).map(Ok),
+            })
+            .try_fold(None, |r, t| -> Result<Option<SystemTime>, CakeError> {
+                let t = t?;
+                Ok(Some(if let Some(r) = r {
+                    std::cmp::max(t, r)
+                } else {
+                    t
+                }))
+            })?;
 
-                    let (mod_time, r) = match (target_mod_time, upstream_mod_time) {
-                        (Some(target), Some(upstream)) => {
-                            if upstream > target {
-                                (Some(now), Some(Ok((TaskHandle::new(index), task))))
-                            } else {
-                                (Some(target), None)
-                            }
-                        }
-                        (Some(target), None) => (Some(target), None),
-                        (None, _) => (Some(now), Some(Ok((TaskHandle::new(index), task)))),
-                    };
+        // Whatever generators left behind when they last ran counts as well.
+        let late_mod_time = task
+            .found_late_inputs()
+            .iter()
+            .filter_map(|input| stat.modified(input).ok())
+            .max();
+        let upstream_mod_time = std::cmp::max(upstream_mod_time, late_mod_time);
 
-                    modification_times.push(mod_time);
+        // A group has nothing to run, and changes when what it gathers does.
+        if task.options.group {
+            return Ok((None, upstream_mod_time));
+        }
 
-                    r
-                },
-            )
-            .collect()
+        Ok(match (task.modified(stat)?, upstream_mod_time) {
+            (Some(target), _) if task.options.once => (None, Some(target)),
+            _ if task.options.phony => (Some(Reason::Phony), Some(now)),
+            _ if task.options.always => (Some(Reason::Always), Some(now)),
+            (None, _) => (Some(Reason::Missing), Some(now)),
+            _ if forced.contains(&handle) => (Some(Reason::Forced), Some(now)),
+            // Restat tasks which last left their targets as they were needn't
+            // run again against the same inputs.
+            (Some(target), _) if settled.contains(&handle) => (None, Some(target)),
+            (Some(target), Some(upstream)) => {
+                if upstream > target {
+                    (Some(Reason::Upstream), Some(now))
+                } else {
+                    (None, Some(target))
+                }
+            }
+            (Some(target), None) => (None, Some(target)),
+        })
     }
 }
 
@@ -506,12 +1475,20 @@ pub trait FrontEnd {
 pub enum GatherUnitsError {
     #[fail(display = "No such root unit")]
     NoRootUnit,
+    #[fail(display = "'{}' holds more than one unit: {}.", dir, files)]
+    AmbiguousUnit { dir: String, files: String },
     #[fail(display = "Bad sub-unit: '{}'.", file)]
     BadSubUnit { file: String },
     #[fail(display = "No front-end for '{}'.", file)]
     NoFrontEnd { file: String, ext: String },
     #[fail(display = "Sub-unit '{}' not under context.", file)]
     UnitNotInContext { file: String },
+    #[fail(display = "Sub-unit '{}' names a directory holding no unit.", dir)]
+    NoUnitIn { dir: String },
+    #[fail(display = "Units are sub-units of each other: {}.", _0)]
+    Cycle(String),
+    #[fail(display = "Sub-units are nested more than {} deep: {}.", depth, chain)]
+    TooDeep { depth: usize, chain: String },
     #[fail(display = "Failed to parse '{}'.", file)]
     ParseError {
         file: String,
@@ -520,17 +1497,50 @@ pub enum GatherUnitsError {
     },
 }
 
+// The units gathered so far, and how they were come upon.
+struct Gathered {
+    units: Vec<(path::PathBuf, Unit)>,
+    // The units being read, each a sub-unit of the one before.
+    chain: Vec<path::PathBuf>,
+    // Every unit read, whichever unit named it.
+    visited: collections::HashSet<path::PathBuf>,
+}
+
 pub struct Engine {
     frontends: collections::HashMap<ffi::OsString, Box<dyn FrontEnd>>,
+    profile: Option<String>,
+    options: sync::Arc<collections::BTreeMap<String, String>>,
+    max_depth: Option<usize>,
 }
 
 impl Engine {
     pub fn new() -> Self {
         Self {
             frontends: std::collections::HashMap::new(),
+            profile: None,
+            options: sync::Arc::default(),
+            max_depth: None,
         }
     }
 
+    /// Limits how deeply sub-units may be nested, those of the root unit
+    /// being one deep.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = Some(depth);
+    }
+
+    /// Sets the options the build was asked for, e.g. `-D LTO=on`, for
+    /// units to read.
+    pub fn set_options(&mut self, options: collections::BTreeMap<String, String>) {
+        self.options = sync::Arc::new(options);
+    }
+
+    /// Names the kind of build, e.g. `release`, for units to tailor their
+    /// tasks to.
+    pub fn set_profile(&mut self, profile: &str) {
+        self.profile = Some(profile.to_string());
+    }
+
     pub fn register_frontend<F>(&mut self, ext: &str, f: F)
     where
         F: FrontEnd + 'static,
@@ -542,16 +1552,51 @@ impl Engine {
         &self,
         dir: &path::Path
     ) -> Result<Vec<(path::PathBuf, Unit)>, GatherUnitsError> {
-        for (ext, frontend) in self.frontends.iter() {
-            let file = dir.join("asmbl").with_extension(ext);
-            if file.exists() {
-                let mut units = vec![];
-                let context: Vec<_> = dir.components().collect();
-                self.parse_unit(&context, dir, &file, frontend, &mut units)?;
-                return Ok(units);
+        // Each front-end reads the units named for it, so a project may mix
+        // them, but there's no telling which a directory means should it
+        // hold several.
+        let file = match self.units_in(dir).as_slice() {
+            [] => return Err(GatherUnitsError::NoRootUnit),
+            [file] => file.clone(),
+            files => {
+                let files: Vec<_> = files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                return Err(GatherUnitsError::AmbiguousUnit {
+                    dir: dir.display().to_string(),
+                    files: files.join(", "),
+                });
             }
-        }
-        Err(GatherUnitsError::NoRootUnit)
+        };
+        let frontend = &self.frontends[file.extension().unwrap()];
+        let mut gathered = Gathered {
+            units: vec![],
+            chain: vec![],
+            visited: collections::HashSet::new(),
+        };
+        let context: Vec<_> = dir.components().collect();
+        let vars = collections::BTreeMap::new();
+        self.parse_unit(&context, dir, &file, frontend, &vars, &mut gathered)?;
+        Ok(gathered.units)
+    }
+
+    /// The file holding the unit from which all others in `dir` are found.
+    pub fn root_unit(&self, dir: &path::Path) -> Option<path::PathBuf> {
+        self.units_in(dir).into_iter().next()
+    }
+
+    // The files in `dir` which some front-end could read as its unit, in
+    // order.
+    fn units_in(&self, dir: &path::Path) -> Vec<path::PathBuf> {
+        let mut files: Vec<_> = self
+            .frontends
+            .keys()
+            .map(|ext| dir.join("asmbl").with_extension(ext))
+            .filter(|file| file.exists())
+            .collect();
+        files.sort();
+        files
     }
 
     fn parse_unit<'v, 'p>(
@@ -560,12 +1605,66 @@ impl Engine {
         dir: &path::Path,
         file: &path::Path,
         frontend: &Box<dyn FrontEnd>,
-        units: &mut Vec<(path::PathBuf, Unit)>,
+        vars: &collections::BTreeMap<String, String>,
+        gathered: &mut Gathered,
     ) -> Result<(), GatherUnitsError> {
-        let unit_builder = UnitBuilder::new(context, dir.to_path_buf());
+        let context_dir: path::PathBuf = context.iter().collect();
+        let name = |file: &path::Path| {
+            let file = file.strip_prefix(&context_dir).unwrap_or(file);
+            file.to_string_lossy().into_owned()
+        };
+        if gathered.chain.iter().any(|unit| unit == file) {
+            let mut chain: Vec<_> = gathered.chain.iter().map(|unit| name(unit)).collect();
+            chain.push(name(file));
+            return Err(GatherUnitsError::Cycle(chain.join(" -> ")));
+        }
+        if let Some(depth) = self.max_depth.filter(|depth| gathered.chain.len() > *depth) {
+            let mut chain: Vec<_> = gathered.chain.iter().map(|unit| name(unit)).collect();
+            chain.push(name(file));
+            return Err(GatherUnitsError::TooDeep {
+                depth,
+                chain: chain.join(" -> "),
+            });
+        }
+        // Units shared by several others are read the once.
+        if !gathered.visited.insert(file.to_path_buf()) {
+            return Ok(());
+        }
+
+        let mut unit_builder = UnitBuilder::new(context, dir.to_path_buf());
+        if let Some(profile) = &self.profile {
+            unit_builder.set_profile(profile);
+        }
+        unit_builder.set_options(self.options.clone());
+        unit_builder.inherit_vars(vars);
 
         match frontend.parse_unit(&file, unit_builder) {
-            Ok(unit) => {
+            Ok(mut unit) => {
+                // Sub-units naming a directory mean the unit it holds, as
+                // for the context itself.
+                for sub_unit in unit.sub_units.iter_mut() {
+                    let dir = recipe::absolute(&context_dir, sub_unit);
+                    if !dir.is_dir() {
+                        continue;
+                    }
+                    match self.units_in(&dir).as_slice() {
+                        [] => {
+                            return Err(GatherUnitsError::NoUnitIn {
+                                dir: sub_unit.to_string_lossy().into_owned(),
+                            })
+                        }
+                        [file] => sub_unit.push(file.file_name().unwrap()),
+                        files => {
+                            let files: Vec<_> = files.iter().map(|file| name(file)).collect();
+                            return Err(GatherUnitsError::AmbiguousUnit {
+                                dir: sub_unit.to_string_lossy().into_owned(),
+                                files: files.join(", "),
+                            });
+                        }
+                    }
+                }
+
+                gathered.chain.push(file.to_path_buf());
                 for sub_unit in unit.sub_units.iter() {
                     let ext = sub_unit.extension().unwrap_or(ffi::OsStr::new(""));
 
@@ -577,20 +1676,22 @@ impl Engine {
                             ext: ext.to_string_lossy().into_owned(),
                         })?;
 
+                    // Sub-units are named relative to the context.
+                    let file = recipe::absolute(&context_dir, sub_unit);
                     self.parse_unit(
                         context,
-                        sub_unit
-                            .parent()
-                            .ok_or_else(|| GatherUnitsError::BadSubUnit {
-                                file: sub_unit.to_string_lossy().into_owned(),
-                            })?,
+                        file.parent().ok_or_else(|| GatherUnitsError::BadSubUnit {
+                            file: sub_unit.to_string_lossy().into_owned(),
+                        })?,
                         &file,
                         &frontend,
-                        units,
+                        &unit.vars,
+                        gathered,
                     )?;
                 }
+                gathered.chain.pop();
 
-                units.push((dir.to_path_buf(), unit));
+                gathered.units.push((dir.to_path_buf(), unit));
 
                 Ok(())
             }
@@ -601,3 +1702,1040 @@ impl Engine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{declare, spec};
+    use asmbl_utils::testing::TempDir;
+
+    // A single unit holding a chain of tasks, each consuming the last.
+    fn pipeline(context: &path::Path, targets: &[&str]) -> Vec<(path::PathBuf, Unit)> {
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        let mut upstream = None;
+        for target in targets {
+            let consumes = upstream.into_iter().map(PrerequisiteSpec::Handle).collect();
+            upstream = builder
+                .add_task(vec![target.to_string()], spec(consumes))
+                .unwrap()
+                .last();
+        }
+        vec![(context.to_path_buf(), builder.unit())]
+    }
+
+    #[test]
+    fn can_name_targets_after_upstream_targets() {
+        let context = path::Path::new("/project");
+        let units = pipeline(context, &["a.txt", "%f.o", "%f.a"]);
+        let tasks = TaskList::new(context, path::Path::new("../out"), units).unwrap();
+        let targets: Vec<_> = tasks
+            .into_iter()
+            .map(|task| task.targets[0].to_path_buf())
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                path::PathBuf::from("../out/a.txt"),
+                path::PathBuf::from("../out/a.o"),
+                path::PathBuf::from("../out/a.a"),
+            ]
+        );
+    }
+
+    // Everything exists, and always has.
+    struct Ancient;
+
+    impl Stat for Ancient {
+        fn modified(&self, _: &path::Path) -> std::io::Result<SystemTime> {
+            Ok(std::time::UNIX_EPOCH)
+        }
+    }
+
+    #[test]
+    fn phony_tasks_always_run() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let a = builder
+                .add_task(vec!["a".to_string()], spec(vec![]))
+                .unwrap();
+            let mut phony = spec(a.map(PrerequisiteSpec::Handle).collect());
+            phony.recipe = None;
+            phony.options.phony = true;
+            builder.add_task(vec!["all".to_string()], phony).unwrap();
+        });
+        let out_of_date: Vec<_> = tasks
+            .retain_out_of_date(
+                &Ancient,
+                &collections::HashSet::new(),
+                &collections::HashSet::new(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(_, task)| task.targets[0].to_path_buf())
+            .collect();
+        assert_eq!(out_of_date, vec![path::PathBuf::from("../out/all")]);
+    }
+
+    #[test]
+    fn phony_tasks_have_nothing_to_prepare() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let mut phony = spec(vec![]);
+            phony.recipe = None;
+            phony.options.phony = true;
+            builder.add_task(vec!["all".to_string()], phony).unwrap();
+        });
+        let all = tasks.get(tasks.find(path::Path::new("../out/all")).unwrap());
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        assert!(matches!(
+            all.prepare(&config),
+            Err(recipe::RecipePrepareError::NoRecipe)
+        ));
+        assert!(matches!(
+            all.render(&config),
+            Err(recipe::RecipePrepareError::NoRecipe)
+        ));
+    }
+
+    // Sources were edited after everything else was built.
+    struct Edited;
+
+    impl Stat for Edited {
+        fn modified(&self, path: &path::Path) -> std::io::Result<SystemTime> {
+            match path.extension() {
+                Some(extension) if extension == "c" => Ok(SystemTime::now()),
+                _ => Ok(std::time::UNIX_EPOCH),
+            }
+        }
+    }
+
+    #[test]
+    fn groups_change_with_their_files_but_never_run() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let files = |names: &[&str]| {
+                names
+                    .iter()
+                    .map(|name| PrerequisiteSpec::Named(path::PathBuf::from(name), false))
+                    .collect()
+            };
+            let mut group = |name: &str, files| {
+                builder
+                    .add_task(vec![name.to_string()], builtins::group(files))
+                    .unwrap()
+                    .map(PrerequisiteSpec::Handle)
+            };
+            let headers = group("headers", files(&["a.h"]));
+            let sources = group("sources", files(&["a.c"]));
+            let lib = group("lib", headers.chain(sources).collect());
+            let compile = spec(lib.collect());
+            builder.add_task(vec!["a.o".to_string()], compile).unwrap();
+        });
+        let out_of_date = |stat: &dyn Stat| -> Vec<_> {
+            tasks
+                .retain_out_of_date(
+                    stat,
+                    &collections::HashSet::new(),
+                    &collections::HashSet::new(),
+                )
+                .unwrap()
+                .into_iter()
+                .map(|(_, task)| task.targets[0].to_path_buf())
+                .collect()
+        };
+        assert!(out_of_date(&Ancient).is_empty());
+        let edited = vec![path::PathBuf::from("../out/a.o")];
+        assert_eq!(out_of_date(&Edited), edited);
+        let assumed = AssumeNew::new(&Ancient, vec![path::PathBuf::from("a.h")]);
+        assert_eq!(out_of_date(&assumed), edited);
+    }
+
+    #[test]
+    fn plans_say_why_tasks_are_to_run() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("a.c"), false);
+            let a = builder
+                .add_task(vec!["a.o".to_string()], spec(vec![source]))
+                .unwrap();
+            let b = builder
+                .add_task(vec!["b.o".to_string()], spec(vec![]))
+                .unwrap();
+            let objects = a.chain(b).map(PrerequisiteSpec::Handle).collect();
+            builder
+                .add_task(vec!["a".to_string()], spec(objects))
+                .unwrap();
+        });
+        let find = |target: &str| tasks.find(&path::Path::new("../out").join(target)).unwrap();
+
+        let durations = Durations::load(path::Path::new("/nonexistent")).unwrap();
+        let forced = [find("b.o")].iter().cloned().collect();
+        let plan = tasks
+            .plan(&Request {
+                stat: &Edited,
+                forced: &forced,
+                settled: &collections::HashSet::new(),
+                selected: None,
+                schedule: Schedule::Breadth,
+                first: Some(find("b.o")),
+                durations: &durations,
+            })
+            .unwrap();
+        assert_eq!(plan.order()[0], find("b.o"));
+        assert_eq!(plan.reason(find("b.o")), Some(Reason::Forced));
+        assert_eq!(plan.reason(find("a.o")), Some(Reason::Upstream));
+        assert_eq!(plan.reason(find("a")), Some(Reason::Upstream));
+        assert_eq!(plan.estimate(), std::time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn settled_restat_tasks_are_up_to_date() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("a.c"), false);
+            let mut task = spec(vec![source]);
+            task.options.restat = true;
+            let a = builder.add_task(vec!["a.h".to_string()], task).unwrap();
+            let object = spec(a.map(PrerequisiteSpec::Handle).collect());
+            builder.add_task(vec!["a.o".to_string()], object).unwrap();
+        });
+        let a = tasks.find(path::Path::new("../out/a.h")).unwrap();
+        let o = tasks.find(path::Path::new("../out/a.o")).unwrap();
+
+        let none = collections::HashSet::new();
+        let settled: collections::HashSet<_> = Some(a).into_iter().collect();
+        let out_of_date = |settled| {
+            let out_of_date = tasks.retain_out_of_date(&Edited, &none, settled).unwrap();
+            let handles = out_of_date.into_iter().map(|(handle, _)| handle);
+            handles.collect::<Vec<_>>()
+        };
+        assert_eq!(out_of_date(&none), vec![a, o]);
+        assert_eq!(out_of_date(&settled), vec![]);
+
+        // Once the restat task has run, what depends upon it is only out of
+        // date should its targets have changed.
+        assert!(!tasks.is_out_of_date(o, &Edited, &none, &none).unwrap());
+    }
+
+    #[test]
+    fn always_tasks_run_whereas_once_tasks_need_only_their_targets() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let mut always = spec(vec![]);
+            always.options.always = true;
+            builder
+                .add_task(vec!["version.h".to_string()], always)
+                .unwrap();
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("fetch.c"), false);
+            let mut once = spec(vec![source]);
+            once.options.once = true;
+            builder
+                .add_task(vec!["toolchain".to_string()], once)
+                .unwrap();
+        });
+
+        let forced = tasks.handles().collect();
+        let out_of_date: Vec<_> = tasks
+            .retain_out_of_date(&Edited, &forced, &collections::HashSet::new())
+            .unwrap()
+            .into_iter()
+            .map(|(_, task)| task.targets[0].to_path_buf())
+            .collect();
+        assert_eq!(out_of_date, vec![path::PathBuf::from("../out/version.h")]);
+    }
+
+    #[test]
+    fn cant_both_always_and_only_once_run() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        let mut task = spec(vec![]);
+        task.options.always = true;
+        task.options.once = true;
+        assert!(matches!(
+            builder.add_task(vec!["a".to_string()], task),
+            Err(unit::AddTaskError::AlwaysAndOnce)
+        ));
+    }
+
+    #[test]
+    fn includes_yet_to_be_produced_are_passed_over() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        let deps = builder
+            .add_task(vec!["a.o.d".to_string()], spec(vec![]))
+            .unwrap();
+        for include in deps {
+            builder.add_include(include);
+        }
+        let units = vec![(context.to_path_buf(), builder.unit())];
+        assert!(TaskList::new(context, path::Path::new("../out"), units).is_ok());
+    }
+
+    #[test]
+    fn tasks_need_targets() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        assert!(matches!(
+            builder.add_task(vec![], spec(vec![])),
+            Err(unit::AddTaskError::NoTargets)
+        ));
+    }
+
+    #[test]
+    fn cant_omit_recipes_from_files() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        let mut task = spec(vec![]);
+        task.recipe = None;
+        assert!(matches!(
+            builder.add_task(vec!["a".to_string()], task),
+            Err(unit::AddTaskError::NoRecipe)
+        ));
+    }
+
+    #[test]
+    fn can_consume_targets_by_glob() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            for target in ["gen/a.h", "gen/sub/b.h", "gen/c.c", "d.h"] {
+                builder
+                    .add_task(vec![target.to_string()], spec(vec![]))
+                    .unwrap();
+            }
+            let glob = PrerequisiteSpec::Glob(path::PathBuf::from("gen/**/*.h"));
+            builder
+                .add_task(vec!["all".to_string()], spec(vec![glob]))
+                .unwrap();
+        });
+        let all = tasks.find(path::Path::new("../out/all")).unwrap();
+        assert_eq!(
+            tasks.get(all).inputs,
+            vec![
+                sync::Arc::from(path::Path::new("../out/gen/a.h")),
+                sync::Arc::from(path::Path::new("../out/gen/sub/b.h")),
+            ]
+        );
+    }
+
+    #[test]
+    fn globs_must_match_something() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        builder
+            .add_task(vec!["gen/a.c".to_string()], spec(vec![]))
+            .unwrap();
+        let glob = PrerequisiteSpec::Glob(path::PathBuf::from("gen/*.h"));
+        builder
+            .add_task(vec!["all".to_string()], spec(vec![glob]))
+            .unwrap();
+        let units = vec![(context.to_path_buf(), builder.unit())];
+        assert!(matches!(
+            TaskList::new(context, path::Path::new("../out"), units),
+            Err(NewTaskListError::UnmatchedGlob(pattern)) if pattern == "gen/*.h"
+        ));
+    }
+
+    #[test]
+    fn can_consume_generated_files() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let gen = builder
+                .add_task(vec!["gen".to_string()], spec(vec![]))
+                .unwrap()
+                .last()
+                .unwrap();
+            let generated = PrerequisiteSpec::Late(gen, String::from("**/*.c"));
+            builder
+                .add_task(vec!["lib.a".to_string()], spec(vec![generated]))
+                .unwrap();
+        });
+        let lib = tasks.get(tasks.find(path::Path::new("../out/lib.a")).unwrap());
+        assert!(lib.inputs.is_empty());
+        assert_eq!(lib.late_inputs, vec![String::from("../out/gen/**/*.c")]);
+        assert!(matches!(
+            lib.upstream[..],
+            [Prerequisite::Handle(handle)] if handle == tasks.find(path::Path::new("../out/gen")).unwrap()
+        ));
+    }
+
+    #[test]
+    fn cant_build_cycles() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        let named = PrerequisiteSpec::Named(path::PathBuf::from("../out/b"), false);
+        let a = builder
+            .add_task(vec!["a".to_string()], spec(vec![named]))
+            .unwrap()
+            .last();
+        let consumes = a.into_iter().map(PrerequisiteSpec::Handle).collect();
+        builder
+            .add_task(vec!["b".to_string()], spec(consumes))
+            .unwrap();
+        let units = vec![(context.to_path_buf(), builder.unit())];
+        match TaskList::new(context, path::Path::new("../out"), units) {
+            Err(NewTaskListError::Cycle(cycle)) => {
+                let mut targets = cycle.targets().to_vec();
+                targets.sort();
+                assert_eq!(
+                    targets,
+                    vec![
+                        path::PathBuf::from("../out/a"),
+                        path::PathBuf::from("../out/b"),
+                    ]
+                );
+            }
+            _ => panic!("Expected a cycle"),
+        }
+    }
+
+    #[test]
+    fn can_build_deep_pipelines() {
+        let context = path::Path::new("/project");
+        let targets: Vec<_> = (0..100_000).map(|index| format!("t{}", index)).collect();
+        let targets: Vec<_> = targets.iter().map(|target| target.as_str()).collect();
+        let tasks = TaskList::new(
+            context,
+            path::Path::new("../out"),
+            pipeline(context, &targets),
+        )
+        .unwrap();
+        assert_eq!(tasks.tasks.len(), 100_000);
+        for (index, task) in tasks.tasks.iter().enumerate() {
+            for upstream in task.upstream.iter() {
+                if let Prerequisite::Handle(handle) = upstream {
+                    assert!(handle.index < index);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn announcing_tasks_differently_doesnt_change_them() {
+        let plain = TaskOptions::default();
+        let described = TaskOptions {
+            description: Some("Compiling".to_string()),
+            echo: false,
+            ..TaskOptions::default()
+        };
+        let offline = TaskOptions {
+            network: false,
+            ..TaskOptions::default()
+        };
+        assert_eq!(plain.identity(), described.identity());
+        assert_ne!(plain.identity(), offline.identity());
+    }
+
+    #[test]
+    fn option_identities_tell_options_apart() {
+        let identity = |options: TaskOptions| options.identity();
+        let wrapped = |wrapper: &[&str]| TaskOptions {
+            wrapper: wrapper.iter().map(|s| s.to_string()).collect(),
+            ..TaskOptions::default()
+        };
+        assert_eq!(
+            identity(wrapped(&["ccache"])),
+            "inherit_all_env=false wrapper=1[6:ccache] image=- launcher=- network=true \
+             result=- stdout=- stdin=- aggregate=true phony=false group=false depfile=- \
+             produces=0[] cwd=- tools=0[] sandbox_allow=0[] build_options=0[]"
+        );
+        // Lists are told apart however they're split.
+        assert_ne!(
+            identity(wrapped(&["a b", "c"])),
+            identity(wrapped(&["a", "b c"]))
+        );
+        assert_ne!(
+            identity(TaskOptions {
+                produces: vec!["a".to_string()],
+                depfile: Some("b".to_string()),
+                ..TaskOptions::default()
+            }),
+            identity(TaskOptions {
+                produces: vec!["a".to_string(), "b".to_string()],
+                ..TaskOptions::default()
+            })
+        );
+        // Unread options differ from empty ones.
+        let read = |value: Option<&str>| TaskOptions {
+            build_options: Some(("LTO".to_string(), value.map(String::from)))
+                .into_iter()
+                .collect(),
+            ..TaskOptions::default()
+        };
+        assert_ne!(identity(read(None)), identity(read(Some(""))));
+        assert_ne!(identity(read(Some("-"))), identity(read(None)));
+    }
+
+    #[test]
+    fn can_tell_edges_apart() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let a = builder
+                .add_task(vec!["a".to_string()], spec(vec![]))
+                .unwrap();
+            let b = builder
+                .add_task(vec!["b".to_string()], spec(vec![]))
+                .unwrap();
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("c.in"), false);
+            let mut task = spec(vec![source]);
+            task.depends_on = a.map(PrerequisiteSpec::Handle).collect();
+            task.not_before = b.map(PrerequisiteSpec::Handle).collect();
+            builder.add_task(vec!["c".to_string()], task).unwrap();
+        });
+        let find = |target| tasks.find(path::Path::new(target)).unwrap();
+        let c = find("../out/c");
+        assert_eq!(
+            tasks.upstream(c).collect::<Vec<_>>(),
+            vec![
+                (Upstream::File(path::Path::new("c.in")), Edge::Consumes),
+                (Upstream::Task(find("../out/a")), Edge::DependsOn),
+                (Upstream::Task(find("../out/b")), Edge::NotBefore),
+            ]
+        );
+    }
+
+    #[test]
+    fn files_produced_besides_targets_are_depended_upon_through_their_producer() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let mut docs = spec(vec![]);
+            docs.options.produces = vec!["docs/**/*.html".to_string()];
+            builder.add_task(vec!["docs".to_string()], docs).unwrap();
+            let page =
+                PrerequisiteSpec::Named(path::PathBuf::from("../out/docs/api/index.html"), false);
+            builder
+                .add_task(vec!["index.tar".to_string()], spec(vec![page]))
+                .unwrap();
+        });
+        let find = |target| tasks.find(path::Path::new(target)).unwrap();
+        assert_eq!(
+            tasks.upstream(find("../out/index.tar")).collect::<Vec<_>>(),
+            vec![(Upstream::Task(find("../out/docs")), Edge::Consumes)]
+        );
+        assert!(tasks.get(find("../out/docs")).products().is_empty());
+    }
+
+    #[test]
+    fn executing_tasks_makes_their_targets_directories() {
+        let context = TempDir::new("execute");
+        let tasks = declare(&context, &context, |builder| {
+            builder
+                .add_task(vec!["obj/a.o".to_string()], spec(vec![]))
+                .unwrap();
+        });
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        let a = tasks.find(&context.join("obj/a.o")).unwrap();
+        let status = tasks.get(a).execute(&config, |_| {}).unwrap().wait();
+        assert!(status.unwrap().success());
+        assert!(context.join("obj").is_dir());
+    }
+
+    #[test]
+    fn tasks_sharing_a_target_are_traced_to_their_declarations() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        for line in &[Some(3), None] {
+            let origin = Origin {
+                unit: context.join("asmbl.lua"),
+                line: *line,
+            };
+            let spec = TaskSpec {
+                origin: Some(origin),
+                ..spec(vec![])
+            };
+            builder.add_task(vec!["a".to_string()], spec).unwrap();
+        }
+        let units = vec![(context.to_path_buf(), builder.unit())];
+        let err = TaskList::new(context, path::Path::new("../out"), units).err();
+        assert_eq!(
+            err.unwrap().to_string(),
+            "Target '../out/a' is produced by more than one task \
+             (declared at /project/asmbl.lua:3 and /project/asmbl.lua)."
+        );
+    }
+
+    #[test]
+    fn atomic_tasks_targets_are_moved_into_place_once_they_succeed() {
+        let context = TempDir::new("atomic");
+        let tasks = declare(&context, &context, |builder| {
+            let mut spec = spec(vec![]);
+            spec.recipe = Some(Recipe::parse("touch $@").unwrap());
+            spec.options.atomic = true;
+            builder.add_task(vec!["a".to_string()], spec).unwrap();
+        });
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        let a = tasks.get(tasks.find(&context.join("a")).unwrap());
+        let status = a.execute(&config, |_| {}).unwrap().wait().unwrap();
+        assert!(!context.join("a").exists());
+        a.finish(&config, status.success()).unwrap();
+        assert!(context.join("a").exists());
+        assert!(!context.join(".asmbl-tmp.a").exists());
+    }
+
+    #[test]
+    fn tasks_are_declared_only_when_their_probes_hold() {
+        let context = TempDir::new("probe");
+        fs::create_dir_all(context.join("vendor")).unwrap();
+
+        let components: Vec<_> = context.components().collect();
+        let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+        for (target, probe) in [("a", "vendor"), ("b", "missing")] {
+            let mut spec = spec(vec![]);
+            spec.options.enabled_when = vec![Probe::Exists(path::PathBuf::from(probe))];
+            builder.add_task(vec![target.to_string()], spec).unwrap();
+        }
+        let unit = builder.unit();
+        let probed = unit.probed.clone();
+        let units = vec![(context.to_path_buf(), unit)];
+        let tasks = TaskList::new(&context, &context, units).unwrap();
+
+        assert!(tasks.find(&context.join("a")).is_some());
+        assert!(tasks.find(&context.join("b")).is_none());
+        assert_eq!(
+            probed,
+            vec![context.join("vendor"), context.join("missing")]
+        );
+    }
+
+    #[test]
+    fn stdout_can_be_written_to_a_target() {
+        let context = TempDir::new("stdout");
+        let tasks = declare(&context, &context, |builder| {
+            let mut spec = spec(vec![]);
+            spec.recipe = Some(Recipe::parse("echo $@[1]").unwrap());
+            spec.options.stdout = Some(target_index("$@[0]").unwrap());
+            let targets = vec!["out/a.txt".to_string(), "b".to_string()];
+            builder.add_task(targets, spec).unwrap();
+        });
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        let a = tasks.get(tasks.find(&context.join("out/a.txt")).unwrap());
+        let mut child = a
+            .execute(&config, |cmd| {
+                cmd.stdout(std::process::Stdio::piped());
+            })
+            .unwrap();
+        assert!(child.stdout.is_none());
+        assert!(child.wait().unwrap().success());
+        assert_eq!(
+            fs::read_to_string(context.join("out/a.txt")).unwrap(),
+            format!("{}\n", context.join("b").display())
+        );
+    }
+
+    #[test]
+    fn stdin_can_be_read_from_an_input() {
+        let context = TempDir::new("stdin");
+        fs::write(context.join("in.txt"), "hello").unwrap();
+        let tasks = declare(&context, &context, |builder| {
+            let mut spec = spec(vec![PrerequisiteSpec::Named(
+                path::PathBuf::from("in.txt"),
+                false,
+            )]);
+            spec.recipe = Some(Recipe::parse("cat").unwrap());
+            spec.options.stdin = Some(input_index("$<[0]").unwrap());
+            builder.add_task(vec!["a".to_string()], spec).unwrap();
+        });
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        let a = tasks.get(tasks.find(&context.join("a")).unwrap());
+        let output = a
+            .execute(&config, |cmd| {
+                cmd.stdout(std::process::Stdio::piped());
+            })
+            .unwrap()
+            .wait_with_output();
+        assert_eq!(output.unwrap().stdout, b"hello");
+    }
+
+    #[test]
+    fn targets_are_listed_with_the_tasks_producing_them() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            let a = builder
+                .add_task(vec!["a".to_string(), "a.map".to_string()], spec(vec![]))
+                .unwrap();
+            let consumes = a.take(1).map(PrerequisiteSpec::Handle).collect();
+            builder
+                .add_task(vec!["b".to_string()], spec(consumes))
+                .unwrap();
+        });
+        let find = |target: &str| tasks.find(&context.join(target)).unwrap();
+
+        let targets: Vec<_> = tasks.targets().collect();
+        assert_eq!(
+            targets,
+            vec![
+                (context.join("a").as_path(), find("a")),
+                (context.join("a.map").as_path(), find("a")),
+                (context.join("b").as_path(), find("b")),
+            ]
+        );
+        assert_ne!(find("a"), find("b"));
+        assert_eq!(tasks.find(&context.join("c")), None);
+    }
+
+    #[test]
+    fn task_lists_may_be_shared_between_threads() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            for target in &["a", "b", "c"] {
+                builder
+                    .add_task(vec![target.to_string()], spec(vec![]))
+                    .unwrap();
+            }
+        });
+
+        let tasks = &tasks;
+        let found: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = ["a", "b", "c"]
+                .iter()
+                .map(|target| {
+                    let target = context.join(target);
+                    scope.spawn(move || tasks.find(&target))
+                })
+                .collect();
+            let found = workers.into_iter().map(|worker| worker.join().unwrap());
+            found.collect()
+        });
+        let handles: Vec<_> = tasks.handles().map(Some).collect();
+        assert_eq!(found, handles);
+    }
+
+    #[test]
+    fn only_options_a_unit_read_bear_upon_its_tasks() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let identity = |options: &[(&str, &str)]| {
+            let options = options
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+            builder.set_options(sync::Arc::new(options));
+            assert_eq!(builder.option("LTO"), Some("on"));
+            builder.option("MISSING");
+            builder
+                .add_task(vec!["a".to_string()], spec(vec![]))
+                .unwrap();
+            let units = vec![(context.to_path_buf(), builder.unit())];
+            let tasks = TaskList::new(context, context, units).unwrap();
+            let a = tasks.get(tasks.find(&context.join("a")).unwrap());
+            a.options.identity()
+        };
+
+        let lto = identity(&[("LTO", "on")]);
+        assert_eq!(lto, identity(&[("LTO", "on"), ("OTHER", "1")]));
+        assert_ne!(lto, identity(&[("LTO", "on"), ("MISSING", "1")]));
+    }
+
+    #[test]
+    fn sub_units_inherit_and_override_variables() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let mut parent = UnitBuilder::new(&components, context.to_path_buf());
+        let set = |builder: &mut UnitBuilder, name: &str, value: &str| {
+            builder.set_var(name.to_string(), value.to_string())
+        };
+        set(&mut parent, "WARN", "-Wall").unwrap();
+        set(&mut parent, "CFLAGS", "-O2").unwrap();
+        assert!(set(&mut parent, "inputs", "").is_err());
+        assert!(set(&mut parent, "C C", "").is_err());
+        let parent = parent.unit();
+
+        let mut child = UnitBuilder::new(&components, context.join("lib"));
+        child.inherit_vars(&parent.vars);
+        set(&mut child, "CFLAGS", "-O3").unwrap();
+        assert_eq!(child.var("WARN"), Some("-Wall"));
+        let mut spec = spec(vec![]);
+        spec.recipe = Some(Recipe::parse("/bin/cc $WARN $CFLAGS -o $@").unwrap());
+        child.add_task(vec!["a".to_string()], spec).unwrap();
+        let units = vec![
+            (context.to_path_buf(), parent),
+            (context.join("lib"), child.unit()),
+        ];
+        let tasks = TaskList::new(context, context, units).unwrap();
+        let a = tasks.get(tasks.find(&context.join("lib/a")).unwrap());
+
+        let mut config = Config::new(context.to_path_buf(), context.to_path_buf());
+        assert!(a
+            .render(&config)
+            .unwrap()
+            .ends_with("-Wall -O3 -o /project/lib/a"));
+        // Whatever the build was asked for takes precedence.
+        config
+            .variables
+            .insert(String::from("WARN"), String::from("-Werror"));
+        assert!(a.render(&config).unwrap().contains("-Werror -O3"));
+    }
+
+    #[test]
+    fn rendered_commands_dont_give_away_secrets() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            let mut spec = spec(vec![]);
+            spec.env = vec![EnvSpec::define(
+                String::from("API_TOKEN"),
+                String::from("hunter2"),
+            )];
+            spec.recipe = Some(Recipe::parse("/bin/cc -o $@").unwrap());
+            builder.add_task(vec!["a".to_string()], spec).unwrap();
+        });
+        let a = tasks.get(tasks.find(&context.join("a")).unwrap());
+        let config = Config::new(context.to_path_buf(), context.to_path_buf());
+        assert_eq!(a.render(&config).unwrap(), "/bin/cc -o /project/a");
+    }
+
+    #[test]
+    fn environment_changes_are_told_variable_by_variable() {
+        let target = TempDir::new("env");
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            builder
+                .add_task(vec!["a".to_string()], spec(vec![]))
+                .unwrap();
+        });
+        let a = tasks.get(tasks.find(&context.join("a")).unwrap());
+
+        let define = |name: &str, value: &str| EnvSpec::define(name.to_string(), value.to_string());
+        let mut config = Config::new(context.to_path_buf(), target.to_path_buf());
+        config.env = vec![
+            define("CC", "gcc"),
+            define("CFLAGS", "-O2\n-g"),
+            define("API_TOKEN", "abc"),
+        ];
+        let mut state = State::load(&target).unwrap();
+        state.record(a, &config);
+        state.save().unwrap();
+
+        config.env = vec![
+            define("CFLAGS", "-O3"),
+            define("API_TOKEN", "def"),
+            define("LANG", "C"),
+        ];
+        let state = State::load(&target).unwrap();
+        let changes: Vec<_> = state
+            .env_changes(a, &config)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "API_TOKEN: <redacted> -> <redacted>",
+                "-CC=\"gcc\"",
+                "CFLAGS: \"-O2\\n-g\" -> \"-O3\"",
+                "+LANG=\"C\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn units_colliding_over_a_target_are_both_named() {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        let units = [("a", "%f.o"), ("b", "../a/%f.o")]
+            .iter()
+            .map(|(dir, target)| {
+                let dir = context.join(dir);
+                let mut builder = UnitBuilder::new(&components, dir.clone());
+                let consumes = vec![PrerequisiteSpec::Named(dir.join("x.c"), false)];
+                builder
+                    .add_task(vec![target.to_string()], spec(consumes))
+                    .unwrap();
+                (dir, builder.unit())
+            })
+            .collect::<Vec<_>>();
+        let err = TaskList::new(context, path::Path::new("../out"), units).err();
+        assert_eq!(
+            err.unwrap().to_string(),
+            "Units in '/project/a' and '/project/b' both produce '../out/a/x.o', \
+             from 'a/%f.o' and 'a/%f.o' respectively."
+        );
+    }
+
+    #[test]
+    fn tasks_are_summarised_by_program_and_input() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            let input = PrerequisiteSpec::Named(context.join("src/foo.c"), false);
+            let mut compile = spec(vec![input]);
+            compile.recipe = Some(Recipe::parse("/usr/bin/cc -c $< -o $@").unwrap());
+            let mut link = spec(vec![]);
+            link.options.description = Some(String::from("Linking"));
+            builder.add_task(vec!["a.o".to_string()], compile).unwrap();
+            builder.add_task(vec!["a".to_string()], link).unwrap();
+        });
+        let summary = |target| {
+            let task = tasks.find(path::Path::new(target)).unwrap();
+            tasks.get(task).summary()
+        };
+        assert_eq!(summary("../out/a.o"), "CC src/foo.c");
+        assert_eq!(summary("../out/a"), "Linking");
+    }
+
+    #[test]
+    fn can_depend_upon_whatever_depfiles_list() {
+        let context = TempDir::new("depfile");
+        let header = context.join("a.h");
+        fs::write(
+            context.join("a.o.d"),
+            format!("a.o: a.c \\\n  {}\n", header.display()),
+        )
+        .unwrap();
+
+        let tasks = declare(&context, &context, |builder| {
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("a.c"), false);
+            let mut task = spec(vec![source]);
+            task.options.depfile = Some(String::from("$@[0].d"));
+            builder.add_task(vec!["a.o".to_string()], task).unwrap();
+        });
+
+        let a = tasks.find(&context.join("a.o")).unwrap();
+        assert_eq!(
+            tasks.get(a).depfile(),
+            Some(context.join("a.o.d").as_path())
+        );
+        assert_eq!(
+            tasks.upstream(a).collect::<Vec<_>>(),
+            vec![
+                (Upstream::File(path::Path::new("a.c")), Edge::Consumes),
+                (Upstream::File(path::Path::new("a.h")), Edge::Discovered),
+            ]
+        );
+    }
+
+    // Declares a single task, named after the front-end, whatever the unit
+    // holds.
+    struct Stub(&'static str);
+
+    impl FrontEnd for Stub {
+        fn parse_unit<'v, 'p>(
+            &self,
+            _: &path::Path,
+            mut unit_builder: UnitBuilder<'v, 'p>,
+        ) -> Result<Unit, ParseUnitError> {
+            unit_builder
+                .add_task(vec![self.0.to_string()], spec(vec![]))
+                .unwrap();
+            Ok(unit_builder.unit())
+        }
+    }
+
+    #[test]
+    fn units_are_read_by_the_front_end_for_their_extension() {
+        let context = TempDir::new("units");
+        let mut engine = Engine::new();
+        engine.register_frontend("lua", Stub("lua"));
+        engine.register_frontend("toml", Stub("toml"));
+
+        fs::write(context.join("asmbl.toml"), "").unwrap();
+        let units = engine.gather_units(&context).unwrap();
+        let tasks = TaskList::new(&context, path::Path::new("../out"), units).unwrap();
+        let targets: Vec<_> = tasks.targets().map(|(target, _)| target).collect();
+        assert_eq!(targets, vec![path::Path::new("../out/toml")]);
+
+        fs::write(context.join("asmbl.lua"), "").unwrap();
+        assert!(matches!(
+            engine.gather_units(&context),
+            Err(GatherUnitsError::AmbiguousUnit { .. })
+        ));
+        assert_eq!(engine.root_unit(&context), Some(context.join("asmbl.lua")));
+    }
+
+    // Takes each line of a unit to name a sub-unit, and declares a single
+    // task besides.
+    struct Lines;
+
+    impl FrontEnd for Lines {
+        fn parse_unit<'v, 'p>(
+            &self,
+            path: &path::Path,
+            mut unit_builder: UnitBuilder<'v, 'p>,
+        ) -> Result<Unit, ParseUnitError> {
+            for line in fs::read_to_string(path)?.lines() {
+                unit_builder.add_sub_unit(path::PathBuf::from(line))?;
+            }
+            unit_builder
+                .add_task(vec![String::from("unit")], spec(vec![]))
+                .unwrap();
+            Ok(unit_builder.unit())
+        }
+    }
+
+    #[test]
+    fn shared_sub_units_are_read_once_and_cycles_refused() {
+        let context = TempDir::new("sub-units");
+        for dir in &["a", "b", "c"] {
+            fs::create_dir_all(context.join(dir)).unwrap();
+        }
+        let mut engine = Engine::new();
+        engine.register_frontend("lua", Lines);
+        engine.register_frontend("toml", Lines);
+
+        fs::write(context.join("asmbl.lua"), "a/asmbl.toml\nb/asmbl.lua\n").unwrap();
+        fs::write(context.join("a/asmbl.toml"), "../c/asmbl.lua\n").unwrap();
+        fs::write(context.join("b/asmbl.lua"), "../c/asmbl.lua\n").unwrap();
+        fs::write(context.join("c/asmbl.lua"), "").unwrap();
+        let units = engine.gather_units(&context).unwrap();
+        let dirs: Vec<_> = units.iter().map(|(dir, _)| dir.clone()).collect();
+        let expected: Vec<_> = ["c", "a", "b", ""]
+            .iter()
+            .map(|dir| context.join(dir))
+            .collect();
+        assert_eq!(dirs, expected);
+
+        fs::write(context.join("c/asmbl.lua"), "../a/asmbl.toml\n").unwrap();
+        match engine.gather_units(&context) {
+            Err(GatherUnitsError::Cycle(chain)) => assert_eq!(
+                chain,
+                "asmbl.lua -> a/asmbl.toml -> c/asmbl.lua -> a/asmbl.toml"
+            ),
+            gathered => panic!("{:?}", gathered.map(|units| units.len())),
+        }
+    }
+
+    #[test]
+    fn sub_units_may_name_directories_as_deep_as_allowed() {
+        let context = TempDir::new("sub-dirs");
+        fs::create_dir_all(context.join("a/b")).unwrap();
+        let mut engine = Engine::new();
+        engine.register_frontend("lua", Lines);
+        engine.register_frontend("toml", Lines);
+
+        fs::write(context.join("asmbl.lua"), "a\n").unwrap();
+        fs::write(context.join("a/asmbl.toml"), "b\n").unwrap();
+        fs::write(context.join("a/b/asmbl.lua"), "").unwrap();
+        let units = engine.gather_units(&context).unwrap();
+        let sub_units: Vec<_> = units
+            .iter()
+            .flat_map(|(_, unit)| unit.sub_units.iter().cloned())
+            .collect();
+        assert_eq!(
+            sub_units,
+            vec![
+                path::PathBuf::from("a/b/asmbl.lua"),
+                path::PathBuf::from("a/asmbl.toml"),
+            ]
+        );
+
+        engine.set_max_depth(1);
+        match engine.gather_units(&context) {
+            Err(GatherUnitsError::TooDeep { depth: 1, chain }) => {
+                assert_eq!(chain, "asmbl.lua -> a/asmbl.toml -> a/b/asmbl.lua")
+            }
+            gathered => panic!("{:?}", gathered.map(|units| units.len())),
+        }
+
+        fs::remove_file(context.join("a/b/asmbl.lua")).unwrap();
+        assert!(matches!(
+            engine.gather_units(&context),
+            Err(GatherUnitsError::NoUnitIn { .. })
+        ));
+    }
+}
@@ -1,7 +1,17 @@
+use std::{collections, io};
+
 mod parser;
 
 pub use parser::Error as ParserError;
 
+#[derive(Debug, failure::Fail)]
+pub enum StreamError {
+    #[fail(display = "Failed to read make file")]
+    IoError(#[fail(cause)] io::Error),
+    #[fail(display = "Failed to parse make file")]
+    ParserError(#[fail(cause)] ParserError),
+}
+
 pub struct Iterator<'a> {
   rules: Vec<parser::Rule<'a>>,
   rule_index: usize,
@@ -51,6 +61,84 @@ impl<'a> std::iter::Iterator for Iterator<'a> {
   }
 }
 
-pub fn cake(i: &str) -> Result<Iterator, parser::Error> {
+fn cake(i: &str) -> Result<Iterator, parser::Error> {
     Ok(Iterator::new(parser::parse(i)?))
 }
+
+/// Yields each (target, prerequisite) pair of a make file as it's read,
+/// holding no more than one rule in memory at a time.
+pub struct Stream<R> {
+    lines: io::Lines<R>,
+    rule: String,
+    pairs: collections::VecDeque<(String, String)>,
+}
+
+impl<R: io::BufRead> Stream<R> {
+    fn flush(&mut self) -> Result<(), StreamError> {
+        for (target, prerequisite) in cake(&self.rule).map_err(StreamError::ParserError)? {
+            self.pairs.push_back((target.to_string(), prerequisite.to_string()));
+        }
+        self.rule.clear();
+        Ok(())
+    }
+}
+
+impl<R: io::BufRead> std::iter::Iterator for Stream<R> {
+    type Item = Result<(String, String), StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(pair) = self.pairs.pop_front() {
+                return Some(Ok(pair));
+            }
+
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.rule.push_str(&line);
+                    self.rule.push('\n');
+                    // A rule carries on for as long as its lines are escaped.
+                    if !line.ends_with('\\') {
+                        if let Err(err) = self.flush() {
+                            return Some(Err(err));
+                        }
+                    }
+                }
+                Some(Err(err)) => return Some(Err(StreamError::IoError(err))),
+                None if self.rule.is_empty() => return None,
+                None => {
+                    if let Err(err) = self.flush() {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn stream<R: io::BufRead>(reader: R) -> Stream<R> {
+    Stream {
+        lines: reader.lines(),
+        rule: String::new(),
+        pairs: collections::VecDeque::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_stream_rules() {
+        let pairs: Vec<_> = stream(io::Cursor::new("a.o: a.c \\\n  a.h\n\na.h:\nb.o: b.c"))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                (String::from("a.o"), String::from("a.c")),
+                (String::from("a.o"), String::from("a.h")),
+                (String::from("b.o"), String::from("b.c")),
+            ]
+        );
+    }
+}
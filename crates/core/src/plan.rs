@@ -0,0 +1,118 @@
+use std::{collections, fmt, time};
+
+use crate::{CakeError, Durations, Schedule, Stat, TaskHandle, TaskList};
+
+/// Why a task is to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// Its targets merely name it, so it runs every time.
+    Phony,
+    /// It's declared to run every time.
+    Always,
+    /// It was asked to run regardless, e.g. having failed last time.
+    Forced,
+    /// Some of its targets are missing.
+    Missing,
+    /// Something upstream has changed since its targets were written.
+    Upstream,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Phony => "it's phony",
+            Self::Always => "it always runs",
+            Self::Forced => "it failed or changed since it last ran",
+            Self::Missing => "its targets are missing",
+            Self::Upstream => "something it depends upon changed",
+        })
+    }
+}
+
+/// What a build is asked to do.
+pub struct Request<'a> {
+    pub stat: &'a dyn Stat,
+    /// Tasks to run whatever their targets look like.
+    pub forced: &'a collections::HashSet<TaskHandle>,
+    /// Restat tasks which last left their targets as they were.
+    pub settled: &'a collections::HashSet<TaskHandle>,
+    /// The only tasks to consider, when not all of them.
+    pub selected: Option<&'a collections::HashSet<TaskHandle>>,
+    pub schedule: Schedule,
+    /// A task whose upstream is to come before everything else.
+    pub first: Option<TaskHandle>,
+    /// How long tasks took last time, for ordering and estimates.
+    pub durations: &'a Durations,
+}
+
+/// The tasks a build is to run, why, and in which order, worked out
+/// without running anything.
+#[derive(Debug)]
+pub struct Plan {
+    order: Vec<TaskHandle>,
+    reasons: collections::HashMap<TaskHandle, Reason>,
+    estimate: time::Duration,
+}
+
+impl Plan {
+    /// The tasks to run, in the order they're to be started.
+    pub fn order(&self) -> &[TaskHandle] {
+        &self.order
+    }
+
+    /// Why the task is to run, if it is.
+    pub fn reason(&self, handle: TaskHandle) -> Option<Reason> {
+        self.reasons.get(&handle).cloned()
+    }
+
+    /// How long running every task one after another should take, going
+    /// by how long each took last time.
+    pub fn estimate(&self) -> time::Duration {
+        self.estimate
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+impl TaskList {
+    /// Works out which tasks are out of date and the order to run them in.
+    pub fn plan(&self, request: &Request) -> Result<Plan, CakeError> {
+        let reasons: collections::HashMap<_, _> = self
+            .dirty(request.stat, request.forced, request.settled)?
+            .into_iter()
+            .filter(|(handle, _)| {
+                request
+                    .selected
+                    .is_none_or(|selected| selected.contains(handle))
+            })
+            .collect();
+
+        let estimate = |handle: &TaskHandle| request.durations.estimate(self.get(*handle));
+        let weight = |task: &crate::Task| {
+            request
+                .durations
+                .estimate(task)
+                .map_or(1, |duration| duration.as_millis() as u64)
+        };
+
+        let dirty: Vec<_> = reasons.keys().cloned().collect();
+        let mut order = self.schedule(&dirty, request.schedule, weight);
+        if let Some(first) = request.first {
+            // Everything the task needs comes first, otherwise keeping to
+            // the schedule.
+            let needed = self.upstream_closure(Some(first));
+            let (needed, rest): (Vec<_>, Vec<_>) = order
+                .into_iter()
+                .partition(|handle| needed.contains(handle));
+            order = needed.into_iter().chain(rest).collect();
+        }
+
+        Ok(Plan {
+            estimate: order.iter().filter_map(estimate).sum(),
+            order,
+            reasons,
+        })
+    }
+}
@@ -0,0 +1,31 @@
+use std::{path, process};
+
+/// Something found out about the context or the machine as a unit is read,
+/// upon which whether its tasks are declared at all may depend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// Whether a file or directory exists, relative to the unit.
+    Exists(path::PathBuf),
+    /// Whether a command, run from the unit's directory, succeeds; e.g.
+    /// `pkg-config --exists zlib`.
+    Succeeds(Vec<String>),
+}
+
+impl Probe {
+    pub(crate) fn holds(&self, dir: &path::Path) -> bool {
+        match self {
+            Self::Exists(path) => dir.join(path).exists(),
+            Self::Succeeds(cmd) => match cmd.split_first() {
+                Some((program, args)) => process::Command::new(program)
+                    .args(args)
+                    .current_dir(dir)
+                    .stdin(process::Stdio::null())
+                    .stdout(process::Stdio::null())
+                    .stderr(process::Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success()),
+                None => false,
+            },
+        }
+    }
+}
@@ -1,20 +1,46 @@
-use std::{ffi, path, rc};
+use std::{ffi, fs, io, path, sync};
 
-use crate::env::{EnvSpec, EnvSpecValue};
+use sha2::Digest;
+
+use crate::config::{Config, IsolationError};
+use crate::env::{self, EnvSpec, EnvSpecValue};
+use crate::launcher::Launcher;
+use crate::relativiser::Relativiser;
 use crate::targets::Targets;
+use crate::tools;
+use crate::unit::TaskOptions;
 
 mod parser;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Part of a path picked out by a modifier, as in `$<[0]:stem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathPart {
+    /// The directory holding the file, `.` when that's where it's run.
+    Dir,
+    /// The file's name.
+    Name,
+    /// The file's name without its extension.
+    Stem,
+    /// The file's extension, without the dot.
+    Ext,
+    /// The absolute path of the file.
+    Abs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Variable {
     Targets,
     Target(usize),
+    TargetPart(usize, PathPart),
     Inputs,
     Input(usize),
+    InputPart(usize, PathPart),
     Other(String),
+    // The file holding an inline script; never produced by the parser.
+    Script,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ArgElement {
     Str(String),
     Var(Variable),
@@ -27,14 +53,33 @@ pub enum RecipePrepareError {
     NonUnicodePath,
     #[fail(display = "Recipe string must contain at least the command to run.")]
     NotEnoughArgs,
+    #[fail(display = "The task has no recipe to run.")]
+    NoRecipe,
     #[fail(display = "Couldn't find recipe command '{}'.", 0)]
     NoSuchCmd(String),
+    #[fail(
+        display = "Recipe command '{}' would be looked up on a PATH the task doesn't inherit.",
+        _0
+    )]
+    UndeclaredPath(String),
     #[fail(display = "Input index '{}' is out-of-range.", 0)]
     InputIndexOutOfRange(usize),
     #[fail(display = "Target index '{}' is out-of-range.", 0)]
     TargetIndexOutOfRange(usize),
     #[fail(display = "Unrecognised bindings '{}'.", 0)]
     UnrecognisedBinding(String),
+    #[fail(display = "Unable to isolate task.")]
+    IsolationError(#[fail(cause)] IsolationError),
+    #[fail(display = "Unable to write script '{}'.", _0)]
+    ScriptError(String, #[fail(cause)] io::Error),
+    #[fail(display = "Can't reach '{}' from the recipe's working directory.", _0)]
+    OutsideCwd(String),
+}
+
+impl From<IsolationError> for RecipePrepareError {
+    fn from(err: IsolationError) -> Self {
+        Self::IsolationError(err)
+    }
 }
 
 #[derive(Debug, failure::Fail)]
@@ -45,6 +90,35 @@ pub enum RecipeParseError {
     ParseElementError,
     #[fail(display = "Recipe string must contain at least the command to run.")]
     NotEnoughArgs,
+    #[fail(display = "'{}' doesn't name a target, as e.g. '$@[0]' does.", _0)]
+    NotATarget(String),
+    #[fail(display = "'{}' doesn't name an input, as e.g. '$<[0]' does.", _0)]
+    NotAnInput(String),
+}
+
+/// The index of the target `reference` names, e.g. 1 for `$@[1]`; a bare
+/// `$@` names the first.
+pub fn target_index(reference: &str) -> Result<usize, RecipeParseError> {
+    match parser::parse_elements(reference)?[..] {
+        [ArgElement::Var(Variable::Target(index))] => Ok(index),
+        [ArgElement::Var(Variable::Targets)] => Ok(0),
+        _ => Err(RecipeParseError::NotATarget(reference.to_string())),
+    }
+}
+
+/// The index of the input `reference` names, e.g. 1 for `$<[1]`; a bare
+/// `$<` names the first.
+pub fn input_index(reference: &str) -> Result<usize, RecipeParseError> {
+    match parser::parse_elements(reference)?[..] {
+        [ArgElement::Var(Variable::Input(index))] => Ok(index),
+        [ArgElement::Var(Variable::Inputs)] => Ok(0),
+        _ => Err(RecipeParseError::NotAnInput(reference.to_string())),
+    }
+}
+
+/// Splits a single argument into the text and variables it's made of.
+pub(crate) fn parse_elements(arg: &str) -> Result<Vec<ArgElement>, RecipeParseError> {
+    Ok(parser::parse_elements(arg)?)
 }
 
 impl From<parser::ParseArgsError> for RecipeParseError {
@@ -59,9 +133,35 @@ impl From<parser::ParseElementsError> for RecipeParseError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+struct Script {
+    body: String,
+    hash: String,
+}
+
+// How the shell is asked to run a line.
+#[cfg(windows)]
+const SHELL: &[&str] = &["cmd.exe", "/C"];
+#[cfg(not(windows))]
+const SHELL: &[&str] = &["sh", "-c"];
+
+// How a recipe's command line is carried out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Form {
+    // Run directly, each argument just as it's given.
+    Exec,
+    // Handed to the shell as a single line, with the paths substituted into
+    // it quoted to suit.
+    Shell,
+}
+
+#[derive(Debug, Clone)]
 pub struct Recipe {
     elements: Vec<ArgElement>,
+    form: Form,
+    // Whether the command names one of asmbl's own built-in recipes.
+    builtin: bool,
+    script: Option<Script>,
 }
 
 impl Recipe {
@@ -74,31 +174,506 @@ impl Recipe {
                 elements.extend(parser::parse_elements(&arg)?);
                 elements.push(ArgElement::Break);
             }
-            Ok(Self { elements })
+            Ok(Self {
+                elements,
+                form: Form::Exec,
+                builtin: false,
+                script: None,
+            })
         }
     }
 
+    /// A recipe handing `line` to the shell, `sh -c` or `cmd.exe /C` on
+    /// Windows, so that it may use pipes, redirection and the like. Its own
+    /// variables are still substituted, so a literal `$` is written `$$`.
+    pub fn shell(line: &str) -> Result<Self, RecipeParseError> {
+        Ok(Self::shell_line(parser::parse_elements(line)?))
+    }
+
+    /// A recipe running each of `commands` in turn through the shell, going
+    /// no further once one fails.
+    pub fn sequence(commands: Vec<Vec<String>>) -> Result<Self, RecipeParseError> {
+        let mut line = vec![];
+        for command in commands {
+            if command.is_empty() {
+                return Err(RecipeParseError::NotEnoughArgs);
+            }
+            if !line.is_empty() {
+                line.push(ArgElement::Str(String::from(" && ")));
+            }
+            for (index, arg) in command.iter().enumerate() {
+                if index > 0 {
+                    line.push(ArgElement::Str(String::from(" ")));
+                }
+                // Only what's written out needs quoting here, a run at a
+                // time; paths are quoted as they're substituted.
+                let mut literal = String::new();
+                for element in parser::parse_elements(arg)? {
+                    match element {
+                        ArgElement::Str(s) => literal.push_str(&s),
+                        element => {
+                            if !literal.is_empty() {
+                                line.push(ArgElement::Str(quote(&literal)));
+                                literal.clear();
+                            }
+                            line.push(element);
+                        }
+                    }
+                }
+                if !literal.is_empty() {
+                    line.push(ArgElement::Str(quote(&literal)));
+                }
+            }
+        }
+        if line.is_empty() {
+            return Err(RecipeParseError::NotEnoughArgs);
+        }
+        Ok(Self::shell_line(line))
+    }
+
+    fn shell_line(line: Vec<ArgElement>) -> Self {
+        let mut elements = vec![];
+        for arg in SHELL {
+            elements.push(ArgElement::Str(arg.to_string()));
+            elements.push(ArgElement::Break);
+        }
+        elements.extend(line);
+        elements.push(ArgElement::Break);
+        Self {
+            elements,
+            form: Form::Shell,
+            builtin: false,
+            script: None,
+        }
+    }
+
+    pub fn builtin(name: &str, args: Vec<String>) -> Result<Self, RecipeParseError> {
+        let mut recipe = Self::new(std::iter::once(name.to_string()).chain(args).collect())?;
+        recipe.builtin = true;
+        Ok(recipe)
+    }
+
     pub fn parse(s: &str) -> Result<Self, RecipeParseError> {
         Self::new(parser::parse_args(s)?)
     }
 
+    /// A recipe running `body` with `interpreter`, or failing that with the
+    /// interpreter named by the body's shebang line, or failing that with
+    /// `sh`. The script receives the task's targets as its arguments.
+    pub fn script(body: &str, interpreter: Option<&str>) -> Result<Self, RecipeParseError> {
+        let interpreter = match interpreter {
+            Some(interpreter) => parser::parse_args(interpreter)?,
+            None => match body.lines().next().and_then(|line| line.strip_prefix("#!")) {
+                // Resolve `env` ourselves so that the script also runs where
+                // `/usr/bin/env` doesn't exist.
+                Some(shebang) => match shebang.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["/usr/bin/env", ref args @ ..] => args.iter().map(|a| a.to_string()).collect(),
+                    ref args => args.iter().map(|a| a.to_string()).collect(),
+                },
+                None => vec![String::from("sh")],
+            },
+        };
+
+        if interpreter.is_empty() {
+            return Err(RecipeParseError::NotEnoughArgs);
+        }
+
+        let mut hasher = sha2::Sha256::new();
+        for arg in interpreter.iter() {
+            hasher.update(arg.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(body.as_bytes());
+        let hash = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let mut elements = vec![];
+        for arg in interpreter.into_iter() {
+            elements.push(ArgElement::Str(arg));
+            elements.push(ArgElement::Break);
+        }
+        elements.push(ArgElement::Var(Variable::Script));
+        elements.push(ArgElement::Break);
+
+        Ok(Self {
+            elements,
+            form: Form::Exec,
+            builtin: false,
+            script: Some(Script {
+                body: body.to_string(),
+                hash,
+            }),
+        })
+    }
+
+    /// Spells out everything which bears upon what the recipe runs, each
+    /// string prefixed with its length so that no two recipes are spelt
+    /// alike; tasks are run again should it change.
+    pub(crate) fn identity(&self) -> String {
+        let quoted = |s: &str| format!("{}:{}", s.len(), s);
+        let part = |part: &PathPart| match part {
+            PathPart::Dir => "dir",
+            PathPart::Name => "name",
+            PathPart::Stem => "stem",
+            PathPart::Ext => "ext",
+            PathPart::Abs => "abs",
+        };
+        let mut identity = String::from(match (self.form, self.builtin) {
+            (Form::Exec, false) => "exec",
+            (Form::Exec, true) => "builtin",
+            (Form::Shell, _) => "shell",
+        });
+        for element in self.elements.iter() {
+            let element = match element {
+                ArgElement::Str(s) => format!("s{}", quoted(s)),
+                ArgElement::Var(Variable::Targets) => String::from("@"),
+                ArgElement::Var(Variable::Target(index)) => format!("@{}", index),
+                ArgElement::Var(Variable::TargetPart(index, p)) => {
+                    format!("@{}:{}", index, part(p))
+                }
+                ArgElement::Var(Variable::Inputs) => String::from("<"),
+                ArgElement::Var(Variable::Input(index)) => format!("<{}", index),
+                ArgElement::Var(Variable::InputPart(index, p)) => {
+                    format!("<{}:{}", index, part(p))
+                }
+                ArgElement::Var(Variable::Other(name)) => format!("${}", quoted(name)),
+                ArgElement::Var(Variable::Script) => String::from("script"),
+                ArgElement::Break => String::from(";"),
+            };
+            identity.push(' ');
+            identity.push_str(&element);
+        }
+        if let Some(script) = &self.script {
+            identity.push_str(" #");
+            identity.push_str(&script.hash);
+        }
+        identity
+    }
+
+    /// The name of the program the recipe runs, where it's spelt out; for
+    /// shell recipes, that which the line starts with.
+    pub fn program(&self) -> Option<&str> {
+        let skip = match self.form {
+            Form::Exec => 0,
+            Form::Shell => SHELL.len(),
+        };
+        let program = match self.elements.split(|e| *e == ArgElement::Break).nth(skip)? {
+            [ArgElement::Str(program)] if self.form == Form::Exec => program.as_str(),
+            [ArgElement::Str(line), ..] if self.form == Form::Shell => {
+                line.split_whitespace().next()?
+            }
+            _ => return None,
+        };
+        path::Path::new(program.trim_matches(|c| c == '\'' || c == '"'))
+            .file_name()?
+            .to_str()
+    }
+
+    /// The names of the variables, the invocation's or its units', the
+    /// recipe refers to.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.elements.iter().filter_map(|element| match element {
+            ArgElement::Var(Variable::Other(name)) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
     pub fn prepare(
         &self,
         // Wouldn't it be nice if these were all moves...
         targets: &Targets,
-        inputs: &Vec<rc::Rc<path::Path>>,
-        env: &Vec<EnvSpec>,
+        inputs: &[sync::Arc<path::Path>],
+        env: &[&EnvSpec],
+        options: &TaskOptions,
+        config: &Config,
     ) -> Result<std::process::Command, RecipePrepareError> {
+        self.command(targets, inputs, env, options, config, true)
+    }
+
+    /// The command `prepare` gives, spelt out; nothing is written to disk
+    /// along the way.
+    pub fn render(
+        &self,
+        targets: &Targets,
+        inputs: &[sync::Arc<path::Path>],
+        env: &[&EnvSpec],
+        options: &TaskOptions,
+        config: &Config,
+    ) -> Result<String, RecipePrepareError> {
+        self.command(targets, inputs, env, options, config, false)
+            .map(|cmd| line(&cmd))
+    }
+
+    fn command(
+        &self,
+        targets: &Targets,
+        inputs: &[sync::Arc<path::Path>],
+        env: &[&EnvSpec],
+        options: &TaskOptions,
+        config: &Config,
+        write_scripts: bool,
+    ) -> Result<std::process::Command, RecipePrepareError> {
+        // Built-in recipes are implemented by asmbl itself and so always
+        // run directly on the host.
+        let (launcher, image) = if self.builtin {
+            (Launcher::Native, None)
+        } else {
+            (
+                options.launcher.unwrap_or(Launcher::Native),
+                options.image.as_ref(),
+            )
+        };
+
+        let builtin = if self.builtin {
+            let exe = config
+                .builtin_exe
+                .to_str()
+                .ok_or(RecipePrepareError::NonUnicodePath)?;
+            vec![exe.to_string(), String::from("builtin")]
+        } else {
+            vec![]
+        };
+
+        let script = match &self.script {
+            Some(script) if write_scripts => Some(write_script(&config.target_dir, script)?),
+            Some(script) => Some(script_file(&config.target_dir, script)),
+            None => None,
+        };
+
+        // Paths are relative to the context, and so need rebasing for
+        // recipes run from elsewhere.
+        let cwd = options.cwd.as_ref().map(|cwd| config.context_dir.join(cwd));
+        let rebase = cwd
+            .as_ref()
+            .map(|cwd| Relativiser::new(cwd, config.context_dir.clone()));
+
+        let args: Vec<_> = config
+            .wrapper
+            .iter()
+            .chain(options.wrapper.iter())
+            .cloned()
+            .chain(builtin)
+            .chain(self.expand(
+                targets,
+                inputs,
+                script.as_deref(),
+                launcher,
+                rebase.as_ref(),
+                options,
+                config,
+            )?)
+            .collect();
+
+        let (cmd, args) = args
+            .split_first()
+            .ok_or(RecipePrepareError::NotEnoughArgs)?;
+
+        let inherits_path = options.inherit_all_env
+            || env
+                .iter()
+                .any(|spec| spec.name() == "PATH" && *spec.value() == EnvSpecValue::INHERIT);
+
+        let terminal = match options.color {
+            true => env::terminal(config),
+            false => vec![],
+        };
+
+        // The derived variables come first so that tasks may override them.
+        let env: Vec<_> = env::derived(config, targets.iter().next().map(|t| t.as_ref()))
+            .into_iter()
+            .map(|(name, value)| match launcher {
+                Launcher::Native => Ok((name, value)),
+                _ => value
+                    .to_str()
+                    .map(|value| (name, ffi::OsString::from(launcher.translate(value))))
+                    .ok_or(RecipePrepareError::NonUnicodePath),
+            })
+            .chain(terminal.into_iter().map(Ok))
+            .chain(env::resolve(env.iter().cloned()).into_iter().map(Ok))
+            .collect::<Result<_, _>>()?;
+
+        // Tools' executables come first on whatever PATH the task would
+        // otherwise have.
+        let path = match env.iter().rev().find(|(name, _)| *name == "PATH") {
+            Some((_, path)) => Some(path.clone()),
+            None if !config.clear_env || options.inherit_all_env => std::env::var_os("PATH"),
+            None => None,
+        };
+        let tool_env = tools::env(&options.tools, &config.tool_store, path.as_deref());
+        let env: Vec<_> = env
+            .into_iter()
+            .filter(|(name, _)| tool_env.is_empty() || *name != "PATH")
+            .chain(
+                tool_env
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.clone())),
+            )
+            .collect();
+
+        if let Some(image) = image {
+            // The context and target directories are mounted at the same
+            // locations they occupy on the host so that relative paths
+            // resolve identically inside the container.
+            let mount = |dir: &path::Path, mode: &str| {
+                dir.to_str()
+                    .map(|dir| format!("{}:{}:{}", dir, dir, mode))
+                    .ok_or(RecipePrepareError::NonUnicodePath)
+            };
+
+            let mut container = std::process::Command::new(&config.container_runtime);
+            container
+                .args(["run", "--rm", "--volume"])
+                .arg(mount(&config.context_dir, "ro")?)
+                .arg("--volume")
+                .arg(mount(&config.target_dir, "rw")?)
+                .arg("--workdir")
+                .arg(cwd.as_ref().unwrap_or(&config.context_dir));
+            if !options.tools.is_empty() {
+                container
+                    .arg("--volume")
+                    .arg(mount(&config.tool_store, "ro")?);
+            }
+            if !options.network {
+                container.args(["--network", "none"]);
+            }
+            // Values are handed over through the runtime's own environment,
+            // as its arguments are there for anyone to see.
+            for (name, value) in env {
+                container.arg("--env").arg(name).env(name, value);
+            }
+            container.arg(image).arg(cmd).args(args);
+            return Ok(container);
+        }
+
+        // Shell built-ins have no program to find, so the shell runs them.
+        let shell_builtin = launcher == Launcher::Native
+            && config
+                .shell_builtins
+                .iter()
+                .any(|builtin| builtin.eq_ignore_ascii_case(cmd));
+        let args: Vec<_> = match shell_builtin {
+            true => ["/C", cmd]
+                .iter()
+                .map(|arg| arg.to_string())
+                .chain(args.iter().cloned())
+                .collect(),
+            false => args.to_vec(),
+        };
+
+        // Only commands run directly on the host can be located up front.
+        let cmd_path = path::PathBuf::from(cmd);
+        let cmd_path = if shell_builtin {
+            Some(config.shell.clone())
+        } else if launcher != Launcher::Native || cmd_path.exists() {
+            Some(cmd_path)
+        } else if let Some(tool) = tools::find(&options.tools, &config.tool_store, cmd) {
+            Some(tool)
+        } else if config.strict_path && !inherits_path {
+            return Err(RecipePrepareError::UndeclaredPath(cmd.to_owned()));
+        } else {
+            match std::env::var_os("PATH") {
+                Some(paths) => std::env::split_paths(&paths)
+                    .map(|path| path.join(cmd))
+                    .find(|path| path.exists()),
+                None => None,
+            }
+        }
+        .ok_or_else(|| RecipePrepareError::NoSuchCmd(cmd.to_owned()))?;
+
+        let mut cmd = launcher.command(&config.git_bash, cmd_path.as_os_str(), &args);
+        if let Some(cwd) = cwd {
+            cmd.current_dir(cwd);
+        }
+        if config.clear_env && !options.inherit_all_env {
+            cmd.env_clear();
+        }
+        launcher.forward_env(&mut cmd, env.iter().map(|(name, _)| *name));
+        cmd.envs(env);
+        if !options.network {
+            (config.deny_network)(&mut cmd)?;
+        }
+        Ok(cmd)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn expand(
+        &self,
+        targets: &Targets,
+        inputs: &[sync::Arc<path::Path>],
+        script: Option<&path::Path>,
+        launcher: Launcher,
+        rebase: Option<&Relativiser>,
+        options: &TaskOptions,
+        config: &Config,
+    ) -> Result<Vec<String>, RecipePrepareError> {
+        let (raw_targets, raw_inputs) = (targets, inputs);
+
+        // Paths are separated consistently so that tools needn't cope with
+        // a mixture, and the command doesn't change with how it's joined.
+        let translate = |path: &path::Path| {
+            let path = match rebase {
+                Some(rebase) => rebase.relativise(path).map_err(|_| {
+                    RecipePrepareError::OutsideCwd(path.to_string_lossy().into_owned())
+                })?,
+                None => path.to_path_buf(),
+            };
+            path.to_str()
+                .map(|path| launcher.translate(&config.separator.apply(path)))
+                .ok_or(RecipePrepareError::NonUnicodePath)
+        };
+
         let targets = targets
             .iter()
-            .map(|path| path.to_str().ok_or(RecipePrepareError::NonUnicodePath))
+            .map(|path| translate(path))
             .collect::<Result<Vec<_>, RecipePrepareError>>()?;
 
         let inputs = inputs
             .iter()
-            .map(|input| input.to_str().ok_or(RecipePrepareError::NonUnicodePath))
+            .map(|input| translate(input))
             .collect::<Result<Vec<_>, RecipePrepareError>>()?;
 
+        // Paths handed to the shell mustn't be split or interpreted by it.
+        let (targets, inputs) = match self.form {
+            Form::Exec => (targets, inputs),
+            Form::Shell => (
+                targets.iter().map(|target| quote(target)).collect(),
+                inputs.iter().map(|input| quote(input)).collect(),
+            ),
+        };
+
+        // Parts are taken from paths as the recipe sees them, but for
+        // absolute paths, which are taken from the context.
+        let part = |path: &path::Path, part: PathPart| -> Result<String, RecipePrepareError> {
+            let value = match part {
+                PathPart::Abs => {
+                    let path = absolute(&config.context_dir, path);
+                    let path = path.to_str().ok_or(RecipePrepareError::NonUnicodePath)?;
+                    launcher.translate(&config.separator.apply(path))
+                }
+                _ => {
+                    let translated = translate(path)?;
+                    let translated = path::Path::new(&translated);
+                    let value = match part {
+                        PathPart::Dir => translated.parent().map(path::Path::as_os_str),
+                        PathPart::Name => translated.file_name(),
+                        PathPart::Stem => translated.file_stem(),
+                        _ => translated.extension(),
+                    };
+                    match value.and_then(|value| value.to_str()) {
+                        Some("") | None if part == PathPart::Dir => String::from("."),
+                        value => value.unwrap_or_default().to_string(),
+                    }
+                }
+            };
+            Ok(match self.form {
+                Form::Exec => value,
+                Form::Shell => quote(&value),
+            })
+        };
+
         let mut args = vec![];
 
         let mut e = 0;
@@ -112,19 +687,41 @@ impl Recipe {
                             if *index >= inputs.len() {
                                 return Err(RecipePrepareError::InputIndexOutOfRange(*index));
                             }
-                            arg.push_str(inputs[*index])
+                            arg.push_str(&inputs[*index])
                         }
                         Variable::Target(index) => {
                             if *index >= targets.len() {
                                 return Err(RecipePrepareError::TargetIndexOutOfRange(*index));
                             }
-                            arg.push_str(targets[*index])
+                            arg.push_str(&targets[*index])
+                        }
+                        Variable::InputPart(index, which) => match raw_inputs.get(*index) {
+                            Some(input) => arg.push_str(&part(input, *which)?),
+                            None => return Err(RecipePrepareError::InputIndexOutOfRange(*index)),
+                        },
+                        Variable::TargetPart(index, which) => {
+                            match raw_targets.iter().nth(*index) {
+                                Some(target) => arg.push_str(&part(target, *which)?),
+                                None => {
+                                    return Err(RecipePrepareError::TargetIndexOutOfRange(*index))
+                                }
+                            }
                         }
                         Variable::Inputs => arg.push_str(&inputs.join(" ")),
                         Variable::Targets => arg.push_str(&targets.join(" ")),
-                        Variable::Other(name) => {
-                            return Err(RecipePrepareError::UnrecognisedBinding(name.to_owned()))
-                        }
+                        Variable::Other(name) => match variable(name, options, config) {
+                            Some(value) => arg.push_str(value),
+                            None => {
+                                return Err(RecipePrepareError::UnrecognisedBinding(
+                                    name.to_owned(),
+                                ))
+                            }
+                        },
+                        Variable::Script => arg.push_str(
+                            &script
+                                .map(translate)
+                                .unwrap_or(Err(RecipePrepareError::NonUnicodePath))?,
+                        ),
                     },
                     ArgElement::Break => unreachable!(),
                 }
@@ -134,33 +731,363 @@ impl Recipe {
             e += 1;
         }
 
-        let (cmd, args) = args
-            .split_first()
-            .ok_or(RecipePrepareError::NotEnoughArgs)?;
+        if script.is_some() {
+            args.extend(targets);
+        }
 
-        let cmd_path = path::PathBuf::from(cmd);
-        let cmd_path = if cmd_path.exists() {
-            Some(cmd_path)
-        } else {
-            match std::env::var_os("PATH") {
-                Some(paths) => std::env::split_paths(&paths)
-                    .map(|path| path.join(cmd))
-                    .find(|path| path.exists()),
-                None => None,
+        Ok(args)
+    }
+}
+
+/// What the named variable stands for in a task's recipe: the invocation's
+/// value, or failing that its units'.
+pub(crate) fn variable<'a>(
+    name: &str,
+    options: &'a TaskOptions,
+    config: &'a Config,
+) -> Option<&'a String> {
+    config
+        .variables
+        .get(name)
+        .or_else(|| options.vars.get(name))
+}
+
+// The absolute path of `path`, relative to `context`, with any '..' resolved.
+pub(crate) fn absolute(context: &path::Path, path: &path::Path) -> path::PathBuf {
+    let mut absolute = path::PathBuf::new();
+    for component in context.join(path).components() {
+        match component {
+            path::Component::CurDir => {}
+            path::Component::ParentDir => {
+                absolute.pop();
             }
+            component => absolute.push(component),
         }
-        .ok_or_else(|| RecipePrepareError::NoSuchCmd(cmd.to_owned()))?;
+    }
+    absolute
+}
 
-        let mut cmd = std::process::Command::new(&cmd_path);
-        cmd.args(args)
-            .env_clear()
-            .envs(env.into_iter().filter_map(|env| {
-                let value = match env.value() {
-                    EnvSpecValue::INHERIT => std::env::var_os(&env.name()),
-                    EnvSpecValue::DEFINE(value) => Some(ffi::OsString::from(value)),
-                };
-                value.map(|v| (env.name().clone(), v))
-            }));
-        Ok(cmd)
+// Quotes an argument for the shell, unless there's no need to.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c));
+    match (plain, cfg!(windows)) {
+        (true, _) => arg.to_string(),
+        (false, true) => format!("\"{}\"", arg.replace('"', "\"\"")),
+        (false, false) => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+// The command as it'd be typed into a shell, from within the directory it
+// runs in. Its environment is left out, being mostly asmbl's own variables
+// and perhaps holding secrets.
+fn line(cmd: &std::process::Command) -> String {
+    let mut words = vec![];
+    if let Some(cwd) = cmd.get_current_dir() {
+        words.push(String::from("cd"));
+        words.push(quote(&cwd.to_string_lossy()));
+        words.push(String::from("&&"));
+    }
+    words.push(quote(&cmd.get_program().to_string_lossy()));
+    words.extend(cmd.get_args().map(|arg| quote(&arg.to_string_lossy())));
+    words.join(" ")
+}
+
+// Scripts are named after their hash so that tasks sharing a script share
+// the file, and an existing file never needs rewriting.
+fn script_file(target_dir: &path::Path, script: &Script) -> path::PathBuf {
+    target_dir.join(".asmbl").join("scripts").join(&script.hash)
+}
+
+fn write_script(
+    target_dir: &path::Path,
+    script: &Script,
+) -> Result<path::PathBuf, RecipePrepareError> {
+    let file = script_file(target_dir, script);
+    let write = || -> io::Result<()> {
+        if file.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(file.parent().unwrap())?;
+        let partial = file.with_extension("part");
+        fs::write(&partial, &script.body)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&partial, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&partial, &file)
+    };
+    write()
+        .map_err(|err| RecipePrepareError::ScriptError(file.to_string_lossy().into_owned(), err))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_choose_script_interpreter() {
+        let interpreter = |recipe: Recipe| -> Vec<ArgElement> {
+            recipe
+                .elements
+                .into_iter()
+                .take_while(|element| *element != ArgElement::Var(Variable::Script))
+                .filter(|element| *element != ArgElement::Break)
+                .collect()
+        };
+        let strs = |args: &[&str]| -> Vec<ArgElement> {
+            args.iter()
+                .map(|arg| ArgElement::Str(arg.to_string()))
+                .collect()
+        };
+
+        let recipe = Recipe::script("echo hi", None).unwrap();
+        assert_eq!(interpreter(recipe), strs(&["sh"]));
+        let recipe = Recipe::script("#!/bin/bash -e\necho hi", None).unwrap();
+        assert_eq!(interpreter(recipe), strs(&["/bin/bash", "-e"]));
+        let recipe = Recipe::script("#!/usr/bin/env python3\nprint()", None).unwrap();
+        assert_eq!(interpreter(recipe), strs(&["python3"]));
+        let recipe = Recipe::script("#!/bin/bash\necho hi", Some("zsh -f")).unwrap();
+        assert_eq!(interpreter(recipe), strs(&["zsh", "-f"]));
+    }
+
+    #[test]
+    fn strict_path_requires_tasks_to_inherit_path() {
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        config.strict_path = true;
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("a")));
+        let recipe = Recipe::parse("sh -c true").unwrap();
+        let options = TaskOptions::default();
+        let prepare = |env: &[&EnvSpec]| recipe.prepare(&targets, &[], env, &options, &config);
+
+        match prepare(&[]) {
+            Err(RecipePrepareError::UndeclaredPath(cmd)) => assert_eq!(cmd, "sh"),
+            _ => panic!("PATH was searched"),
+        }
+        assert!(prepare(&[&EnvSpec::inherit(String::from("PATH"))]).is_ok());
+    }
+
+    #[test]
+    fn tools_come_first_on_the_path() {
+        let store = asmbl_utils::testing::TempDir::new("tools");
+        let tool = crate::Tool::new(
+            String::from("protoc"),
+            String::from("https://example.com/protoc.zip"),
+            "ab".repeat(32),
+            Some(path::PathBuf::from("bin")),
+        )
+        .unwrap();
+        let bin = tool.bin_dir(&store);
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("protoc"), "").unwrap();
+
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        config.strict_path = true;
+        config.tool_store = store.to_path_buf();
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("a")));
+        let recipe = Recipe::parse("protoc --version").unwrap();
+        let options = TaskOptions {
+            tools: vec![tool.clone()],
+            ..TaskOptions::default()
+        };
+        let path = EnvSpec::define(String::from("PATH"), String::from("/usr/bin"));
+        let cmd = recipe
+            .prepare(&targets, &[], &[&path], &options, &config)
+            .unwrap();
+        assert_eq!(cmd.get_program(), bin.join("protoc"));
+        let env: Vec<_> = cmd.get_envs().collect();
+        let expected = std::env::join_paths(vec![bin.clone(), path::PathBuf::from("/usr/bin")]);
+        assert!(env.contains(&(ffi::OsStr::new("PATH"), Some(expected.unwrap().as_os_str()))));
+        assert!(env.contains(&(
+            ffi::OsStr::new("ASMBL_TOOL_PROTOC"),
+            Some(tool.dir(&store).as_os_str())
+        )));
+    }
+
+    #[test]
+    fn containers_are_given_the_environment_out_of_sight() {
+        let mut config = Config::new(path::PathBuf::from("/project"), path::PathBuf::from("/out"));
+        config.container_runtime = String::from("podman");
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("../out/a")));
+        let recipe = Recipe::parse("touch $@").unwrap();
+        let options = TaskOptions {
+            image: Some(String::from("alpine")),
+            network: false,
+            ..TaskOptions::default()
+        };
+        let token = EnvSpec::define(String::from("API_TOKEN"), String::from("hunter2"));
+        let cmd = recipe
+            .command(&targets, &[], &[&token], &options, &config, false)
+            .unwrap();
+
+        assert_eq!(cmd.get_program(), "podman");
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        let (env, command) = args.split_at(args.len() - 3);
+        assert_eq!(
+            env[..10],
+            [
+                "run",
+                "--rm",
+                "--volume",
+                "/project:/project:ro",
+                "--volume",
+                "/out:/out:rw",
+                "--workdir",
+                "/project",
+                "--network",
+                "none",
+            ]
+        );
+        assert!(env[10..]
+            .windows(2)
+            .any(|env| env == ["--env", "API_TOKEN"]));
+        assert!(!args.iter().any(|arg| arg.contains("hunter2")));
+        assert_eq!(command, ["alpine", "touch", "../out/a"]);
+        assert!(cmd
+            .get_envs()
+            .any(|(name, value)| name == "API_TOKEN" && value == Some("hunter2".as_ref())));
+    }
+
+    #[test]
+    fn shell_builtins_are_run_through_the_shell() {
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        config.shell_builtins = vec![String::from("copy")];
+        config.shell = path::PathBuf::from("cmd.exe");
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("b")));
+        let recipe = Recipe::parse("COPY a $@").unwrap();
+        let options = TaskOptions::default();
+        let render = recipe.render(&targets, &[], &[], &options, &config);
+        assert!(render.unwrap().ends_with("cmd.exe /C COPY a b"));
+    }
+
+    #[test]
+    fn paths_can_be_separated_with_forward_slashes() {
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        config.separator = crate::Separator::Forward;
+        let targets = Targets::Single(sync::Arc::from(path::Path::new(r"..\out/obj\a.o")));
+        let inputs = [sync::Arc::from(path::Path::new(r"src\a.c"))];
+        let recipe = Recipe::parse("/bin/cc -c $< -o $@").unwrap();
+        let options = TaskOptions::default();
+        let render = recipe.render(&targets, &inputs, &[], &options, &config);
+        assert!(render.unwrap().ends_with("src/a.c -o ../out/obj/a.o"));
+    }
+
+    #[test]
+    fn parts_of_paths_can_be_picked_out() {
+        let config = Config::new(path::PathBuf::from("/project"), path::PathBuf::from("/out"));
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("../out/a.o")));
+        let inputs = [
+            sync::Arc::from(path::Path::new("src/a.c")),
+            sync::Arc::from(path::Path::new("Makefile")),
+        ];
+        let recipe = Recipe::parse(
+            "/bin/cc $<[0]:dir $<[0]:name $<[0]:stem $<[0]:ext $<[1]:dir $<[1]:ext $@[0]:abs",
+        )
+        .unwrap();
+        let options = TaskOptions::default();
+        let render = recipe.render(&targets, &inputs, &[], &options, &config);
+        assert!(render.unwrap().ends_with("src a.c a c . '' /out/a.o"));
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_recipes_working_directory() {
+        let config = Config::new(path::PathBuf::from("/project"), path::PathBuf::from("/out"));
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("../out/a.o")));
+        let inputs = [sync::Arc::from(path::Path::new("src/a.c"))];
+        let recipe = Recipe::parse("/bin/cc -c $< -o $@").unwrap();
+        let options = TaskOptions {
+            cwd: Some(path::PathBuf::from("src")),
+            ..TaskOptions::default()
+        };
+        let render = recipe
+            .render(&targets, &inputs, &[], &options, &config)
+            .unwrap();
+        assert!(render.starts_with("cd /project/src && "));
+        assert!(render.ends_with("a.c -o ../../out/a.o"));
+    }
+
+    #[test]
+    fn shell_recipes_quote_the_paths_substituted_into_them() {
+        let config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("out/a b")));
+        let inputs = [sync::Arc::from(path::Path::new("a.c"))];
+        let options = TaskOptions::default();
+        let render = |recipe: Recipe| {
+            assert_eq!(recipe.program(), Some("cc"));
+            recipe
+                .render(&targets, &inputs, &[], &options, &config)
+                .unwrap()
+        };
+
+        let shell = Recipe::shell("cc $< -o $@ 2>&1 | tee log").unwrap();
+        assert!(render(shell).ends_with(r"-c 'cc a.c -o '\''out/a b'\'' 2>&1 | tee log'"));
+        let commands = vec![
+            vec![String::from("cc"), String::from("$<"), String::from("-o$@")],
+            vec![String::from("echo"), String::from("it's built")],
+        ];
+        let sequence = Recipe::sequence(commands).unwrap();
+        let quoted = r"'cc a.c -o'\''out/a b'\'' && echo '\''it'\''\'\'''\''s built'\'''";
+        assert!(render(sequence).ends_with(quoted));
+    }
+
+    #[test]
+    fn can_refer_to_the_invocations_variables() {
+        let mut config = Config::new(path::PathBuf::from("/"), path::PathBuf::from("/"));
+        config
+            .variables
+            .insert(String::from("jobs"), String::from("4"));
+        let targets = Targets::Single(sync::Arc::from(path::Path::new("a")));
+        let recipe = Recipe::parse("/bin/make -j$jobs $profile").unwrap();
+        let options = TaskOptions::default();
+        let render = |config: &Config| recipe.render(&targets, &[], &[], &options, config);
+
+        match render(&config) {
+            Err(RecipePrepareError::UnrecognisedBinding(name)) => assert_eq!(name, "profile"),
+            _ => panic!("$profile was bound"),
+        }
+        config
+            .variables
+            .insert(String::from("profile"), String::from("release"));
+        assert!(render(&config).unwrap().ends_with("-j4 release"));
+    }
+
+    #[test]
+    fn script_identity_follows_content() {
+        let a = Recipe::script("echo a", None).unwrap();
+        let b = Recipe::script("echo b", None).unwrap();
+        let c = Recipe::script("echo a", Some("bash")).unwrap();
+        assert_ne!(a.identity(), b.identity());
+        assert_ne!(a.identity(), c.identity());
+        assert_eq!(
+            a.identity(),
+            Recipe::script("echo a", None).unwrap().identity()
+        );
+    }
+
+    #[test]
+    fn identities_tell_recipes_apart() {
+        let identity = |recipe: Recipe| recipe.identity();
+        let args = |args: &[&str]| Recipe::new(args.iter().map(|a| a.to_string()).collect());
+        assert_eq!(
+            identity(Recipe::parse("cc -c $<[0]:stem $@ $CC").unwrap()),
+            "exec s2:cc ; s2:-c ; <0:stem ; @ ; $2:CC ;"
+        );
+        // Arguments are told apart however they're split.
+        assert_ne!(
+            identity(args(&["a b", "c"]).unwrap()),
+            identity(args(&["a", "b c"]).unwrap())
+        );
+        assert_ne!(
+            identity(args(&["a;", "b"]).unwrap()),
+            identity(args(&["a", ";b"]).unwrap())
+        );
+        assert_ne!(
+            identity(Recipe::shell("true").unwrap()),
+            identity(args(&["true"]).unwrap())
+        );
     }
 }
@@ -1,4 +1,4 @@
-use super::{ArgElement, Variable};
+use super::{ArgElement, PathPart, Variable};
 use nom::*;
 
 fn space(c: char) -> bool {
@@ -37,6 +37,23 @@ fn variable_name(i: &str) -> IResult<&str, &str> {
     ))(i)
 }
 
+fn path_part(i: &str) -> IResult<&str, PathPart> {
+    nom::sequence::preceded(
+        nom::character::complete::char(':'),
+        nom::combinator::map_opt(
+            nom::bytes::complete::take_while1(alphanumeric),
+            |s| match s {
+                "dir" => Some(PathPart::Dir),
+                "name" => Some(PathPart::Name),
+                "stem" => Some(PathPart::Stem),
+                "ext" => Some(PathPart::Ext),
+                "abs" => Some(PathPart::Abs),
+                _ => None,
+            },
+        ),
+    )(i)
+}
+
 fn variable(i: &str) -> IResult<&str, Variable> {
     let (r, (_, name, index)) = nom::sequence::tuple((
         nom::character::complete::char('$'),
@@ -44,14 +61,22 @@ fn variable(i: &str) -> IResult<&str, Variable> {
         nom::combinator::opt(index),
     ))(i)?;
 
+    // Only a single target or input has parts to pick out.
+    let (r, part) = match (name, index) {
+        ("@" | "targets" | "<" | "inputs", Some(_)) => nom::combinator::opt(path_part)(r)?,
+        _ => (r, None),
+    };
+
     match name {
-        "@" | "targets" => match index {
-            Some(index) => Ok((r, Variable::Target(index))),
-            None => Ok((r, Variable::Targets)),
+        "@" | "targets" => match (index, part) {
+            (Some(index), Some(part)) => Ok((r, Variable::TargetPart(index, part))),
+            (Some(index), None) => Ok((r, Variable::Target(index))),
+            (None, _) => Ok((r, Variable::Targets)),
         },
-        "<" | "inputs" => match index {
-            Some(index) => Ok((r, Variable::Input(index))),
-            None => Ok((r, Variable::Inputs)),
+        "<" | "inputs" => match (index, part) {
+            (Some(index), Some(part)) => Ok((r, Variable::InputPart(index, part))),
+            (Some(index), None) => Ok((r, Variable::Input(index))),
+            (None, _) => Ok((r, Variable::Inputs)),
         },
         _ => Ok((r, Variable::Other(name.to_string()))),
     }
@@ -59,6 +84,9 @@ fn variable(i: &str) -> IResult<&str, Variable> {
 
 fn element(i: &str) -> IResult<&str, ArgElement> {
     nom::branch::alt((
+        nom::combinator::map(nom::bytes::complete::tag("$$"), |_| {
+            ArgElement::Str("$".to_string())
+        }),
         nom::combinator::map(variable, |v| ArgElement::Var(v)),
         nom::combinator::map(
             nom::bytes::complete::take_while1(|c| c != '$'),
@@ -150,6 +178,17 @@ mod test {
         assert_eq!(variable("$<[7]"), Ok(("", Variable::Input(7))));
         assert_eq!(variable("$@"), Ok(("", Variable::Targets)));
         assert_eq!(variable("$@[29]"), Ok(("", Variable::Target(29))));
+        assert_eq!(
+            variable("$<[0]:stem"),
+            Ok(("", Variable::InputPart(0, PathPart::Stem)))
+        );
+        assert_eq!(
+            variable("$@[1]:abs"),
+            Ok(("", Variable::TargetPart(1, PathPart::Abs)))
+        );
+        assert_eq!(variable("$<[0]:/mnt"), Ok((":/mnt", Variable::Input(0))));
+        assert_eq!(variable("$<[0]:dirs"), Ok((":dirs", Variable::Input(0))));
+        assert_eq!(variable("$@:dir"), Ok((":dir", Variable::Targets)));
         assert_eq!(
             variable("$cake"),
             Ok(("", Variable::Other("cake".to_string())))
@@ -195,6 +234,10 @@ mod test {
             element("$@[42] cheese"),
             Ok((" cheese", ArgElement::Var(Variable::Target(42))))
         );
+        assert_eq!(
+            element("$$@"),
+            Ok(("@", ArgElement::Str("$".to_string())))
+        );
     }
 
     #[test]
@@ -1,4 +1,4 @@
-use std::path;
+use std::{cell, collections, ffi, path};
 
 #[derive(Debug, failure::Fail)]
 pub enum Error {
@@ -6,60 +6,133 @@ pub enum Error {
     Underflow,
 }
 
+/// Expresses paths relative to a context directory, interpreting relative
+/// paths as relative to some base directory.
 pub struct Relativiser {
+    // The context, split and normalised once up front.
+    context: Vec<ffi::OsString>,
     base: path::PathBuf,
+    // The same few paths (headers in depfiles, say) come up over and over.
+    memo: cell::RefCell<collections::HashMap<path::PathBuf, path::PathBuf>>,
+}
+
+// Resolves '.' and '..' lexically.
+fn normalise(path: &path::Path) -> Result<Vec<&ffi::OsStr>, Error> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            path::Component::CurDir => { /*NOP*/ }
+            path::Component::ParentDir => {
+                if components.pop().is_none() {
+                    return Err(Error::Underflow);
+                }
+            }
+            _ => components.push(component.as_os_str()),
+        }
+    }
+    Ok(components)
 }
 
 impl Relativiser {
-    pub fn new(base: path::PathBuf) -> Self {
-        Self { base }
+    pub fn new(context: &path::Path, base: path::PathBuf) -> Self {
+        // A context which can't be normalised is taken as it stands.
+        let context = match normalise(context) {
+            Ok(components) => components.into_iter().map(ffi::OsString::from).collect(),
+            Err(_) => context.iter().map(ffi::OsString::from).collect(),
+        };
+        Self {
+            context,
+            base,
+            memo: cell::RefCell::new(collections::HashMap::new()),
+        }
     }
 
-    pub fn relativise(
-        &self,
-        context: &Vec<path::Component>,
-        path: &path::Path,
-    ) -> Result<path::PathBuf, Error> {
-        use std::borrow::Cow;
+    pub fn relativise(&self, path: &path::Path) -> Result<path::PathBuf, Error> {
+        if let Some(relative) = self.memo.borrow().get(path) {
+            return Ok(relative.clone());
+        }
 
-        let abs = if path.is_absolute() {
-            Cow::Borrowed(path)
+        let relative = if path.is_absolute() {
+            self.relativise_absolute(path)?
         } else {
-            Cow::Owned(self.base.join(path))
+            self.relativise_absolute(&self.base.join(path))?
         };
 
-        let mut components: Vec<path::Component> = Vec::new();
-        for component in abs.components() {
-            match component {
-                path::Component::CurDir => { /*NOP*/ }
-                path::Component::ParentDir => {
-                    if let None = components.pop() {
-                        return Err(Error::Underflow);
-                    }
-                }
-                _ => components.push(component),
-            }
-        }
+        self.memo
+            .borrow_mut()
+            .insert(path.to_path_buf(), relative.clone());
 
-        let mut shared_component_index: usize = 0;
-        while shared_component_index < std::cmp::min(components.len(), context.len())
-            && components[shared_component_index] == context[shared_component_index]
-        {
-            shared_component_index += 1;
-        }
+        Ok(relative)
+    }
+
+    fn relativise_absolute(&self, abs: &path::Path) -> Result<path::PathBuf, Error> {
+        let components = normalise(abs)?;
+
+        let shared = components
+            .iter()
+            .zip(self.context.iter())
+            .take_while(|(component, context)| *component == context)
+            .count();
 
         let mut path = path::PathBuf::new();
 
         // Walk backwards until we match with context..
-        for _ in shared_component_index..context.len() {
+        for _ in shared..self.context.len() {
             path.push("..");
         }
 
-        // Walk forwards building the rest of the path...
-        for c in shared_component_index..components.len() {
-            path.push(components[c]);
+        // Walk forwards building the rest of the path, each component
+        // pushed separately so that it's separated as the platform would.
+        for component in components[shared..].iter() {
+            path.push(component);
         }
 
         Ok(path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relativiser() -> Relativiser {
+        Relativiser::new(
+            path::Path::new("/project/./src/.."),
+            path::PathBuf::from("/project/lib"),
+        )
+    }
+
+    #[test]
+    fn can_relativise_paths() {
+        let relativiser = relativiser();
+        for (path, relative) in [
+            ("a.c", "lib/a.c"),
+            ("../include/./a.h", "include/a.h"),
+            ("/project/lib/a.c", "lib/a.c"),
+            ("/usr/include/stdio.h", "../usr/include/stdio.h"),
+        ] {
+            assert_eq!(
+                relativiser.relativise(path::Path::new(path)).unwrap(),
+                path::PathBuf::from(relative)
+            );
+        }
+    }
+
+    #[test]
+    fn remembers_relativised_paths() {
+        let relativiser = relativiser();
+        let first = relativiser.relativise(path::Path::new("a.h")).unwrap();
+        assert_eq!(
+            relativiser.relativise(path::Path::new("a.h")).unwrap(),
+            first
+        );
+        assert_eq!(relativiser.memo.borrow().len(), 1);
+    }
+
+    #[test]
+    fn cant_climb_above_root() {
+        assert!(relativiser()
+            .relativise(path::Path::new("../../../../a.h"))
+            .is_err());
+    }
+}
@@ -0,0 +1,111 @@
+/// The version of asmbl doing the building.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What units may require of asmbl by name, so that a version which doesn't
+/// understand some option fails rather than quietly ignoring it.
+pub const CAPABILITIES: &[&str] = &[
+    "atomic",
+    "color",
+    "cwd",
+    "depfile",
+    "groups",
+    "hooks",
+    "images",
+    "launchers",
+    "network",
+    "once",
+    "output-dir",
+    "pools",
+    "probes",
+    "produces",
+    "restat",
+    "results",
+    "sandbox-allow",
+    "scripts",
+    "shell",
+    "stdin",
+    "stdout",
+    "tools",
+];
+
+#[derive(Debug, failure::Fail)]
+pub enum RequirementError {
+    #[fail(display = "Can't make sense of version requirement '{}'.", _0)]
+    BadRequirement(String),
+    #[fail(display = "The unit requires asmbl {}, but this is asmbl {}.", _0, _1)]
+    Version(String, &'static str),
+    #[fail(
+        display = "The unit requires asmbl to support '{}', which asmbl {} doesn't.",
+        _0, _1
+    )]
+    Capability(String, &'static str),
+}
+
+// A version's major, minor and patch numbers, missing ones taken as zero.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut numbers = version.trim().split('.').map(|n| n.parse().ok());
+    let mut next = || numbers.next().unwrap_or(Some(0));
+    let version = (next()?, next()?, next()?);
+    match numbers.next() {
+        None => Some(version),
+        Some(_) => None,
+    }
+}
+
+/// Fails unless this is a version of asmbl meeting `requirement`, comma
+/// separated comparisons such as `>=0.3, <1`. A bare version is a minimum.
+pub fn require_version(requirement: &str) -> Result<(), RequirementError> {
+    let bad = || RequirementError::BadRequirement(requirement.to_string());
+    let current = parse_version(VERSION).unwrap();
+    for comparison in requirement.split(',') {
+        let comparison = comparison.trim();
+        let (op, version) = ["<=", ">=", "<", ">", "="]
+            .iter()
+            .find_map(|op| Some((*op, comparison.strip_prefix(op)?)))
+            .unwrap_or((">=", comparison));
+        let version = parse_version(version).ok_or_else(bad)?;
+        let met = match op {
+            "<=" => current <= version,
+            ">=" => current >= version,
+            "<" => current < version,
+            ">" => current > version,
+            _ => current == version,
+        };
+        if !met {
+            return Err(RequirementError::Version(requirement.to_string(), VERSION));
+        }
+    }
+    Ok(())
+}
+
+/// Fails unless this version of asmbl has the named capability.
+pub fn require_capability(capability: &str) -> Result<(), RequirementError> {
+    match CAPABILITIES.contains(&capability) {
+        true => Ok(()),
+        false => Err(RequirementError::Capability(
+            capability.to_string(),
+            VERSION,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_require_versions_and_capabilities() {
+        assert!(require_version(">=0.1, <1").is_ok());
+        assert!(require_version("0.1").is_ok());
+        assert!(matches!(
+            require_version(">=99.0"),
+            Err(RequirementError::Version(..))
+        ));
+        assert!(matches!(
+            require_version(">=one"),
+            Err(RequirementError::BadRequirement(..))
+        ));
+        assert!(require_capability("groups").is_ok());
+        assert!(require_capability("teleport").is_err());
+    }
+}
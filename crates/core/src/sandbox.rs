@@ -0,0 +1,269 @@
+use std::{collections, fs, io, path, process};
+
+use sha2::Digest;
+
+use crate::config::Config;
+use crate::recipe;
+use crate::{Edge, Prerequisite, Task, TaskList};
+
+/// A directory of a task's own, beneath `.asmbl/sandbox` in the target
+/// directory, holding links to only the files it declared it depends upon,
+/// in which it's run so that whatever else it reads goes missing. The
+/// sandbox mirrors the directory holding both the context and the target
+/// directory, so that the paths the task is given lead where they would
+/// otherwise.
+///
+/// Directories the unit or invocation allow the task to read from are linked
+/// whole, sparing it declaring everything it reads from, e.g., an SDK.
+///
+/// Paths given as absolute, whether by the unit or the recipe, lead outside
+/// of the sandbox, as does everything for tasks run in containers.
+pub struct Sandbox {
+    root: path::PathBuf,
+    // The directory the sandbox stands in for.
+    base: path::PathBuf,
+    // The links to the task's inputs, as opposed to what it wrote itself.
+    links: collections::HashSet<path::PathBuf>,
+}
+
+// Links `to` to `from`, which may be a directory.
+#[cfg(unix)]
+fn link(from: &path::Path, to: &path::Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(from, to)
+}
+
+// Symbolic links are privileged elsewhere, so files are hard linked, and
+// directories recreated, instead.
+#[cfg(not(unix))]
+fn link(from: &path::Path, to: &path::Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return fs::hard_link(from, to);
+    }
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        link(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+// Moves everything in `from`, besides the given links, to `to`, merging it
+// with any directories already there.
+fn move_out(
+    from: &path::Path,
+    to: &path::Path,
+    links: &collections::HashSet<path::PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let (from, to) = (entry.path(), to.join(entry.file_name()));
+        if links.contains(&from) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() && to.is_dir() {
+            move_out(&from, &to, links)?;
+            continue;
+        }
+        // Directories can't be renamed over unless they're empty.
+        if to.is_dir() {
+            fs::remove_dir_all(&to)?;
+        }
+        fs::rename(&from, &to)?;
+    }
+    Ok(())
+}
+
+impl Sandbox {
+    /// Lays out a fresh sandbox for the task, or none for those run in
+    /// containers, which the sandbox would be lost on.
+    pub fn new(tasks: &TaskList, task: &Task, config: &Config) -> io::Result<Option<Self>> {
+        if task.options.image.is_some() {
+            return Ok(None);
+        }
+
+        // Whatever the task consumes or depends upon, along with anything
+        // it was found to depend upon when it last ran; the tasks it's only
+        // ordered after are none of its business.
+        let mut inputs = task.inputs();
+        for (upstream, edge) in task.upstream.iter().zip(task.edges.iter()) {
+            match (upstream, edge) {
+                (_, Edge::NotBefore) => {}
+                (Prerequisite::Named(path, _), _) => inputs.push(path.clone()),
+                (Prerequisite::Handle(handle), _) => {
+                    inputs.extend(tasks.get(*handle).targets.iter().cloned())
+                }
+            }
+        }
+        let inputs: Vec<_> = inputs.iter().filter(|input| input.is_relative()).collect();
+        let targets = task.recipe_targets();
+        let allowed = config
+            .sandbox_allow
+            .iter()
+            .chain(task.options.sandbox_allow.iter());
+        let allowed: Vec<_> = allowed.map(path::PathBuf::as_path).collect();
+
+        let mut base = config.context_dir.clone();
+        let relative = allowed.iter().filter(|dir| dir.is_relative());
+        let named = inputs.iter().map(|input| input.as_ref());
+        let named = named.chain(targets.iter().map(|target| target.as_ref()));
+        let named = named.chain(relative.copied());
+        for path in named.map(|path| recipe::absolute(&config.context_dir, path)) {
+            while !path.starts_with(&base) && base.pop() {}
+        }
+        while !config.target_dir.starts_with(&base) && base.pop() {}
+
+        let key = sha2::Sha256::digest(task.key().as_bytes());
+        let name: String = key[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut sandbox = Self {
+            root: config.target_dir.join(".asmbl").join("sandbox").join(name),
+            base,
+            links: collections::HashSet::new(),
+        };
+
+        // Anything left over from an earlier run which never finished.
+        match fs::remove_dir_all(&sandbox.root) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            removed => removed?,
+        }
+        fs::create_dir_all(sandbox.place(config, path::Path::new("")))?;
+
+        // Allowed directories come first, outermost first, taking in any
+        // inputs within them; those beyond the sandbox are there to be read
+        // as they are.
+        let mut allowed: Vec<_> = allowed
+            .into_iter()
+            .filter(|dir| recipe::absolute(&config.context_dir, dir).starts_with(&sandbox.base))
+            .collect();
+        allowed.sort_by_key(|dir| {
+            recipe::absolute(&config.context_dir, dir)
+                .components()
+                .count()
+        });
+        let inputs = inputs.into_iter().map(|input| input.as_ref());
+        for input in allowed.into_iter().chain(inputs) {
+            let from = recipe::absolute(&config.context_dir, input);
+            // Inputs which are yet to be, or are optional, stay missing.
+            if !from.exists() {
+                continue;
+            }
+            let to = sandbox.place(config, input);
+            if to.ancestors().any(|linked| sandbox.links.contains(linked)) {
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            link(&from, &to)?;
+            sandbox.links.insert(to);
+        }
+        for target in targets.iter() {
+            if let Some(parent) = sandbox.place(config, target).parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        Ok(Some(sandbox))
+    }
+
+    // Where `path`, relative to the context, lies within the sandbox.
+    fn place(&self, config: &Config, path: &path::Path) -> path::PathBuf {
+        let path = recipe::absolute(&config.context_dir, path);
+        match path.strip_prefix(&self.base) {
+            Ok(path) => self.root.join(path),
+            Err(_) => path,
+        }
+    }
+
+    /// Runs the task's command from within the sandbox.
+    pub fn enter(&self, task: &Task, config: &Config, cmd: &mut process::Command) {
+        let cwd = task
+            .options
+            .cwd
+            .as_deref()
+            .unwrap_or_else(|| path::Path::new(""));
+        cmd.current_dir(self.place(config, cwd));
+    }
+
+    /// Moves whatever the task wrote within the sandbox to where it would
+    /// otherwise have been written, should it have succeeded, and clears
+    /// the sandbox away.
+    pub fn finish(self, succeeded: bool) -> io::Result<()> {
+        if succeeded {
+            move_out(&self.root, &self.base, &self.links)?;
+        }
+        fs::remove_dir_all(&self.root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{declare, spec};
+    use crate::PrerequisiteSpec;
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn sandboxes_hold_only_declared_inputs() {
+        let root = TempDir::new("sandbox");
+        let context = root.join("project");
+        fs::create_dir_all(context.join("src")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(context.join("src/main.c"), "").unwrap();
+        fs::write(context.join("src/undeclared.h"), "").unwrap();
+
+        let tasks = declare(&context, path::Path::new("../out"), |builder| {
+            let source = PrerequisiteSpec::Named(path::PathBuf::from("src/main.c"), false);
+            builder
+                .add_task(vec![String::from("main.o")], spec(vec![source]))
+                .unwrap();
+        });
+        let config = Config::new(context.clone(), root.join("out"));
+        let task = tasks.get(tasks.handles().next().unwrap());
+
+        let sandbox = Sandbox::new(&tasks, task, &config).unwrap().unwrap();
+        let dir = sandbox.place(&config, path::Path::new(""));
+        assert!(dir.join("src/main.c").exists());
+        assert!(!dir.join("src/undeclared.h").exists());
+        assert!(dir.join("../out").is_dir());
+
+        fs::write(dir.join("../out/main.o"), "").unwrap();
+        sandbox.finish(true).unwrap();
+        assert!(root.join("out/main.o").exists());
+        assert!(context.join("src/main.c").exists());
+    }
+
+    #[test]
+    fn allowed_directories_are_linked_whole() {
+        let root = TempDir::new("allowed");
+        let context = root.join("project");
+        fs::create_dir_all(context.join("sdk/include")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(context.join("sdk/include/sdk.h"), "").unwrap();
+
+        let tasks = declare(&context, path::Path::new("../out"), |builder| {
+            builder
+                .allow_in_sandbox(path::PathBuf::from("sdk/include"))
+                .unwrap();
+            let header = PrerequisiteSpec::Named(path::PathBuf::from("sdk/include/sdk.h"), false);
+            builder
+                .add_task(vec![String::from("main.o")], spec(vec![header]))
+                .unwrap();
+        });
+        let mut config = Config::new(context.clone(), root.join("out"));
+        config.sandbox_allow.push(path::PathBuf::from("sdk"));
+        config
+            .sandbox_allow
+            .push(path::PathBuf::from("/usr/include"));
+        let task = tasks.get(tasks.handles().next().unwrap());
+
+        let sandbox = Sandbox::new(&tasks, task, &config).unwrap().unwrap();
+        let dir = sandbox.place(&config, path::Path::new(""));
+        assert!(dir.join("sdk/include/sdk.h").exists());
+        assert_eq!(sandbox.links.len(), 1);
+        sandbox.finish(true).unwrap();
+        assert!(context.join("sdk/include/sdk.h").exists());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{cmp, collections};
+
+use crate::{Prerequisite, Task, TaskHandle, TaskList};
+
+/// The order in which independent work is taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Work through the graph a level at a time, interleaving final targets.
+    Breadth,
+    /// Finish each final target before starting on the next.
+    Depth,
+    /// Favour whatever heads the longest remaining chain of work.
+    CriticalPath,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Unknown schedule '{}'.", _0)]
+pub struct UnknownSchedule(String);
+
+impl std::str::FromStr for Schedule {
+    type Err = UnknownSchedule;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "breadth" => Ok(Self::Breadth),
+            "depth" => Ok(Self::Depth),
+            "critical-path" => Ok(Self::CriticalPath),
+            _ => Err(UnknownSchedule(s.to_string())),
+        }
+    }
+}
+
+// Orders the nodes of a graph given in topological order, with edges
+// running from each node to those it depends upon.
+fn order(upstream: &[Vec<usize>], weights: &[u64], schedule: Schedule) -> Vec<usize> {
+    let mut downstream = vec![vec![]; upstream.len()];
+    for (node, upstream) in upstream.iter().enumerate() {
+        for &up in upstream.iter() {
+            downstream[up].push(node);
+        }
+    }
+
+    match schedule {
+        Schedule::Breadth => (0..upstream.len()).collect(),
+        Schedule::Depth => {
+            // Everything each final node needs, depth first, before moving
+            // onto the next final node.
+            let mut order = Vec::with_capacity(upstream.len());
+            let mut visited = vec![false; upstream.len()];
+            for sink in (0..upstream.len()).filter(|node| downstream[*node].is_empty()) {
+                let mut stack = vec![(sink, false)];
+                while let Some((node, expanded)) = stack.pop() {
+                    if expanded {
+                        order.push(node);
+                    } else if !visited[node] {
+                        visited[node] = true;
+                        stack.push((node, true));
+                        stack.extend(upstream[node].iter().rev().map(|up| (*up, false)));
+                    }
+                }
+            }
+            order
+        }
+        Schedule::CriticalPath => {
+            // The heaviest chain of work starting at each node.
+            let mut path = vec![0u64; upstream.len()];
+            for node in (0..upstream.len()).rev() {
+                let longest = downstream[node].iter().map(|down| path[*down]).max();
+                path[node] = weights[node].max(1) + longest.unwrap_or(0);
+            }
+
+            let mut pending: Vec<_> = upstream.iter().map(|upstream| upstream.len()).collect();
+            let mut ready: collections::BinaryHeap<_> = (0..upstream.len())
+                .filter(|node| pending[*node] == 0)
+                .map(|node| (path[node], cmp::Reverse(node)))
+                .collect();
+
+            let mut order = Vec::with_capacity(upstream.len());
+            while let Some((_, cmp::Reverse(node))) = ready.pop() {
+                order.push(node);
+                for &down in downstream[node].iter() {
+                    pending[down] -= 1;
+                    if pending[down] == 0 {
+                        ready.push((path[down], cmp::Reverse(down)));
+                    }
+                }
+            }
+            order
+        }
+    }
+}
+
+impl TaskList {
+    /// Orders the given tasks for running according to `schedule`, with
+    /// every task still following any of the others it depends upon.
+    pub fn schedule<F>(
+        &self,
+        handles: &[TaskHandle],
+        schedule: Schedule,
+        weight: F,
+    ) -> Vec<TaskHandle>
+    where
+        F: Fn(&Task) -> u64,
+    {
+        let mut handles = handles.to_vec();
+        handles.sort_by_key(|handle| handle.index);
+
+        let local: collections::HashMap<_, _> = handles
+            .iter()
+            .enumerate()
+            .map(|(local, handle)| (handle.index, local))
+            .collect();
+
+        let upstream: Vec<Vec<_>> = handles
+            .iter()
+            .map(|handle| {
+                self.tasks[handle.index]
+                    .upstream
+                    .iter()
+                    .filter_map(|upstream| match upstream {
+                        Prerequisite::Handle(handle) => local.get(&handle.index).cloned(),
+                        Prerequisite::Named(..) => None,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let weights: Vec<_> = handles
+            .iter()
+            .map(|handle| weight(self.get(*handle)))
+            .collect();
+
+        order(&upstream, &weights, schedule)
+            .into_iter()
+            .map(|local| handles[local])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two independent chains, 0 -> 2 -> 4 and 1 -> 3.
+    fn chains() -> Vec<Vec<usize>> {
+        vec![vec![], vec![], vec![0], vec![1], vec![2]]
+    }
+
+    #[test]
+    fn can_order_breadth_first() {
+        assert_eq!(
+            order(&chains(), &[1; 5], Schedule::Breadth),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn can_order_depth_first() {
+        assert_eq!(
+            order(&chains(), &[1; 5], Schedule::Depth),
+            vec![1, 3, 0, 2, 4]
+        );
+    }
+
+    #[test]
+    fn can_order_by_critical_path() {
+        assert_eq!(
+            order(&chains(), &[1, 1, 1, 1, 10], Schedule::CriticalPath),
+            vec![0, 2, 4, 1, 3]
+        );
+        assert_eq!(
+            order(&chains(), &[1, 1, 1, 20, 1], Schedule::CriticalPath),
+            vec![1, 3, 0, 2, 4]
+        );
+    }
+}
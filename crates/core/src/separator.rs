@@ -0,0 +1,65 @@
+use std::{borrow::Cow, path};
+
+/// How the paths in expanded recipes are separated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separator {
+    /// As the platform separates them, e.g. `\` on Windows.
+    Native,
+    /// With `/` throughout, which most tools accept everywhere.
+    Forward,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Unknown separator '{}'.", _0)]
+pub struct UnknownSeparator(String);
+
+impl std::str::FromStr for Separator {
+    type Err = UnknownSeparator;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Self::Native),
+            "forward" => Ok(Self::Forward),
+            _ => Err(UnknownSeparator(s.to_string())),
+        }
+    }
+}
+
+impl Separator {
+    /// Rewrites the path so that it's separated throughout as asked, even
+    /// where its parts were joined with different separators.
+    pub fn apply(self, path: &str) -> Cow<'_, str> {
+        let (from, to) = match self {
+            Self::Native if path::MAIN_SEPARATOR == '/' => return Cow::Borrowed(path),
+            Self::Native => ('/', path::MAIN_SEPARATOR),
+            Self::Forward => ('\\', '/'),
+        };
+        match path.contains(from) {
+            true => Cow::Owned(path.replace(from, &to.to_string())),
+            false => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// Rebuilds the path from its components, so that it's separated as the
+/// platform would separate it, rather than as its parts happened to be.
+pub(crate) fn native(path: &path::Path) -> path::PathBuf {
+    path.components().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_separate_paths_with_forward_slashes() {
+        assert_eq!(
+            Separator::Forward.apply(r"..\out/obj\a.o"),
+            "../out/obj/a.o"
+        );
+        assert_eq!(Separator::Forward.apply("../out/a.o"), "../out/a.o");
+        if path::MAIN_SEPARATOR == '/' {
+            assert_eq!(Separator::Native.apply("../out/a.o"), "../out/a.o");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections;
+
+use crate::{Task, TaskHandle, TaskList};
+
+/// One of several slices into which a build is split, e.g. across CI
+/// machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    index: usize,
+    count: usize,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Invalid shard '{}', expected K/N where 1 <= K <= N.", _0)]
+pub struct BadShard(String);
+
+impl std::str::FromStr for Shard {
+    type Err = BadShard;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || BadShard(s.to_string());
+        let mut parts = s.splitn(2, '/');
+        let index: usize = parts.next().unwrap().parse().map_err(|_| bad())?;
+        let count: usize = parts.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+        if index == 0 || index > count {
+            return Err(bad());
+        }
+        Ok(Self {
+            index: index - 1,
+            count,
+        })
+    }
+}
+
+// Greedily hands each of the (heaviest first) costs to the least loaded
+// shard, returning the shard chosen for each.
+fn assign(costs: &[u64], count: usize) -> Vec<usize> {
+    let mut loads = vec![0u64; count];
+    costs
+        .iter()
+        .map(|cost| {
+            let (shard, _) = loads
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, load)| (**load, *index))
+                .unwrap();
+            loads[shard] += cost;
+            shard
+        })
+        .collect()
+}
+
+impl TaskList {
+    /// Splits the final targets of the build between `shard`'s siblings,
+    /// balancing the total weight of the tasks each must run, and returns
+    /// the tasks this shard needs. Tasks shared between final targets
+    /// assigned to different shards are run by each.
+    pub fn shard<F>(&self, shard: Shard, weight: F) -> collections::HashSet<TaskHandle>
+    where
+        F: Fn(&Task) -> u64,
+    {
+        let mut sinks: Vec<_> = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.downstream.get(*index).is_empty())
+            .map(|(index, task)| {
+                let closure = self.upstream_closure(Some(TaskHandle::new(index)));
+                // Every task costs something, however quick it has been.
+                let cost: u64 = closure
+                    .iter()
+                    .map(|handle| weight(self.get(*handle)).max(1))
+                    .sum();
+                (cost, task.targets[0].clone(), closure)
+            })
+            .collect();
+
+        // Heaviest first, falling back on target paths for determinism.
+        sinks.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        let costs: Vec<_> = sinks.iter().map(|(cost, _, _)| *cost).collect();
+        sinks
+            .into_iter()
+            .zip(assign(&costs, shard.count))
+            .filter(|(_, assigned)| *assigned == shard.index)
+            .flat_map(|((_, _, closure), _)| closure)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_shard() {
+        assert_eq!(
+            "2/8".parse::<Shard>().ok(),
+            Some(Shard { index: 1, count: 8 })
+        );
+        assert!("0/8".parse::<Shard>().is_err());
+        assert!("9/8".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn can_assign() {
+        assert_eq!(assign(&[5, 4, 3, 2, 1], 2), vec![0, 1, 1, 0, 0]);
+        assert_eq!(assign(&[1, 1, 1], 4), vec![0, 1, 2]);
+    }
+}
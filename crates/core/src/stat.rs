@@ -0,0 +1,48 @@
+use std::{collections, fs, io, path, time::SystemTime};
+
+/// Source of last-modification times used to decide what's out-of-date.
+pub trait Stat {
+    fn modified(&self, path: &path::Path) -> io::Result<SystemTime>;
+}
+
+/// Asks the file-system directly about every file.
+pub struct FileSystem;
+
+impl Stat for FileSystem {
+    fn modified(&self, path: &path::Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+}
+
+/// Asks another source, but takes the given files to have changed just now
+/// whatever it says, as make's `-W` does, so that whatever depends upon
+/// them is rebuilt without them being touched.
+pub struct AssumeNew<'a> {
+    stat: &'a dyn Stat,
+    paths: collections::HashSet<path::PathBuf>,
+    now: SystemTime,
+}
+
+impl<'a> AssumeNew<'a> {
+    pub fn new<I>(stat: &'a dyn Stat, paths: I) -> Self
+    where
+        I: IntoIterator<Item = path::PathBuf>,
+    {
+        Self {
+            stat,
+            paths: paths.into_iter().collect(),
+            now: SystemTime::now(),
+        }
+    }
+}
+
+impl Stat for AssumeNew<'_> {
+    fn modified(&self, path: &path::Path) -> io::Result<SystemTime> {
+        // Files which are missing stay so.
+        let modified = self.stat.modified(path)?;
+        match self.paths.contains(path) {
+            true => Ok(self.now),
+            false => Ok(modified),
+        }
+    }
+}
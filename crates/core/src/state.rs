@@ -0,0 +1,355 @@
+use std::{collections, fmt, io, path};
+
+use asmbl_utils::io::{records, StateFile};
+use sha2::Digest;
+
+use crate::config::Config;
+use crate::env;
+use crate::recipe;
+use crate::{Edge, Prerequisite, Task, TaskHandle, TaskList};
+
+/// How each task was last run successfully, kept in `.asmbl/state` under
+/// the target directory, so that tasks are re-run when their recipe,
+/// environment or inputs change and not only when files do.
+pub struct State {
+    file: StateFile,
+    entries: collections::BTreeMap<String, Entry>,
+    // The environment each task last succeeded with, kept in `.asmbl/env`,
+    // so that what changed can be told apart.
+    envs: collections::BTreeMap<String, Env>,
+}
+
+// Variables by name; the values of those which look like secrets are only
+// kept as digests.
+type Env = collections::BTreeMap<String, String>;
+
+/// How a variable in a task's environment differs from when the task last
+/// succeeded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    Added(String, String),
+    Removed(String, String),
+    Changed(String, String, String),
+}
+
+impl fmt::Display for EnvChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown = |name: &str, value: &str| match env::looks_secret(name) {
+            true => String::from("<redacted>"),
+            false => format!("{:?}", value),
+        };
+        match self {
+            Self::Added(name, value) => write!(f, "+{}={}", name, shown(name, value)),
+            Self::Removed(name, value) => write!(f, "-{}={}", name, shown(name, value)),
+            Self::Changed(name, was, now) => {
+                write!(f, "{}: {} -> {}", name, shown(name, was), shown(name, now))
+            }
+        }
+    }
+}
+
+// Digests of the command line, environment and set of inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    command: String,
+    env: String,
+    inputs: String,
+}
+
+fn digest<I, S>(parts: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<[u8]>,
+{
+    let mut hasher = sha2::Sha256::new();
+    for part in parts {
+        hasher.update(part.as_ref());
+        hasher.update([0]);
+    }
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Entry {
+    fn new(task: &Task, config: &Config) -> Self {
+        let targets = task.targets().map(|target| target.to_string_lossy());
+        // Changing what the recipe's variables stand for changes its command.
+        let variables = task.recipe.iter().flat_map(|recipe| recipe.variables());
+        let variables = variables.map(|name| {
+            let value = recipe::variable(name, &task.options, config);
+            format!("{}={:?}", name, value)
+        });
+        let command = digest(
+            config
+                .wrapper
+                .iter()
+                .cloned()
+                .chain(task.recipe.as_ref().map(recipe::Recipe::identity))
+                .chain(Some(task.options.identity()))
+                .chain(
+                    config
+                        .sandbox_allow
+                        .iter()
+                        .map(|dir| dir.to_string_lossy().into_owned()),
+                )
+                .chain(targets.map(|target| target.into_owned()))
+                .chain(variables),
+        );
+
+        let env = digest(
+            env::resolve(env::merge(&config.env, &task.env))
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value.to_string_lossy())),
+        );
+
+        // What the task discovered it depends upon is down to how it ran,
+        // rather than how it's asked to run.
+        let upstream = task.upstream.iter().zip(task.edges.iter());
+        let named = upstream.filter_map(|upstream| match upstream {
+            (_, Edge::Discovered) => None,
+            (Prerequisite::Named(path, _), _) => Some(path),
+            (Prerequisite::Handle(_), _) => None,
+        });
+        let inputs = digest(
+            task.inputs
+                .iter()
+                .chain(named)
+                .map(|input| input.to_string_lossy().into_owned())
+                .chain(task.late_inputs.iter().cloned()),
+        );
+
+        Self {
+            command,
+            env,
+            inputs,
+        }
+    }
+}
+
+// Digests of the task's command line and environment, as recorded.
+pub(crate) fn identity(task: &Task, config: &Config) -> (String, String) {
+    let entry = Entry::new(task, config);
+    (entry.command, entry.env)
+}
+
+fn env_of(task: &Task, config: &Config) -> Env {
+    env::resolve(env::merge(&config.env, &task.env))
+        .into_iter()
+        .map(|(name, value)| {
+            let value = value.to_string_lossy();
+            let value = match env::looks_secret(name) {
+                true => digest(Some(value.as_bytes())),
+                false => value.into_owned(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+// Values are kept one to a line, so newlines, and the backslashes escaping
+// them, are escaped.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+impl State {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "state");
+
+        // Each record holds the three digests and a target path.
+        let entries = records(&file.read()?, 4)
+            .map(|record| {
+                let entry = Entry {
+                    command: record[0].to_string(),
+                    env: record[1].to_string(),
+                    inputs: record[2].to_string(),
+                };
+                (record[3].to_string(), entry)
+            })
+            .collect();
+
+        // Each record holds a target path, a variable's name and its value.
+        let mut envs = collections::BTreeMap::new();
+        for record in records(&file.sibling("env").read()?, 3) {
+            envs.entry(record[0].to_string())
+                .or_insert_with(Env::new)
+                .insert(record[1].to_string(), unescape(record[2]));
+        }
+
+        Ok(Self {
+            file,
+            entries,
+            envs,
+        })
+    }
+
+    /// The tasks which haven't succeeded as they'd now be run, including
+    /// any which have never succeeded at all.
+    pub fn stale(&self, tasks: &TaskList, config: &Config) -> collections::HashSet<TaskHandle> {
+        tasks
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| self.entries.get(&task.key()) != Some(&Entry::new(task, config)))
+            .map(|(index, _)| TaskHandle::new(index))
+            .collect()
+    }
+
+    pub fn record(&mut self, task: &Task, config: &Config) {
+        self.entries.insert(task.key(), Entry::new(task, config));
+        self.envs.insert(task.key(), env_of(task, config));
+    }
+
+    /// How the task's environment has changed, variable by variable, since
+    /// it last succeeded; nothing has if it never has.
+    pub fn env_changes(&self, task: &Task, config: &Config) -> Vec<EnvChange> {
+        let was = match self.envs.get(&task.key()) {
+            Some(was) => was,
+            None => return vec![],
+        };
+        let now = env_of(task, config);
+        let mut changes = vec![];
+        for (name, value) in was.iter() {
+            match now.get(name) {
+                None => changes.push(EnvChange::Removed(name.clone(), value.clone())),
+                Some(now) if now != value => {
+                    changes.push(EnvChange::Changed(name.clone(), value.clone(), now.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, value) in now.into_iter() {
+            if !was.contains_key(&name) {
+                changes.push(EnvChange::Added(name, value));
+            }
+        }
+        changes
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, entry) in self.entries.iter() {
+            content.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                entry.command, entry.env, entry.inputs, target
+            ));
+        }
+
+        let mut env = String::new();
+        for (target, vars) in self.envs.iter() {
+            for (name, value) in vars.iter() {
+                env.push_str(&format!("{}\t{}\t{}\n", target, name, escape(value)));
+            }
+        }
+
+        self.file.write(&content)?;
+        self.file.sibling("env").write(&env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{declare, spec};
+    use crate::{EnvSpec, Recipe, TaskSpec};
+    use asmbl_utils::testing::TempDir;
+    use std::fs;
+
+    // A task making `a` in `dir` by running `recipe` with `env`.
+    fn task(dir: &path::Path, recipe: &str, env: &[(&str, &str)]) -> (TaskList, TaskHandle) {
+        let tasks = declare(dir, dir, |builder| {
+            let spec = TaskSpec {
+                env: env
+                    .iter()
+                    .map(|(name, value)| EnvSpec::define(name.to_string(), value.to_string()))
+                    .collect(),
+                recipe: Some(Recipe::parse(recipe).unwrap()),
+                ..spec(vec![])
+            };
+            builder.add_task(vec![String::from("a")], spec).unwrap();
+        });
+        let handle = tasks.find(&dir.join("a")).unwrap();
+        (tasks, handle)
+    }
+
+    #[test]
+    fn tasks_are_stale_until_they_succeed_as_they_would_now_run() {
+        let dir = TempDir::new("state");
+        let config = Config::new(dir.to_path_buf(), dir.to_path_buf());
+        let (tasks, a) = task(&dir, "cc a.c", &[]);
+        let stale = |state: &State, tasks: &TaskList| state.stale(tasks, &config).contains(&a);
+
+        let mut state = State::load(&dir).unwrap();
+        assert!(stale(&state, &tasks));
+        state.record(tasks.get(a), &config);
+        assert!(!stale(&state, &tasks));
+        state.save().unwrap();
+        let state = State::load(&dir).unwrap();
+        assert!(!stale(&state, &tasks));
+
+        assert!(stale(&state, &task(&dir, "cc b.c", &[]).0));
+        assert!(stale(&state, &task(&dir, "cc a.c", &[("CC", "clang")]).0));
+    }
+
+    #[test]
+    fn environments_are_told_apart_variable_by_variable() {
+        let dir = TempDir::new("state-env");
+        let config = Config::new(dir.to_path_buf(), dir.to_path_buf());
+        let was = [("A", "1"), ("B", "two\nlines"), ("API_TOKEN", "s")];
+        let (tasks, a) = task(&dir, "true", &was);
+
+        let mut state = State::load(&dir).unwrap();
+        assert_eq!(state.env_changes(tasks.get(a), &config), vec![]);
+        state.record(tasks.get(a), &config);
+        state.save().unwrap();
+        let state = State::load(&dir).unwrap();
+
+        let now = [("A", "2"), ("API_TOKEN", "t"), ("C", "3")];
+        let (tasks, a) = task(&dir, "true", &now);
+        let changes = state.env_changes(tasks.get(a), &config);
+        let secret = |value: &str| digest(Some(value));
+        assert_eq!(
+            changes,
+            vec![
+                EnvChange::Changed("A".into(), "1".into(), "2".into()),
+                EnvChange::Changed("API_TOKEN".into(), secret("s"), secret("t")),
+                EnvChange::Removed("B".into(), "two\nlines".into()),
+                EnvChange::Added("C".into(), "3".into()),
+            ]
+        );
+        assert_eq!(
+            changes[1].to_string(),
+            "API_TOKEN: <redacted> -> <redacted>"
+        );
+    }
+
+    #[test]
+    fn lines_which_cant_be_read_are_passed_over() {
+        let dir = TempDir::new("state-garbled");
+        fs::create_dir_all(dir.join(".asmbl")).unwrap();
+        fs::write(dir.join(".asmbl/state"), "garbled\nc\te\ti\ta\n").unwrap();
+        fs::write(dir.join(".asmbl/env"), "a\tA\n a\tB\t1\n").unwrap();
+        let state = State::load(&dir).unwrap();
+        assert_eq!(state.entries.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(state.envs.keys().collect::<Vec<_>>(), vec![" a"]);
+    }
+}
@@ -1,11 +1,12 @@
-use std::{path, rc};
+use std::{ffi, path, sync};
 
+use crate::separator;
 use crate::targets_spec::{TargetSpec, TargetsSpec};
 
 #[derive(Clone, Debug)]
 pub enum Targets {
-    Single(rc::Rc<path::Path>),
-    Multi(Vec<rc::Rc<path::Path>>),
+    Single(sync::Arc<path::Path>),
+    Multi(Vec<sync::Arc<path::Path>>),
 }
 
 impl Targets {
@@ -16,20 +17,28 @@ impl Targets {
         }
     }
 
+    /// Where each target is written before being moved into place.
+    pub fn staged(&self) -> Self {
+        let staged = |target: &sync::Arc<path::Path>| sync::Arc::from(staging(target));
+        match self {
+            Self::Single(path) => Self::Single(staged(path)),
+            Self::Multi(paths) => Self::Multi(paths.iter().map(staged).collect()),
+        }
+    }
+
     pub fn try_from(
-        (prefix, input, spec): (path::PathBuf, &Option<rc::Rc<path::Path>>, TargetsSpec),
+        (prefix, input, spec): (path::PathBuf, &Option<sync::Arc<path::Path>>, &TargetsSpec),
     ) -> Result<Self, crate::targets_spec::ResolveError> {
-        let resolve_spec = |prefix: path::PathBuf, spec: TargetSpec| {
-            Ok(rc::Rc::from(
-                spec.resolve(prefix, input.as_ref().map(|i| i.as_ref()))?,
-            ))
+        let resolve_spec = |prefix: path::PathBuf, spec: &TargetSpec| {
+            let target = spec.resolve(prefix, input.as_ref().map(|i| i.as_ref()))?;
+            Ok(sync::Arc::from(separator::native(&target)))
         };
 
         Ok(match spec {
             TargetsSpec::Single(spec) => Self::Single(resolve_spec(prefix, spec)?),
             TargetsSpec::Multi(specs) => Self::Multi(
                 specs
-                    .into_iter()
+                    .iter()
                     // FIXME The first/last one doesn't need to be a clone
                     .map(|spec| resolve_spec(prefix.clone(), spec))
                     .collect::<Result<Vec<_>, _>>()?,
@@ -38,8 +47,16 @@ impl Targets {
     }
 }
 
+/// A hidden file beside the target, so that it can be renamed into place
+/// atomically, with the target's extension kept for tools which go by it.
+pub fn staging(target: &path::Path) -> path::PathBuf {
+    let mut name = ffi::OsString::from(".asmbl-tmp.");
+    name.push(target.file_name().unwrap_or_default());
+    target.with_file_name(name)
+}
+
 impl std::ops::Index<usize> for Targets {
-    type Output = rc::Rc<path::Path>;
+    type Output = sync::Arc<path::Path>;
 
     fn index(&self, index: usize) -> &Self::Output {
         match self {
@@ -51,7 +68,7 @@ impl std::ops::Index<usize> for Targets {
 }
 
 impl IntoIterator for Targets {
-    type Item = rc::Rc<path::Path>;
+    type Item = sync::Arc<path::Path>;
     type IntoIter = TargetIntoIterator;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -63,12 +80,12 @@ impl IntoIterator for Targets {
 }
 
 pub enum TargetIterator<'a> {
-    Single(Option<&'a rc::Rc<path::Path>>),
-    Multi(std::slice::Iter<'a, rc::Rc<path::Path>>),
+    Single(Option<&'a sync::Arc<path::Path>>),
+    Multi(std::slice::Iter<'a, sync::Arc<path::Path>>),
 }
 
 impl<'a> Iterator for TargetIterator<'a> {
-    type Item = &'a rc::Rc<path::Path>;
+    type Item = &'a sync::Arc<path::Path>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
@@ -79,12 +96,12 @@ impl<'a> Iterator for TargetIterator<'a> {
 }
 
 pub enum TargetIntoIterator {
-    Single(Option<rc::Rc<path::Path>>),
-    Multi(std::vec::IntoIter<rc::Rc<path::Path>>),
+    Single(Option<sync::Arc<path::Path>>),
+    Multi(std::vec::IntoIter<sync::Arc<path::Path>>),
 }
 
 impl Iterator for TargetIntoIterator {
-    type Item = rc::Rc<path::Path>;
+    type Item = sync::Arc<path::Path>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
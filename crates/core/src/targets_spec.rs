@@ -20,7 +20,7 @@ pub enum ResolveError {
 
 impl TargetSpec {
     pub fn resolve(
-        self,
+        &self,
         mut prefix: path::PathBuf,
         input: Option<&path::Path>,
     ) -> Result<path::PathBuf, ResolveError> {
@@ -55,6 +55,12 @@ impl TargetSpec {
     }
 }
 
+impl std::fmt::Display for TargetSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
 impl From<String> for TargetSpec {
     fn from(path: String) -> Self {
         // TODO would be nice to check markers here...
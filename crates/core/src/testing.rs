@@ -0,0 +1,31 @@
+//! What the tests of one module and another have in common.
+
+use std::path;
+
+use crate::{PrerequisiteSpec, Recipe, TaskList, TaskOptions, TaskSpec, UnitBuilder};
+
+/// A task consuming `consumes`, which does nothing when run.
+pub(crate) fn spec(consumes: Vec<PrerequisiteSpec<path::PathBuf>>) -> TaskSpec<path::PathBuf> {
+    TaskSpec {
+        consumes,
+        depends_on: vec![],
+        not_before: vec![],
+        env: vec![],
+        recipe: Some(Recipe::parse("true").unwrap()),
+        options: TaskOptions::default(),
+        origin: None,
+    }
+}
+
+/// The tasks `declare` adds to a unit in `context`, their targets beneath
+/// `target_dir`.
+pub(crate) fn declare<F>(context: &path::Path, target_dir: &path::Path, declare: F) -> TaskList
+where
+    F: FnOnce(&mut UnitBuilder),
+{
+    let components: Vec<_> = context.components().collect();
+    let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+    declare(&mut builder);
+    let units = vec![(context.to_path_buf(), builder.unit())];
+    TaskList::new(context, target_dir, units).unwrap()
+}
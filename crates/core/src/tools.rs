@@ -0,0 +1,257 @@
+use std::{collections, ffi, path};
+
+use crate::{TaskHandle, TaskList};
+
+/// A tool pinned by a unit, e.g. a particular protoc release, which is
+/// downloaded into the tool store and put on the PATH of the unit's tasks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tool {
+    name: String,
+    url: String,
+    sha256: String,
+    bin: Option<path::PathBuf>,
+}
+
+#[derive(Debug, failure::Fail)]
+pub enum BadTool {
+    #[fail(
+        display = "Tool name '{}' may only hold letters, digits, '-' and '_'.",
+        _0
+    )]
+    Name(String),
+    #[fail(display = "Tool '{}' needs a SHA-256 digest of 64 hex digits.", _0)]
+    Digest(String),
+    #[fail(
+        display = "Tool '{}' must keep its executables in a relative path without '..'.",
+        _0
+    )]
+    Bin(String),
+}
+
+impl Tool {
+    /// A tool downloaded from `url`, which must have the given SHA-256
+    /// digest. Archives are unpacked, `bin` naming the directory within
+    /// holding the executables if not the top.
+    pub fn new(
+        name: String,
+        url: String,
+        sha256: String,
+        bin: Option<path::PathBuf>,
+    ) -> Result<Self, BadTool> {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(BadTool::Name(name));
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(BadTool::Digest(name));
+        }
+        let valid_bin = bin.as_ref().is_none_or(|bin| {
+            bin.components()
+                .all(|component| matches!(component, path::Component::Normal(_)))
+        });
+        if !valid_bin {
+            return Err(BadTool::Bin(name));
+        }
+        Ok(Self {
+            name,
+            url,
+            sha256: sha256.to_ascii_lowercase(),
+            bin,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Where in `store` the tool lives, named by its digest so that units
+    /// pinning the same download share it.
+    pub fn dir(&self, store: &path::Path) -> path::PathBuf {
+        store.join(&self.sha256)
+    }
+
+    /// The directory in `store` holding the tool's executables.
+    pub fn bin_dir(&self, store: &path::Path) -> path::PathBuf {
+        match &self.bin {
+            Some(bin) => self.dir(store).join(bin),
+            None => self.dir(store),
+        }
+    }
+
+    // The variable telling tasks where the tool lives.
+    fn variable(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| match c {
+                '-' => '_',
+                c => c.to_ascii_uppercase(),
+            })
+            .collect();
+        format!("ASMBL_TOOL_{}", name)
+    }
+}
+
+/// What tasks using `tools` are told of them: `ASMBL_TOOL_<NAME>` for each,
+/// and a PATH on which their executables come before those of `path`.
+pub(crate) fn env(
+    tools: &[Tool],
+    store: &path::Path,
+    path: Option<&ffi::OsStr>,
+) -> Vec<(String, ffi::OsString)> {
+    if tools.is_empty() {
+        return vec![];
+    }
+    let bins = tools.iter().map(|tool| tool.bin_dir(store));
+    let rest = path.into_iter().flat_map(std::env::split_paths);
+    // Tool directories are named by digests, which can't hold a separator.
+    let path = std::env::join_paths(bins.chain(rest)).unwrap_or_default();
+    tools
+        .iter()
+        .map(|tool| (tool.variable(), tool.dir(store).into_os_string()))
+        .chain(Some((String::from("PATH"), path)))
+        .collect()
+}
+
+/// Looks for `cmd` amongst the executables of `tools`.
+pub(crate) fn find(tools: &[Tool], store: &path::Path, cmd: &str) -> Option<path::PathBuf> {
+    tools
+        .iter()
+        .map(|tool| tool.bin_dir(store).join(cmd))
+        .find(|path| path.exists())
+}
+
+impl TaskList {
+    /// The tools the given tasks use, each only once however many of them
+    /// use it.
+    pub fn tools<'a, I>(&self, handles: I) -> Vec<&Tool>
+    where
+        I: IntoIterator<Item = &'a TaskHandle>,
+    {
+        let mut seen = collections::HashSet::new();
+        handles
+            .into_iter()
+            .flat_map(|handle| self.get(*handle).tools())
+            .filter(|tool| seen.insert(tool.sha256()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::testing::{declare, spec};
+    use asmbl_utils::testing::TempDir;
+
+    fn tool(name: &str, sha256: &str, bin: Option<&str>) -> Result<Tool, BadTool> {
+        let url = format!("https://example.com/{}.zip", name);
+        Tool::new(
+            name.to_string(),
+            url,
+            sha256.to_string(),
+            bin.map(path::PathBuf::from),
+        )
+    }
+
+    #[test]
+    fn tools_are_refused_unless_pinned_soundly() {
+        let digest = "AB".repeat(32);
+        assert!(matches!(tool("", &digest, None), Err(BadTool::Name(_))));
+        assert!(matches!(
+            tool("pro toc", &digest, None),
+            Err(BadTool::Name(_))
+        ));
+        assert!(matches!(
+            tool("protoc", "ab", None),
+            Err(BadTool::Digest(_))
+        ));
+        let digest_of_x = "x".repeat(64);
+        assert!(matches!(
+            tool("protoc", &digest_of_x, None),
+            Err(BadTool::Digest(_))
+        ));
+        for bin in &["../bin", "/bin", "bin/../.."] {
+            assert!(matches!(
+                tool("protoc", &digest, Some(bin)),
+                Err(BadTool::Bin(_))
+            ));
+        }
+        let protoc = tool("protoc", &digest, Some("bin")).unwrap();
+        assert_eq!(protoc.sha256(), "ab".repeat(32));
+        let store = path::Path::new("/store");
+        assert_eq!(
+            protoc.bin_dir(store),
+            store.join("ab".repeat(32)).join("bin")
+        );
+    }
+
+    #[test]
+    fn tasks_are_told_where_their_tools_are() {
+        let store = TempDir::new("tool-env");
+        let protoc = tool("protoc", &"ab".repeat(32), Some("bin")).unwrap();
+        let clang_format = tool("clang-format", &"cd".repeat(32), None).unwrap();
+        assert!(env(&[], &store, Some(ffi::OsStr::new("/usr/bin"))).is_empty());
+
+        let tools = [protoc.clone(), clang_format.clone()];
+        let env = env(&tools, &store, Some(ffi::OsStr::new("/usr/bin")));
+        let path = std::env::join_paths(vec![
+            protoc.bin_dir(&store),
+            clang_format.bin_dir(&store),
+            path::PathBuf::from("/usr/bin"),
+        ]);
+        assert_eq!(
+            env,
+            vec![
+                (String::from("ASMBL_TOOL_PROTOC"), protoc.dir(&store).into()),
+                (
+                    String::from("ASMBL_TOOL_CLANG_FORMAT"),
+                    clang_format.dir(&store).into()
+                ),
+                (String::from("PATH"), path.unwrap()),
+            ]
+        );
+
+        // Only tools already in the store are found.
+        assert_eq!(find(&tools, &store, "protoc"), None);
+        fs::create_dir_all(protoc.bin_dir(&store)).unwrap();
+        fs::write(protoc.bin_dir(&store).join("protoc"), "").unwrap();
+        assert_eq!(
+            find(&tools, &store, "protoc"),
+            Some(protoc.bin_dir(&store).join("protoc"))
+        );
+        assert_eq!(find(&tools, &store, "clang-format"), None);
+    }
+
+    #[test]
+    fn tools_shared_by_tasks_are_listed_once() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            builder.add_tool(tool("protoc", &"ab".repeat(32), None).unwrap());
+            // Pinning a tool again replaces it.
+            builder.add_tool(tool("protoc", &"cd".repeat(32), None).unwrap());
+            for target in &["a", "b"] {
+                builder
+                    .add_task(vec![target.to_string()], spec(vec![]))
+                    .unwrap();
+            }
+        });
+
+        let handles: Vec<_> = tasks.handles().collect();
+        let tools = tasks.tools(&handles);
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].sha256(), "cd".repeat(32));
+    }
+}
@@ -1,20 +1,33 @@
-use crate::env::EnvSpec;
+use crate::env::{self, EnvSpec};
+use crate::hooks::Hook;
+use crate::ignore::Ignore;
+use crate::launcher::Launcher;
+use crate::probe::Probe;
 use crate::recipe::Recipe;
 use crate::relativiser;
 use crate::targets_spec::TargetsSpec;
+use crate::tools::Tool;
 
-use std::{path, rc};
+use std::{collections, fmt, ops, path, sync};
 
+#[derive(Clone)]
 pub enum PrerequisiteSpec<Path> {
     Named(Path, bool),
     Handle(TargetSpecHandle),
+    /// Every target, of any task, matching a glob pattern.
+    Glob(Path),
+    /// Whatever files matching a glob pattern lie beneath the given target
+    /// once the task producing it has run.
+    Late(TargetSpecHandle, String),
 }
 
 impl PrerequisiteSpec<path::PathBuf> {
-    pub fn resolve(self, offset: usize) -> PrerequisiteSpec<rc::Rc<path::Path>> {
+    pub fn resolve(self, offset: usize) -> PrerequisiteSpec<sync::Arc<path::Path>> {
         match self {
-            Self::Named(path, optional) => PrerequisiteSpec::Named(rc::Rc::from(path) as rc::Rc<path::Path>, optional),
+            Self::Named(path, optional) => PrerequisiteSpec::Named(sync::Arc::from(path) as sync::Arc<path::Path>, optional),
             Self::Handle(handle) => PrerequisiteSpec::Handle(handle.resolve(offset)),
+            Self::Glob(pattern) => PrerequisiteSpec::Glob(sync::Arc::from(pattern)),
+            Self::Late(handle, pattern) => PrerequisiteSpec::Late(handle.resolve(offset), pattern),
         }
     }
 }
@@ -41,16 +54,18 @@ impl TargetSpecHandle {
     }
 }
 
+/// Yields a handle for each target of each of a run of tasks, all of which
+/// have the same number of targets.
 pub struct TargetSpecHandleIterator {
-    task_index: usize,
+    task_indices: ops::Range<usize>,
     target_count: usize,
     target_index: usize,
 }
 
 impl TargetSpecHandleIterator {
-    pub fn new(task_index: usize, target_count: usize) -> Self {
+    pub fn new(task_indices: ops::Range<usize>, target_count: usize) -> Self {
         Self {
-            task_index,
+            task_indices,
             target_count,
             target_index: 0,
         }
@@ -61,12 +76,238 @@ impl Iterator for TargetSpecHandleIterator {
     type Item = TargetSpecHandle;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.target_index < self.target_count {
-            let handle = TargetSpecHandle::new(self.task_index, self.target_index);
-            self.target_index += 1;
-            Some(handle)
-        } else {
-            None
+        while !self.task_indices.is_empty() {
+            if self.target_index < self.target_count {
+                let handle = TargetSpecHandle::new(self.task_indices.start, self.target_index);
+                self.target_index += 1;
+                return Some(handle);
+            }
+            self.task_indices.start += 1;
+            self.target_index = 0;
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskOptions {
+    /// Pass the full environment of asmbl through to the task.
+    pub inherit_all_env: bool,
+    /// Command the recipe is run through, e.g. a compiler cache.
+    pub wrapper: Vec<String>,
+    /// Container image the task should be run inside of.
+    pub image: Option<String>,
+    /// Launcher to use in place of the unit's default.
+    pub launcher: Option<Launcher>,
+    /// Whether the task may access the network.
+    pub network: bool,
+    /// Index of the target which receives the task's exit status as JSON.
+    pub result: Option<usize>,
+    /// Index of the target the recipe's stdout is written to, for tools
+    /// which write nowhere else.
+    pub stdout: Option<usize>,
+    /// Index of the input the recipe's stdin is read from, for tools which
+    /// read nowhere else.
+    pub stdin: Option<usize>,
+    /// Whether a task consuming several inputs runs once for all of them,
+    /// rather than once for each.
+    pub aggregate: bool,
+    /// Whether the task's targets merely name it, rather than being files
+    /// it produces; such a task always runs.
+    pub phony: bool,
+    /// Whether the task merely groups its prerequisites, e.g. the headers of
+    /// a header-only library, so that others may depend upon them as one.
+    /// Such a task never runs, and changes whenever they do.
+    pub group: bool,
+    /// What to announce when the task runs, in place of its command.
+    pub description: Option<String>,
+    /// Whether to announce the task at all when it runs.
+    pub echo: bool,
+    /// File in which the task lists, as make rules, whatever else it turned
+    /// out to depend upon, e.g. `$@[0].d` for the headers a compiler read.
+    pub depfile: Option<String>,
+    /// Patterns for the files the task produces besides its targets, e.g.
+    /// `docs/**/*.html`, which can't be known until it's run.
+    pub produces: Vec<String>,
+    /// Whether the task may leave its targets as they were, in which case
+    /// whatever depends upon it needn't run after all.
+    pub restat: bool,
+    /// Whether the task runs every time, as when it embeds the current
+    /// commit, even though its targets are files.
+    pub always: bool,
+    /// Whether the task runs only while its targets are missing, as when it
+    /// downloads a toolchain, whatever else has changed.
+    pub once: bool,
+    /// Whether the recipe writes its targets to temporary files, moved into
+    /// place only once it succeeds.
+    pub atomic: bool,
+    /// Pool, declared by some unit, which limits how many of its tasks run
+    /// at once whatever the number of jobs, e.g. for links hungry for
+    /// memory.
+    pub pool: Option<String>,
+    /// Whether the task is told of the terminal its output ends up on, so
+    /// that it may colour it.
+    pub color: bool,
+    /// Directory the recipe is run from, rather than the context; paths in
+    /// the recipe are expressed relative to it.
+    pub cwd: Option<path::PathBuf>,
+    /// Tools fetched into the tool store for the task, whose executables are
+    /// put on its PATH.
+    pub tools: Vec<Tool>,
+    /// Probes which must all hold for the task to be declared at all.
+    pub enabled_when: Vec<Probe>,
+    /// Directories the task may read anything within when sandboxed, e.g.
+    /// an SDK, without declaring each file it reads.
+    pub sandbox_allow: Vec<path::PathBuf>,
+    /// Values the recipe may refer to by name, e.g. `$CC`, declared by its
+    /// unit or those above it; the invocation's own take precedence.
+    pub vars: collections::BTreeMap<String, String>,
+    /// The build options, as given with `--define`, which the task's unit
+    /// read as it was declared, so that changing them has the task run
+    /// again.
+    pub build_options: collections::BTreeMap<String, Option<String>>,
+}
+
+impl TaskOptions {
+    /// The options which bear upon what the task produces, so that changing
+    /// how it's merely announced doesn't call for it to be run again.
+    pub(crate) fn identity(&self) -> String {
+        let Self {
+            inherit_all_env,
+            wrapper,
+            image,
+            launcher,
+            network,
+            result,
+            stdout,
+            stdin,
+            aggregate,
+            phony,
+            group,
+            description: _,
+            echo: _,
+            depfile,
+            produces,
+            restat: _,
+            always: _,
+            once: _,
+            atomic: _,
+            pool: _,
+            color: _,
+            cwd,
+            tools,
+            enabled_when: _,
+            sandbox_allow,
+            vars: _,
+            build_options,
+        } = self;
+        // Each string is prefixed with its length, and each list with its
+        // count, so that no two sets of options are spelt alike.
+        let quoted = |s: &str| format!("{}:{}", s.len(), s);
+        let path = |p: &path::Path| quoted(&p.to_string_lossy());
+        let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let list = |items: Vec<String>| format!("{}[{}]", items.len(), items.join(" "));
+        let launcher = launcher.map(|launcher| match launcher {
+            Launcher::Native => String::from("native"),
+            Launcher::Wsl => String::from("wsl"),
+            Launcher::GitBash => String::from("git-bash"),
+        });
+        // A tool's executables are found by its name, within a directory
+        // named by its digest.
+        let tools = tools
+            .iter()
+            .map(|tool| {
+                let bin = tool.bin_dir(path::Path::new(""));
+                format!("{}={}", quoted(tool.name()), path(&bin))
+            })
+            .collect();
+        let build_options = build_options
+            .iter()
+            .map(|(name, value)| {
+                let value = value.as_deref().map(quoted);
+                format!("{}={}", quoted(name), optional(value))
+            })
+            .collect();
+
+        [
+            ("inherit_all_env", inherit_all_env.to_string()),
+            ("wrapper", list(wrapper.iter().map(|s| quoted(s)).collect())),
+            ("image", optional(image.as_deref().map(quoted))),
+            ("launcher", optional(launcher)),
+            ("network", network.to_string()),
+            ("result", optional(result.map(|index| index.to_string()))),
+            ("stdout", optional(stdout.map(|index| index.to_string()))),
+            ("stdin", optional(stdin.map(|index| index.to_string()))),
+            ("aggregate", aggregate.to_string()),
+            ("phony", phony.to_string()),
+            ("group", group.to_string()),
+            ("depfile", optional(depfile.as_deref().map(quoted))),
+            (
+                "produces",
+                list(produces.iter().map(|s| quoted(s)).collect()),
+            ),
+            ("cwd", optional(cwd.as_deref().map(path))),
+            ("tools", list(tools)),
+            (
+                "sandbox_allow",
+                list(sandbox_allow.iter().map(|p| path(p)).collect()),
+            ),
+            ("build_options", list(build_options)),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        Self {
+            inherit_all_env: false,
+            wrapper: vec![],
+            image: None,
+            launcher: None,
+            network: true,
+            result: None,
+            stdout: None,
+            stdin: None,
+            aggregate: true,
+            phony: false,
+            group: false,
+            description: None,
+            echo: true,
+            depfile: None,
+            produces: vec![],
+            restat: false,
+            always: false,
+            once: false,
+            atomic: false,
+            pool: None,
+            color: true,
+            cwd: None,
+            tools: vec![],
+            enabled_when: vec![],
+            sandbox_allow: vec![],
+            vars: collections::BTreeMap::new(),
+            build_options: collections::BTreeMap::new(),
+        }
+    }
+}
+
+/// Where a task was declared, for pointing users back at it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    pub unit: path::PathBuf,
+    /// The line of the declaration, for front-ends which know it.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.unit.display(), line),
+            None => write!(f, "{}", self.unit.display()),
         }
     }
 }
@@ -76,27 +317,14 @@ pub struct TaskSpec<Path> {
     pub depends_on: Vec<PrerequisiteSpec<Path>>,
     pub not_before: Vec<PrerequisiteSpec<Path>>,
     pub env: Vec<EnvSpec>,
-    pub recipe: Recipe,
+    /// What to run, which only phony tasks may go without.
+    pub recipe: Option<Recipe>,
+    pub options: TaskOptions,
+    pub origin: Option<Origin>,
 }
 
 impl TaskSpec<path::PathBuf> {
-    fn new(
-        consumes: Vec<PrerequisiteSpec<path::PathBuf>>,
-        depends_on: Vec<PrerequisiteSpec<path::PathBuf>>,
-        not_before: Vec<PrerequisiteSpec<path::PathBuf>>,
-        env: Vec<EnvSpec>,
-        recipe: Recipe,
-    ) -> Self {
-        Self {
-            consumes,
-            depends_on,
-            not_before,
-            env,
-            recipe: recipe,
-        }
-    }
-
-    pub fn resolve(self, offset: usize) -> TaskSpec<rc::Rc<path::Path>> {
+    pub fn resolve(self, offset: usize) -> TaskSpec<sync::Arc<path::Path>> {
         let resolve_prequisites = |prerequisites: Vec<PrerequisiteSpec<path::PathBuf>>| {
             prerequisites
                 .into_iter()
@@ -109,6 +337,8 @@ impl TaskSpec<path::PathBuf> {
             not_before: resolve_prequisites(self.not_before),
             env: self.env,
             recipe: self.recipe,
+            options: self.options,
+            origin: self.origin,
         }
     }
 }
@@ -116,7 +346,21 @@ impl TaskSpec<path::PathBuf> {
 pub struct Unit {
     tasks: Vec<(TargetsSpec, TaskSpec<path::PathBuf>)>,
     includes: Vec<TargetSpecHandle>,
+    pub hooks: Vec<Hook>,
+    /// Where beneath the target prefix the unit's targets go, if not
+    /// alongside the unit itself.
+    pub output_dir: Option<path::PathBuf>,
     pub sub_units: Vec<path::PathBuf>,
+    /// Files whose existence was probed as the unit was read, or which bore
+    /// upon what its globs found, which would have it read differently
+    /// should they change.
+    pub probed: Vec<path::PathBuf>,
+    /// The variables the unit declared, along with those it inherited,
+    /// which its sub-units inherit in turn.
+    pub vars: collections::BTreeMap<String, String>,
+    /// The pools the unit declared, along with how many of their tasks may
+    /// run at once.
+    pub pools: collections::BTreeMap<String, usize>,
 }
 
 impl Unit {
@@ -124,32 +368,36 @@ impl Unit {
         Self {
             tasks: vec![],
             includes: vec![],
+            hooks: vec![],
+            output_dir: None,
             sub_units: vec![],
+            probed: vec![],
+            vars: collections::BTreeMap::new(),
+            pools: collections::BTreeMap::new(),
         }
     }
 
-    fn add_task(
+    fn add_tasks(
         &mut self,
-        targets: TargetsSpec,
-        consumes: Vec<PrerequisiteSpec<path::PathBuf>>,
-        depends_on: Vec<PrerequisiteSpec<path::PathBuf>>,
-        not_before: Vec<PrerequisiteSpec<path::PathBuf>>,
-        env: Vec<EnvSpec>,
-        recipe: Recipe,
+        targets: Vec<String>,
+        tasks: Vec<TaskSpec<path::PathBuf>>,
     ) -> TargetSpecHandleIterator {
         let target_count = targets.len();
-        let task_index = self.tasks.len();
-        self.tasks.push((
-            targets,
-            TaskSpec::new(consumes, depends_on, not_before, env, recipe),
-        ));
-        TargetSpecHandleIterator::new(task_index, target_count)
+        let first = self.tasks.len();
+        for task in tasks {
+            self.tasks.push((targets.clone().into(), task));
+        }
+        TargetSpecHandleIterator::new(first..self.tasks.len(), target_count)
     }
 
     fn add_include(&mut self, include: TargetSpecHandle) {
         self.includes.push(include)
     }
 
+    fn add_hook(&mut self, hook: Hook) {
+        self.hooks.push(hook)
+    }
+
     fn add_sub_unit(&mut self, sub_unit: path::PathBuf) {
         self.sub_units.push(sub_unit)
     }
@@ -166,7 +414,20 @@ impl Unit {
 
 pub struct UnitBuilder<'p, 'v> {
     context: &'v Vec<path::Component<'p>>,
+    // The directory holding the unit.
+    dir: path::PathBuf,
     relativiser: relativiser::Relativiser,
+    launcher: Option<Launcher>,
+    env: Vec<EnvSpec>,
+    tools: Vec<Tool>,
+    sandbox_allow: Vec<path::PathBuf>,
+    vars: collections::BTreeMap<String, String>,
+    profile: Option<String>,
+    options: sync::Arc<collections::BTreeMap<String, String>>,
+    // The options read so far, and what they were.
+    read_options: collections::BTreeMap<String, Option<String>>,
+    // Resolves targets into the output directory, when there is one.
+    output: Option<relativiser::Relativiser>,
     unit: Unit,
 }
 
@@ -176,6 +437,39 @@ pub enum AddTaskError {
     RelativiseError(#[fail(cause)] relativiser::Error),
     #[fail(display = "Non unicode path.")]
     NonUnicodePath,
+    #[fail(display = "A task must have at least one target.")]
+    NoTargets,
+    #[fail(display = "Only phony tasks may go without a recipe.")]
+    NoRecipe,
+    #[fail(display = "A task can't run both always and only once.")]
+    AlwaysAndOnce,
+    #[fail(display = "A group has nothing to run.")]
+    GroupWithRecipe,
+    #[fail(display = "The task's stdout goes to target {}, which it lacks.", _0)]
+    NoStdoutTarget(usize),
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "Variable name '{}' may only hold letters, digits, '-' and '_', \
+               and can't be 'inputs' or 'targets'.",
+    _0
+)]
+pub struct BadVarName(String);
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "Pool '{}' must let at least one task run.", _0)]
+pub struct EmptyPool(String);
+
+#[derive(Debug, failure::Fail)]
+pub enum SetOutputDirError {
+    #[fail(
+        display = "Output directory '{}' must be a relative path without '..'.",
+        _0
+    )]
+    BadPath(String),
+    #[fail(display = "Output directory must be set before any task is declared.")]
+    TasksDeclared,
 }
 
 impl From<relativiser::Error> for AddTaskError {
@@ -188,7 +482,20 @@ impl<'p, 'v> UnitBuilder<'p, 'v> {
     pub fn new(context: &'v Vec<path::Component<'p>>, base: path::PathBuf) -> Self {
         Self {
             context,
-            relativiser: relativiser::Relativiser::new(base),
+            relativiser: relativiser::Relativiser::new(
+                &context.iter().collect::<path::PathBuf>(),
+                base.clone(),
+            ),
+            dir: base,
+            launcher: None,
+            env: vec![],
+            tools: vec![],
+            sandbox_allow: vec![],
+            vars: collections::BTreeMap::new(),
+            profile: None,
+            options: sync::Arc::default(),
+            read_options: collections::BTreeMap::new(),
+            output: None,
             unit: Unit::new(),
         }
     }
@@ -196,16 +503,37 @@ impl<'p, 'v> UnitBuilder<'p, 'v> {
     pub fn add_task(
         &mut self,
         targets: Vec<String>,
-        consumes: Vec<PrerequisiteSpec<path::PathBuf>>,
-        depends_on: Vec<PrerequisiteSpec<path::PathBuf>>,
-        not_before: Vec<PrerequisiteSpec<path::PathBuf>>,
-        env: Vec<EnvSpec>,
-        recipe: Recipe,
+        task: TaskSpec<path::PathBuf>,
     ) -> Result<TargetSpecHandleIterator, AddTaskError> {
+        // Tasks are known by their first target.
+        if targets.is_empty() {
+            return Err(AddTaskError::NoTargets);
+        }
+        if task.recipe.is_none() && !task.options.phony {
+            return Err(AddTaskError::NoRecipe);
+        }
+        if task.options.always && task.options.once {
+            return Err(AddTaskError::AlwaysAndOnce);
+        }
+        if task.options.group && task.recipe.is_some() {
+            return Err(AddTaskError::GroupWithRecipe);
+        }
+        // A task whose probes don't all hold is left out altogether.
+        let probes = &task.options.enabled_when;
+        if !probes.iter().all(|probe| self.probe(probe)) {
+            return Ok(self.unit.add_tasks(targets, vec![]));
+        }
+        match task.options.stdout {
+            Some(stdout) if stdout >= targets.len() => {
+                return Err(AddTaskError::NoStdoutTarget(stdout))
+            }
+            _ => {}
+        }
+
         let targets = targets
             .into_iter()
             .map(|path| {
-                self.relativise(path::Path::new(&path))
+                self.relativise_target(path::Path::new(&path))
                     .map_err(|err| AddTaskError::from(err))
                     .and_then(|path| {
                         path.into_os_string()
@@ -221,33 +549,92 @@ impl<'p, 'v> UnitBuilder<'p, 'v> {
                     PrerequisiteSpec::Named(name, optional) => {
                         Ok(PrerequisiteSpec::Named(self.relativise(&name)?, optional))
                     }
+                    PrerequisiteSpec::Glob(pattern) => {
+                        Ok(PrerequisiteSpec::Glob(self.relativise_target(&pattern)?))
+                    }
                     _ => Ok(prerequisite),
                 }
             };
 
-        let consumes = consumes
+        let consumes = task
+            .consumes
             .into_iter()
             .map(relativise_prequisite)
             .collect::<Result<Vec<_>, _>>()?;
 
-        let depends_on = depends_on
+        let depends_on = task
+            .depends_on
             .into_iter()
             .map(relativise_prequisite)
             .collect::<Result<Vec<_>, _>>()?;
 
-        let not_before = not_before
+        let not_before = task
+            .not_before
             .into_iter()
             .map(relativise_prequisite)
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(self.unit.add_task(
-            targets.into(),
+        let mut task = TaskSpec {
             consumes,
             depends_on,
             not_before,
-            env,
-            recipe,
-        ))
+            ..task
+        };
+        if let Some(cwd) = &task.options.cwd {
+            task.options.cwd = Some(self.relativise(cwd)?);
+        }
+
+        // Fanning out gives each input a task of its own, whose targets
+        // will generally need '%f' to tell them apart.
+        let tasks = if task.options.aggregate || task.consumes.len() <= 1 {
+            vec![task]
+        } else {
+            task.consumes
+                .iter()
+                .map(|input| TaskSpec {
+                    consumes: vec![input.clone()],
+                    depends_on: task.depends_on.clone(),
+                    not_before: task.not_before.clone(),
+                    env: task.env.clone(),
+                    recipe: task.recipe.clone(),
+                    options: task.options.clone(),
+                    origin: task.origin.clone(),
+                })
+                .collect()
+        };
+
+        Ok(self.unit.add_tasks(targets, tasks))
+    }
+
+    /// Whether `probe` holds, noting any file it looked for so that the unit
+    /// is read again should that file come or go.
+    pub fn probe(&mut self, probe: &Probe) -> bool {
+        if let Probe::Exists(path) = probe {
+            self.unit.probed.push(self.dir.join(path));
+        }
+        probe.holds(&self.dir)
+    }
+
+    /// The files matching `pattern`, relative to the unit, less any ignored
+    /// by `.asmblignore` files; the unit is read again should those change.
+    pub fn glob(&mut self, pattern: &str) -> Result<Vec<path::PathBuf>, glob::PatternError> {
+        let pattern = format!(
+            "{}/{}",
+            glob::Pattern::escape(&self.dir.to_string_lossy()),
+            pattern
+        );
+        let ignore = Ignore::new(self.context.iter().collect());
+        let found = glob::glob_with(&pattern, crate::GLOB_OPTIONS)?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file() && !ignore.is_ignored(path))
+            .filter_map(|path| {
+                path.strip_prefix(&self.dir)
+                    .ok()
+                    .map(path::Path::to_path_buf)
+            })
+            .collect();
+        self.unit.probed.extend(ignore.files());
+        Ok(found)
     }
 
     pub fn add_sub_unit(&mut self, sub_unit: path::PathBuf) -> Result<(), relativiser::Error> {
@@ -258,11 +645,155 @@ impl<'p, 'v> UnitBuilder<'p, 'v> {
         self.unit.add_include(include)
     }
 
-    pub fn unit(self) -> Unit {
+    pub fn add_hook(&mut self, hook: Hook) {
+        self.unit.add_hook(hook)
+    }
+
+    pub fn set_launcher(&mut self, launcher: Launcher) {
+        self.launcher = Some(launcher)
+    }
+
+    /// Sends the unit's targets to `dir`, relative to the target prefix,
+    /// rather than mirroring the unit's place in the context.
+    pub fn set_output_dir(&mut self, dir: path::PathBuf) -> Result<(), SetOutputDirError> {
+        if !self.unit.tasks.is_empty() {
+            return Err(SetOutputDirError::TasksDeclared);
+        }
+        let valid = dir
+            .components()
+            .all(|component| matches!(component, path::Component::Normal(_)));
+        if !valid || dir.as_os_str().is_empty() {
+            return Err(SetOutputDirError::BadPath(
+                dir.to_string_lossy().into_owned(),
+            ));
+        }
+        let root: path::PathBuf = self.context.iter().collect();
+        self.output = Some(relativiser::Relativiser::new(&root, root.join(&dir)));
+        self.unit.output_dir = Some(dir);
+        Ok(())
+    }
+
+    /// Adds to the environment given to every task in the unit, beneath
+    /// that which each task declares itself.
+    pub fn add_default_env(&mut self, env: Vec<EnvSpec>) {
+        self.env.extend(env)
+    }
+
+    pub(crate) fn set_profile(&mut self, profile: &str) {
+        self.profile = Some(profile.to_string());
+    }
+
+    /// The kind of build asked for, e.g. `debug` or `release`, if any was.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub(crate) fn set_options(
+        &mut self,
+        options: sync::Arc<collections::BTreeMap<String, String>>,
+    ) {
+        self.options = options;
+    }
+
+    /// The value of a build option, noting that the unit read it so that its
+    /// tasks run again should it change.
+    pub fn option(&mut self, name: &str) -> Option<&str> {
+        let value = self.options.get(name);
+        self.read_options.insert(name.to_string(), value.cloned());
+        value.map(String::as_str)
+    }
+
+    /// Starts the unit off with the variables of the unit above it.
+    pub fn inherit_vars(&mut self, vars: &collections::BTreeMap<String, String>) {
+        self.vars.extend(vars.clone());
+    }
+
+    /// Declares a variable which every task in the unit, and in its
+    /// sub-units, may refer to in its recipe.
+    pub fn set_var(&mut self, name: String, value: String) -> Result<(), BadVarName> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            && name != "inputs"
+            && name != "targets";
+        if !valid {
+            return Err(BadVarName(name));
+        }
+        self.vars.insert(name, value);
+        Ok(())
+    }
+
+    /// The value of a variable declared in the unit or one above it.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Declares a pool, which no more than `depth` of the tasks using it,
+    /// whichever unit declared them, may run in at once.
+    pub fn add_pool(&mut self, name: String, depth: usize) -> Result<(), EmptyPool> {
+        if depth == 0 {
+            return Err(EmptyPool(name));
+        }
+        self.unit.pools.insert(name, depth);
+        Ok(())
+    }
+
+    /// Gives every task in the unit `tool`, in place of any tool of the same
+    /// name given before.
+    pub fn add_tool(&mut self, tool: Tool) {
+        self.tools.retain(|existing| existing.name() != tool.name());
+        self.tools.push(tool)
+    }
+
+    /// Lets every task in the unit read anything within `dir` when
+    /// sandboxed. Absolute paths, e.g. `/usr/include`, are kept as they are.
+    pub fn allow_in_sandbox(&mut self, dir: path::PathBuf) -> Result<(), relativiser::Error> {
+        let dir = match dir.is_absolute() {
+            true => dir,
+            false => self.relativise(&dir)?,
+        };
+        self.sandbox_allow.push(dir);
+        Ok(())
+    }
+
+    pub fn unit(mut self) -> Unit {
+        if let Some(launcher) = self.launcher {
+            for (_, task) in self.unit.tasks.iter_mut() {
+                task.options.launcher.get_or_insert(launcher);
+            }
+        }
+        for (_, task) in self.unit.tasks.iter_mut() {
+            task.options.tools.extend(self.tools.iter().cloned());
+            task.options
+                .sandbox_allow
+                .extend(self.sandbox_allow.iter().cloned());
+            task.options.vars = self.vars.clone();
+            task.options.build_options = self.read_options.clone();
+        }
+        self.unit.vars = self.vars;
+        if !self.env.is_empty() {
+            for (_, task) in self.unit.tasks.iter_mut() {
+                task.env = env::merge(&self.env, &task.env)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+            }
+            for hook in self.unit.hooks.iter_mut() {
+                hook.add_default_env(&self.env);
+            }
+        }
         self.unit
     }
 
     fn relativise(&self, path: &path::Path) -> Result<path::PathBuf, relativiser::Error> {
-        self.relativiser.relativise(self.context, path)
+        self.relativiser.relativise(path)
+    }
+
+    fn relativise_target(&self, path: &path::Path) -> Result<path::PathBuf, relativiser::Error> {
+        match &self.output {
+            Some(output) => output.relativise(path),
+            None => self.relativise(path),
+        }
     }
 }
@@ -0,0 +1,430 @@
+use std::{collections, fmt, io, path};
+
+use crate::{Prerequisite, Stat, Task, TaskList};
+
+/// A problem with a task graph which can be found without running it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// A prerequisite which neither exists nor is produced by any task.
+    MissingProducer {
+        prerequisite: path::PathBuf,
+        task: path::PathBuf,
+    },
+    /// A target outside of the target directory.
+    TargetEscape(path::PathBuf),
+    /// A prerequisite outside of both the context and target directories.
+    PrerequisiteEscape {
+        prerequisite: path::PathBuf,
+        task: path::PathBuf,
+    },
+    /// A task which no phony task leads to, in a graph having them.
+    Unreachable(path::PathBuf),
+    /// A task passed the whole of asmbl's environment, which may differ from
+    /// one machine to another.
+    NotHermetic(path::PathBuf),
+    /// Targets differing only in case, which are one and the same on some
+    /// file-systems.
+    CaseCollision(path::PathBuf, path::PathBuf),
+}
+
+// Names each kind of finding, both for `Finding::kind` and for listing them
+// all, so that neither can leave one out.
+macro_rules! kinds {
+    ($($variant:ident $fields:tt => $kind:literal,)*) => {
+        impl Finding {
+            /// Every kind of finding there is.
+            pub const KINDS: &'static [&'static str] = &[$($kind),*];
+
+            /// A stable name for the kind of finding, for machine-readable
+            /// output.
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $(Self::$variant $fields => $kind,)*
+                }
+            }
+        }
+    };
+}
+
+kinds! {
+    MissingProducer { .. } => "missing-producer",
+    TargetEscape(_) => "target-escape",
+    PrerequisiteEscape { .. } => "prerequisite-escape",
+    Unreachable(_) => "unreachable",
+    NotHermetic(_) => "not-hermetic",
+    CaseCollision(..) => "case-collision",
+}
+
+impl Finding {
+    /// The paths the finding concerns, most relevant first.
+    pub fn paths(&self) -> Vec<&path::Path> {
+        match self {
+            Self::TargetEscape(task) | Self::Unreachable(task) | Self::NotHermetic(task) => {
+                vec![task]
+            }
+            Self::MissingProducer { prerequisite, task }
+            | Self::PrerequisiteEscape { prerequisite, task } => vec![prerequisite, task],
+            Self::CaseCollision(first, second) => vec![first, second],
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingProducer { prerequisite, task } => write!(
+                f,
+                "Prerequisite '{}' of the task producing '{}' doesn't exist and nothing produces it.",
+                prerequisite.display(),
+                task.display()
+            ),
+            Self::TargetEscape(target) => write!(
+                f,
+                "Target '{}' lies outside of the target directory.",
+                target.display()
+            ),
+            Self::PrerequisiteEscape { prerequisite, task } => write!(
+                f,
+                "Prerequisite '{}' of the task producing '{}' lies outside of the project.",
+                prerequisite.display(),
+                task.display()
+            ),
+            Self::Unreachable(task) => write!(
+                f,
+                "No phony task leads to the task producing '{}'.",
+                task.display()
+            ),
+            Self::NotHermetic(task) => write!(
+                f,
+                "The task producing '{}' is passed the whole environment, so may build differently elsewhere.",
+                task.display()
+            ),
+            Self::CaseCollision(first, second) => write!(
+                f,
+                "Targets '{}' and '{}' differ only in case.",
+                first.display(),
+                second.display()
+            ),
+        }
+    }
+}
+
+impl TaskList {
+    /// Looks the graph over for problems, touching nothing but `stat`.
+    pub fn validate(&self, target_prefix: &path::Path, stat: &dyn Stat) -> Vec<Finding> {
+        let mut findings = vec![];
+
+        // Targets by their lower-case names.
+        let mut folded: collections::HashMap<_, &path::Path> = collections::HashMap::new();
+
+        for task in self.tasks.iter() {
+            let first = task.targets[0].to_path_buf();
+
+            if task.options.inherit_all_env {
+                findings.push(Finding::NotHermetic(first.clone()));
+            }
+
+            for target in task.targets() {
+                let name = target.to_string_lossy().to_lowercase();
+                if let Some(other) = folded.insert(name, target) {
+                    findings.push(Finding::CaseCollision(
+                        other.to_path_buf(),
+                        target.to_path_buf(),
+                    ));
+                }
+            }
+
+            for target in task.targets() {
+                if !target.starts_with(target_prefix) {
+                    findings.push(Finding::TargetEscape(target.to_path_buf()));
+                }
+            }
+
+            for upstream in task.upstream.iter() {
+                let (prerequisite, optional) = match upstream {
+                    Prerequisite::Named(prerequisite, optional) => (prerequisite, *optional),
+                    Prerequisite::Handle(_) => continue,
+                };
+
+                let escapes = prerequisite.is_absolute()
+                    || (prerequisite.starts_with("..") && !prerequisite.starts_with(target_prefix));
+                if escapes {
+                    findings.push(Finding::PrerequisiteEscape {
+                        prerequisite: prerequisite.to_path_buf(),
+                        task: first.clone(),
+                    });
+                }
+
+                if !optional {
+                    if let Err(err) = stat.modified(prerequisite) {
+                        if err.kind() == io::ErrorKind::NotFound {
+                            findings.push(Finding::MissingProducer {
+                                prerequisite: prerequisite.to_path_buf(),
+                                task: first.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        findings.extend(
+            self.unreachable()
+                .map(|task| Finding::Unreachable(task.targets[0].to_path_buf())),
+        );
+        findings
+    }
+
+    // The tasks no phony task leads to, should there be any phony tasks to
+    // stand for what's wanted of the build.
+    fn unreachable(&self) -> impl Iterator<Item = &Task> {
+        let mut reached = vec![false; self.tasks.len()];
+        let mut next: Vec<_> = (0..self.tasks.len())
+            .filter(|index| self.tasks[*index].is_phony())
+            .collect();
+        let any = !next.is_empty();
+        while let Some(index) = next.pop() {
+            if std::mem::replace(&mut reached[index], true) {
+                continue;
+            }
+            next.extend(
+                self.tasks[index]
+                    .upstream
+                    .iter()
+                    .filter_map(|upstream| match upstream {
+                        Prerequisite::Handle(handle) => Some(handle.index),
+                        Prerequisite::Named(..) => None,
+                    }),
+            );
+        }
+        self.tasks
+            .iter()
+            .zip(reached)
+            .filter(move |(_, reached)| any && !reached)
+            .map(|(task, _)| task)
+    }
+}
+
+/// Tasks which depend upon one another, by their first targets, each
+/// depending upon the next and the last upon the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle(Vec<path::PathBuf>);
+
+impl Cycle {
+    pub fn targets(&self) -> &[path::PathBuf] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let targets: Vec<_> = self
+            .0
+            .iter()
+            .map(|target| format!("'{}'", target.display()))
+            .collect();
+        write!(f, "{}", targets.join(", "))
+    }
+}
+
+/// Finds a cycle amongst the tasks which couldn't be ordered, of which
+/// there's always one if any remain. Handles still refer to positions
+/// within `tasks`.
+pub(crate) fn cycle(tasks: &[Option<Task>]) -> Option<Cycle> {
+    let upstream = |index: usize| {
+        tasks[index]
+            .as_ref()
+            .unwrap()
+            .upstream
+            .iter()
+            .filter_map(|upstream| match upstream {
+                Prerequisite::Handle(handle) if tasks[handle.index].is_some() => Some(handle.index),
+                _ => None,
+            })
+    };
+
+    // Every remaining task waits upon another, so following the first of
+    // them from anywhere must eventually come back round.
+    let mut index = (0..tasks.len()).find(|index| tasks[*index].is_some())?;
+    let mut seen = collections::HashMap::new();
+    let mut path = vec![];
+    while !seen.contains_key(&index) {
+        seen.insert(index, path.len());
+        path.push(index);
+        index = upstream(index).next().unwrap();
+    }
+
+    Some(Cycle(
+        path[seen[&index]..]
+            .iter()
+            .map(|index| tasks[*index].as_ref().unwrap().targets[0].to_path_buf())
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time;
+
+    use crate::testing::{declare, spec};
+    use crate::{PrerequisiteSpec, TaskSpec};
+
+    // Only the given files exist.
+    struct Existing(&'static [&'static str]);
+
+    impl Stat for Existing {
+        fn modified(&self, path: &path::Path) -> io::Result<time::SystemTime> {
+            match self.0.iter().any(|file| path == path::Path::new(file)) {
+                true => Ok(time::UNIX_EPOCH),
+                false => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    // The named files, each optional or not.
+    fn consuming(files: &[(&str, bool)]) -> Vec<PrerequisiteSpec<path::PathBuf>> {
+        files
+            .iter()
+            .map(|(file, optional)| PrerequisiteSpec::Named(file.into(), *optional))
+            .collect()
+    }
+
+    fn tasks(declared: Vec<(&str, TaskSpec<path::PathBuf>)>) -> TaskList {
+        let context = path::Path::new("/project");
+        declare(context, path::Path::new("../out"), |builder| {
+            for (target, spec) in declared {
+                builder.add_task(vec![target.to_string()], spec).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn sound_graphs_have_nothing_to_find() {
+        let tasks = tasks(vec![
+            ("a.o", spec(consuming(&[("a.c", false), ("a.h", true)]))),
+            ("a", spec(consuming(&[("../out/a.o", false)]))),
+        ]);
+        let findings = tasks.validate(path::Path::new("../out"), &Existing(&["a.c"]));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn what_nothing_produces_is_found() {
+        let consumes = [("a.c", false), ("a.h", false)];
+        let tasks = tasks(vec![("a.o", spec(consuming(&consumes)))]);
+        let findings = tasks.validate(path::Path::new("../out"), &Existing(&["a.c"]));
+        assert_eq!(
+            findings,
+            vec![Finding::MissingProducer {
+                prerequisite: path::PathBuf::from("a.h"),
+                task: path::PathBuf::from("../out/a.o"),
+            }]
+        );
+        assert_eq!(findings[0].kind(), "missing-producer");
+        assert_eq!(
+            findings[0].paths(),
+            vec![path::Path::new("a.h"), path::Path::new("../out/a.o")]
+        );
+    }
+
+    #[test]
+    fn paths_escaping_the_project_are_found() {
+        let consumes = [("/usr/include/a.h", false), ("../a.c", false)];
+        let tasks = tasks(vec![("a.o", spec(consuming(&consumes)))]);
+        // Prerequisites are relative to the context by now.
+        let stat = Existing(&["../usr/include/a.h", "../a.c"]);
+        let findings = tasks.validate(path::Path::new("../out"), &stat);
+        let task = || path::PathBuf::from("../out/a.o");
+        assert_eq!(
+            findings,
+            vec![
+                Finding::PrerequisiteEscape {
+                    prerequisite: path::PathBuf::from("../usr/include/a.h"),
+                    task: task(),
+                },
+                Finding::PrerequisiteEscape {
+                    prerequisite: path::PathBuf::from("../a.c"),
+                    task: task(),
+                },
+            ]
+        );
+
+        // Targets are only found to escape a target directory they don't
+        // lie within.
+        let findings = tasks.validate(path::Path::new("../elsewhere"), &stat);
+        assert!(findings.contains(&Finding::TargetEscape(task())));
+        assert_eq!(findings[0].kind(), "target-escape");
+        assert_eq!(
+            findings[0].to_string(),
+            "Target '../out/a.o' lies outside of the target directory."
+        );
+    }
+
+    #[test]
+    fn what_may_build_differently_elsewhere_is_found() {
+        let mut leaky = spec(vec![]);
+        leaky.options.inherit_all_env = true;
+        let tasks = tasks(vec![
+            ("README", spec(vec![])),
+            ("a.o", leaky),
+            ("readme", spec(vec![])),
+        ]);
+        let findings = tasks.validate(path::Path::new("../out"), &Existing(&[]));
+        assert_eq!(
+            findings,
+            vec![
+                Finding::NotHermetic(path::PathBuf::from("../out/a.o")),
+                Finding::CaseCollision(
+                    path::PathBuf::from("../out/README"),
+                    path::PathBuf::from("../out/readme")
+                ),
+            ]
+        );
+        assert_eq!(findings[1].kind(), "case-collision");
+    }
+
+    #[test]
+    fn tasks_no_phony_task_leads_to_are_found() {
+        let phony = |consumes: &[(&str, bool)]| {
+            let mut phony = spec(consuming(consumes));
+            phony.recipe = None;
+            phony.options.phony = true;
+            phony
+        };
+        let tasks = tasks(vec![
+            ("a.o", spec(vec![])),
+            ("a", spec(consuming(&[("../out/a.o", false)]))),
+            ("b", spec(consuming(&[("../out/a.o", false)]))),
+            ("all", phony(&[("../out/a", false)])),
+            ("clean", phony(&[])),
+        ]);
+        let findings = tasks.validate(path::Path::new("../out"), &Existing(&[]));
+        assert_eq!(
+            findings,
+            vec![Finding::Unreachable(path::PathBuf::from("../out/b"))]
+        );
+        assert_eq!(findings[0].kind(), "unreachable");
+    }
+
+    #[test]
+    fn every_kind_of_finding_is_listed() {
+        let task = || path::PathBuf::from("../out/a");
+        let findings = [
+            Finding::MissingProducer {
+                prerequisite: task(),
+                task: task(),
+            },
+            Finding::TargetEscape(task()),
+            Finding::PrerequisiteEscape {
+                prerequisite: task(),
+                task: task(),
+            },
+            Finding::Unreachable(task()),
+            Finding::NotHermetic(task()),
+            Finding::CaseCollision(task(), task()),
+        ];
+        let kinds: Vec<_> = findings.iter().map(Finding::kind).collect();
+        assert_eq!(kinds, Finding::KINDS);
+    }
+}
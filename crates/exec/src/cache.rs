@@ -0,0 +1,199 @@
+use std::{fs, io, path, time};
+
+use crate::content;
+
+/// The files tasks produced, kept under `.asmbl/cache` in the target
+/// directory by the digest of the task's action, so that a task run alike
+/// again, e.g. on switching back to a branch, has them restored instead.
+///
+/// Each file is kept once, by the digest of its content, in `objects`; a
+/// file in `actions` lists those each action produced, in order.
+pub struct Cache {
+    dir: path::PathBuf,
+}
+
+impl Cache {
+    pub fn open(target_dir: &path::Path) -> Self {
+        Self {
+            dir: target_dir.join(".asmbl").join("cache"),
+        }
+    }
+
+    fn object(&self, digest: &str) -> path::PathBuf {
+        self.dir.join("objects").join(&digest[..2]).join(digest)
+    }
+
+    fn action(&self, action: &str) -> path::PathBuf {
+        self.dir.join("actions").join(action)
+    }
+
+    /// Puts the files `action` produced in place of `files`, returning
+    /// whether they were kept at all. Restored files count as new, so as
+    /// to be newer than whatever they were made from.
+    pub fn restore(&self, action: &str, files: &[path::PathBuf]) -> io::Result<bool> {
+        let listing = match fs::read_to_string(self.action(action)) {
+            Ok(listing) => listing,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let objects: Vec<_> = listing.lines().map(|digest| self.object(digest)).collect();
+        if objects.len() != files.len() || !objects.iter().all(|object| object.is_file()) {
+            return Ok(false);
+        }
+
+        for (object, file) in objects.iter().zip(files.iter()) {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match fs::remove_file(file) {
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+            place(object, file)?;
+            fs::OpenOptions::new()
+                .write(true)
+                .open(file)?
+                .set_modified(time::SystemTime::now())?;
+        }
+        Ok(true)
+    }
+
+    /// Keeps the files `action` produced, unless any isn't a file.
+    pub fn store(&self, action: &str, files: &[path::PathBuf]) -> io::Result<()> {
+        let mut listing = String::new();
+        for file in files {
+            match fs::metadata(file) {
+                Ok(metadata) if metadata.is_file() => {}
+                _ => return Ok(()),
+            }
+            let digest = content::digest(file)?;
+            let object = self.object(&digest);
+            if !object.exists() {
+                fs::create_dir_all(object.parent().unwrap())?;
+                let partial = object.with_extension("part");
+                fs::copy(file, &partial)?;
+                fs::rename(&partial, &object)?;
+            }
+            listing.push_str(&digest);
+            listing.push('\n');
+        }
+
+        let file = self.action(action);
+        fs::create_dir_all(file.parent().unwrap())?;
+        let partial = file.with_extension("part");
+        fs::write(&partial, listing)?;
+        fs::rename(&partial, &file)
+    }
+
+    /// Copies any of `files` restored by hard link apart from the cache, so
+    /// that a task writing to them in place leaves what's kept alone, even
+    /// when the cache is no longer in use.
+    pub fn detach(&self, files: &[path::PathBuf]) -> io::Result<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        for file in files {
+            use std::os::unix::fs::MetadataExt;
+
+            match fs::symlink_metadata(file) {
+                Ok(metadata) if metadata.is_file() && metadata.nlink() > 1 => {}
+                _ => continue,
+            }
+            let mut partial = file.as_os_str().to_owned();
+            partial.push(".part");
+            let partial = path::PathBuf::from(partial);
+            fs::copy(file, &partial)?;
+            fs::rename(&partial, file)?;
+        }
+        #[cfg(not(unix))]
+        let _ = files;
+        Ok(())
+    }
+}
+
+// Hard links are only told apart from the files they share their content
+// with on unix, so elsewhere files are copied into place.
+#[cfg(unix)]
+fn place(object: &path::Path, file: &path::Path) -> io::Result<()> {
+    match fs::hard_link(object, file) {
+        Ok(()) => Ok(()),
+        // The cache may well be on another file system.
+        Err(_) => fs::copy(object, file).map(|_| ()),
+    }
+}
+
+#[cfg(not(unix))]
+fn place(object: &path::Path, file: &path::Path) -> io::Result<()> {
+    fs::copy(object, file).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn kept_files_are_restored_and_detached() {
+        let dir = TempDir::new("cache");
+        let files = vec![dir.join("a.o"), dir.join("b.o")];
+        fs::write(&files[0], "a").unwrap();
+        fs::write(&files[1], "b").unwrap();
+
+        let cache = Cache::open(&dir);
+        assert!(!cache.restore("1234", &files).unwrap());
+        cache.store("1234", &files).unwrap();
+        fs::remove_file(&files[0]).unwrap();
+        fs::write(&files[1], "c").unwrap();
+        assert!(cache.restore("1234", &files).unwrap());
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&files[1]).unwrap(), "b");
+
+        // Writing to a restored file in place leaves the cache alone.
+        cache.detach(&files).unwrap();
+        fs::write(&files[0], "d").unwrap();
+        assert!(cache.restore("1234", &files).unwrap());
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "a");
+    }
+
+    #[test]
+    fn actions_are_only_kept_or_restored_whole() {
+        let dir = TempDir::new("cache-whole");
+        let files = vec![dir.join("a.o"), dir.join("b.o")];
+        fs::write(&files[0], "a").unwrap();
+
+        // A task leaving a target missing has nothing kept.
+        let cache = Cache::open(&dir);
+        cache.store("1234", &files).unwrap();
+        assert!(!cache.restore("1234", &files).unwrap());
+
+        fs::write(&files[1], "b").unwrap();
+        cache.store("1234", &files).unwrap();
+        assert!(!cache.restore("1234", &files[..1]).unwrap());
+        let digest = content::digest(&files[1]).unwrap();
+        fs::remove_file(cache.object(&digest)).unwrap();
+        fs::write(&files[0], "c").unwrap();
+        assert!(!cache.restore("1234", &files).unwrap());
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "c");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_only_detached_from_a_cache() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new("cache-detach");
+        let files = vec![dir.join("a.o")];
+        fs::write(&files[0], "a").unwrap();
+        fs::hard_link(&files[0], dir.join("elsewhere")).unwrap();
+        let links = || fs::metadata(&files[0]).unwrap().nlink();
+
+        let cache = Cache::open(&dir);
+        cache.detach(&files).unwrap();
+        assert_eq!(links(), 2);
+        cache.store("1234", &[dir.join("elsewhere")]).unwrap();
+        cache.detach(&files).unwrap();
+        assert_eq!(links(), 1);
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "a");
+    }
+}
@@ -0,0 +1,306 @@
+use std::{cell, collections, fs, io, path, time};
+
+use rayon::prelude::*;
+use sha2::Digest;
+
+use asmbl_core::Stat;
+use asmbl_utils::io::StateFile;
+
+/// Modification times which only move on when a file's content changes,
+/// so that checkouts and clock skew don't cause rebuilds.
+///
+/// The digest of each file seen, along with when that content was first
+/// seen, is kept in `.asmbl/content` under the target directory. So is the
+/// file's size, modification time and inode, which so long as they stay
+/// the same spare it being hashed again.
+pub struct ContentHashes {
+    file: StateFile,
+    // When this run started; content seen for the first time dates from
+    // then.
+    now: time::SystemTime,
+    entries: cell::RefCell<collections::BTreeMap<path::PathBuf, Entry>>,
+    // Files already hashed during this run.
+    checked: cell::RefCell<collections::HashSet<path::PathBuf>>,
+}
+
+struct Entry {
+    digest: String,
+    seen: time::SystemTime,
+    // Missing from entries saved before stamps were.
+    stamp: Option<Stamp>,
+}
+
+// What's taken to change whenever a file's content does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Stamp {
+    size: u64,
+    modified: u128,
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Self {
+            size: metadata.len(),
+            modified: modified.as_nanos(),
+            inode,
+        }
+    }
+
+    fn parse(stamp: &str) -> Option<Self> {
+        let mut fields = stamp.splitn(3, ':');
+        Some(Self {
+            size: fields.next()?.parse().ok()?,
+            modified: fields.next()?.parse().ok()?,
+            inode: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+pub(crate) fn digest(path: &path::Path) -> io::Result<String> {
+    let mut hasher = sha2::Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl ContentHashes {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "content");
+
+        // Each line holds a digest, the time it was first seen in
+        // nanoseconds since the epoch, optionally the file's stamp as
+        // `size:modified:inode`, and a path, separated by tabs.
+        let entries = file
+            .read()?
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let digest = fields.next()?.to_string();
+                let since = fields.next()?.parse().ok()?;
+                let rest = fields.next()?;
+                let (stamp, path) = match rest.split_once('\t') {
+                    Some((stamp, path)) => match Stamp::parse(stamp) {
+                        Some(stamp) => (Some(stamp), path),
+                        None => (None, rest),
+                    },
+                    None => (None, rest),
+                };
+                let seen = time::UNIX_EPOCH + time::Duration::from_nanos(since);
+                let entry = Entry {
+                    digest,
+                    seen,
+                    stamp,
+                };
+                Some((path::PathBuf::from(path), entry))
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            now: time::SystemTime::now(),
+            entries: cell::RefCell::new(entries),
+            checked: cell::RefCell::new(collections::HashSet::new()),
+        })
+    }
+
+    // The digest of the file, which needn't be worked out again should the
+    // file's stamp be as it was.
+    fn digest(&self, path: &path::Path, stamp: Stamp) -> io::Result<String> {
+        match self.entries.borrow().get(path) {
+            Some(entry) if entry.stamp == Some(stamp) => return Ok(entry.digest.clone()),
+            _ => {}
+        }
+        digest(path)
+    }
+
+    // Notes the file's content, which dates from `now` unless it was seen
+    // before, or `always` says otherwise.
+    fn note(
+        &self,
+        path: &path::Path,
+        digest: String,
+        stamp: Stamp,
+        now: time::SystemTime,
+        always: bool,
+    ) {
+        let mut entries = self.entries.borrow_mut();
+        let seen = match entries.get(path) {
+            Some(entry) if entry.digest == digest && !always => entry.seen,
+            _ => now,
+        };
+        let stamp = Some(stamp);
+        let entry = Entry {
+            digest,
+            seen,
+            stamp,
+        };
+        entries.insert(path.to_path_buf(), entry);
+        self.checked.borrow_mut().insert(path.to_path_buf());
+    }
+
+    /// Hashes those of `paths` larger than `threshold` bytes which have
+    /// changed, several at a time, rather than waiting for each to be asked
+    /// after in turn.
+    pub fn hash_ahead<'a, I>(&self, paths: I, threshold: u64)
+    where
+        I: IntoIterator<Item = &'a path::Path>,
+    {
+        let mut large = vec![];
+        for path in paths {
+            if self.checked.borrow().contains(path) {
+                continue;
+            }
+            let stamp = match fs::metadata(path) {
+                Ok(metadata) if metadata.is_file() && metadata.len() > threshold => {
+                    Stamp::of(&metadata)
+                }
+                _ => continue,
+            };
+            match self.entries.borrow().get(path) {
+                Some(entry) if entry.stamp == Some(stamp) => continue,
+                _ => large.push((path, stamp)),
+            }
+        }
+        large.sort();
+        large.dedup();
+
+        // Anything which can't be read is left to be found out about later.
+        let digests: Vec<_> = large
+            .into_par_iter()
+            .filter_map(|(path, stamp)| Some((path, stamp, digest(path).ok()?)))
+            .collect();
+        for (path, stamp, digest) in digests {
+            self.note(path, digest, stamp, self.now, false);
+        }
+    }
+
+    /// Notes that a task has just produced `path`, so that it counts as
+    /// newer than everything the task consumed even if its content is
+    /// unchanged.
+    pub fn produced(&self, path: &path::Path) -> io::Result<()> {
+        self.ran(path, true)
+    }
+
+    /// Notes that a restat task has just run, which may have left `path` as
+    /// it was; only should its content have changed does it count as newer.
+    pub fn restat(&self, path: &path::Path) -> io::Result<()> {
+        self.ran(path, false)
+    }
+
+    // Notes what a task which has just run left at `path`. Should it have
+    // left nothing, whatever was known of the file is forgotten, so that
+    // it's found to be missing when next asked after.
+    fn ran(&self, path: &path::Path, always: bool) -> io::Result<()> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                self.entries.borrow_mut().remove(path);
+                self.checked.borrow_mut().remove(path);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let stamp = Stamp::of(&metadata);
+        let digest = self.digest(path, stamp)?;
+        self.note(path, digest, stamp, time::SystemTime::now(), always);
+        Ok(())
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (path, entry) in self.entries.borrow().iter() {
+            let since = entry
+                .seen
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default();
+            content.push_str(&format!("{}\t{}\t", entry.digest, since.as_nanos()));
+            if let Some(stamp) = entry.stamp {
+                content.push_str(&format!(
+                    "{}:{}:{}\t",
+                    stamp.size, stamp.modified, stamp.inode
+                ));
+            }
+            content.push_str(&format!("{}\n", path.display()));
+        }
+
+        self.file.write(&content)
+    }
+}
+
+impl Stat for ContentHashes {
+    fn modified(&self, path: &path::Path) -> io::Result<time::SystemTime> {
+        // Directories have no content of their own to speak of.
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            return metadata.modified();
+        }
+
+        if !self.checked.borrow().contains(path) {
+            let stamp = Stamp::of(&metadata);
+            let digest = self.digest(path, stamp)?;
+            self.note(path, digest, stamp, self.now, false);
+        }
+        Ok(self.entries.borrow()[path].seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_utils::testing::TempDir;
+
+    #[test]
+    fn large_files_are_hashed_ahead_and_remembered() {
+        let dir = TempDir::new("content");
+        let (small, large) = (dir.join("small"), dir.join("large"));
+        fs::write(&small, "small").unwrap();
+        fs::write(&large, vec![b'x'; 4096]).unwrap();
+
+        let content = ContentHashes::load(&dir).unwrap();
+        content.hash_ahead(vec![small.as_path(), large.as_path()], 1024);
+        assert!(!content.checked.borrow().contains(&small));
+        assert!(content.checked.borrow().contains(&large));
+        let seen = content.modified(&large).unwrap();
+        content.modified(&small).unwrap();
+        content.save().unwrap();
+
+        // The stamps saved spare either being hashed again.
+        let content = ContentHashes::load(&dir).unwrap();
+        for path in [&small, &large].iter() {
+            let stamp = content.entries.borrow()[path.as_path()].stamp;
+            assert_eq!(stamp, Some(Stamp::of(&fs::metadata(path).unwrap())));
+        }
+        assert_eq!(content.modified(&large).unwrap(), seen);
+    }
+
+    #[test]
+    fn targets_a_task_didnt_leave_are_forgotten() {
+        let dir = TempDir::new("content-missing");
+        let target = dir.join("target");
+        fs::write(&target, "a").unwrap();
+        let content = ContentHashes::load(&dir).unwrap();
+        content.modified(&target).unwrap();
+        content.save().unwrap();
+
+        fs::remove_file(&target).unwrap();
+        content.produced(&target).unwrap();
+        content.restat(&target).unwrap();
+        assert!(content.modified(&target).is_err());
+        content.save().unwrap();
+        let content = ContentHashes::load(&dir).unwrap();
+        assert!(content.entries.borrow().is_empty());
+    }
+}
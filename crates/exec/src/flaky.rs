@@ -0,0 +1,49 @@
+use std::{collections, io, path};
+
+use asmbl_core::Task;
+use asmbl_utils::io::{records, StateFile};
+
+/// How many times the task producing each target has failed and then
+/// succeeded without its inputs changing in between, kept in `.asmbl/flaky`
+/// under the target directory.
+pub struct Flaky {
+    file: StateFile,
+    counts: collections::BTreeMap<String, u64>,
+}
+
+impl Flaky {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "flaky");
+
+        // Each record holds a count and a target path.
+        let counts = records(&file.read()?, 2)
+            .filter_map(|record| Some((record[1].to_string(), record[0].parse().ok()?)))
+            .collect();
+
+        Ok(Self { file, counts })
+    }
+
+    pub fn record(&mut self, task: &Task) {
+        *self.counts.entry(task.key()).or_insert(0) += 1;
+    }
+
+    /// Each flaky target along with its count, most flaky first.
+    pub fn worst(&self) -> Vec<(&str, u64)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(target, count)| (target.as_str(), *count))
+            .collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, count) in self.counts.iter() {
+            content.push_str(&format!("{}\t{}\n", count, target));
+        }
+
+        self.file.write(&content)
+    }
+}
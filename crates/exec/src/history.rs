@@ -0,0 +1,153 @@
+use std::{fs, io, io::Write, path, process, time};
+
+use asmbl_core::Task;
+use asmbl_utils::io::StateFile;
+
+/// Every command run, when, for how long and how it went, appended to
+/// `.asmbl/history` under the target directory as each finishes.
+pub struct History {
+    file: StateFile,
+}
+
+/// A command run on behalf of a task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub started: time::SystemTime,
+    pub took: time::Duration,
+    /// How the command exited, as the platform describes it.
+    pub status: String,
+    pub command: String,
+    /// The targets of the task which ran it.
+    pub targets: Vec<path::PathBuf>,
+}
+
+// Commands are kept to a field of a line, so tabs, newlines and the
+// backslashes escaping them are escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+impl Run {
+    // Each line holds the seconds since the epoch at which the command
+    // started, the milliseconds it took, its status, the command and the
+    // task's targets, separated by tabs.
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let started = time::Duration::from_secs(fields.next()?.parse().ok()?);
+        let took = time::Duration::from_millis(fields.next()?.parse().ok()?);
+        let status = fields.next()?.to_string();
+        let command = unescape(fields.next()?);
+        let targets: Vec<_> = fields.map(path::PathBuf::from).collect();
+        if targets.is_empty() {
+            return None;
+        }
+        Some(Self {
+            started: time::UNIX_EPOCH + started,
+            took,
+            status,
+            command,
+            targets,
+        })
+    }
+
+    fn line(&self) -> String {
+        let started = self
+            .started
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = format!(
+            "{}\t{}\t{}\t{}",
+            started.as_secs(),
+            self.took.as_millis(),
+            self.status,
+            escape(&self.command)
+        );
+        for target in self.targets.iter() {
+            line.push('\t');
+            line.push_str(&target.to_string_lossy());
+        }
+        line.push('\n');
+        line
+    }
+}
+
+impl History {
+    pub fn open(target_dir: &path::Path) -> Self {
+        Self {
+            file: StateFile::new(target_dir, "history"),
+        }
+    }
+
+    /// Notes that the task ran `command`, which has just finished.
+    pub fn record(
+        &self,
+        task: &Task,
+        command: &str,
+        status: process::ExitStatus,
+        took: time::Duration,
+    ) -> io::Result<()> {
+        let run = Run {
+            started: time::SystemTime::now() - took,
+            took,
+            status: status.to_string(),
+            command: command.to_string(),
+            targets: task.targets().map(path::Path::to_path_buf).collect(),
+        };
+
+        fs::create_dir_all(self.file.path().parent().unwrap())?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file.path())?;
+        file.write_all(run.line().as_bytes())
+    }
+
+    /// Every command recorded, oldest first.
+    pub fn runs(&self) -> io::Result<Vec<Run>> {
+        // Anything which can't be made sense of is ignored.
+        Ok(self.file.read()?.lines().filter_map(Run::parse).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_survive_being_written_out() {
+        let run = Run {
+            started: time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000),
+            took: time::Duration::from_millis(1250),
+            status: String::from("exit status: 1"),
+            command: String::from("sh -c 'printf \"a\\tb\\n\"'"),
+            targets: vec![
+                path::PathBuf::from("../out/a"),
+                path::PathBuf::from("../out/b"),
+            ],
+        };
+        let line = run.line();
+        assert_eq!(line.lines().count(), 1);
+        assert_eq!(Run::parse(line.trim_end_matches('\n')), Some(run));
+    }
+}
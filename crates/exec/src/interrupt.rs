@@ -0,0 +1,119 @@
+use std::{
+    process,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+};
+
+// The signal which last asked for the build to stop, and how many have.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+// Whether the build was asked to make way for another.
+static YIELDED: AtomicBool = AtomicBool::new(false);
+
+/// The signal which asked for the build to stop, should one have, e.g. on
+/// Ctrl-C.
+pub fn interrupted() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Whether the build was asked to make way for another, e.g. by the daemon,
+/// in which case nothing more is started but the tasks running are seen
+/// through.
+pub fn yielded() -> bool {
+    YIELDED.load(Ordering::SeqCst)
+}
+
+// How many times the build has been asked to stop.
+pub(crate) fn count() -> usize {
+    COUNT.load(Ordering::SeqCst)
+}
+
+/// Gives the command a process group of its own, so that whatever it goes
+/// on to start is stopped along with it. Tasks started so are no longer in
+/// the terminal's foreground, so it's left to the runner to pass Ctrl-C on.
+pub fn own_group(cmd: &mut process::Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::*;
+
+    // SIGHUP, SIGINT and SIGTERM.
+    const SIGNALS: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+    extern "C" fn note(signal: libc::c_int) {
+        SIGNAL.store(signal, Ordering::SeqCst);
+        COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    extern "C" fn make_way(_: libc::c_int) {
+        YIELDED.store(true, Ordering::SeqCst);
+    }
+
+    /// Has SIGUSR1 ask the build to make way for another from here on,
+    /// rather than end asmbl there and then.
+    pub fn yield_on_signal() {
+        let handler = make_way as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe { libc::signal(libc::SIGUSR1, handler) };
+    }
+
+    /// Takes over the signals asking asmbl to stop until dropped, when
+    /// whatever handled them before does again.
+    pub(crate) struct Catch([libc::sighandler_t; 3]);
+
+    pub(crate) fn catch() -> Catch {
+        let handler = note as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let mut previous = [libc::SIG_DFL; 3];
+        for (signal, previous) in SIGNALS.iter().zip(previous.iter_mut()) {
+            *previous = unsafe { libc::signal(*signal, handler) };
+        }
+        Catch(previous)
+    }
+
+    impl Drop for Catch {
+        fn drop(&mut self) {
+            for (signal, previous) in SIGNALS.iter().zip(self.0.iter()) {
+                unsafe { libc::signal(*signal, *previous) };
+            }
+        }
+    }
+
+    /// Asks the process group led by `pid` to terminate, or, should it
+    /// have been asked already, kills it.
+    pub(crate) fn stop(pid: u32, again: bool) {
+        let signal = match again {
+            false => libc::SIGTERM,
+            true => libc::SIGKILL,
+        };
+        unsafe {
+            // The process may not lead a group of its own after all.
+            if libc::kill(-(pid as libc::pid_t), signal) != 0 {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+}
+
+// Elsewhere, the console passes Ctrl-C on to every process attached to it.
+#[cfg(not(unix))]
+mod imp {
+    pub(crate) struct Catch;
+
+    pub(crate) fn catch() -> Catch {
+        Catch
+    }
+
+    pub(crate) fn stop(_: u32, _: bool) {}
+
+    pub fn yield_on_signal() {}
+}
+
+pub use imp::yield_on_signal;
+pub(crate) use imp::{catch, stop};
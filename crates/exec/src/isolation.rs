@@ -0,0 +1,55 @@
+use std::process;
+
+use asmbl_core::IsolationError;
+
+/// Arranges for the command to run inside a fresh network namespace
+/// containing nothing but an unconfigured loopback device.
+#[cfg(target_os = "linux")]
+pub fn deny_network(cmd: &mut process::Command) -> Result<(), IsolationError> {
+    use std::os::unix::process::CommandExt;
+
+    // An unprivileged process may only create a network namespace from
+    // within a user namespace of its own; map the current user straight
+    // through so that anything the task writes is owned as usual.
+    let (uid_map, gid_map) = unsafe {
+        (
+            format!("{} {} 1", libc::getuid(), libc::getuid()),
+            format!("{} {} 1", libc::getgid(), libc::getgid()),
+        )
+    };
+
+    // Runs between fork and exec, so sticks to async-signal-safe calls.
+    fn write(file: &[u8], content: &[u8]) -> std::io::Result<()> {
+        unsafe {
+            let fd = libc::open(file.as_ptr() as *const libc::c_char, libc::O_WRONLY);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+            libc::close(fd);
+            if written < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            write(b"/proc/self/setgroups\0", b"deny")?;
+            write(b"/proc/self/uid_map\0", uid_map.as_bytes())?;
+            write(b"/proc/self/gid_map\0", gid_map.as_bytes())?;
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn deny_network(_: &mut process::Command) -> Result<(), IsolationError> {
+    Err(IsolationError::NetworkUnsupported)
+}
@@ -0,0 +1,460 @@
+use std::{collections, io, process, sync::mpsc, thread, time};
+
+use asmbl_core::{Task, TaskHandle, TaskList, Upstream};
+
+use crate::interrupt;
+
+/// Told how a run is going, e.g. so as to show its progress.
+pub trait BuildObserver {
+    /// How many of the tasks to be run have anything to run.
+    fn planned(&mut self, _count: usize) {}
+
+    /// A task is about to be started in the given slot, numbered from zero
+    /// up to the number of jobs, which is its own until it finishes.
+    fn started(&mut self, _task: &Task, _slot: usize) {}
+
+    /// A task has finished, whether it succeeded, and how long it took.
+    fn finished(&mut self, _task: &Task, _slot: usize, _succeeded: bool, _took: time::Duration) {}
+
+    /// A task with something to run was passed over after all, having been
+    /// left up to date by the restat tasks it waited on.
+    fn skipped(&mut self, _task: &Task) {}
+
+    /// A task needn't run after all, its targets having been restored in its
+    /// stead, e.g. from the cache.
+    fn restored(&mut self, _task: &Task) {}
+}
+
+/// Runs the given tasks, starting each in turn as soon as everything it
+/// depends upon amongst them has finished, with at most `jobs` running
+/// at once. Fewer still of the tasks in a pool run at once should the
+/// pool's depth say so.
+///
+/// `start` launches a task, returning the child along with whatever
+/// `finish` needs to know about it once it exits, or nothing should it
+/// have restored the task's targets instead; tasks with nothing to run are
+/// passed over as soon as they're ready. `finish` says whether
+/// the task succeeded. Nothing depending upon a failed task is started,
+/// and unless `keep_going`, nothing else is either.
+///
+/// Restat tasks may leave their targets as they were, so a task which
+/// was waiting only on restat tasks, or on tasks passed over for this
+/// reason, is passed over as well unless `out_of_date` says that it
+/// still is.
+///
+/// Should any closure fail, nothing more is started, though tasks
+/// already running are still seen through to the end; the first error
+/// is returned. `observer` is told of everything along the way.
+///
+/// Likewise, should asmbl be asked to stop, e.g. on Ctrl-C, nothing more
+/// is started and the process group of each task running, as set up with
+/// `own_group`, is asked to terminate; those asked twice are killed. The
+/// tasks are then finished as usual, and `interrupted` says why. Should it
+/// be asked to make way for another build instead, nothing more is started
+/// but the tasks running are left to finish, and `yielded` says so.
+#[allow(clippy::too_many_arguments)]
+pub fn run<T, E, O, S, F>(
+    tasks: &TaskList,
+    order: &[TaskHandle],
+    jobs: usize,
+    keep_going: bool,
+    observer: &mut dyn BuildObserver,
+    mut out_of_date: O,
+    mut start: S,
+    mut finish: F,
+) -> Result<(), E>
+where
+    E: From<io::Error>,
+    O: FnMut(TaskHandle) -> Result<bool, E>,
+    S: FnMut(&Task) -> Result<Option<(process::Child, T)>, E>,
+    F: FnMut(&Task, T, process::ExitStatus, time::Duration) -> Result<bool, E>,
+{
+    let selected: collections::HashSet<_> = order.iter().copied().collect();
+
+    // How many of the selected tasks each task is still waiting on.
+    let mut pending: collections::HashMap<_, _> = order
+        .iter()
+        .map(|handle| {
+            let upstream: collections::HashSet<_> = upstream_tasks(tasks, *handle)
+                .filter(|upstream| selected.contains(upstream))
+                .collect();
+            (*handle, upstream.len())
+        })
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut waiting: Vec<_> = order.to_vec();
+    let mut running = collections::HashMap::new();
+    // Tasks whose targets may well be as they were before the build.
+    let mut unchanged = collections::HashSet::new();
+    let mut error = None;
+    let mut failed = false;
+
+    let _catch = interrupt::catch();
+    // How many times asmbl had been asked to stop before, and since, the
+    // running tasks were last told to.
+    let (before, mut told) = (interrupt::count(), interrupt::count());
+
+    let planned = order
+        .iter()
+        .filter(|handle| tasks.get(**handle).has_recipe());
+    observer.planned(planned.count());
+
+    loop {
+        while error.is_none()
+            && (keep_going || !failed)
+            && interrupt::count() == before
+            && !interrupt::yielded()
+            && running.len() < jobs.max(1)
+        {
+            let ready = |handle: &TaskHandle| {
+                pending[handle] == 0 && pool_has_room(tasks, *handle, running.keys())
+            };
+            let next = match waiting.iter().position(ready) {
+                Some(next) => waiting.remove(next),
+                None => break,
+            };
+            if waited_on_unchanged(tasks, next, &selected, &unchanged) {
+                match out_of_date(next) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if tasks.get(next).has_recipe() {
+                            observer.skipped(tasks.get(next));
+                        }
+                        unchanged.insert(next);
+                        release(tasks, next, &mut pending);
+                        continue;
+                    }
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                }
+            }
+            if !tasks.get(next).has_recipe() {
+                release(tasks, next, &mut pending);
+                continue;
+            }
+            let slot = (0..)
+                .find(|slot| running.values().all(|(_, _, running, _)| running != slot))
+                .unwrap();
+            match start(tasks.get(next)) {
+                Ok(None) => {
+                    observer.restored(tasks.get(next));
+                    release(tasks, next, &mut pending);
+                }
+                Ok(Some((mut child, state))) => {
+                    observer.started(tasks.get(next), slot);
+                    let started = time::Instant::now();
+                    running.insert(next, (state, started, slot, child.id()));
+                    let sender = sender.clone();
+                    thread::spawn(move || sender.send((next, child.wait())));
+                }
+                Err(err) => error = Some(err),
+            }
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        // Signals can't interrupt waiting on a channel, so it's done a little
+        // at a time.
+        let (handle, status) = loop {
+            let asked = interrupt::count();
+            if asked > told {
+                for (_, _, _, pid) in running.values() {
+                    interrupt::stop(*pid, told > before);
+                }
+                told = asked;
+            }
+            match receiver.recv_timeout(time::Duration::from_millis(50)) {
+                Ok(finished) => break finished,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                // The sender is kept whilst tasks are running, so this
+                // shouldn't happen.
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::other("Lost track of the tasks running.").into());
+                }
+            }
+        };
+        let (state, started, slot, _) = running.remove(&handle).unwrap();
+        let task = tasks.get(handle);
+        let took = started.elapsed();
+        let result = status
+            .map_err(E::from)
+            .and_then(|status| finish(task, state, status, took));
+        if let Ok(succeeded) = result {
+            observer.finished(task, slot, succeeded, took);
+        }
+        match result {
+            Ok(true) => {
+                if task.is_restat() {
+                    unchanged.insert(handle);
+                }
+                release(tasks, handle, &mut pending)
+            }
+            Ok(false) => failed = true,
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// Whether the task was waiting on anything at all, and only on tasks
+// which may have left their targets as they were.
+fn waited_on_unchanged(
+    tasks: &TaskList,
+    handle: TaskHandle,
+    selected: &collections::HashSet<TaskHandle>,
+    unchanged: &collections::HashSet<TaskHandle>,
+) -> bool {
+    let mut upstream = upstream_tasks(tasks, handle)
+        .filter(|upstream| selected.contains(upstream))
+        .peekable();
+    upstream.peek().is_some() && upstream.all(|upstream| unchanged.contains(&upstream))
+}
+
+// Whether the task's pool, should it be in one, has room for it alongside
+// the tasks running.
+fn pool_has_room<'a, I>(tasks: &TaskList, handle: TaskHandle, running: I) -> bool
+where
+    I: Iterator<Item = &'a TaskHandle>,
+{
+    let task = tasks.get(handle);
+    let pool = match task.pool() {
+        Some(pool) if task.has_recipe() => pool,
+        _ => return true,
+    };
+    let depth = tasks.pools().get(pool).copied().unwrap_or(usize::MAX);
+    running
+        .filter(|running| tasks.get(**running).pool() == Some(pool))
+        .count()
+        < depth
+}
+
+// Notes that a task has finished, on behalf of everything waiting on it.
+fn release(
+    tasks: &TaskList,
+    handle: TaskHandle,
+    pending: &mut collections::HashMap<TaskHandle, usize>,
+) {
+    let downstream: collections::HashSet<_> = tasks.downstream(handle).iter().copied().collect();
+    for downstream in downstream {
+        if let Some(count) = pending.get_mut(&downstream) {
+            *count -= 1;
+        }
+    }
+}
+
+// The tasks amongst those the given task depends upon.
+fn upstream_tasks(tasks: &TaskList, handle: TaskHandle) -> impl Iterator<Item = TaskHandle> + '_ {
+    tasks
+        .upstream(handle)
+        .filter_map(|(upstream, _)| match upstream {
+            Upstream::Task(upstream) => Some(upstream),
+            Upstream::File(_) => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use asmbl_core::PrerequisiteSpec;
+
+    use super::*;
+    use crate::testing::{declare, spec};
+
+    #[derive(Default)]
+    struct Slots {
+        running: Vec<usize>,
+        most: usize,
+    }
+
+    impl BuildObserver for Slots {
+        fn started(&mut self, _task: &Task, slot: usize) {
+            assert!(!self.running.contains(&slot));
+            self.running.push(slot);
+            self.most = self.most.max(slot + 1);
+        }
+
+        fn finished(&mut self, _task: &Task, slot: usize, _: bool, _: time::Duration) {
+            self.running.retain(|running| *running != slot);
+        }
+    }
+
+    // Runs four independent tasks, each in the given pool, returning the
+    // most slots taken at once.
+    fn most_at_once(jobs: usize, pool: Option<usize>) -> usize {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, path::Path::new("../out"), |builder| {
+            if let Some(depth) = pool {
+                builder.add_pool(String::from("link"), depth).unwrap();
+            }
+            for target in &["a", "b", "c", "d"] {
+                let mut task = spec(Some("true"));
+                task.options.pool = pool.map(|_| String::from("link"));
+                builder.add_task(vec![target.to_string()], task).unwrap();
+            }
+        });
+        let order: Vec<_> = tasks.handles().collect();
+
+        let mut slots = Slots::default();
+        run(
+            &tasks,
+            &order,
+            jobs,
+            false,
+            &mut slots,
+            |_| Ok::<_, io::Error>(true),
+            |_| {
+                Ok(Some((
+                    process::Command::new("sleep").arg("0.1").spawn()?,
+                    (),
+                )))
+            },
+            |_, (), status, _| Ok(status.success()),
+        )
+        .unwrap();
+        slots.most
+    }
+
+    #[test]
+    fn tasks_run_in_slots_no_more_numerous_than_jobs() {
+        assert_eq!(most_at_once(2, None), 2);
+    }
+
+    #[test]
+    fn pools_hold_back_their_tasks_whatever_the_jobs() {
+        assert_eq!(most_at_once(4, Some(1)), 1);
+        assert_eq!(most_at_once(4, Some(3)), 3);
+    }
+
+    // What a run was seen to do, e.g. "started a" and "finished a".
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Events {
+        fn name(task: &Task) -> String {
+            let target = task.targets().next().unwrap();
+            target.file_name().unwrap().to_string_lossy().into_owned()
+        }
+
+        fn position(&self, event: &str) -> usize {
+            self.0
+                .iter()
+                .position(|seen| seen == event)
+                .unwrap_or_else(|| panic!("'{}' not in {:?}", event, self.0))
+        }
+
+        fn contains(&self, event: &str) -> bool {
+            self.0.iter().any(|seen| seen == event)
+        }
+    }
+
+    impl BuildObserver for Events {
+        fn started(&mut self, task: &Task, _: usize) {
+            self.0.push(format!("started {}", Self::name(task)));
+        }
+
+        fn finished(&mut self, task: &Task, _: usize, succeeded: bool, _: time::Duration) {
+            let finished = match succeeded {
+                true => "finished",
+                false => "failed",
+            };
+            self.0.push(format!("{} {}", finished, Self::name(task)));
+        }
+    }
+
+    // Tasks named after what they do: those named "slow..." take a while,
+    // those named "fail..." fail, and those named "broken..." can't be
+    // started; each depends upon those it's paired with in `depends_on`.
+    fn declare_tasks(names: &[&str], depends_on: &[(&str, &str)]) -> TaskList {
+        let context = path::Path::new("/project");
+        declare(context, context, |builder| {
+            let mut handles: collections::HashMap<_, Vec<_>> = collections::HashMap::new();
+            for name in names {
+                let mut task = spec(Some("true"));
+                task.depends_on = depends_on
+                    .iter()
+                    .filter(|(downstream, _)| downstream == name)
+                    .flat_map(|(_, upstream)| handles[upstream].clone())
+                    .map(PrerequisiteSpec::Handle)
+                    .collect();
+                let task = builder.add_task(vec![name.to_string()], task).unwrap();
+                handles.insert(*name, task.collect());
+            }
+        })
+    }
+
+    fn run_tasks(tasks: &TaskList, jobs: usize, keep_going: bool) -> (io::Result<()>, Events) {
+        let order: Vec<_> = tasks.handles().collect();
+        let mut events = Events::default();
+        let result = run(
+            tasks,
+            &order,
+            jobs,
+            keep_going,
+            &mut events,
+            |_| Ok(true),
+            |task| {
+                let name = Events::name(task);
+                let mut cmd = match name.as_str() {
+                    name if name.starts_with("slow") => process::Command::new("sleep"),
+                    name if name.starts_with("fail") => process::Command::new("false"),
+                    name if name.starts_with("broken") => {
+                        return Err(io::Error::other(name));
+                    }
+                    _ => process::Command::new("true"),
+                };
+                cmd.arg("0.2");
+                Ok(Some((cmd.spawn()?, ())))
+            },
+            |_, (), status, _| Ok(status.success()),
+        );
+        (result, events)
+    }
+
+    #[test]
+    fn tasks_start_only_once_those_they_depend_upon_finish() {
+        let tasks = declare_tasks(
+            &["slow", "a", "b", "c"],
+            &[("a", "slow"), ("b", "a"), ("c", "slow")],
+        );
+        let (result, events) = run_tasks(&tasks, 4, false);
+        result.unwrap();
+        assert_eq!(events.0.len(), 8);
+        assert!(events.position("finished slow") < events.position("started a"));
+        assert!(events.position("finished slow") < events.position("started c"));
+        assert!(events.position("finished a") < events.position("started b"));
+    }
+
+    #[test]
+    fn failures_stop_everything_else_unless_keeping_going() {
+        let tasks = declare_tasks(&["fail", "after", "other"], &[("after", "fail")]);
+        let (result, events) = run_tasks(&tasks, 1, false);
+        result.unwrap();
+        assert_eq!(events.0, vec!["started fail", "failed fail"]);
+
+        // Only what depends upon the failed task is held back.
+        let (result, events) = run_tasks(&tasks, 1, true);
+        result.unwrap();
+        assert!(events.contains("finished other"));
+        assert!(!events.contains("started after"));
+    }
+
+    #[test]
+    fn errors_are_returned_once_tasks_already_running_finish() {
+        let tasks = declare_tasks(&["slow", "broken", "later"], &[]);
+        let (result, events) = run_tasks(&tasks, 2, true);
+        assert_eq!(result.unwrap_err().to_string(), "broken");
+        assert_eq!(events.0, vec!["started slow", "finished slow"]);
+    }
+}
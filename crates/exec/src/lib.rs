@@ -0,0 +1,32 @@
+//! Runs the tasks `asmbl-core` resolves, and keeps what's learnt about them
+//! from one build to the next.
+
+mod cache;
+mod content;
+mod flaky;
+mod history;
+mod interrupt;
+mod isolation;
+mod jobs;
+mod output;
+mod produced;
+mod quarantine;
+mod restat;
+mod status;
+#[cfg(test)]
+mod testing;
+mod watch;
+
+pub use cache::Cache;
+pub use content::ContentHashes;
+pub use flaky::Flaky;
+pub use history::{History, Run};
+pub use interrupt::{interrupted, own_group, yield_on_signal, yielded};
+pub use isolation::deny_network;
+pub use jobs::{run, BuildObserver};
+pub use output::Capture;
+pub use produced::Produced;
+pub use quarantine::Quarantine;
+pub use restat::Restat;
+pub use status::{Outcome, Snapshot};
+pub use watch::{Baseline, WatchBackend, WatchBackendError};
@@ -0,0 +1,131 @@
+use std::{
+    io::{self, Read, Write},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+// Only so much of what each task writes to stderr is kept for reports.
+const STDERR_TAIL: usize = 16 * 1024;
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn write_all(self, chunk: &[u8]) {
+        let _ = match self {
+            Self::Stdout => io::stdout().write_all(chunk),
+            Self::Stderr => io::stderr().write_all(chunk),
+        };
+    }
+}
+
+// Output held back until the task finishes, in the order it came.
+type Held = Arc<Mutex<Vec<(Stream, Vec<u8>)>>>;
+
+/// What a task writes, gathered as it runs so that the output of tasks
+/// running side by side doesn't interleave.
+pub struct Capture {
+    readers: Vec<thread::JoinHandle<Vec<u8>>>,
+    held: Option<Held>,
+}
+
+impl Capture {
+    /// Takes over whichever of the child's stdout and stderr were piped,
+    /// passing what's written straight on if `live` and otherwise holding
+    /// it back until `finish`.
+    pub fn new(child: &mut process::Child, live: bool) -> Self {
+        let held = match live {
+            true => None,
+            false => Some(Arc::new(Mutex::new(vec![]))),
+        };
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| (Stream::Stdout, Box::new(out) as _));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| (Stream::Stderr, Box::new(err) as _));
+        let readers = stdout
+            .into_iter()
+            .chain(stderr)
+            .map(|(stream, reader): (_, Box<dyn Read + Send>)| {
+                let held = held.clone();
+                thread::spawn(move || read(reader, stream, held))
+            })
+            .collect();
+        Self { readers, held }
+    }
+
+    /// Waits for the task's output to end, writes out whatever was held
+    /// back in one go, and returns the tail of what it wrote to stderr.
+    pub fn finish(self) -> Vec<u8> {
+        let mut tail = vec![];
+        for reader in self.readers {
+            tail.extend(reader.join().unwrap_or_default());
+        }
+
+        let held = match self.held {
+            Some(held) => held,
+            None => return tail,
+        };
+        let held = std::mem::take(&mut *held.lock().unwrap());
+        let stdout = io::stdout();
+        let stderr = io::stderr();
+        let (mut stdout, mut stderr) = (stdout.lock(), stderr.lock());
+        for (stream, chunk) in held {
+            let _ = match stream {
+                Stream::Stdout => stdout.write_all(&chunk),
+                Stream::Stderr => stderr.write_all(&chunk),
+            };
+        }
+        let _ = stdout.flush();
+        tail
+    }
+}
+
+// Passes on or holds back whatever comes from one stream, returning the
+// tail of it should it be stderr.
+fn read(mut reader: Box<dyn Read + Send>, stream: Stream, held: Option<Held>) -> Vec<u8> {
+    let mut tail = vec![];
+    let mut buffer = [0; 4096];
+    while let Ok(read) = reader.read(&mut buffer) {
+        if read == 0 {
+            break;
+        }
+        let chunk = &buffer[..read];
+        match &held {
+            Some(held) => held.lock().unwrap().push((stream, chunk.to_vec())),
+            None => stream.write_all(chunk),
+        }
+        if let Stream::Stderr = stream {
+            tail.extend_from_slice(chunk);
+            if tail.len() > STDERR_TAIL {
+                tail.drain(..tail.len() - STDERR_TAIL);
+            }
+        }
+    }
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_tail_of_stderr() {
+        let mut child = process::Command::new("sh")
+            .args(["-c", "echo held; echo failed >&2"])
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let output = Capture::new(&mut child, false);
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output.finish(), b"failed\n");
+    }
+}
@@ -0,0 +1,71 @@
+use std::{collections, io, path};
+
+use asmbl_core::{Stat, Task, TaskHandle, TaskList};
+use asmbl_utils::io::{records, StateFile};
+
+/// The files found matching what each task produces, besides its targets,
+/// when it last ran, kept in `.asmbl/produced` under the target directory.
+pub struct Produced {
+    file: StateFile,
+    products: collections::BTreeMap<String, Vec<path::PathBuf>>,
+}
+
+impl Produced {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "produced");
+
+        // Each record holds a target path and a file it was produced along
+        // with.
+        let mut products = collections::BTreeMap::new();
+        for record in records(&file.read()?, 2) {
+            products
+                .entry(record[0].to_string())
+                .or_insert_with(Vec::new)
+                .push(path::PathBuf::from(record[1]));
+        }
+
+        Ok(Self { file, products })
+    }
+
+    /// Notes whatever the task has just produced.
+    pub fn record(&mut self, task: &Task) {
+        let products = task.products();
+        if products.is_empty() {
+            self.products.remove(&task.key());
+        } else {
+            self.products.insert(task.key(), products);
+        }
+    }
+
+    /// The files the task was last found to have produced.
+    pub fn get(&self, task: &Task) -> &[path::PathBuf] {
+        self.products.get(&task.key()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Forgets what the task produced, once it's gone.
+    pub fn forget(&mut self, task: &Task) {
+        self.products.remove(&task.key());
+    }
+
+    /// The tasks which have lost any of the files they produced.
+    pub fn missing(&self, tasks: &TaskList, stat: &dyn Stat) -> collections::HashSet<TaskHandle> {
+        tasks
+            .handles()
+            .filter(|handle| {
+                let products = self.get(tasks.get(*handle));
+                products.iter().any(|file| stat.modified(file).is_err())
+            })
+            .collect()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, files) in self.products.iter() {
+            for file in files {
+                content.push_str(&format!("{}\t{}\n", target, file.display()));
+            }
+        }
+
+        self.file.write(&content)
+    }
+}
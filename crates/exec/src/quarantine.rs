@@ -0,0 +1,58 @@
+use std::{collections, io, path};
+
+use asmbl_core::{Task, TaskHandle, TaskList};
+use asmbl_utils::io::StateFile;
+
+/// The tasks which failed during previous runs, identified by their first
+/// target and kept in `.asmbl/failed` under the target directory along
+/// with the state of their inputs at the time.
+pub struct Quarantine {
+    file: StateFile,
+    targets: collections::BTreeMap<String, String>,
+}
+
+impl Quarantine {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "failed");
+
+        // Each line holds an input state and a target path separated by a
+        // tab, though a bare target path is accepted too.
+        let targets = file
+            .read()?
+            .lines()
+            .map(|line| match line.split_once('\t') {
+                Some((state, target)) => (target.to_string(), state.to_string()),
+                None => (line.to_string(), String::new()),
+            })
+            .collect();
+
+        Ok(Self { file, targets })
+    }
+
+    pub fn insert(&mut self, task: &Task, input_state: String) {
+        self.targets.insert(task.key(), input_state);
+    }
+
+    /// Releases the task, returning the state its inputs were in when it
+    /// failed, if it was quarantined.
+    pub fn remove(&mut self, task: &Task) -> Option<String> {
+        self.targets.remove(&task.key())
+    }
+
+    /// The quarantined tasks which still exist in the given task list.
+    pub fn handles(&self, tasks: &TaskList) -> collections::HashSet<TaskHandle> {
+        self.targets
+            .keys()
+            .filter_map(|target| tasks.find(path::Path::new(target)))
+            .collect()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, input_state) in self.targets.iter() {
+            content.push_str(&format!("{}\t{}\n", input_state, target));
+        }
+
+        self.file.write(&content)
+    }
+}
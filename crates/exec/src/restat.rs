@@ -0,0 +1,134 @@
+use std::{collections, io, path};
+
+use asmbl_core::{Stat, Task, TaskHandle, TaskList};
+use asmbl_utils::io::{records, StateFile};
+
+/// The state of the inputs restat tasks last succeeded against, kept in
+/// `.asmbl/restat` under the target directory, so that a task which left its
+/// targets older than its inputs isn't run again until they change.
+pub struct Restat {
+    file: StateFile,
+    input_states: collections::BTreeMap<String, String>,
+}
+
+impl Restat {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "restat");
+
+        // Each record holds an input state and a target path.
+        let input_states = records(&file.read()?, 2)
+            .map(|record| (record[1].to_string(), record[0].to_string()))
+            .collect();
+
+        Ok(Self { file, input_states })
+    }
+
+    /// Notes the state of the inputs the task has just succeeded against.
+    pub fn record(&mut self, task: &Task, input_state: String) {
+        if task.is_restat() {
+            self.input_states.insert(task.key(), input_state);
+        } else {
+            self.input_states.remove(&task.key());
+        }
+    }
+
+    /// The restat tasks whose inputs are as they were when they last
+    /// succeeded.
+    pub fn settled(&self, tasks: &TaskList, stat: &dyn Stat) -> collections::HashSet<TaskHandle> {
+        tasks
+            .handles()
+            .filter(|handle| {
+                let task = tasks.get(*handle);
+                task.is_restat()
+                    && self.input_states.get(&task.key()) == Some(&task.input_state(stat))
+            })
+            .collect()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (target, input_state) in self.input_states.iter() {
+            content.push_str(&format!("{}\t{}\n", input_state, target));
+        }
+
+        self.file.write(&content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{declare, spec, touch, Within};
+    use asmbl_core::{FileSystem, PrerequisiteSpec};
+    use asmbl_utils::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn restat_tasks_settle_until_their_inputs_change() {
+        let dir = TempDir::new("restat");
+        touch(&dir.join("a.c"), 1);
+        let tasks = declare(&dir, &dir, |builder| {
+            for (target, restat) in &[("a.h", true), ("b.h", false)] {
+                let mut spec = spec(Some("true"));
+                spec.consumes = vec![PrerequisiteSpec::Named(path::PathBuf::from("a.c"), false)];
+                spec.options.restat = *restat;
+                builder.add_task(vec![target.to_string()], spec).unwrap();
+            }
+        });
+        let a = tasks.find(&dir.join("a.h")).unwrap();
+        let b = tasks.find(&dir.join("b.h")).unwrap();
+        let settled = |restat: &Restat| {
+            let settled = restat.settled(&tasks, &Within(&dir));
+            settled.into_iter().collect::<Vec<_>>()
+        };
+
+        let mut restat = Restat::load(&dir).unwrap();
+        assert_eq!(settled(&restat), vec![]);
+        for handle in &[a, b] {
+            let task = tasks.get(*handle);
+            restat.record(task, task.input_state(&Within(&dir)));
+        }
+        restat.save().unwrap();
+        let restat = Restat::load(&dir).unwrap();
+        assert_eq!(settled(&restat), vec![a]);
+
+        touch(&dir.join("a.c"), 2);
+        assert_eq!(settled(&restat), vec![]);
+    }
+
+    #[test]
+    fn tasks_no_longer_restat_are_forgotten() {
+        let dir = TempDir::new("restat-forgotten");
+        let declare_a = |restat| {
+            declare(&dir, &dir, |builder| {
+                let mut spec = spec(Some("true"));
+                spec.options.restat = restat;
+                builder.add_task(vec![String::from("a.h")], spec).unwrap();
+            })
+        };
+
+        let tasks = declare_a(true);
+        let a = tasks.get(tasks.handles().next().unwrap());
+        let mut restat = Restat::load(&dir).unwrap();
+        restat.record(a, a.input_state(&FileSystem));
+        assert_eq!(restat.input_states.len(), 1);
+
+        let tasks = declare_a(false);
+        let a = tasks.get(tasks.handles().next().unwrap());
+        restat.record(a, a.input_state(&FileSystem));
+        assert!(restat.input_states.is_empty());
+    }
+
+    #[test]
+    fn lines_which_cant_be_read_are_passed_over() {
+        let dir = TempDir::new("restat-garbled");
+        fs::create_dir_all(dir.join(".asmbl")).unwrap();
+        fs::write(dir.join(".asmbl/restat"), "garbled\n0123\ta.h\n").unwrap();
+        let restat = Restat::load(&dir).unwrap();
+        let input_states: Vec<_> = restat.input_states.into_iter().collect();
+        assert_eq!(
+            input_states,
+            vec![(String::from("a.h"), String::from("0123"))]
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::{collections, io, path, time};
+
+use asmbl_core::{Edge, Stat, TaskList, Upstream};
+use asmbl_utils::io::StateFile;
+
+/// The task graph as the last build found it, kept in `.asmbl/graph` under
+/// the target directory, so that how much is out of date can be told without
+/// reading any units, e.g. for a shell prompt.
+pub struct Snapshot {
+    file: StateFile,
+    tasks: Vec<Node>,
+}
+
+// A task, with the files it produces and reads and the tasks before it,
+// which come before it in the snapshot. Phony tasks and groups have no
+// targets of their own, and are only out of date through those before them.
+#[derive(Debug, PartialEq)]
+struct Node {
+    targets: Vec<path::PathBuf>,
+    upstream: Vec<usize>,
+    inputs: Vec<path::PathBuf>,
+}
+
+impl Node {
+    // Each line holds how many targets the task has, its targets, the tasks
+    // before it separated by commas, and the files it reads, separated by
+    // tabs.
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let count: usize = fields.next()?.parse().ok()?;
+        let targets: Vec<_> = fields
+            .by_ref()
+            .take(count)
+            .map(path::PathBuf::from)
+            .collect();
+        let upstream = match fields.next()? {
+            "" => vec![],
+            upstream => upstream
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?,
+        };
+        let inputs = fields.map(path::PathBuf::from).collect();
+        match targets.len() == count {
+            true => Some(Self {
+                targets,
+                upstream,
+                inputs,
+            }),
+            false => None,
+        }
+    }
+
+    fn line(&self) -> String {
+        let mut line = self.targets.len().to_string();
+        for target in self.targets.iter() {
+            line.push('\t');
+            line.push_str(&target.to_string_lossy());
+        }
+        let upstream: Vec<_> = self.upstream.iter().map(usize::to_string).collect();
+        line.push('\t');
+        line.push_str(&upstream.join(","));
+        for input in self.inputs.iter() {
+            line.push('\t');
+            line.push_str(&input.to_string_lossy());
+        }
+        line.push('\n');
+        line
+    }
+}
+
+impl Snapshot {
+    pub fn load(target_dir: &path::Path) -> io::Result<Self> {
+        let file = StateFile::new(target_dir, "graph");
+
+        // A snapshot which can't be made sense of whole is no snapshot at all.
+        let tasks: Option<Vec<_>> = file.read()?.lines().map(Node::parse).collect();
+        let tasks = tasks
+            .filter(|tasks| {
+                let mut upstream = tasks.iter().enumerate().flat_map(|(index, task)| {
+                    task.upstream.iter().map(move |upstream| (index, *upstream))
+                });
+                upstream.all(|(index, upstream)| upstream < index)
+            })
+            .unwrap_or_default();
+
+        Ok(Self { file, tasks })
+    }
+
+    /// Takes the graph in place of whatever the last build found.
+    pub fn record(&mut self, tasks: &TaskList) {
+        let index: collections::HashMap<_, _> = tasks
+            .handles()
+            .enumerate()
+            .map(|(index, handle)| (handle, index))
+            .collect();
+        self.tasks = tasks
+            .handles()
+            .map(|handle| {
+                let task = tasks.get(handle);
+                let targets = match task.is_phony() || task.is_group() {
+                    true => vec![],
+                    false => task.targets().map(path::Path::to_path_buf).collect(),
+                };
+                let mut upstream = vec![];
+                let mut inputs = vec![];
+                for (prerequisite, edge) in tasks.upstream(handle) {
+                    match (prerequisite, edge) {
+                        (_, Edge::NotBefore) => {}
+                        (Upstream::Task(handle), _) => upstream.push(index[&handle]),
+                        (Upstream::File(file), _) => inputs.push(file.to_path_buf()),
+                    }
+                }
+                Node {
+                    targets,
+                    upstream,
+                    inputs,
+                }
+            })
+            .collect();
+    }
+
+    /// How many tasks there are.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// How many tasks look to be out of date, going by when their files last
+    /// changed alone, the recorded commands and the like going unread.
+    pub fn out_of_date(&self, stat: &dyn Stat) -> usize {
+        let mut stale = vec![false; self.tasks.len()];
+        for (index, task) in self.tasks.iter().enumerate() {
+            stale[index] =
+                task.upstream.iter().any(|upstream| stale[*upstream]) || self.is_stale(task, stat);
+        }
+        stale.into_iter().filter(|stale| *stale).count()
+    }
+
+    // Whether the task's targets are missing, or older than what it reads.
+    fn is_stale(&self, task: &Node, stat: &dyn Stat) -> bool {
+        if task.targets.is_empty() {
+            return false;
+        }
+        let oldest = task
+            .targets
+            .iter()
+            .map(|target| stat.modified(target).ok())
+            .min()
+            .flatten();
+        let oldest = match oldest {
+            Some(oldest) => oldest,
+            None => return true,
+        };
+        let upstream = task
+            .upstream
+            .iter()
+            .flat_map(|upstream| self.tasks[*upstream].targets.iter());
+        // Inputs which are missing are for the build to complain about.
+        task.inputs
+            .iter()
+            .chain(upstream)
+            .filter_map(|input| stat.modified(input).ok())
+            .any(|modified| modified > oldest)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let content: String = self.tasks.iter().map(Node::line).collect();
+        self.file.write(&content)
+    }
+}
+
+/// How the last build went, kept in `.asmbl/outcome` under the target
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub finished: time::SystemTime,
+    pub took: time::Duration,
+    pub succeeded: bool,
+}
+
+impl Outcome {
+    fn file(target_dir: &path::Path) -> StateFile {
+        StateFile::new(target_dir, "outcome")
+    }
+
+    /// How the last build went, should there have been one.
+    pub fn load(target_dir: &path::Path) -> io::Result<Option<Self>> {
+        let content = Self::file(target_dir).read()?;
+
+        // The file holds the seconds since the epoch at which the build
+        // finished, the milliseconds it took and whether it succeeded.
+        let mut fields = content.trim_end().split('\t');
+        let mut field = || fields.next().and_then(|field| field.parse().ok());
+        Ok(match (field(), field(), field()) {
+            (Some(finished), Some(took), Some(succeeded)) => Some(Self {
+                finished: time::UNIX_EPOCH + time::Duration::from_secs(finished),
+                took: time::Duration::from_millis(took),
+                succeeded: succeeded == 1,
+            }),
+            _ => None,
+        })
+    }
+
+    pub fn save(&self, target_dir: &path::Path) -> io::Result<()> {
+        let finished = self
+            .finished
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let content = format!(
+            "{}\t{}\t{}\n",
+            finished.as_secs(),
+            self.took.as_millis(),
+            self.succeeded as u8
+        );
+        Self::file(target_dir).write(&content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{declare, spec, touch};
+    use asmbl_core::PrerequisiteSpec;
+    use asmbl_utils::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn snapshots_tell_what_is_out_of_date_once_saved() {
+        let dir = TempDir::new("status");
+        let file = |name: &str| dir.join(name);
+
+        let mut snapshot = Snapshot::load(&dir).unwrap();
+        assert!(snapshot.is_empty());
+        snapshot.tasks = vec![
+            Node {
+                targets: vec![file("a.o")],
+                upstream: vec![],
+                inputs: vec![file("a.c"), file("a.h")],
+            },
+            Node {
+                targets: vec![file("a"), file("a.map")],
+                upstream: vec![0],
+                inputs: vec![],
+            },
+            Node {
+                targets: vec![],
+                upstream: vec![1],
+                inputs: vec![],
+            },
+        ];
+        snapshot.save().unwrap();
+        let snapshot = Snapshot::load(&dir).unwrap();
+        assert_eq!(snapshot.len(), 3);
+
+        touch(&file("a.c"), 1);
+        touch(&file("a.o"), 2);
+        touch(&file("a"), 3);
+        touch(&file("a.map"), 3);
+        assert_eq!(snapshot.out_of_date(&asmbl_core::FileSystem), 0);
+        touch(&file("a.o"), 4);
+        assert_eq!(snapshot.out_of_date(&asmbl_core::FileSystem), 2);
+        touch(&file("a.c"), 5);
+        assert_eq!(snapshot.out_of_date(&asmbl_core::FileSystem), 3);
+        // Missing targets are out of date, missing inputs for the build to
+        // complain about.
+        fs::remove_file(file("a.c")).unwrap();
+        touch(&file("a.o"), 6);
+        fs::remove_file(file("a.map")).unwrap();
+        assert_eq!(snapshot.out_of_date(&asmbl_core::FileSystem), 2);
+
+        let outcome = Outcome {
+            finished: time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000),
+            took: time::Duration::from_millis(1250),
+            succeeded: true,
+        };
+        outcome.save(&dir).unwrap();
+        assert_eq!(Outcome::load(&dir).unwrap(), Some(outcome));
+    }
+
+    #[test]
+    fn snapshots_record_only_what_bears_upon_tasks_being_out_of_date() {
+        let context = path::Path::new("/project");
+        let tasks = declare(context, context, |builder| {
+            let mut header = spec(Some("true"));
+            header.consumes = vec![PrerequisiteSpec::Named(path::PathBuf::from("a.in"), false)];
+            let header = builder.add_task(vec![String::from("a.h")], header).unwrap();
+            let first = builder
+                .add_task(vec![String::from("first")], spec(Some("true")))
+                .unwrap();
+            let mut object = spec(Some("true"));
+            object.consumes = header.map(PrerequisiteSpec::Handle).collect();
+            object.not_before = first.map(PrerequisiteSpec::Handle).collect();
+            let object = builder.add_task(vec![String::from("a.o")], object).unwrap();
+            let mut all = spec(None);
+            all.options.phony = true;
+            all.depends_on = object.map(PrerequisiteSpec::Handle).collect();
+            builder.add_task(vec![String::from("all")], all).unwrap();
+        });
+
+        let mut snapshot = Snapshot::load(path::Path::new("/nonexistent")).unwrap();
+        snapshot.record(&tasks);
+        let file = |name: &str| context.join(name);
+        assert_eq!(
+            snapshot.tasks,
+            vec![
+                Node {
+                    targets: vec![file("a.h")],
+                    upstream: vec![],
+                    inputs: vec![path::PathBuf::from("a.in")],
+                },
+                Node {
+                    targets: vec![file("first")],
+                    upstream: vec![],
+                    inputs: vec![],
+                },
+                Node {
+                    targets: vec![file("a.o")],
+                    upstream: vec![0],
+                    inputs: vec![],
+                },
+                Node {
+                    targets: vec![],
+                    upstream: vec![2],
+                    inputs: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn records_which_dont_hold_together_are_passed_over() {
+        let dir = TempDir::new("status-garbled");
+        fs::create_dir_all(dir.join(".asmbl")).unwrap();
+        let graph = |content: &str| {
+            fs::write(dir.join(".asmbl/graph"), content).unwrap();
+            Snapshot::load(&dir).unwrap().len()
+        };
+        assert_eq!(graph("1\ta.o\t\ta.c\n0\t0\n"), 2);
+        // Tasks may only come after those before them.
+        assert_eq!(graph("1\ta.o\t1\ta.c\n0\t0\n"), 0);
+        assert_eq!(graph("2\ta.o\n"), 0);
+        assert_eq!(graph("x\ta.o\t\n"), 0);
+        assert_eq!(graph("1\ta.o\tx\n"), 0);
+
+        assert_eq!(Outcome::load(&dir).unwrap(), None);
+        fs::write(dir.join(".asmbl/outcome"), "1700000000\tlong\t1\n").unwrap();
+        assert_eq!(Outcome::load(&dir).unwrap(), None);
+    }
+}
@@ -0,0 +1,54 @@
+//! What the tests of one module and another have in common.
+
+use std::{fs, io, path, time};
+
+use asmbl_core::{Recipe, Stat, TaskList, TaskOptions, TaskSpec, UnitBuilder};
+
+/// A task running `recipe`, should it have one, and consuming nothing.
+pub(crate) fn spec(recipe: Option<&str>) -> TaskSpec<path::PathBuf> {
+    TaskSpec {
+        consumes: vec![],
+        depends_on: vec![],
+        not_before: vec![],
+        env: vec![],
+        recipe: recipe.map(|recipe| Recipe::parse(recipe).unwrap()),
+        options: TaskOptions::default(),
+        origin: None,
+    }
+}
+
+/// The tasks `declare` adds to a unit in `context`, their targets beneath
+/// `target_dir`.
+pub(crate) fn declare<F>(context: &path::Path, target_dir: &path::Path, declare: F) -> TaskList
+where
+    F: FnOnce(&mut UnitBuilder),
+{
+    let components: Vec<_> = context.components().collect();
+    let mut builder = UnitBuilder::new(&components, context.to_path_buf());
+    declare(&mut builder);
+    let units = vec![(context.to_path_buf(), builder.unit())];
+    TaskList::new(context, target_dir, units).unwrap()
+}
+
+/// Writes `file`, should it be missing, and has it last changed `secs`
+/// seconds into the epoch.
+pub(crate) fn touch(file: &path::Path, secs: u64) {
+    fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(file)
+        .unwrap()
+        .set_modified(time::UNIX_EPOCH + time::Duration::from_secs(secs))
+        .unwrap();
+}
+
+/// Asks the file-system, taking relative paths to lie within the given
+/// directory, as they would were asmbl run from it.
+pub(crate) struct Within<'a>(pub &'a path::Path);
+
+impl Stat for Within<'_> {
+    fn modified(&self, path: &path::Path) -> io::Result<time::SystemTime> {
+        fs::metadata(self.0.join(path))?.modified()
+    }
+}
@@ -0,0 +1,317 @@
+use std::{collections, fs, io, path, thread, time};
+
+/// How watch mode finds out that files have changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchBackend {
+    /// Have the operating system report changes as they happen; only inotify,
+    /// on Linux, is supported so far.
+    Native,
+    /// Look at every file every so often, for file-systems whose changes the
+    /// operating system doesn't hear about, e.g. network shares.
+    Poll,
+}
+
+#[derive(Debug, failure::Fail)]
+pub enum WatchBackendError {
+    #[fail(display = "Unknown watch backend '{}'.", _0)]
+    Unknown(String),
+    #[fail(
+        display = "The '{}' watch backend isn't supported on this platform; use 'poll'.",
+        _0
+    )]
+    Unsupported(String),
+}
+
+impl std::str::FromStr for WatchBackend {
+    type Err = WatchBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" if cfg!(target_os = "linux") => Ok(Self::Native),
+            "native" => Err(WatchBackendError::Unsupported(s.to_string())),
+            "poll" => Ok(Self::Poll),
+            _ => Err(WatchBackendError::Unknown(s.to_string())),
+        }
+    }
+}
+
+impl Default for WatchBackend {
+    fn default() -> Self {
+        match cfg!(target_os = "linux") {
+            true => Self::Native,
+            false => Self::Poll,
+        }
+    }
+}
+
+impl WatchBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Poll => "poll",
+        }
+    }
+
+    /// Blocks until any of the given files differs from `baseline`, polling
+    /// at the given interval if need be.
+    pub fn wait(
+        &self,
+        files: &[path::PathBuf],
+        baseline: &Baseline,
+        poll_interval: time::Duration,
+    ) -> io::Result<()> {
+        if files.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "There are no files to watch.",
+            ));
+        }
+        match self {
+            Self::Native => native::wait(files, baseline, poll_interval),
+            Self::Poll => poll(files, baseline, poll_interval),
+        }
+    }
+}
+
+// What's known of a file; nothing, should it be missing.
+type FileState = Option<(Option<time::SystemTime>, u64)>;
+
+fn look(file: &path::Path) -> FileState {
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+/// The files being watched as they were before a build, so that those
+/// changed whilst it ran are built again as soon as it's done.
+pub struct Baseline {
+    taken: time::SystemTime,
+    states: collections::HashMap<path::PathBuf, FileState>,
+}
+
+impl Baseline {
+    pub fn take(files: &[path::PathBuf]) -> Self {
+        // File-systems keep time more coarsely than the clock, so that a file
+        // written just after now may seem to have been written just before.
+        let coarseness = time::Duration::from_secs(1);
+        Self {
+            taken: time::SystemTime::now() - coarseness,
+            states: files
+                .iter()
+                .map(|file| (file.clone(), look(file)))
+                .collect(),
+        }
+    }
+
+    /// Whether any of `files` has changed since the baseline was taken; those
+    /// it didn't cover, found by the build, have if they're newer than it.
+    pub fn changed(&self, files: &[path::PathBuf]) -> bool {
+        files.iter().any(|file| match self.states.get(file) {
+            Some(state) => look(file) != *state,
+            None => match look(file) {
+                Some((Some(modified), _)) => modified >= self.taken,
+                _ => false,
+            },
+        })
+    }
+}
+
+fn poll(files: &[path::PathBuf], baseline: &Baseline, interval: time::Duration) -> io::Result<()> {
+    while !baseline.changed(files) {
+        thread::sleep(interval);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod native {
+    use std::{
+        collections, ffi, fs, io,
+        io::Read,
+        mem,
+        os::unix::{ffi::OsStrExt, io::FromRawFd},
+        path, ptr, time,
+    };
+
+    use super::Baseline;
+
+    pub fn wait(
+        files: &[path::PathBuf],
+        baseline: &Baseline,
+        poll_interval: time::Duration,
+    ) -> io::Result<()> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut inotify = unsafe { fs::File::from_raw_fd(fd) };
+
+        // Files are watched through the directories holding them, as editors
+        // often replace a file rather than write to it.
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_ATTRIB
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO;
+        let parents: collections::HashSet<_> = files
+            .iter()
+            .map(|file| file.parent().unwrap_or_else(|| path::Path::new("")))
+            .collect();
+        let mut dirs = collections::HashMap::new();
+        for dir in parents {
+            let name = match dir.as_os_str().is_empty() {
+                true => ffi::CString::new(".")?,
+                false => ffi::CString::new(dir.as_os_str().as_bytes())?,
+            };
+            let wd = unsafe { libc::inotify_add_watch(fd, name.as_ptr(), mask) };
+            if wd < 0 {
+                // Missing directories are left for the build to complain about.
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => return Err(err),
+                }
+            }
+            // The same directory may be reached by different paths, each
+            // of which is watched through the one descriptor.
+            dirs.entry(wd).or_insert_with(Vec::new).push(dir);
+        }
+        // Without a directory to watch there'd be nothing to wake up for, so
+        // look for them to appear instead.
+        if dirs.is_empty() {
+            return super::poll(files, baseline, poll_interval);
+        }
+        // Whatever changed before the watches were in place would otherwise
+        // go unnoticed.
+        if baseline.changed(files) {
+            return Ok(());
+        }
+
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = inotify.read(&mut buffer)?;
+            let mut offset = 0;
+            while offset + header <= read {
+                let event: libc::inotify_event = unsafe {
+                    ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::inotify_event)
+                };
+                let name = &buffer[offset + header..offset + header + event.len as usize];
+                offset += header + event.len as usize;
+
+                // Events were lost, so any of them might have mattered.
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    return Ok(());
+                }
+                let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+                let name = ffi::OsStr::from_bytes(&name[..end]);
+                for dir in dirs.get(&event.wd).into_iter().flatten() {
+                    if files.contains(&dir.join(name)) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod native {
+    use std::{io, path, time};
+
+    use super::Baseline;
+
+    pub fn wait(_: &[path::PathBuf], _: &Baseline, _: time::Duration) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "The native watch backend isn't supported on this platform.",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_utils::testing::TempDir;
+    use std::sync::mpsc;
+
+    // Waits in a thread of its own, so that a backend which never notices
+    // the change fails the test rather than hanging it.
+    fn waits_for<F>(backend: WatchBackend, files: Vec<path::PathBuf>, baseline: Baseline, change: F)
+    where
+        F: FnOnce(),
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let interval = time::Duration::from_millis(10);
+            sender
+                .send(backend.wait(&files, &baseline, interval))
+                .unwrap();
+        });
+        change();
+        let waited = receiver.recv_timeout(time::Duration::from_secs(10));
+        waited.expect("change went unnoticed").unwrap();
+    }
+
+    fn notices_changes(backend: WatchBackend) {
+        let dir = TempDir::new("watch");
+        let file = dir.join("watched");
+        fs::write(&file, "a").unwrap();
+        let files = vec![file.clone()];
+
+        let baseline = Baseline::take(&files);
+        waits_for(backend, files, baseline, || {
+            fs::write(&file, "ab").unwrap();
+        });
+    }
+
+    fn notices_changes_made_before_waiting(backend: WatchBackend) {
+        let dir = TempDir::new("watch-early");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        fs::write(&old, "a").unwrap();
+
+        // As a build would, whilst finding another file to watch.
+        let baseline = Baseline::take(std::slice::from_ref(&old));
+        fs::write(&new, "a").unwrap();
+        let files = vec![old.clone(), new];
+        assert!(baseline.changed(&files));
+        waits_for(backend, files, baseline, || {});
+
+        let files = vec![old.clone()];
+        let baseline = Baseline::take(&files);
+        fs::remove_file(&old).unwrap();
+        assert!(baseline.changed(&files));
+        waits_for(backend, files, baseline, || {});
+    }
+
+    #[test]
+    fn can_watch_natively() {
+        notices_changes(WatchBackend::Native);
+        notices_changes_made_before_waiting(WatchBackend::Native);
+    }
+
+    #[test]
+    fn can_watch_by_polling() {
+        notices_changes(WatchBackend::Poll);
+        notices_changes_made_before_waiting(WatchBackend::Poll);
+    }
+
+    #[test]
+    fn unchanged_files_arent_taken_for_changed() {
+        let dir = TempDir::new("watch-unchanged");
+        let file = dir.join("watched");
+        fs::write(&file, "a").unwrap();
+        let files = vec![file, dir.join("missing")];
+        assert!(!Baseline::take(&files).changed(&files));
+    }
+
+    #[test]
+    fn nothing_to_watch_is_refused() {
+        let baseline = Baseline::take(&[]);
+        let interval = time::Duration::from_millis(10);
+        assert!(WatchBackend::Poll.wait(&[], &baseline, interval).is_err());
+        assert!(WatchBackend::default()
+            .wait(&[], &baseline, interval)
+            .is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fs, path};
+use std::{fs, path, sync};
 
 use asmbl_core as core;
 use asmbl_utils as utils;
@@ -76,11 +76,22 @@ impl FrontEnd {
 #[derive(Clone)]
 struct TargetSpecHandle {
     inner: core::TargetSpecHandle,
+    // The targets of the task, as declared, shared between its handles.
+    targets: sync::Arc<Vec<String>>,
 }
 
-impl From<core::TargetSpecHandle> for TargetSpecHandle {
-    fn from(inner: core::TargetSpecHandle) -> Self {
-        Self { inner }
+impl TargetSpecHandle {
+    fn sibling(&self, target_index: usize) -> rlua::Result<Self> {
+        if target_index >= self.targets.len() {
+            return Err(rlua::Error::RuntimeError(format!(
+                "Task has no target at index {}.",
+                target_index
+            )));
+        }
+        Ok(Self {
+            inner: core::TargetSpecHandle::new(self.inner.task_index, target_index),
+            targets: self.targets.clone(),
+        })
     }
 }
 
@@ -90,7 +101,55 @@ impl Into<core::TargetSpecHandle> for TargetSpecHandle {
     }
 }
 
-impl rlua::UserData for TargetSpecHandle {}
+// Indices count from zero, as they do in recipes.
+impl rlua::UserData for TargetSpecHandle {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("sibling", |_, this, target_index: usize| {
+            this.sibling(target_index)
+        });
+        methods.add_method("all", |_, this, ()| {
+            (0..this.targets.len())
+                .map(|target_index| this.sibling(target_index))
+                .collect::<rlua::Result<rlua::Variadic<_>>>()
+        });
+        methods.add_method("path_hint", |_, this, ()| {
+            Ok(this.targets[this.inner.target_index].clone())
+        });
+        methods.add_method("generated", |_, this, pattern: String| {
+            Ok(Generated {
+                inner: this.inner,
+                pattern,
+            })
+        });
+    }
+}
+
+// An inline script to be used as a task's recipe.
+struct Script {
+    body: String,
+    interpreter: Option<String>,
+}
+
+impl rlua::UserData for Script {}
+
+// A line to be handed to the shell as a task's recipe.
+struct Shell(String);
+
+impl rlua::UserData for Shell {}
+
+// Every target matching a pattern, as declared rather than as found on disk.
+struct GlobTarget(String);
+
+impl rlua::UserData for GlobTarget {}
+
+// Files matching a pattern beneath a target, as found once the task
+// producing it has run.
+struct Generated {
+    inner: core::TargetSpecHandle,
+    pattern: String,
+}
+
+impl rlua::UserData for Generated {}
 
 struct PrerequisiteSpec {
     inner: core::PrerequisiteSpec<path::PathBuf>,
@@ -108,6 +167,17 @@ impl<'lua> rlua::FromLua<'lua> for PrerequisiteSpec {
             rlua::Value::String(s) => Ok(Self {
                 inner: core::PrerequisiteSpec::Named(path::PathBuf::from(s.to_str()?), false),
             }),
+            rlua::Value::UserData(u) if u.is::<Generated>() => {
+                let generated = u.borrow::<Generated>()?;
+                Ok(Self {
+                    inner: core::PrerequisiteSpec::Late(generated.inner, generated.pattern.clone()),
+                })
+            }
+            rlua::Value::UserData(u) if u.is::<GlobTarget>() => Ok(Self {
+                inner: core::PrerequisiteSpec::Glob(path::PathBuf::from(
+                    &u.borrow::<GlobTarget>()?.0,
+                )),
+            }),
             rlua::Value::UserData(u) => Ok(Self {
                 inner: core::PrerequisiteSpec::Handle(
                     u.borrow::<TargetSpecHandle>()?.clone().into(),
@@ -117,8 +187,8 @@ impl<'lua> rlua::FromLua<'lua> for PrerequisiteSpec {
                 from: type_name(&v),
                 to: "PrerequisiteSpec",
                 message: Some(String::from(
-                    "Value must be the fully qualified name of a target \
-                     or a handle returned from the task function",
+                    "Value must be the fully qualified name of a target, \
+                     a handle returned from the task function or a glob_target",
                 )),
             }),
         }
@@ -129,6 +199,26 @@ fn make_lua_error<F: failure::Fail>(fail: F) -> rlua::Error {
     rlua::Error::external(failure::Error::from(fail))
 }
 
+// Where in the unit the function calling this one was itself called, found
+// by having Lua's `error` say where it would blame, three levels up past
+// pcall and the caller.
+fn origin(ctx: rlua::Context, unit: &path::Path) -> core::Origin {
+    let globals = ctx.globals();
+    let location = globals.get("pcall").and_then(|pcall: rlua::Function| {
+        let error: rlua::Function = globals.get("error")?;
+        pcall.call::<_, (bool, String)>((error, "", 3))
+    });
+    // Of the form '<chunk>:<line>: '.
+    let line = location.ok().and_then(|(_, location)| {
+        let location = location.trim_end().trim_end_matches(':');
+        location.rsplit(':').next()?.parse().ok()
+    });
+    core::Origin {
+        unit: unit.to_path_buf(),
+        line,
+    }
+}
+
 enum SequenceIterator<'lua, T>
 where
     T: rlua::FromLua<'lua>,
@@ -236,25 +326,128 @@ impl<'lua> rlua::FromLua<'lua> for TargetsSpec {
 
 struct TargetSpecHandleIterator {
     inner: core::TargetSpecHandleIterator,
+    targets: sync::Arc<Vec<String>>,
 }
 
-impl From<core::TargetSpecHandleIterator> for TargetSpecHandleIterator {
-    fn from(inner: core::TargetSpecHandleIterator) -> Self {
-        Self { inner }
+impl TargetSpecHandleIterator {
+    fn new(inner: core::TargetSpecHandleIterator, targets: Vec<String>) -> Self {
+        Self {
+            inner,
+            targets: sync::Arc::new(targets),
+        }
     }
 }
 
 impl<'lua> rlua::ToLuaMulti<'lua> for TargetSpecHandleIterator {
     fn to_lua_multi(self, ctx: rlua::Context<'lua>) -> Result<rlua::MultiValue<'lua>, rlua::Error> {
         use rlua::ToLua;
+        let targets = self.targets;
         Ok(self
             .inner
             .into_iter()
-            .map(|handle| TargetSpecHandle::from(handle).to_lua(ctx.clone()))
+            .map(|inner| {
+                TargetSpecHandle {
+                    inner,
+                    targets: targets.clone(),
+                }
+                .to_lua(ctx)
+            })
             .collect::<Result<rlua::MultiValue<'lua>, _>>()?)
     }
 }
 
+// Sequence entries name variables to inherit; keyed entries define them.
+fn env_specs(t: rlua::Table) -> rlua::Result<Vec<core::EnvSpec>> {
+    t.pairs::<rlua::Value, String>()
+        .map(|pair| {
+            let (name, value) = pair?;
+            match name {
+                rlua::Value::Number(_) | rlua::Value::Integer(_) => {
+                    Ok(core::EnvSpec::inherit(value))
+                }
+                rlua::Value::String(s) => Ok(core::EnvSpec::define(s.to_str()?.to_string(), value)),
+                _ => Err(rlua::Error::FromLuaConversionError {
+                    from: type_name(&name),
+                    to: "EnvSpecKey",
+                    message: Some(String::from("Value must be a string or number")),
+                }),
+            }
+        })
+        .collect()
+}
+
+// The name of a file, or of a handle's target, without its directory or
+// extension, so that whatever's derived from it needn't pick paths apart.
+fn stem(file: &rlua::Value) -> rlua::Result<Option<String>> {
+    let path = match file {
+        rlua::Value::String(s) => s.to_str()?.to_string(),
+        rlua::Value::UserData(u) if u.is::<TargetSpecHandle>() => {
+            let handle = u.borrow::<TargetSpecHandle>()?;
+            handle.targets[handle.inner.target_index].clone()
+        }
+        _ => return Ok(None),
+    };
+    Ok(path::Path::new(&path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned()))
+}
+
+// A probe, as `{exists = "vendor"}` or `{succeeds = {"pkg-config", ...}}`.
+fn probe(t: rlua::Table) -> rlua::Result<core::Probe> {
+    if let Some(path) = t.get::<_, Option<PathBuf>>("exists")? {
+        return Ok(core::Probe::Exists(path.into()));
+    }
+    match t.get::<_, Option<Vec<String>>>("succeeds")? {
+        Some(cmd) => Ok(core::Probe::Succeeds(cmd)),
+        None => Err(rlua::Error::FromLuaConversionError {
+            from: "table",
+            to: "Probe",
+            message: Some(String::from("Table must have 'exists' or 'succeeds'")),
+        }),
+    }
+}
+
+// The recipe given as `run` to task and hook alike.
+fn recipe(run: Option<rlua::Value>) -> rlua::Result<core::Recipe> {
+    match run {
+        // A sequence of commands, each a sequence of arguments, run in turn.
+        Some(rlua::Value::Table(t)) if matches!(t.get(1)?, rlua::Value::Table(_)) => {
+            let commands = t
+                .sequence_values::<rlua::Table>()
+                .map(|command| command?.sequence_values().collect())
+                .collect::<Result<_, _>>()?;
+            core::Recipe::sequence(commands).map_err(make_lua_error)
+        }
+        Some(rlua::Value::Table(t)) => {
+            core::Recipe::new(t.sequence_values().collect::<Result<Vec<_>, _>>()?)
+                .map_err(make_lua_error)
+        }
+        Some(rlua::Value::String(s)) => core::Recipe::parse(s.to_str()?).map_err(make_lua_error),
+        Some(rlua::Value::UserData(u)) if u.is::<Script>() => {
+            let script = u.borrow::<Script>()?;
+            core::Recipe::script(&script.body, script.interpreter.as_deref())
+                .map_err(make_lua_error)
+        }
+        Some(rlua::Value::UserData(u)) if u.is::<Shell>() => {
+            core::Recipe::shell(&u.borrow::<Shell>()?.0).map_err(make_lua_error)
+        }
+        Some(v) => Err(rlua::Error::FromLuaConversionError {
+            from: type_name(&v),
+            to: "ExecRecipe",
+            message: Some(String::from(
+                "Value must be a string or a sequence of strings",
+            )),
+        }),
+        None => Err(rlua::Error::FromLuaConversionError {
+            from: "nil",
+            to: "ExecRecipe",
+            message: Some(String::from(
+                "Value must be a string or a sequence of strings",
+            )),
+        }),
+    }
+}
+
 impl core::FrontEnd for FrontEnd {
     fn parse_unit<'v, 'p>(
         &self,
@@ -271,10 +464,32 @@ impl core::FrontEnd for FrontEnd {
                     "task",
                     scope.create_function_mut(
                         |ctx, args: rlua::Table| -> Result<TargetSpecHandleIterator, _> {
-                            let targets = match args.get::<_, Option<TargetsSpec>>("targets")? {
-                                Some(targets) => targets,
+                            // A phony task goes by its name alone.
+                            let name: Option<String> = args.get("name")?;
+                            let declared = match args.get::<_, Option<TargetsSpec>>("targets")? {
+                                Some(targets) => Some(targets),
                                 None => args.get("target")?,
                             };
+                            let mut targets: Vec<String> = match (&name, declared) {
+                                (Some(name), None) => vec![name.clone()],
+                                (Some(name), Some(_)) => {
+                                    return Err(rlua::Error::RuntimeError(format!(
+                                        "Task '{}' is named, so can't have targets as well.",
+                                        name
+                                    )))
+                                }
+                                (None, Some(targets)) => targets.into(),
+                                (None, None) => args.get::<_, TargetsSpec>("target")?.into(),
+                            };
+
+                            // The result file is just another of the task's targets.
+                            let result = match args.get::<_, Option<String>>("result")? {
+                                Some(result) => {
+                                    targets.push(result);
+                                    Some(targets.len() - 1)
+                                }
+                                None => None,
+                            };
 
                             let make_prequisite_specs =
                                 |key| -> Result<Vec<core::PrerequisiteSpec<path::PathBuf>>, _> {
@@ -285,73 +500,261 @@ impl core::FrontEnd for FrontEnd {
                                 };
 
                             let run = match args.get::<_, Option<rlua::Value>>("run")? {
-                                Some(rlua::Value::Table(t)) => core::Recipe::new(
-                                    t.sequence_values().collect::<Result<Vec<_>, _>>()?,
-                                )
-                                .map_err(|err| make_lua_error(err))?,
-                                Some(rlua::Value::String(s)) => core::Recipe::parse(s.to_str()?)
-                                    .map_err(|err| make_lua_error(err))?,
-                                Some(v) => {
-                                    return Err(rlua::Error::FromLuaConversionError {
-                                        from: type_name(&v),
-                                        to: "ExecRecipe",
-                                        message: Some(String::from(
-                                            "Value must be a string or a sequence of strings",
-                                        )),
-                                    });
-                                }
-                                None => {
-                                    // FIXME - this will probably mean phony at some point in the future
-                                    return Err(rlua::Error::FromLuaConversionError {
-                                        from: "nil",
-                                        to: "ExecRecipe",
-                                        message: Some(String::from(
-                                            "Value must be a string or a sequence of strings",
-                                        )),
-                                    });
-                                }
+                                None if name.is_some() => None,
+                                run => Some(recipe(run)?),
                             };
 
-                            let env: Vec<core::EnvSpec> = match args
-                                .get::<_, Option<rlua::Table>>("env")?
-                            {
-                                Some(t) => t
-                                    .pairs::<rlua::Value, String>()
-                                    .map(|pair| {
-                                        let (name, value) = pair?;
-                                        match name {
-                                            rlua::Value::Number(_) | rlua::Value::Integer(_) => {
-                                                Ok(core::EnvSpec::inherit(value))
-                                            }
-                                            rlua::Value::String(s) => Ok(core::EnvSpec::define(
-                                                s.to_str()?.to_string(),
-                                                value,
-                                            )),
-                                            _ => Err(rlua::Error::FromLuaConversionError {
-                                                from: type_name(&name),
-                                                to: "EnvSpecKey",
-                                                message: Some(String::from(
-                                                    "Value must be a string or number",
-                                                )),
-                                            }),
-                                        }
-                                    })
-                                    .collect::<Result<Vec<_>, _>>()?,
+                            let env = match args.get::<_, Option<rlua::Table>>("env")? {
+                                Some(t) => env_specs(t)?,
                                 None => vec![],
                             };
 
-                            Ok(unit_builder
+                            let options = core::TaskOptions {
+                                inherit_all_env: args
+                                    .get::<_, Option<bool>>("inherit_all_env")?
+                                    .unwrap_or(false),
+                                wrapper: Sequence::new(ctx, args.get("wrapper")?)
+                                    .into_iter()
+                                    .collect::<Result<Vec<String>, _>>()?,
+                                image: args.get("image")?,
+                                launcher: args
+                                    .get::<_, Option<String>>("launcher")?
+                                    .map(|launcher| launcher.parse())
+                                    .transpose()
+                                    .map_err(make_lua_error)?,
+                                network: args.get::<_, Option<bool>>("network")?.unwrap_or(true),
+                                result,
+                                stdout: args
+                                    .get::<_, Option<String>>("stdout")?
+                                    .map(|stdout| core::target_index(&stdout))
+                                    .transpose()
+                                    .map_err(make_lua_error)?,
+                                stdin: args
+                                    .get::<_, Option<String>>("stdin")?
+                                    .map(|stdin| core::input_index(&stdin))
+                                    .transpose()
+                                    .map_err(make_lua_error)?,
+                                aggregate: args
+                                    .get::<_, Option<bool>>("aggregate")?
+                                    .unwrap_or(true),
+                                phony: name.is_some(),
+                                group: false,
+                                description: args.get("description")?,
+                                echo: args.get::<_, Option<bool>>("echo")?.unwrap_or(true),
+                                depfile: args.get("depfile")?,
+                                produces: Sequence::new(ctx, args.get("produces")?)
+                                    .into_iter()
+                                    .collect::<Result<Vec<String>, _>>()?,
+                                restat: args.get::<_, Option<bool>>("restat")?.unwrap_or(false),
+                                always: args.get::<_, Option<bool>>("always")?.unwrap_or(false),
+                                once: args.get::<_, Option<bool>>("once")?.unwrap_or(false),
+                                atomic: args.get::<_, Option<bool>>("atomic")?.unwrap_or(false),
+                                pool: args.get("pool")?,
+                                color: args.get::<_, Option<bool>>("color")?.unwrap_or(true),
+                                cwd: args.get::<_, Option<PathBuf>>("cwd")?.map(Into::into),
+                                tools: vec![],
+                                enabled_when: match args.get("enabled_when")? {
+                                    Some(enabled_when) => vec![probe(enabled_when)?],
+                                    None => vec![],
+                                },
+                                // Filled in from the unit.
+                                sandbox_allow: vec![],
+                                vars: Default::default(),
+                                build_options: Default::default(),
+                            };
+
+                            let handles = unit_builder
                                 .borrow_mut()
                                 .add_task(
-                                    targets.into(),
-                                    make_prequisite_specs("consumes")?,
-                                    make_prequisite_specs("depends_on")?,
-                                    make_prequisite_specs("not_before")?,
-                                    env,
-                                    run,
+                                    targets.clone(),
+                                    core::TaskSpec {
+                                        consumes: make_prequisite_specs("consumes")?,
+                                        depends_on: make_prequisite_specs("depends_on")?,
+                                        not_before: make_prequisite_specs("not_before")?,
+                                        env,
+                                        recipe: run,
+                                        options,
+                                        origin: Some(origin(ctx, path)),
+                                    },
                                 )
-                                .map_err(|err| make_lua_error(err))?
-                                .into())
+                                .map_err(make_lua_error)?;
+
+                            Ok(TargetSpecHandleIterator::new(handles, targets))
+                        },
+                    )?,
+                )?;
+
+                ctx.globals().set(
+                    "fetch",
+                    scope.create_function_mut(
+                        |ctx, args: rlua::Table| -> Result<TargetSpecHandleIterator, _> {
+                            let url: String = args.get("url")?;
+                            let sha256: String = args.get("sha256")?;
+                            let targets: Vec<String> =
+                                match args.get::<_, Option<TargetsSpec>>("targets")? {
+                                    Some(targets) => targets,
+                                    None => args.get("target")?,
+                                }
+                                .into();
+
+                            let spec = core::TaskSpec {
+                                origin: Some(origin(ctx, path)),
+                                ..core::builtins::fetch(&url, &sha256).map_err(make_lua_error)?
+                            };
+                            let handles = unit_builder
+                                .borrow_mut()
+                                .add_task(targets.clone(), spec)
+                                .map_err(make_lua_error)?;
+
+                            Ok(TargetSpecHandleIterator::new(handles, targets))
+                        },
+                    )?,
+                )?;
+
+                ctx.globals().set(
+                    "extract",
+                    scope.create_function_mut(
+                        |ctx, args: rlua::Table| -> Result<TargetSpecHandleIterator, _> {
+                            let archive: PrerequisiteSpec = args.get("consumes")?;
+                            let targets: Vec<String> =
+                                match args.get::<_, Option<TargetsSpec>>("targets")? {
+                                    Some(targets) => targets,
+                                    None => args.get("target")?,
+                                }
+                                .into();
+
+                            let spec = core::TaskSpec {
+                                origin: Some(origin(ctx, path)),
+                                ..core::builtins::extract(archive.into()).map_err(make_lua_error)?
+                            };
+                            let handles = unit_builder
+                                .borrow_mut()
+                                .add_task(targets.clone(), spec)
+                                .map_err(make_lua_error)?;
+
+                            Ok(TargetSpecHandleIterator::new(handles, targets))
+                        },
+                    )?,
+                )?;
+
+                ctx.globals().set(
+                    "probe",
+                    scope.create_function_mut(
+                        |ctx, args: rlua::Table| -> Result<TargetSpecHandleIterator, _> {
+                            let tool: String = args.get("tool")?;
+                            let tool_args = match args.get::<_, Option<Vec<String>>>("args")? {
+                                Some(tool_args) => tool_args,
+                                None => vec![String::from("--version")],
+                            };
+                            let targets: Vec<String> =
+                                match args.get::<_, Option<TargetsSpec>>("targets")? {
+                                    Some(targets) => targets,
+                                    None => args.get("target")?,
+                                }
+                                .into();
+
+                            let spec = core::TaskSpec {
+                                origin: Some(origin(ctx, path)),
+                                ..core::builtins::probe(&tool, &tool_args)
+                                    .map_err(make_lua_error)?
+                            };
+                            let handles = unit_builder
+                                .borrow_mut()
+                                .add_task(targets.clone(), spec)
+                                .map_err(make_lua_error)?;
+
+                            Ok(TargetSpecHandleIterator::new(handles, targets))
+                        },
+                    )?,
+                )?;
+
+                ctx.globals().set(
+                    "group",
+                    scope.create_function_mut(
+                        |ctx, args: rlua::Table| -> Result<TargetSpecHandleIterator, _> {
+                            let name: String = args.get("name")?;
+                            let files = Sequence::new(ctx, args.get("files")?)
+                                .into_iter()
+                                .map(|r: Result<PrerequisiteSpec, _>| r.map(|p| p.into()))
+                                .collect::<Result<_, _>>()?;
+
+                            let spec = core::TaskSpec {
+                                origin: Some(origin(ctx, path)),
+                                ..core::builtins::group(files)
+                            };
+                            let handles = unit_builder
+                                .borrow_mut()
+                                .add_task(vec![name.clone()], spec)
+                                .map_err(make_lua_error)?;
+
+                            Ok(TargetSpecHandleIterator::new(handles, vec![name]))
+                        },
+                    )?,
+                )?;
+
+                ctx.globals().set(
+                    "foreach",
+                    ctx.create_function(|ctx, (files, each): (rlua::Value, rlua::Function)| {
+                        // Everything `each` returns, e.g. the handles of the
+                        // tasks it declares, gathered into a single list.
+                        let gathered = ctx.create_table()?;
+                        for file in Sequence::new(ctx, files).into_iter::<rlua::Value>() {
+                            let file = file?;
+                            let stem = stem(&file)?;
+                            for value in each.call::<_, rlua::MultiValue>((file, stem))? {
+                                gathered.set(gathered.raw_len() + 1, value)?;
+                            }
+                        }
+                        Ok(gathered)
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "glob_target",
+                    ctx.create_function(|_, pattern: String| Ok(GlobTarget(pattern)))?,
+                )?;
+
+                ctx.globals().set(
+                    "shell",
+                    ctx.create_function(|_, line: String| Ok(Shell(line)))?,
+                )?;
+
+                ctx.globals().set(
+                    "script",
+                    ctx.create_function(|_, (body, interpreter): (String, Option<String>)| {
+                        Ok(Script { body, interpreter })
+                    })?,
+                )?;
+
+                // What the unit is being read for, e.g. `asmbl.profile`, which is nil
+                // unless a profile was named.
+                let asmbl = ctx.create_table()?;
+                asmbl.set("profile", unit_builder.borrow().profile())?;
+                ctx.globals().set("asmbl", asmbl)?;
+
+                // The options the build was asked for, e.g. `options.LTO`,
+                // looked up as they're read so that the unit's tasks run
+                // again should those it read change.
+                let options = ctx.create_table()?;
+                let lookup = ctx.create_table()?;
+                lookup.set(
+                    "__index",
+                    scope.create_function_mut(|_, (_, name): (rlua::Table, String)| {
+                        Ok(unit_builder.borrow_mut().option(&name).map(String::from))
+                    })?,
+                )?;
+                options.set_metatable(Some(lookup));
+                ctx.globals().set("options", options)?;
+
+                ctx.globals().set(
+                    "requires_asmbl",
+                    ctx.create_function(
+                        |_, (version, capabilities): (String, Option<Vec<String>>)| {
+                            core::require_version(&version).map_err(make_lua_error)?;
+                            for capability in capabilities.unwrap_or_default() {
+                                core::require_capability(&capability).map_err(make_lua_error)?;
+                            }
+                            Ok(())
                         },
                     )?,
                 )?;
@@ -367,6 +770,147 @@ impl core::FrontEnd for FrontEnd {
                     })?,
                 )?;
 
+                ctx.globals().set(
+                    "set_launcher",
+                    scope.create_function_mut(|_, launcher: String| -> Result<(), _> {
+                        unit_builder
+                            .borrow_mut()
+                            .set_launcher(launcher.parse().map_err(make_lua_error)?);
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "set_output_dir",
+                    scope.create_function_mut(|_, dir: PathBuf| -> Result<(), _> {
+                        unit_builder
+                            .borrow_mut()
+                            .set_output_dir(dir.into())
+                            .map_err(make_lua_error)?;
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "default_env",
+                    scope.create_function_mut(|_, env: rlua::Table| -> Result<(), _> {
+                        unit_builder.borrow_mut().add_default_env(env_specs(env)?);
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "exists",
+                    scope.create_function_mut(|_, path: PathBuf| {
+                        Ok(unit_builder
+                            .borrow_mut()
+                            .probe(&core::Probe::Exists(path.into())))
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "succeeds",
+                    scope.create_function_mut(|_, cmd: Vec<String>| {
+                        Ok(unit_builder.borrow_mut().probe(&core::Probe::Succeeds(cmd)))
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "set_var",
+                    scope.create_function_mut(|_, (name, value): (String, String)| {
+                        unit_builder
+                            .borrow_mut()
+                            .set_var(name, value)
+                            .map_err(make_lua_error)
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "var",
+                    scope.create_function_mut(|_, name: String| {
+                        Ok(unit_builder.borrow().var(&name).map(String::from))
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "glob",
+                    scope.create_function_mut(|_, pattern: String| {
+                        let found = unit_builder
+                            .borrow_mut()
+                            .glob(&pattern)
+                            .map_err(make_lua_error)?;
+                        Ok(found
+                            .iter()
+                            .map(|path| path.to_string_lossy().into_owned())
+                            .collect::<Vec<_>>())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "tools",
+                    scope.create_function_mut(|_, tools: rlua::Table| -> Result<(), _> {
+                        for pair in tools.pairs::<String, rlua::Table>() {
+                            let (name, tool) = pair?;
+                            let tool = core::Tool::new(
+                                name,
+                                tool.get("url")?,
+                                tool.get("sha256")?,
+                                tool.get::<_, Option<PathBuf>>("bin")?.map(Into::into),
+                            )
+                            .map_err(make_lua_error)?;
+                            unit_builder.borrow_mut().add_tool(tool);
+                        }
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "sandbox_allow",
+                    scope.create_function_mut(|_, dirs: Vec<PathBuf>| -> Result<(), _> {
+                        for dir in dirs {
+                            unit_builder
+                                .borrow_mut()
+                                .allow_in_sandbox(dir.into())
+                                .map_err(make_lua_error)?;
+                        }
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "pools",
+                    scope.create_function_mut(|_, pools: rlua::Table| -> Result<(), _> {
+                        for pair in pools.pairs::<String, usize>() {
+                            let (name, depth) = pair?;
+                            unit_builder
+                                .borrow_mut()
+                                .add_pool(name, depth)
+                                .map_err(make_lua_error)?;
+                        }
+                        Ok(())
+                    })?,
+                )?;
+
+                ctx.globals().set(
+                    "hook",
+                    scope.create_function_mut(|_, args: rlua::Table| -> Result<(), _> {
+                        let trigger = args
+                            .get::<_, Option<String>>("on")?
+                            .map_or(Ok(core::HookTrigger::Always), |on| on.parse())
+                            .map_err(make_lua_error)?;
+                        let env = match args.get::<_, Option<rlua::Table>>("env")? {
+                            Some(t) => env_specs(t)?,
+                            None => vec![],
+                        };
+                        unit_builder.borrow_mut().add_hook(core::Hook::new(
+                            trigger,
+                            recipe(args.get("run")?)?,
+                            env,
+                        ));
+                        Ok(())
+                    })?,
+                )?;
+
                 ctx.globals().set(
                     "include",
                     scope.create_function_mut(|_, target: TargetSpecHandle| -> Result<(), _> {
@@ -387,3 +931,270 @@ impl core::FrontEnd for FrontEnd {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asmbl_core::FrontEnd as _;
+    use asmbl_utils::testing::TempDir;
+
+    fn read(script: &str) -> Result<core::Unit, failure::Error> {
+        let context = TempDir::new("lua");
+        let unit = context.join("asmbl.lua");
+        fs::write(&unit, script).unwrap();
+        let components: Vec<_> = context.components().collect();
+        let builder = core::UnitBuilder::new(&components, context.to_path_buf());
+        FrontEnd::new()
+            .parse_unit(&unit, builder)
+            .map_err(failure::Error::from)
+    }
+
+    fn parse(script: &str) -> core::Unit {
+        match read(script) {
+            Ok(unit) => unit,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // What's wrong with the unit, every cause in turn.
+    fn parse_error(script: &str) -> String {
+        let err = match read(script) {
+            Ok(_) => panic!("the unit parsed"),
+            Err(err) => err,
+        };
+        let causes: Vec<_> = err.iter_chain().map(|cause| cause.to_string()).collect();
+        causes.join(": ")
+    }
+
+    // Each task the unit declared, along with its targets.
+    fn tasks(unit: core::Unit) -> Vec<(Vec<String>, core::TaskSpec<path::PathBuf>)> {
+        let (tasks, _) = unit.decompose();
+        tasks
+            .into_iter()
+            .map(|(targets, task)| {
+                let targets = (0..targets.len())
+                    .map(|index| targets[index].to_string())
+                    .collect();
+                (targets, task)
+            })
+            .collect()
+    }
+
+    // The files named among some prerequisites.
+    fn named(prerequisites: &[core::PrerequisiteSpec<path::PathBuf>]) -> Vec<String> {
+        prerequisites
+            .iter()
+            .filter_map(|prerequisite| match prerequisite {
+                core::PrerequisiteSpec::Named(path, _) => Some(path.display().to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn named_tasks_cant_have_targets_as_well() {
+        for targets in &["targets = {'b'}", "target = 'b'"] {
+            let script = format!("task{{name = 'a', {}}}", targets);
+            let err = parse_error(&script);
+            assert!(
+                err.contains("Task 'a' is named, so can't have targets as well."),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn tasks_can_fan_out_per_input() {
+        let tasks = tasks(parse(
+            "task{target = 'all.o', consumes = {'a.c', 'b.c'}, run = 'cc -c $<'}\n\
+             local a, b = task{\n\
+                 target = '%f.o', consumes = {'a.c', 'b.c'}, run = 'cc -c $<', aggregate = false\n\
+             }\n\
+             assert(a and b, 'a handle for each input')",
+        ));
+        assert_eq!(tasks.len(), 3);
+        let (targets, all) = &tasks[0];
+        assert_eq!(targets, &["all.o"]);
+        assert!(all.options.aggregate);
+        assert_eq!(named(&all.consumes), ["a.c", "b.c"]);
+        for ((targets, task), input) in tasks[1..].iter().zip(&["a.c", "b.c"]) {
+            assert_eq!(targets, &["%f.o"]);
+            assert!(!task.options.aggregate);
+            assert_eq!(named(&task.consumes), [*input]);
+        }
+    }
+
+    #[test]
+    fn handles_lead_to_their_siblings() {
+        let tasks = tasks(parse(
+            "local h = task{targets = {'x.h', 'x.c'}, run = 'gen'}\n\
+             assert(h:path_hint() == 'x.h')\n\
+             assert(h:sibling(1):path_hint() == 'x.c')\n\
+             local all = {h:all()}\n\
+             assert(#all == 2 and all[1]:path_hint() == 'x.h' and all[2]:path_hint() == 'x.c')\n\
+             task{target = 'x.o', consumes = {h:sibling(1)}, run = 'cc -c $<'}",
+        ));
+        match tasks[1].1.consumes.as_slice() {
+            [core::PrerequisiteSpec::Handle(handle)] => {
+                assert_eq!((handle.task_index, handle.target_index), (0, 1));
+            }
+            _ => panic!("x.o doesn't consume x.c alone"),
+        }
+
+        let err = parse_error("local h = task{target = 'x', run = 'gen'}\nh:sibling(5)");
+        assert!(err.contains("Task has no target at index 5."), "{}", err);
+    }
+
+    #[test]
+    fn foreach_gathers_what_each_returns() {
+        let tasks = tasks(parse(
+            "local objects = foreach({'src/a.c', 'src/b.c'}, function(file, stem)\n\
+                 return task{target = stem .. '.o', consumes = file, run = 'cc -c $<'}\n\
+             end)\n\
+             assert(#objects == 2)\n\
+             task{target = 'lib.a', consumes = objects, run = 'ar rcs $@ $<'}",
+        ));
+        let targets: Vec<_> = tasks
+            .iter()
+            .map(|(targets, _)| targets[0].as_str())
+            .collect();
+        assert_eq!(targets, ["a.o", "b.o", "lib.a"]);
+        assert_eq!(named(&tasks[1].1.consumes), ["src/b.c"]);
+        let consumed: Vec<_> = tasks[2]
+            .1
+            .consumes
+            .iter()
+            .map(|prerequisite| match prerequisite {
+                core::PrerequisiteSpec::Handle(handle) => handle.task_index,
+                _ => panic!("lib.a consumes something other than a handle"),
+            })
+            .collect();
+        assert_eq!(consumed, [0, 1]);
+    }
+
+    #[test]
+    fn units_can_declare_variables() {
+        let unit = parse(
+            "set_var('CC', 'clang')\n\
+             assert(var('CC') == 'clang')\n\
+             assert(var('LD') == nil)\n\
+             task{target = 'a.o', consumes = 'a.c', run = '$CC -c $<'}",
+        );
+        assert_eq!(unit.vars.get("CC").map(String::as_str), Some("clang"));
+        let tasks = tasks(unit);
+        assert_eq!(
+            tasks[0].1.options.vars.get("CC").map(String::as_str),
+            Some("clang")
+        );
+
+        let err = parse_error("set_var('inputs', 'a.c')");
+        assert!(err.contains("Variable name 'inputs'"), "{}", err);
+    }
+
+    #[test]
+    fn options_read_are_recorded() {
+        let context = TempDir::new("lua-options");
+        fs::write(
+            context.join("asmbl.lua"),
+            "if options.LTO == 'on' then\n\
+                 task{target = 'a', run = 'cc -flto -o $@'}\n\
+             end\n\
+             assert(options.MISSING == nil)",
+        )
+        .unwrap();
+        let mut engine = core::Engine::new();
+        engine.register_frontend("lua", FrontEnd::new());
+        engine.set_options(
+            vec![("LTO", "on"), ("OTHER", "1")]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        );
+        let mut units = engine.gather_units(&context).unwrap();
+        let (_, unit) = units.pop().unwrap();
+
+        let tasks = tasks(unit);
+        assert_eq!(tasks.len(), 1);
+        let read: Vec<_> = tasks[0].1.options.build_options.iter().collect();
+        assert_eq!(
+            read,
+            [
+                (&"LTO".to_string(), &Some("on".to_string())),
+                (&"MISSING".to_string(), &None)
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_gather_files_without_running_anything() {
+        let tasks = tasks(parse(
+            "local headers = group{name = 'headers', files = {'a.h', 'b.h'}}\n\
+             task{target = 'a.o', consumes = 'a.c', depends_on = headers, run = 'cc -c $<'}",
+        ));
+        let (targets, group) = &tasks[0];
+        assert_eq!(targets, &["headers"]);
+        assert!(group.options.group && group.options.phony);
+        assert!(group.recipe.is_none());
+        assert_eq!(named(&group.depends_on), ["a.h", "b.h"]);
+        assert!(matches!(
+            tasks[1].1.depends_on.as_slice(),
+            [core::PrerequisiteSpec::Handle(handle)] if handle.task_index == 0
+        ));
+    }
+
+    #[test]
+    fn hooks_run_as_the_build_turns_out() {
+        let unit = parse(
+            "hook{on = 'failure', run = {'notify-send', 'Build failed'}}\n\
+             hook{run = 'touch .built'}",
+        );
+        let applies: Vec<_> = unit
+            .hooks
+            .iter()
+            .map(|hook| (hook.applies(true), hook.applies(false)))
+            .collect();
+        assert_eq!(applies, [(false, true), (true, true)]);
+
+        let err = parse_error("hook{on = 'sometimes', run = 'true'}");
+        assert!(err.contains("Unknown hook trigger 'sometimes'."), "{}", err);
+    }
+
+    #[test]
+    fn units_can_declare_pools() {
+        let unit = parse(
+            "pools{link = 1, heavy = 2}\n\
+             task{target = 'app', consumes = 'a.o', run = 'ld -o $@ $<', pool = 'link'}",
+        );
+        let pools: Vec<_> = unit
+            .pools
+            .iter()
+            .map(|(name, depth)| (name.as_str(), *depth))
+            .collect();
+        assert_eq!(pools, [("heavy", 2), ("link", 1)]);
+        let tasks = tasks(unit);
+        assert_eq!(tasks[0].1.options.pool.as_deref(), Some("link"));
+
+        let err = parse_error("pools{link = 0}");
+        assert!(
+            err.contains("Pool 'link' must let at least one task run."),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn units_can_allow_directories_in_the_sandbox() {
+        let tasks = tasks(parse(
+            "sandbox_allow{'vendor/sdk', '/usr/include'}\n\
+             task{target = 'a.o', consumes = 'a.c', run = 'cc -c $<'}",
+        ));
+        assert_eq!(
+            tasks[0].1.options.sandbox_allow,
+            [
+                path::PathBuf::from("vendor/sdk"),
+                path::PathBuf::from("/usr/include")
+            ]
+        );
+    }
+}
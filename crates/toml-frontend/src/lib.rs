@@ -0,0 +1,547 @@
+//! Reads units from `asmbl.toml` files, for those who'd rather declare their
+//! tasks than script them:
+//!
+//! ```toml
+//! [[task]]
+//! targets = "hello.o"
+//! consumes = "hello.c"
+//! run = "cc -c $< -o $@"
+//! env = { CCACHE_DIR = "/tmp/ccache", HOME = true }
+//!
+//! [[task]]
+//! targets = ["hello"]
+//! consumes = ["hello.o"]
+//! run = ["cc", "$<", "-o", "$@"]
+//! ```
+//!
+//! A prerequisite naming one of the targets of an earlier task refers to that
+//! task, as a handle would in Lua; anything else is a file. An environment
+//! variable given `true` rather than a value is inherited. A task with
+//! `aggregate = false` is run once for each input, `%f` in its targets
+//! standing for the input's file stem.
+//!
+//! Before any task, `requires_asmbl = ">=0.3"` and `requires = ["groups"]`
+//! say what the unit needs of asmbl, and `vars = { CC = "clang" }` declares
+//! variables its recipes, and those of its sub-units, may refer to as `$CC`.
+//! Each `[[tool]]`, with a `name`, `url`, `sha256` and optionally `bin`, is
+//! given to every task in the unit.
+
+use std::{collections, fs, path};
+
+use asmbl_core as core;
+use asmbl_utils as utils;
+
+use toml::Value;
+
+#[derive(Debug, failure::Fail)]
+pub enum Error {
+    #[fail(display = "Invalid TOML")]
+    Syntax(#[fail(cause)] toml::de::Error),
+    #[fail(
+        display = "Unknown table '{}'; only [[task]], [task.env] and [[tool]] are allowed.",
+        _0
+    )]
+    UnknownTable(String),
+    #[fail(display = "Unknown key '{}'.", _0)]
+    UnknownKey(String),
+    #[fail(display = "Key '{}' must be {}, not {}.", _0, _1, _2)]
+    WrongType(String, &'static str, &'static str),
+    #[fail(display = "Key '{}' is missing.", _0)]
+    MissingKey(&'static str),
+    #[fail(display = "Unmet requirement")]
+    Requirement(#[fail(cause)] core::RequirementError),
+    #[fail(display = "Invalid recipe")]
+    Recipe(#[fail(cause)] core::RecipeParseError),
+    #[fail(display = "Invalid task")]
+    Task(#[fail(cause)] failure::Error),
+    #[fail(display = "Invalid tool")]
+    Tool(#[fail(cause)] core::BadTool),
+    #[fail(display = "Invalid variable")]
+    Var(#[fail(cause)] core::BadVarName),
+    #[fail(display = "Invalid path")]
+    Path(#[fail(cause)] core::Error),
+    #[fail(display = "Invalid pool")]
+    Pool(#[fail(cause)] core::EmptyPool),
+}
+
+impl From<Error> for core::ParseUnitError {
+    fn from(err: Error) -> Self {
+        Self::Other(failure::Error::from(err))
+    }
+}
+
+#[derive(Default)]
+pub struct FrontEnd;
+
+impl FrontEnd {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+// The tables of a document, as written.
+#[derive(Default)]
+struct Tables {
+    // The keys coming before any table.
+    preamble: toml::value::Table,
+    tasks: Vec<toml::value::Table>,
+    tools: Vec<toml::value::Table>,
+}
+
+// An array of tables, as `[[task]]` and `[[tool]]` make.
+fn array_of_tables(key: &str, value: Value) -> Result<Vec<toml::value::Table>, Error> {
+    let wrong_type =
+        |value: &Value| Error::WrongType(key.to_string(), "an array of tables", value.type_str());
+    match value {
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::Table(table) => Ok(table),
+                value => Err(wrong_type(&value)),
+            })
+            .collect(),
+        value => Err(wrong_type(&value)),
+    }
+}
+
+// Gathers the keys of a document into the tables they belong to.
+fn tables(document: &str) -> Result<Tables, Error> {
+    let document: toml::value::Table = toml::from_str(document).map_err(Error::Syntax)?;
+    let mut tables = Tables::default();
+    for (key, value) in document {
+        match key.as_str() {
+            "task" => tables.tasks = array_of_tables(&key, value)?,
+            "tool" => tables.tools = array_of_tables(&key, value)?,
+            _ if is_header(&value) => return Err(Error::UnknownTable(key)),
+            _ => {
+                tables.preamble.insert(key, value);
+            }
+        }
+    }
+    Ok(tables)
+}
+
+// Whether `value` is a table or an array of them, as headers make.
+fn is_header(value: &Value) -> bool {
+    match value {
+        Value::Table(_) => true,
+        Value::Array(values) => !values.is_empty() && values.iter().all(Value::is_table),
+        _ => false,
+    }
+}
+
+// A string, or an array of them.
+fn strings(key: &str, value: Value) -> Result<Vec<String>, Error> {
+    let wrong_type = |value: &Value| {
+        Error::WrongType(
+            key.to_string(),
+            "a string or an array of strings",
+            value.type_str(),
+        )
+    };
+    match value {
+        Value::String(s) => Ok(vec![s]),
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::String(s) => Ok(s),
+                value => Err(wrong_type(&value)),
+            })
+            .collect(),
+        value => Err(wrong_type(&value)),
+    }
+}
+
+fn string(key: &str, value: Value) -> Result<String, Error> {
+    match value {
+        Value::String(s) => Ok(s),
+        value => Err(Error::WrongType(
+            key.to_string(),
+            "a string",
+            value.type_str(),
+        )),
+    }
+}
+
+fn tool(table: toml::value::Table) -> Result<core::Tool, Error> {
+    let (mut name, mut url, mut sha256, mut bin) = (None, None, None, None);
+    for (key, value) in table {
+        match key.as_str() {
+            "name" => name = Some(string(&key, value)?),
+            "url" => url = Some(string(&key, value)?),
+            "sha256" => sha256 = Some(string(&key, value)?),
+            "bin" => bin = Some(path::PathBuf::from(string(&key, value)?)),
+            _ => return Err(Error::UnknownKey(key)),
+        }
+    }
+    core::Tool::new(
+        name.ok_or(Error::MissingKey("name"))?,
+        url.ok_or(Error::MissingKey("url"))?,
+        sha256.ok_or(Error::MissingKey("sha256"))?,
+        bin,
+    )
+    .map_err(Error::Tool)
+}
+
+// A probe, as `{ exists = "vendor" }` or `{ succeeds = ["pkg-config", ...] }`.
+fn probe(key: &str, value: Value) -> Result<core::Probe, Error> {
+    let wrong_type = |value: &Value| {
+        Error::WrongType(
+            key.to_string(),
+            "a table with 'exists' or 'succeeds'",
+            value.type_str(),
+        )
+    };
+    let keys = match value {
+        Value::Table(keys) => keys,
+        value => return Err(wrong_type(&value)),
+    };
+    if keys.len() != 1 {
+        return Err(wrong_type(&Value::Table(keys)));
+    }
+    match keys.into_iter().next().unwrap() {
+        (name, Value::String(path)) if name == "exists" => {
+            Ok(core::Probe::Exists(path::PathBuf::from(path)))
+        }
+        (name, cmd) if name == "succeeds" => Ok(core::Probe::Succeeds(strings(&name, cmd)?)),
+        (name, value) => {
+            let mut keys = toml::value::Table::new();
+            keys.insert(name, value);
+            Err(wrong_type(&Value::Table(keys)))
+        }
+    }
+}
+
+fn flag(key: &str, value: Value) -> Result<bool, Error> {
+    match value {
+        Value::Boolean(flag) => Ok(flag),
+        value => Err(Error::WrongType(
+            key.to_string(),
+            "a boolean",
+            value.type_str(),
+        )),
+    }
+}
+
+fn env_specs(keys: toml::value::Table) -> Result<Vec<core::EnvSpec>, Error> {
+    keys.into_iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => Ok(core::EnvSpec::define(name, value)),
+            Value::Boolean(true) => Ok(core::EnvSpec::inherit(name)),
+            value => Err(Error::WrongType(name, "a string or true", value.type_str())),
+        })
+        .collect()
+}
+
+fn recipe(value: Value) -> Result<core::Recipe, Error> {
+    match value {
+        Value::String(s) => core::Recipe::parse(&s).map_err(Error::Recipe),
+        // An array of commands, each an array of arguments, run in turn.
+        Value::Array(commands) if matches!(commands.first(), Some(Value::Array(_))) => {
+            let commands = commands
+                .into_iter()
+                .map(|command| strings("run", command))
+                .collect::<Result<_, _>>()?;
+            core::Recipe::sequence(commands).map_err(Error::Recipe)
+        }
+        value => core::Recipe::new(strings("run", value)?).map_err(Error::Recipe),
+    }
+}
+
+fn unit(
+    file: &path::Path,
+    document: &str,
+    mut unit_builder: core::UnitBuilder,
+) -> Result<core::Unit, Error> {
+    // The targets of the tasks declared so far, by name.
+    let mut declared = collections::HashMap::new();
+
+    let tables = tables(document)?;
+    for (key, value) in tables.preamble {
+        match key.as_str() {
+            "requires_asmbl" => match value {
+                Value::String(version) => {
+                    core::require_version(&version).map_err(Error::Requirement)?
+                }
+                value => return Err(Error::WrongType(key, "a string", value.type_str())),
+            },
+            "requires" => {
+                for capability in strings(&key, value)? {
+                    core::require_capability(&capability).map_err(Error::Requirement)?;
+                }
+            }
+            "vars" => match value {
+                Value::Table(vars) => {
+                    for (name, value) in vars {
+                        let value = string(&name, value)?;
+                        unit_builder.set_var(name, value).map_err(Error::Var)?;
+                    }
+                }
+                value => return Err(Error::WrongType(key, "a table", value.type_str())),
+            },
+            "sandbox_allow" => {
+                for dir in strings(&key, value)? {
+                    unit_builder
+                        .allow_in_sandbox(path::PathBuf::from(dir))
+                        .map_err(Error::Path)?;
+                }
+            }
+            "pools" => match value {
+                Value::Table(pools) => {
+                    for (name, value) in pools {
+                        let depth = match value {
+                            Value::Integer(depth) if depth >= 0 => depth as usize,
+                            value => {
+                                let found = value.type_str();
+                                return Err(Error::WrongType(name, "a positive integer", found));
+                            }
+                        };
+                        unit_builder.add_pool(name, depth).map_err(Error::Pool)?;
+                    }
+                }
+                value => return Err(Error::WrongType(key, "a table", value.type_str())),
+            },
+            _ => return Err(Error::UnknownKey(key)),
+        }
+    }
+
+    for table in tables.tools {
+        unit_builder.add_tool(tool(table)?);
+    }
+
+    for table in tables.tasks {
+        let mut targets = None;
+        let mut spec = core::TaskSpec {
+            consumes: vec![],
+            depends_on: vec![],
+            not_before: vec![],
+            env: vec![],
+            recipe: None,
+            options: core::TaskOptions::default(),
+            // TOML values keep no track of where they were written.
+            origin: Some(core::Origin {
+                unit: file.to_path_buf(),
+                line: None,
+            }),
+        };
+
+        // The inputs as written, for naming the targets of each task the spec
+        // fans out into.
+        let mut inputs = vec![];
+        for (key, value) in table {
+            let prerequisites = |names: Vec<String>| {
+                names
+                    .into_iter()
+                    .map(|name| match declared.get(&name) {
+                        Some(handle) => core::PrerequisiteSpec::Handle(*handle),
+                        None => core::PrerequisiteSpec::Named(path::PathBuf::from(name), false),
+                    })
+                    .collect()
+            };
+            match key.as_str() {
+                "targets" => targets = Some(strings(&key, value)?),
+                "consumes" => {
+                    inputs = strings(&key, value)?;
+                    spec.consumes = prerequisites(inputs.clone());
+                }
+                "depends_on" => spec.depends_on = prerequisites(strings(&key, value)?),
+                "not_before" => spec.not_before = prerequisites(strings(&key, value)?),
+                "run" => spec.recipe = Some(recipe(value)?),
+                "shell" => match value {
+                    Value::String(line) => {
+                        spec.recipe = Some(core::Recipe::shell(&line).map_err(Error::Recipe)?)
+                    }
+                    value => return Err(Error::WrongType(key, "a string", value.type_str())),
+                },
+                "depfile" => match value {
+                    Value::String(depfile) => spec.options.depfile = Some(depfile),
+                    value => return Err(Error::WrongType(key, "a string", value.type_str())),
+                },
+                "produces" => spec.options.produces = strings(&key, value)?,
+                "enabled_when" => spec.options.enabled_when.push(probe(&key, value)?),
+                "stdout" => {
+                    let stdout = core::target_index(&string(&key, value)?);
+                    spec.options.stdout = Some(stdout.map_err(Error::Recipe)?);
+                }
+                "stdin" => {
+                    let stdin = core::input_index(&string(&key, value)?);
+                    spec.options.stdin = Some(stdin.map_err(Error::Recipe)?);
+                }
+                "cwd" => match value {
+                    Value::String(cwd) => spec.options.cwd = Some(path::PathBuf::from(cwd)),
+                    value => return Err(Error::WrongType(key, "a string", value.type_str())),
+                },
+                "pool" => spec.options.pool = Some(string(&key, value)?),
+                "restat" => spec.options.restat = flag(&key, value)?,
+                "always" => spec.options.always = flag(&key, value)?,
+                "once" => spec.options.once = flag(&key, value)?,
+                "atomic" => spec.options.atomic = flag(&key, value)?,
+                "aggregate" => spec.options.aggregate = flag(&key, value)?,
+                "color" => spec.options.color = flag(&key, value)?,
+                // A group's targets name it, much as a phony task's do.
+                "group" => {
+                    spec.options.group = flag(&key, value)?;
+                    spec.options.phony = spec.options.group;
+                }
+                "env" => match value {
+                    Value::Table(keys) => spec.env = env_specs(keys)?,
+                    value => return Err(Error::WrongType(key, "a table", value.type_str())),
+                },
+                _ => return Err(Error::UnknownKey(key)),
+            }
+        }
+
+        let targets = targets.ok_or(Error::MissingKey("targets"))?;
+        let handles = unit_builder
+            .add_task(targets.clone(), spec)
+            .map_err(|err| Error::Task(failure::Error::from(err)))?
+            .collect::<Vec<_>>();
+        // A spec consuming several inputs fans out into a task for each, its
+        // targets told apart by what each task consumes.
+        let first = handles.first().map_or(0, |handle| handle.task_index);
+        for handle in handles {
+            let input = inputs.get(handle.task_index - first).map(path::Path::new);
+            let target = core::TargetSpec::from(targets[handle.target_index].clone())
+                .resolve(path::PathBuf::new(), input);
+            // Those which can't be named are left for the task list to reject.
+            if let Some(target) = target
+                .ok()
+                .and_then(|target| target.into_os_string().into_string().ok())
+            {
+                declared.insert(target, handle);
+            }
+        }
+    }
+
+    Ok(unit_builder.unit())
+}
+
+impl core::FrontEnd for FrontEnd {
+    fn parse_unit<'v, 'p>(
+        &self,
+        path: &path::Path,
+        unit_builder: core::UnitBuilder<'v, 'p>,
+    ) -> Result<core::Unit, core::ParseUnitError> {
+        let document = utils::io::read_file(fs::File::open(path)?)?;
+        unit(path, &document, unit_builder).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_unit(document: &str) -> Result<core::Unit, Error> {
+        let context = path::Path::new("/project");
+        let components: Vec<_> = context.components().collect();
+        unit(
+            &context.join("asmbl.toml"),
+            document,
+            core::UnitBuilder::new(&components, context.to_path_buf()),
+        )
+    }
+
+    fn parse(document: &str) -> Result<core::TaskList, Error> {
+        let context = path::Path::new("/project");
+        let units = vec![(context.to_path_buf(), parse_unit(document)?)];
+        Ok(core::TaskList::new(context, path::Path::new("../out"), units).unwrap())
+    }
+
+    fn upstream(tasks: &core::TaskList, target: &str) -> Vec<String> {
+        tasks
+            .upstream(tasks.find(path::Path::new(target)).unwrap())
+            .map(|(upstream, _)| match upstream {
+                core::Upstream::Task(handle) => {
+                    let target = tasks.get(handle).targets().next().unwrap();
+                    target.to_string_lossy().into_owned()
+                }
+                core::Upstream::File(file) => file.to_string_lossy().into_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn can_consume_earlier_targets() {
+        let tasks = parse(
+            "[[task]]\n\
+             targets = 'a.o'\n\
+             consumes = 'a.c'\n\
+             run = 'cc -c $< -o $@'\n\
+             \n\
+             [[task]]\n\
+             targets = 'a'\n\
+             consumes = ['a.o', 'b.o']\n\
+             run = 'cc $< -o $@'\n",
+        )
+        .unwrap();
+        assert_eq!(upstream(&tasks, "../out/a"), vec!["../out/a.o", "b.o"]);
+    }
+
+    #[test]
+    fn can_consume_each_target_of_a_task_fanned_out() {
+        let tasks = parse(
+            "[[task]]\n\
+             targets = ['%f.o', '%f.d']\n\
+             consumes = ['a.c', 'b.c']\n\
+             aggregate = false\n\
+             run = 'cc -c $< -o $@'\n\
+             \n\
+             [[task]]\n\
+             targets = 'b'\n\
+             consumes = ['b.o', 'a.d']\n\
+             run = 'cc $< -o $@'\n",
+        )
+        .unwrap();
+        assert_eq!(
+            upstream(&tasks, "../out/b"),
+            vec!["../out/b.o", "../out/a.o"]
+        );
+    }
+
+    #[test]
+    fn env_may_be_a_table_of_its_own() {
+        let unit = parse_unit(
+            "[[task]]\n\
+             targets = 'a'\n\
+             run = 'true'\n\
+             [task.env]\n\
+             CC = 'clang'\n\
+             HOME = true\n",
+        )
+        .unwrap();
+        let (tasks, _) = unit.decompose();
+        assert_eq!(
+            tasks[0].1.env,
+            vec![
+                core::EnvSpec::define("CC".to_string(), "clang".to_string()),
+                core::EnvSpec::inherit("HOME".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let error = |document| parse(document).err().unwrap().to_string();
+        assert_eq!(error("targets = 'a'\n"), "Unknown key 'targets'.");
+        assert_eq!(
+            error("[build]\nx = 1\n"),
+            "Unknown table 'build'; only [[task]], [task.env] and [[tool]] are allowed."
+        );
+        assert_eq!(
+            error("[[task]]\ntargets = 1\n"),
+            "Key 'targets' must be a string or an array of strings, not integer."
+        );
+        assert_eq!(
+            error("[[task]]\nrun = 'true'\n"),
+            "Key 'targets' is missing."
+        );
+        assert_eq!(
+            error("task = 'a'\n"),
+            "Key 'task' must be an array of tables, not string."
+        );
+        assert_eq!(
+            error("[[task]]\ntargets = 'a'\ntargets = 'b'\n"),
+            "Invalid TOML"
+        );
+    }
+}
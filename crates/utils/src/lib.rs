@@ -1,5 +1,5 @@
 pub mod io {
-    use std::fs;
+    use std::{fs, path};
 
     pub fn read_file(file: fs::File) -> Result<String, std::io::Error> {
         use std::io::Read;
@@ -8,12 +8,127 @@ pub mod io {
         buffered_reader.read_to_string(&mut contents)?;
         Ok(contents)
     }
+
+    /// One of the files asmbl keeps what it learns of a build in, beneath
+    /// `.asmbl` in the target directory.
+    #[derive(Debug, Clone)]
+    pub struct StateFile(path::PathBuf);
+
+    impl StateFile {
+        pub fn new(target_dir: &path::Path, name: &str) -> Self {
+            Self(target_dir.join(".asmbl").join(name))
+        }
+
+        /// Another file beside this one.
+        pub fn sibling(&self, name: &str) -> Self {
+            Self(self.0.with_file_name(name))
+        }
+
+        pub fn path(&self) -> &path::Path {
+            &self.0
+        }
+
+        /// The file's content; nothing, should it not have been written yet.
+        pub fn read(&self) -> std::io::Result<String> {
+            match fs::read_to_string(&self.0) {
+                Ok(content) => Ok(content),
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+                Err(err) => Err(err),
+            }
+        }
+
+        /// Replaces the file's content by way of a `.part` file renamed over
+        /// it, so that a build cut short never leaves it half written.
+        pub fn write(&self, content: &str) -> std::io::Result<()> {
+            fs::create_dir_all(self.0.parent().unwrap())?;
+            let partial = self.0.with_extension("part");
+            fs::write(&partial, content)?;
+            fs::rename(&partial, &self.0)
+        }
+    }
+
+    /// The records of a state file, one to a line, split at tabs into `fields`
+    /// fields, the last taking the rest of the line; lines with fewer fields
+    /// are passed over, as is anything else which can't be made sense of.
+    pub fn records(content: &str, fields: usize) -> impl Iterator<Item = Vec<&str>> {
+        content
+            .lines()
+            .map(move |line| line.splitn(fields, '\t').collect::<Vec<_>>())
+            .filter(move |record| record.len() == fields)
+    }
+}
+
+pub mod testing {
+    use std::{env, fs, ops, path, process, sync::atomic};
+
+    /// A directory of its own under the system's temporary directory, removed
+    /// along with whatever was put in it once dropped, be it at the end of a
+    /// test or on an assertion failing part way through.
+    pub struct TempDir(path::PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            static COUNT: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+            let count = COUNT.fetch_add(1, atomic::Ordering::Relaxed);
+            let dir = env::temp_dir().join(format!("asmbl-{}-{}-{}", name, process::id(), count));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        pub fn path(&self) -> &path::Path {
+            &self.0
+        }
+    }
+
+    impl ops::Deref for TempDir {
+        type Target = path::Path;
+
+        fn deref(&self) -> &path::Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<path::Path> for TempDir {
+        fn as_ref(&self) -> &path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::io::{records, StateFile};
+    use super::testing::TempDir;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn state_files_are_empty_until_written() {
+        let dir = TempDir::new("state-file");
+        let file = StateFile::new(&dir, "durations");
+        assert_eq!(file.read().unwrap(), "");
+        file.write("1\ta\n").unwrap();
+        assert_eq!(file.read().unwrap(), "1\ta\n");
+        assert_eq!(file.path(), dir.join(".asmbl").join("durations"));
+        assert!(!dir.join(".asmbl").join("durations.part").exists());
+        assert_eq!(file.sibling("env").path(), dir.join(".asmbl").join("env"));
+    }
+
+    #[test]
+    fn records_with_too_few_fields_are_passed_over() {
+        let content = "1\ta\tb\ngarbled\n\n2\tc\n";
+        assert_eq!(
+            records(content, 2).collect::<Vec<_>>(),
+            vec![vec!["1", "a\tb"], vec!["2", "c"]]
+        );
+    }
 }